@@ -0,0 +1,161 @@
+use anyhow::{Result, anyhow};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use nix::unistd::Uid;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// Which scripts can ever be run no matter what sets them
+// off (a change, a rule, a trigger, --all, or as a then
+// or before script). There are the config's lists:
+//
+//   allow = ["scripts/**"]
+//   deny = ["vendor/**"]
+//
+// With an allow list only the scripts that match it
+// run. The deny list wins over it. The globs are matched
+// against the path relative to the watch root.
+//
+// Scripts that anyone could have changed don't run
+// either (unless --no-permission-check): ones that are
+// world-writable or owned by someone other than the user
+// or root. That matters most for shared or synced
+// directories.
+#[derive(Debug, Clone, Default)]
+pub struct SpawnPolicy {
+    allow: Option<GlobSet>,
+    check_permissions: bool,
+    deny: Option<GlobSet>,
+    root: PathBuf,
+}
+
+impl SpawnPolicy {
+    pub fn new(
+        allow: Option<&[String]>,
+        deny: &[String],
+        root: &Path,
+        check_permissions: bool,
+    ) -> Result<SpawnPolicy> {
+        Ok(SpawnPolicy {
+            allow: allow.map(|allow| globs(allow, "allow")).transpose()?,
+            check_permissions,
+            deny: (!deny.is_empty())
+                .then(|| globs(deny, "deny"))
+                .transpose()?,
+            root: std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf()),
+        })
+    }
+
+    // The lists from a reloaded config
+    pub fn reloaded(&self, allow: Option<&[String]>, deny: &[String]) -> Result<SpawnPolicy> {
+        SpawnPolicy::new(allow, deny, &self.root, self.check_permissions)
+    }
+
+    pub fn check(&self, script: &Path) -> Result<(), &'static str> {
+        if self.check_permissions
+            && let Ok(metadata) = std::fs::metadata(script)
+        {
+            if metadata.mode() & 0o002 != 0 {
+                return Err(
+                    "it's world-writable so anyone could change what it runs (chmod o-w it or use --no-permission-check)",
+                );
+            }
+            let owner = Uid::from_raw(metadata.uid());
+            if owner != Uid::effective() && !owner.is_root() {
+                return Err(
+                    "it's owned by another user who could change what it runs (chown it or use --no-permission-check)",
+                );
+            }
+        }
+        if self.allow.is_none() && self.deny.is_none() {
+            return Ok(());
+        }
+        let full_path = std::fs::canonicalize(script).unwrap_or_else(|_| script.to_path_buf());
+        let relative = full_path.strip_prefix(&self.root).unwrap_or(&full_path);
+        if self
+            .deny
+            .as_ref()
+            .is_some_and(|deny| deny.is_match(relative))
+        {
+            return Err("on the deny list");
+        }
+        if self
+            .allow
+            .as_ref()
+            .is_some_and(|allow| !allow.is_match(relative))
+        {
+            return Err("not on the allow list");
+        }
+        Ok(())
+    }
+}
+
+fn globs(patterns: &[String], list: &str) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(
+            Glob::new(pattern)
+                .map_err(|e| anyhow!("invalid glob `{}` in the {} list: {}", pattern, list, e))?,
+        );
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn strings(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    #[test]
+    fn anything_goes_without_lists() {
+        let policy = SpawnPolicy::new(None, &[], Path::new("/project"), false).unwrap();
+        assert_eq!(policy.check(Path::new("/project/build.sh")), Ok(()));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let policy = SpawnPolicy::new(
+            Some(&strings(&["scripts/**"])),
+            &strings(&["scripts/vendor/**"]),
+            Path::new("/project"),
+            false,
+        )
+        .unwrap();
+        let check = |script: &str| policy.check(&Path::new("/project").join(script));
+        assert_eq!(check("scripts/build.sh"), Ok(()));
+        assert_eq!(check("scripts/vendor/setup.sh"), Err("on the deny list"));
+        assert_eq!(check("build.sh"), Err("not on the allow list"));
+    }
+
+    #[test]
+    fn rejects_bad_globs() {
+        assert!(SpawnPolicy::new(None, &strings(&["["]), Path::new("/"), false).is_err());
+    }
+
+    #[test]
+    fn refuses_world_writable_scripts() {
+        let dir = std::env::temp_dir().join(format!("ws-allow-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("build.sh");
+        std::fs::write(&script, "").unwrap();
+        let chmod = |mode| {
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(mode)).unwrap()
+        };
+        let checked = SpawnPolicy::new(None, &[], &dir, true).unwrap();
+        let unchecked = SpawnPolicy::new(None, &[], &dir, false).unwrap();
+        chmod(0o777);
+        assert!(
+            checked
+                .check(&script)
+                .unwrap_err()
+                .contains("world-writable")
+        );
+        assert_eq!(unchecked.check(&script), Ok(()));
+        chmod(0o755);
+        assert_eq!(checked.check(&script), Ok(()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::filters::build_globs;
+use anyhow::Result;
+use globset::GlobSet;
+use ignore::WalkBuilder;
+use sha1::{Digest, Sha1};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// What --then-if-changed looks at to decide whether the
+// main script actually made anything new. Every file
+// that matches the globs (ignored and hidden ones too
+// since build output usually is) goes into one hash
+// along with its path. When it comes out the same as
+// after the last run the then script gets skipped so a
+// rebuild that changed nothing doesn't deploy or upload
+// it again. Only the directories the globs start with
+// get walked (`dist/**` is just dist) and it's done off
+// the runtime's threads since it reads every file.
+#[derive(Debug, Clone)]
+pub struct Artifacts {
+    // where the walks start, relative to the root
+    bases: Vec<PathBuf>,
+    globs: GlobSet,
+    last: Arc<Mutex<Option<String>>>,
+    root: PathBuf,
+}
+
+impl Artifacts {
+    pub fn new(patterns: &[String], root: &Path) -> Result<Artifacts> {
+        let mut bases: Vec<PathBuf> = patterns.iter().map(|p| base_dir(p)).collect();
+        bases.sort();
+        // a directory that's inside another one already
+        // gets walked with it
+        bases.dedup_by(|inner, outer| inner.starts_with(outer));
+        Ok(Artifacts {
+            bases,
+            globs: build_globs(patterns)?,
+            last: Arc::default(),
+            root: root.to_path_buf(),
+        })
+    }
+
+    // The first check after startup counts as a change
+    pub async fn changed(&self) -> bool {
+        let artifacts = self.clone();
+        let Ok(hash) = tokio::task::spawn_blocking(move || artifacts.hash()).await else {
+            return true;
+        };
+        let previous = self.last.lock().unwrap().replace(hash.clone());
+        previous != Some(hash)
+    }
+
+    fn hash(&self) -> String {
+        let mut files: Vec<PathBuf> = self
+            .bases
+            .iter()
+            .map(|base| self.root.join(base))
+            .filter(|base| base.exists())
+            .flat_map(|base| WalkBuilder::new(base).standard_filters(false).build())
+            .flatten()
+            .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+            .map(|entry| entry.into_path())
+            .filter(|path| {
+                path.strip_prefix(&self.root)
+                    .is_ok_and(|relative| self.globs.is_match(relative))
+            })
+            .collect();
+        files.sort();
+        files.dedup();
+        let mut hasher = Sha1::new();
+        for file in files {
+            let path = file.to_string_lossy();
+            let contents = std::fs::read(&file).unwrap_or_default();
+            // each one goes in after its length so a path
+            // and what's in the file can't run together
+            // into the same bytes as a different pair
+            hasher.update((path.len() as u64).to_le_bytes());
+            hasher.update(path.as_bytes());
+            hasher.update((contents.len() as u64).to_le_bytes());
+            hasher.update(&contents);
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+// The part of a glob before anything that isn't literal
+fn base_dir(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|part| {
+            !part
+                .as_os_str()
+                .to_string_lossy()
+                .contains(['*', '?', '[', '{', '\\'])
+        })
+        .collect()
+}
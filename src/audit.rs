@@ -0,0 +1,278 @@
+use crate::run::{JobResult, describe_status, exit_code};
+use chrono::Local;
+use nix::errno::Errno;
+use nix::sys::signal::kill;
+use nix::unistd::{Pid, Uid, User};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use watchexec::command::Command as WatchCommand;
+use watchexec::job::Job;
+
+// --audit-log <path> adds a line to the file for every
+// command the watcher starts (scripts, then scripts,
+// before scripts, and guards) and another when it's
+// done so "what did it run?" can be answered later:
+//
+//   {"event":"spawn","id":3,"time":"...","program":"/usr/bin/bash",
+//    "argv":["bash","-c","./build.sh"],"cwd":"/src","user":"al","uid":1000}
+//   {"event":"exit","id":3,"time":"...","exit_code":0,"status":"exit 0"}
+//
+// The file is only ever added to. It's kept apart from
+// the script output which goes to the terminal or the
+// --daemon log.
+//
+// --journal keeps the same lines for scripts and then
+// scripts in the watch root, flushed to disk as they're
+// written, so a spawn without an exit after it means the
+// watcher (or the machine) went down in the middle of
+// that run. The next watcher started with --journal
+// lists those and marks them `interrupted` so they only
+// come up once. --resume runs them again.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    // --journal
+    durable: bool,
+    next_id: Arc<AtomicU64>,
+    path: PathBuf,
+    // the id for each job's command that's running. Jobs
+    // are matched up by their command since that's what
+    // the spawn hook gets.
+    running: Arc<Mutex<HashMap<usize, u64>>>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> AuditLog {
+        AuditLog {
+            durable: false,
+            next_id: Arc::default(),
+            path,
+            running: Arc::default(),
+        }
+    }
+
+    pub fn journal(root: &Path) -> AuditLog {
+        AuditLog {
+            durable: true,
+            ..AuditLog::new(root.join(JOURNAL_NAME))
+        }
+    }
+
+    // Returns the id for exited()
+    pub fn spawned(&self, command: &Command) -> u64 {
+        self.spawned_script(command, None)
+    }
+
+    fn spawned_script(&self, command: &Command, script: Option<&Path>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let program = command.get_program();
+        let mut argv = vec![program.to_string_lossy().to_string()];
+        argv.extend(
+            command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().to_string()),
+        );
+        let cwd = command
+            .get_current_dir()
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::current_dir().ok());
+        let uid = Uid::effective();
+        let user = User::from_uid(uid).ok().flatten().map(|user| user.name);
+        self.write(json!({
+            "event": "spawn",
+            "id": id,
+            "pid": std::process::id(),
+            "time": Local::now().to_rfc3339(),
+            "script": script,
+            "program": full_path(Path::new(program)),
+            "argv": argv,
+            "cwd": cwd,
+            "user": user,
+            "uid": uid.as_raw(),
+        }));
+        id
+    }
+
+    pub fn exited(&self, id: u64, exit_code: Option<i64>, status: &str) {
+        self.write(json!({
+            "event": "exit",
+            "id": id,
+            "pid": std::process::id(),
+            "time": Local::now().to_rfc3339(),
+            "exit_code": exit_code,
+            "status": status,
+        }));
+    }
+
+    pub fn exited_with(&self, id: u64, status: &std::io::Result<ExitStatus>) {
+        match status {
+            Ok(status) => self.exited(
+                id,
+                status.code().map(i64::from),
+                &match status.code() {
+                    Some(code) => format!("exit {}", code),
+                    None => "killed".to_string(),
+                },
+            ),
+            Err(e) => self.exited(id, None, &format!("could not run: {}", e)),
+        }
+    }
+
+    // From a job's spawn hook. --keep-alive starts the
+    // same job again after it exits on its own so the run
+    // before gets its exit here.
+    pub fn job_spawned(&self, job_command: &Arc<WatchCommand>, command: &Command, script: &Path) {
+        let id = self.spawned_script(command, Some(script));
+        let before = self
+            .running
+            .lock()
+            .unwrap()
+            .insert(command_key(job_command), id);
+        if let Some(before) = before {
+            self.exited(before, None, "restarted");
+        }
+    }
+
+    // A job that got stopped before it finished has no
+    // result
+    pub fn job_finished(&self, job: &Job, result: Option<&JobResult>) {
+        let Some(id) = self
+            .running
+            .lock()
+            .unwrap()
+            .remove(&command_key(&job.command()))
+        else {
+            return;
+        };
+        match result {
+            Some(result) => self.exited(
+                id,
+                exit_code(&result.status),
+                &describe_status(&result.status),
+            ),
+            None => self.exited(id, None, "stopped"),
+        }
+    }
+
+    // The runs a watcher that went down left unfinished.
+    // They're marked interrupted on the way out. Ones from
+    // a watcher that's still going (another one in the
+    // same root) are left alone.
+    pub fn take_interrupted(&self) -> Vec<Unfinished> {
+        let Ok(contents) = std::fs::read_to_string(&self.path) else {
+            return vec![];
+        };
+        let mut spawned = vec![];
+        let mut settled = HashSet::new();
+        // a line cut off partway through by the crash
+        // doesn't parse and is skipped
+        for line in contents.lines() {
+            let Ok(entry) = serde_json::from_str::<serde_json::Value>(line) else {
+                continue;
+            };
+            match entry["event"].as_str() {
+                Some("spawn") => {
+                    if let Ok(run) = serde_json::from_value::<Unfinished>(entry) {
+                        spawned.push(run);
+                    }
+                }
+                Some(_) => {
+                    if let (Some(pid), Some(id)) = (entry["pid"].as_u64(), entry["id"].as_u64()) {
+                        settled.insert((pid, id));
+                    }
+                }
+                None => {}
+            }
+        }
+        spawned.retain(|run| {
+            run.script.is_some()
+                && !settled.contains(&(u64::from(run.pid), run.id))
+                && !is_alive(run.pid)
+        });
+        for run in &spawned {
+            self.write(json!({
+                "event": "interrupted",
+                "id": run.id,
+                "pid": run.pid,
+                "time": Local::now().to_rfc3339(),
+            }));
+        }
+        spawned
+    }
+
+    fn write(&self, entry: serde_json::Value) {
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| {
+                writeln!(file, "{}", entry)?;
+                if self.durable {
+                    sync_later(file);
+                }
+                Ok(())
+            });
+        if let Err(e) = written {
+            eprintln!(
+                "\x1b[33mcould not add to {}: {}\x1b[0m",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+pub const JOURNAL_NAME: &str = ".watch_scripts.journal.jsonl";
+
+// A run a watcher that went down never saw the end of
+#[derive(Debug, Clone, Deserialize)]
+pub struct Unfinished {
+    pub argv: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    id: u64,
+    pid: u32,
+    pub script: Option<PathBuf>,
+    pub time: String,
+}
+
+// The line is already with the OS once it's written so
+// the wait for the disk happens on a thread of its own
+// instead of holding up the script's start
+fn sync_later(file: File) {
+    std::thread::spawn(move || {
+        let _ = file.sync_data();
+    });
+}
+
+// A process that's there but belongs to someone else
+// still counts
+fn is_alive(pid: u32) -> bool {
+    let Ok(pid) = i32::try_from(pid) else {
+        return false;
+    };
+    matches!(kill(Pid::from_raw(pid), None), Ok(()) | Err(Errno::EPERM))
+}
+
+fn command_key(command: &Arc<WatchCommand>) -> usize {
+    Arc::as_ptr(command) as usize
+}
+
+// Looked up on the PATH the way the shell would
+fn full_path(program: &Path) -> PathBuf {
+    if program.components().count() > 1 {
+        return std::fs::canonicalize(program).unwrap_or_else(|_| program.to_path_buf());
+    }
+    std::env::var_os("PATH")
+        .and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join(program))
+                .find(|candidate| candidate.is_file())
+        })
+        .unwrap_or_else(|| program.to_path_buf())
+}
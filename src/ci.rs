@@ -0,0 +1,43 @@
+use crate::session::RunInfo;
+use serde_json::Value;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+// --ci's line after each run so a wrapper can pick the
+// results out of the log without parsing the rest:
+//
+//   ws: run=3 script=build.sh status="exit 1" success=false duration_ms=812
+//
+// The runs come straight from the session (see
+// Session::finished_runs) so one that printed a lot
+// still gets its line.
+pub fn follow(mut runs: UnboundedReceiver<RunInfo>) {
+    tokio::spawn(async move {
+        while let Some(run) = runs.recv().await {
+            let run = serde_json::json!(run);
+            let fields: Vec<String> = [
+                ("run", "run_number"),
+                ("script", "script"),
+                ("status", "status"),
+                ("success", "success"),
+                ("duration_ms", "duration_ms"),
+            ]
+            .iter()
+            .map(|(name, field)| format!("{}={}", name, value(&run[*field])))
+            .collect();
+            println!("ws: {}", fields.join(" "));
+        }
+    });
+}
+
+// Strings with spaces (or quotes) get quoted and missing
+// values are left empty
+fn value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) if text.is_empty() || text.contains([' ', '"', '=']) => {
+            Value::String(text.clone()).to_string()
+        }
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{Payload, registry, scripts};
+use anyhow::{Result, anyhow};
+use clap_complete::env::Shells;
+use clap_complete::{CompleteEnv, CompletionCandidate};
+use std::ffi::OsStr;
+
+// `ws completions <shell>` prints the code to source in
+// the shell's startup file. It calls back into ws (with
+// COMPLETE set) for each completion so script names come
+// from what's in the directory at the time.
+pub fn print_registration(shell: &str) -> Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(shell)
+        .ok_or_else(|| anyhow!("no completions for {}", shell))?;
+    let exe = std::env::current_exe()?;
+    completer.write_registration(
+        "COMPLETE",
+        "ws",
+        "ws",
+        &exe.to_string_lossy(),
+        &mut std::io::stdout(),
+    )?;
+    Ok(())
+}
+
+// Has to be the first thing main does since the
+// completions go to stdout
+pub fn complete_if_asked() {
+    CompleteEnv::with_factory(Payload::cli).bin("ws").complete();
+}
+
+// The runnable scripts under the current directory for
+// `run`, `trigger`, --then, and --before
+pub fn script_candidates(current: &OsStr) -> Vec<CompletionCandidate> {
+    let Ok(dir) = std::env::current_dir() else {
+        return vec![];
+    };
+    let current = current.to_string_lossy();
+    scripts::find_scripts(&dir)
+        .iter()
+        .filter_map(|script| script.strip_prefix(&dir).ok())
+        .map(|script| script.display().to_string())
+        .filter(|script| script.starts_with(current.as_ref()))
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+// Named watchers for `attach`
+pub fn session_candidates(current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+    registry::names()
+        .into_iter()
+        .filter(|name| name.starts_with(current.as_ref()))
+        .map(CompletionCandidate::new)
+        .collect()
+}
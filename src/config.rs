@@ -0,0 +1,245 @@
+use crate::duration::deserialize_duration;
+use crate::filters::FilterConfig;
+use crate::order::OrderBy;
+use anyhow::{Result, anyhow};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const CONFIG_NAME: &str = ".watch_scripts.toml";
+
+// Settings that are too involved for command line flags
+// along with defaults for a few of the flags. Flags and
+// WATCH_SCRIPTS_* environment variables win over the
+// defaults here. Everything is optional and a missing
+// file is the same as an empty one.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Which scripts can ever be run. See
+    // allow::SpawnPolicy.
+    pub allow: Option<Vec<String>>,
+    // the directory --extend-path adds to PATH (relative
+    // to the watch root, bin by default)
+    pub bin: Option<PathBuf>,
+    // same values as --clear
+    pub clear: Option<String>,
+    pub deny: Vec<String>,
+    // Which filters changed files go through and in
+    // what order. See filters::FilterConfig.
+    pub filters: Option<Vec<FilterConfig>>,
+    // Sets of scripts with their own directories and
+    // then script. See groups::Groups.
+    pub groups: Vec<GroupConfig>,
+    // Commands run as runs start and end. See
+    // lifecycle.rs.
+    pub hooks: HooksConfig,
+    // Styles for lines of output. See
+    // highlight::Highlights.
+    pub highlight: Vec<HighlightConfig>,
+    // Groups of scripts that never run at the same time
+    // (ones that share a database or a port). See
+    // locks::Locks.
+    pub locks: BTreeMap<String, Patterns>,
+    // e.g. "22:00-07:00". See quiet::QuietHours.
+    pub no_run: Option<String>,
+    pub notify: NotifyConfig,
+    // Which of the scripts triggered together go first.
+    // See order::RunOrder.
+    pub order: Vec<String>,
+    // How scripts set off together are lined up before
+    // `order`. See order::OrderBy.
+    pub order_by: OrderBy,
+    // WASM plugins to load along with any from --plugin
+    pub plugins: Vec<PathBuf>,
+    // Matchers for the problems list along with any
+    // from --problems. See problems::ProblemMatchers.
+    pub problems: Vec<String>,
+    // Scripts to run when other files change. See
+    // rules::Rules.
+    pub rules: Vec<RuleConfig>,
+    // Scripts to run on a cron schedule or every so
+    // often. See schedule::Cron.
+    pub schedules: Vec<ScheduleConfig>,
+    // Defaults for every script that a script's own
+    // sidecar file can override. See sidecar.rs.
+    #[serde(deserialize_with = "deserialize_script_defaults")]
+    pub scripts: ScriptConfig,
+    pub shell: Option<String>,
+    pub then: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScriptConfig {
+    // added to the end of the command
+    pub args: Option<Vec<String>>,
+    // how long after a run is set off before it can be
+    // again (see cooldown::Cooldowns)
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub cooldown: Option<Duration>,
+    pub cwd: Option<CwdPolicy>,
+    pub env: BTreeMap<String, String>,
+    // globs (from the watch root) for what the script
+    // writes so those don't set off runs (see
+    // loop_guard::LoopGuard)
+    pub outputs: Option<Vec<String>>,
+    // goes first among scripts triggered together when
+    // higher (see order::RunOrder)
+    pub priority: Option<i64>,
+    // only read from sidecars and relative to the script
+    // what --tags picks scripts by (see tags.rs)
+    pub tags: Option<Vec<String>>,
+    pub then: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+}
+
+// `then` is only read from sidecars so it's turned away
+// here like any other unknown field instead of being
+// quietly ignored
+fn deserialize_script_defaults<'de, D>(deserializer: D) -> Result<ScriptConfig, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let scripts = ScriptConfig::deserialize(deserializer)?;
+    if scripts.then.is_some() {
+        return Err(D::Error::custom(
+            "`then` can't go in [scripts] (use the top level `then` or a script's sidecar)",
+        ));
+    }
+    Ok(scripts)
+}
+
+// Where a script gets run from
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CwdPolicy {
+    // the watch root
+    Root,
+    // the script's own directory (the default)
+    Script,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    pub on_cancel: Option<String>,
+    pub on_failure: Option<String>,
+    pub on_quit: Option<String>,
+    pub on_start: Option<String>,
+    pub on_success: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    pub discord: Option<ChatNotifier>,
+    pub slack: Option<ChatNotifier>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    pub on: Patterns,
+    pub run: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupConfig {
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    pub then: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HighlightConfig {
+    pub pattern: String,
+    pub style: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    // run again this long after the last time it was due
+    // instead of on a cron schedule
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub interval: Option<Duration>,
+    pub schedule: Option<String>,
+    pub script: PathBuf,
+}
+
+// A single glob or a list of them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Patterns {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Patterns {
+    pub fn patterns(&self) -> &[String] {
+        match self {
+            Patterns::One(pattern) => std::slice::from_ref(pattern),
+            Patterns::Many(patterns) => patterns,
+        }
+    }
+}
+
+// A chat webhook that gets a message when a run fails
+// and/or when a script that was failing passes again.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatNotifier {
+    #[serde(default = "default_on")]
+    pub on: Vec<NotifyOn>,
+    // the placeholders from placeholders.rs and `{event}`
+    // get filled in
+    #[serde(default = "default_template")]
+    pub template: String,
+    pub webhook_url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyOn {
+    Failure,
+    Recovery,
+}
+
+fn default_on() -> Vec<NotifyOn> {
+    vec![NotifyOn::Failure, NotifyOn::Recovery]
+}
+
+fn default_template() -> String {
+    "{script} {event} ({status}, run #{run_number})".to_string()
+}
+
+impl Config {
+    // Reads the --config path if one was given or the
+    // .watch_scripts.toml in the directory the watcher
+    // was started from if there is one.
+    pub fn load(explicit: Option<&PathBuf>, dir: &Path) -> Result<Config> {
+        let path = match explicit {
+            Some(path) => path.clone(),
+            None => {
+                let path = dir.join(CONFIG_NAME);
+                if !path.exists() {
+                    return Ok(Config::default());
+                }
+                path
+            }
+        };
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("could not read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| anyhow!("invalid config in {}: {}", path.display(), e))
+    }
+}
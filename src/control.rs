@@ -0,0 +1,556 @@
+use crate::filters::Ignores;
+use crate::inventory::Inventory;
+use crate::registry;
+use crate::run::format_duration;
+use crate::session::{RunInfo, Session};
+use crate::stats::{STATS_NAME, Stats};
+use crate::trigger;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Local};
+use nix::sys::stat::Mode;
+use serde_json::json;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::broadcast::error::RecvError;
+use watchexec::Watchexec;
+use watchexec_events::{Event, Priority, Source};
+
+pub const SOCKET_NAME: &str = ".watch_scripts.sock";
+pub const FIFO_NAME: &str = ".watch_scripts.fifo";
+
+// A Unix socket in the watch root that takes one
+// command per line so a running watcher can be
+// scripted from other shells:
+//
+//   run [script]   run a script (or re-run the last one)
+//   list           the scripts that can be run
+//   rescan         look through the watch root for
+//                  scripts again (new directories are
+//                  picked up on their own but this
+//                  catches anything that was missed)
+//   pause          stop reacting to file changes
+//   resume         start reacting to them again
+//   status         the same JSON as GET /status
+//   ignore [glob]  stop changes to matching files from
+//                  setting off runs (or list the globs)
+//   quit           shut the watcher down
+//   attach         follow along (see below)
+//
+// Every command gets a single line of JSON back. With
+// --stdin the same commands (other than attach) can be
+// typed or piped into the watcher itself.
+// `attach` gets one too and then every message from
+// the run stream (the same ones the WebSocket gets) a
+// line at a time until the client goes away.
+#[derive(Clone)]
+pub struct ControlSocket {
+    pub ignores: Ignores,
+    pub inventory: Inventory,
+    pub root: PathBuf,
+    pub session: Arc<Mutex<Session>>,
+    pub wx: Arc<Watchexec>,
+}
+
+impl ControlSocket {
+    pub fn path(root: &Path) -> PathBuf {
+        root.join(SOCKET_NAME)
+    }
+
+    pub async fn serve(self) -> Result<()> {
+        let path = ControlSocket::path(&self.root);
+        if path.exists() {
+            // a socket nobody answers on was left behind by
+            // a watcher that didn't get to clean up
+            if UnixStream::connect(&path).await.is_ok() {
+                anyhow::bail!("another watcher is already using {}", path.display());
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        let server = Arc::new(self);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let server = Arc::clone(&server);
+                tokio::spawn(async move {
+                    let _ = server.handle(stream).await;
+                });
+            }
+        });
+        Ok(())
+    }
+
+    // Answers that are only an ok aren't shown so they
+    // don't get in the way of the runs' output
+    pub fn read_stdin(self) {
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(tokio::io::stdin()).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let response = if line == "attach" {
+                    json!({ "error": "attach only works on the socket" })
+                } else {
+                    self.command(line).await
+                };
+                if response != json!({ "ok": true }) {
+                    println!("{}", response);
+                }
+            }
+        });
+    }
+
+    pub fn fifo_path(root: &Path) -> PathBuf {
+        root.join(FIFO_NAME)
+    }
+
+    // --fifo makes a named pipe where each line written
+    // is a script to run (relative to the watch root) and
+    // an empty line re-runs the last one, e.g.
+    // `echo build.sh > .watch_scripts.fifo`. Opening it
+    // blocks until something writes to it so it gets its
+    // own thread.
+    pub fn read_fifo(self) -> Result<()> {
+        let path = ControlSocket::fifo_path(&self.root);
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+        nix::unistd::mkfifo(&path, Mode::S_IRUSR | Mode::S_IWUSR)
+            .map_err(|e| anyhow!("could not create {}: {}", path.display(), e))?;
+        let runtime = tokio::runtime::Handle::current();
+        std::thread::spawn(move || {
+            while let Ok(fifo) = std::fs::File::open(&path) {
+                for line in std::io::BufReader::new(fifo).lines().map_while(Result::ok) {
+                    let command = match line.trim() {
+                        "" => "run".to_string(),
+                        script => format!("run {}", script),
+                    };
+                    let response = runtime.block_on(self.command(&command));
+                    if let Some(error) = response.get("error").and_then(|e| e.as_str()) {
+                        eprintln!("ERROR: {}: {}", FIFO_NAME, error);
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn handle(&self, stream: UnixStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if line.trim() == "attach" {
+                return self.attach(writer).await;
+            }
+            let response = self.command(line.trim()).await;
+            writer
+                .write_all(format!("{}\n", response).as_bytes())
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn command(&self, line: &str) -> serde_json::Value {
+        let (command, arg) = match line.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, Some(arg.trim())),
+            None => (line, None),
+        };
+        let event = match command {
+            "run" => match self.script_for(arg) {
+                Ok(path) => trigger::manual_event(&path, Source::Internal),
+                Err(e) => return json!({ "error": e }),
+            },
+            "pause" | "resume" => trigger::command_event(command),
+            "ignore" => return self.ignore(arg),
+            "quit" => trigger::quit_event(),
+            "list" => return json!({ "scripts": relative_paths(&self.inventory) }),
+            "rescan" => {
+                self.inventory.rescan();
+                return json!({ "scripts": relative_paths(&self.inventory) });
+            }
+            "stats" => return json!({ "stats": self.session.lock().unwrap().stats }),
+            "status" => {
+                return self.session.lock().unwrap().status_json(&self.inventory);
+            }
+            _ => return json!({ "error": format!("unknown command: {}", command) }),
+        };
+        self.send(event).await
+    }
+
+    fn ignore(&self, pattern: Option<&str>) -> serde_json::Value {
+        match pattern {
+            Some(pattern) => match self.ignores.add(pattern) {
+                Ok(()) => json!({ "ok": true }),
+                Err(e) => json!({ "error": e.to_string() }),
+            },
+            None => json!({ "ignored": self.ignores.patterns() }),
+        }
+    }
+
+    async fn attach(&self, mut writer: OwnedWriteHalf) -> Result<()> {
+        let mut events = self.session.lock().unwrap().stream().subscribe();
+        writer
+            .write_all(format!("{}\n", json!({ "ok": true })).as_bytes())
+            .await?;
+        loop {
+            match events.recv().await {
+                Ok(message) => {
+                    writer
+                        .write_all(format!("{}\n", message).as_bytes())
+                        .await?
+                }
+                // a slow client just misses some lines
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+
+    fn script_for(&self, requested: Option<&str>) -> Result<PathBuf, String> {
+        match requested {
+            Some(requested) => trigger::resolve_script(&self.inventory, requested),
+            None => self
+                .session
+                .lock()
+                .unwrap()
+                .last_script
+                .clone()
+                .ok_or_else(|| "no script given and nothing has run yet".to_string()),
+        }
+    }
+
+    async fn send(&self, event: Event) -> serde_json::Value {
+        match self.wx.send_event(event, Priority::Urgent).await {
+            Ok(_) => json!({ "ok": true }),
+            Err(e) => json!({ "error": e.to_string() }),
+        }
+    }
+}
+
+fn relative_paths(inventory: &Inventory) -> Vec<String> {
+    inventory
+        .scripts()
+        .iter()
+        .map(|script| {
+            script
+                .strip_prefix(inventory.root())
+                .unwrap_or(script)
+                .display()
+                .to_string()
+        })
+        .collect()
+}
+
+// The socket of the watcher running in the current
+// directory or the closest parent that has one
+fn find_socket() -> Result<Option<PathBuf>> {
+    let cwd = std::env::current_dir()?;
+    Ok(cwd
+        .ancestors()
+        .map(ControlSocket::path)
+        .find(|path| path.exists()))
+}
+
+// The client side of `ws trigger [script]` for git hooks
+// and other tools. It looks for the socket from the
+// current directory up so it works from anywhere in
+// the watched tree. Relative script paths are resolved
+// here since the watcher may have been started from
+// somewhere else.
+pub async fn send_trigger(script: Option<&String>) -> Result<()> {
+    let Some(path) = find_socket()? else {
+        anyhow::bail!(
+            "no running watcher found ({} not found, see --socket)",
+            SOCKET_NAME
+        );
+    };
+    let command = match script {
+        Some(script) => format!("run {}", std::env::current_dir()?.join(script).display()),
+        None => "run".to_string(),
+    };
+    request(&path, &command).await?;
+    Ok(())
+}
+
+// `ws list` asks the running watcher for its inventory
+// and scans the current directory itself when there
+// isn't one.
+pub async fn list_scripts() -> Result<Vec<String>> {
+    if let Some(path) = find_socket()?
+        && let Ok(response) = request(&path, "list").await
+    {
+        let scripts = response["scripts"]
+            .as_array()
+            .map(|scripts| {
+                scripts
+                    .iter()
+                    .filter_map(|script| script.as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        return Ok(scripts);
+    }
+    Ok(relative_paths(&Inventory::scan(&std::env::current_dir()?)))
+}
+
+// `ws stats` gets them from the running watcher and
+// falls back to the ones saved with --keep-stats
+pub async fn stats_table() -> Result<String> {
+    if let Some(path) = find_socket()?
+        && let Ok(response) = request(&path, "stats").await
+        && let Ok(stats) = serde_json::from_value::<Stats>(response["stats"].clone())
+    {
+        return Ok(stats.table());
+    }
+    let cwd = std::env::current_dir()?;
+    match cwd.ancestors().find(|dir| dir.join(STATS_NAME).is_file()) {
+        Some(dir) => Ok(Stats::load(dir).table()),
+        None => Err(anyhow!(
+            "no running watcher found and no {} (see --keep-stats)",
+            STATS_NAME
+        )),
+    }
+}
+
+// `ws status` gets the running watcher's status and
+// lays it out for reading: where it's watching, what's
+// running now, and how the last few runs went.
+pub async fn status_text() -> Result<String> {
+    let Some(path) = find_socket()? else {
+        anyhow::bail!(
+            "no running watcher found ({} not found, see --socket)",
+            SOCKET_NAME
+        );
+    };
+    let status = request(&path, "status").await?;
+    let uptime = Duration::from_secs(status["uptime_secs"].as_u64().unwrap_or_default());
+    let runs = status["runs"].as_u64().unwrap_or_default();
+    let mut text = format!(
+        "Watching {} ({} scripts) for {} | {} run{}{}\n",
+        status["root"].as_str().unwrap_or("?"),
+        status["scripts"].as_u64().unwrap_or_default(),
+        format_duration(&uptime),
+        runs,
+        if runs == 1 { "" } else { "s" },
+        if status["paused"] == true {
+            " | paused"
+        } else {
+            ""
+        }
+    );
+    let running: Vec<RunInfo> =
+        serde_json::from_value(status["running"].clone()).unwrap_or_default();
+    let recent: Vec<RunInfo> = serde_json::from_value(status["recent"].clone()).unwrap_or_default();
+    if running.is_empty() {
+        text.push_str("Nothing running\n");
+    }
+    for run in &running {
+        let elapsed = DateTime::parse_from_rfc3339(&run.started_at)
+            .ok()
+            .and_then(|started| (Local::now().fixed_offset() - started).to_std().ok())
+            .unwrap_or_default();
+        text.push_str(&format!(
+            "Running {} (run {}, {} so far)\n",
+            run.script.display(),
+            run.run_number,
+            format_duration(&elapsed)
+        ));
+    }
+    if !recent.is_empty() {
+        text.push_str("Recent:\n");
+    }
+    for run in recent.iter().rev() {
+        let started = DateTime::parse_from_rfc3339(&run.started_at)
+            .map(|started| started.format("%H:%M:%S").to_string())
+            .unwrap_or_default();
+        let took = run
+            .duration_ms
+            .map(|ms| format!(" in {}", format_duration(&Duration::from_millis(ms as u64))))
+            .unwrap_or_default();
+        text.push_str(&format!(
+            "  {} {} {}{}\n",
+            started,
+            run.script.display(),
+            run.status.as_deref().unwrap_or("unknown"),
+            took
+        ));
+    }
+    Ok(text.trim_end().to_string())
+}
+
+// `ws attach <name>` shows what a named watcher is
+// doing (usually one started with --daemon) as it
+// happens. Ctrl+\ detaches and leaves it running.
+pub async fn attach(name: &str) -> Result<()> {
+    let path = registry::lookup(name)?;
+    // the handler has to be in place before Ctrl+\ can
+    // be pressed or it'd kill the client with a core dump
+    let mut quit = signal(SignalKind::quit())?;
+    let stream = UnixStream::connect(&path)
+        .await
+        .map_err(|e| anyhow!("could not reach session {}: {}", name, e))?;
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(b"attach\n").await?;
+    let mut lines = BufReader::new(reader).lines();
+    let response: serde_json::Value =
+        serde_json::from_str(&lines.next_line().await?.unwrap_or_default())?;
+    if let Some(error) = response.get("error").and_then(|e| e.as_str()) {
+        anyhow::bail!("{}", error);
+    }
+    println!("\x1b[2mAttached to {} (Ctrl+\\ to detach)\x1b[0m", name);
+    loop {
+        tokio::select! {
+            line = lines.next_line() => match line? {
+                Some(line) => show_message(&line),
+                None => {
+                    println!("\x1b[2mSession {} ended\x1b[0m", name);
+                    return Ok(());
+                }
+            },
+            _ = quit.recv() => {
+                println!("\n\x1b[2mDetached from {}\x1b[0m", name);
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn show_message(line: &str) {
+    let Ok(message) = serde_json::from_str::<serde_json::Value>(line) else {
+        return;
+    };
+    let data = &message["data"];
+    let script = data["script"].as_str().unwrap_or_default();
+    match message["type"].as_str() {
+        Some("output") => println!("{}", data["line"].as_str().unwrap_or_default()),
+        Some("run_started") => println!("\x1b[2mRunning {}\x1b[0m", script),
+        Some("run_finished") => {
+            let status = data["status"].as_str().unwrap_or_default();
+            match data["success"].as_bool() {
+                Some(true) => println!("\x1b[32m{} {}\x1b[0m", script, status),
+                Some(false) => println!("\x1b[31m{} {}\x1b[0m", script, status),
+                None => println!("\x1b[33m{} {}\x1b[0m", script, status),
+            }
+        }
+        _ => {}
+    }
+}
+
+async fn request(path: &Path, command: &str) -> Result<serde_json::Value> {
+    let stream = UnixStream::connect(path).await?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+    let response = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .unwrap_or_default();
+    let response: serde_json::Value = serde_json::from_str(&response)?;
+    if let Some(error) = response.get("error").and_then(|e| e.as_str()) {
+        anyhow::bail!("{}", error);
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::Hooks;
+    use crate::stream::RunStream;
+    use std::os::unix::fs::PermissionsExt;
+
+    // A watch root of its own with one script in it
+    fn control(name: &str) -> ControlSocket {
+        let root = std::env::temp_dir().join(format!("ws-control-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        let script = root.join("build.sh");
+        std::fs::write(&script, "#!/bin/bash\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        ControlSocket {
+            ignores: Ignores::new(&root),
+            inventory: Inventory::scan(&root),
+            root,
+            session: Arc::new(Mutex::new(Session::new(RunStream::new(), Hooks::default()))),
+            wx: Arc::new(Watchexec::default()),
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_scripts_by_name() {
+        let control = control("run");
+        assert_eq!(control.command("run build.sh").await, json!({ "ok": true }));
+        assert_eq!(
+            control.command("run   build.sh ").await,
+            json!({ "ok": true })
+        );
+        assert_eq!(
+            control.command("run nope.sh").await,
+            json!({ "error": "nope.sh does not exist" })
+        );
+        assert_eq!(
+            control.command("run").await,
+            json!({ "error": "no script given and nothing has run yet" })
+        );
+        control.session.lock().unwrap().last_script = Some(control.root.join("build.sh"));
+        assert_eq!(control.command("run").await, json!({ "ok": true }));
+        let _ = std::fs::remove_dir_all(&control.root);
+    }
+
+    #[tokio::test]
+    async fn lists_and_rescans_scripts() {
+        let control = control("list");
+        assert_eq!(
+            control.command("list").await,
+            json!({ "scripts": ["build.sh"] })
+        );
+        let added = control.root.join("test.sh");
+        std::fs::write(&added, "#!/bin/bash\n").unwrap();
+        std::fs::set_permissions(&added, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(
+            control.command("rescan").await,
+            json!({ "scripts": ["build.sh", "test.sh"] })
+        );
+        let _ = std::fs::remove_dir_all(&control.root);
+    }
+
+    #[tokio::test]
+    async fn adds_and_lists_ignores() {
+        let control = control("ignore");
+        assert_eq!(control.command("ignore").await, json!({ "ignored": [] }));
+        assert_eq!(control.command("ignore *.log").await, json!({ "ok": true }));
+        assert!(control.command("ignore [").await["error"].is_string());
+        assert_eq!(
+            control.command("ignore").await,
+            json!({ "ignored": ["*.log"] })
+        );
+        let _ = std::fs::remove_dir_all(&control.root);
+    }
+
+    #[tokio::test]
+    async fn answers_the_rest() {
+        let control = control("rest");
+        assert_eq!(control.command("pause").await, json!({ "ok": true }));
+        assert_eq!(control.command("resume").await, json!({ "ok": true }));
+        assert_eq!(control.command("status").await["root"], json!(control.root));
+        assert!(control.command("stats").await["stats"].is_object());
+        assert_eq!(
+            control.command("dance now").await,
+            json!({ "error": "unknown command: dance" })
+        );
+        let _ = std::fs::remove_dir_all(&control.root);
+    }
+}
@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// A script with a `cooldown` in `[scripts]` or its
+// sidecar doesn't get set off again until that long
+// after the last time it was:
+//
+//   [scripts]
+//   cooldown = "2s"
+//
+// so an editor or formatter that writes a file several
+// times in a row ends up as one run. Asking for a script
+// by name isn't held back but still starts the wait.
+#[derive(Debug, Clone, Default)]
+pub struct Cooldowns {
+    last: Arc<Mutex<HashMap<PathBuf, Instant>>>,
+}
+
+impl Cooldowns {
+    // Returns how long is left if it's still cooling
+    // down. Otherwise it counts as triggered now.
+    pub fn hold_back(
+        &self,
+        script: &Path,
+        cooldown: Option<Duration>,
+        manual: bool,
+    ) -> Option<Duration> {
+        let cooldown = cooldown?;
+        let mut last = self.last.lock().unwrap();
+        if !manual
+            && let Some(elapsed) = last.get(script).map(Instant::elapsed)
+            && elapsed < cooldown
+        {
+            return Some(cooldown - elapsed);
+        }
+        last.insert(script.to_path_buf(), Instant::now());
+        None
+    }
+}
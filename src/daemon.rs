@@ -0,0 +1,272 @@
+use anyhow::{Result, anyhow};
+use nix::unistd::{ForkResult, dup2_stderr, dup2_stdin, dup2_stdout, fork, setsid};
+use std::fs::{File, OpenOptions};
+use std::io::{PipeReader, Read, Write};
+use std::os::fd::AsFd;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, SystemTime};
+
+pub const LOG_NAME: &str = ".watch_scripts.log";
+pub const PID_NAME: &str = ".watch_scripts.pid";
+
+// Tells close_log() when the last of the output has
+// made it through the log thread
+static LOG_DONE: Mutex<Option<Receiver<()>>> = Mutex::new(None);
+
+// How the --daemon log gets written. Once it's bigger
+// than `max_size` or older than `max_age` it's moved to
+// `<log>.1` (which moves to `<log>.2` and so on) and a
+// new one is started. Only `keep` old ones are kept.
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    pub colors: bool,
+    pub keep: usize,
+    pub max_age: Option<Duration>,
+    pub max_size: Option<u64>,
+}
+
+impl LogOptions {
+    fn rotates(&self) -> bool {
+        self.max_age.is_some() || self.max_size.is_some()
+    }
+}
+
+// Moves the watcher into the background for --daemon.
+// This has to happen before the tokio runtime starts
+// since only the thread that calls fork() makes it into
+// the child. Output goes to the log file from here on.
+// Unless it's being kept as is it goes through a pipe
+// first so the escape codes can be taken out (so the log
+// can be grepped) and the file can be rotated. That
+// covers the scripts' output too since they inherit the
+// pipe.
+pub fn detach(pidfile: &Path, logfile: &Path, options: LogOptions) -> Result<()> {
+    let log = open_log(logfile)?;
+    let null = File::open("/dev/null")?;
+    // fork twice so the watcher isn't a session leader
+    // and can't pick up a controlling terminal again
+    for _ in 0..2 {
+        match unsafe { fork() }? {
+            ForkResult::Parent { .. } => std::process::exit(0),
+            ForkResult::Child => {
+                let _ = setsid();
+            }
+        }
+    }
+    std::fs::write(pidfile, format!("{}\n", std::process::id()))
+        .map_err(|e| anyhow!("could not write pidfile {}: {}", pidfile.display(), e))?;
+    dup2_stdin(&null)?;
+    if options.colors && !options.rotates() {
+        dup2_stdout(&log)?;
+        dup2_stderr(&log)?;
+        return Ok(());
+    }
+    let (reader, writer) = std::io::pipe()?;
+    dup2_stdout(&writer)?;
+    dup2_stderr(&writer)?;
+    let (done_tx, done_rx) = mpsc::channel();
+    *LOG_DONE.lock().unwrap() = Some(done_rx);
+    let mut log = Log::new(logfile.to_path_buf(), log, options);
+    std::thread::spawn(move || {
+        log.copy_from(reader);
+        let _ = done_tx.send(());
+    });
+    Ok(())
+}
+
+// --ci takes the escape codes out of everything that
+// gets printed, the scripts' output included since they
+// inherit the pipe, the same way they're taken out of
+// the log. stderr ends up in stdout along the way, which
+// is where CI shows it anyway.
+pub fn strip_colors() -> Result<()> {
+    let stdout = File::from(std::io::stdout().as_fd().try_clone_to_owned()?);
+    let (reader, writer) = std::io::pipe()?;
+    dup2_stdout(&writer)?;
+    dup2_stderr(&writer)?;
+    let (done_tx, done_rx) = mpsc::channel();
+    *LOG_DONE.lock().unwrap() = Some(done_rx);
+    let options = LogOptions {
+        colors: false,
+        keep: 0,
+        max_age: None,
+        max_size: None,
+    };
+    let mut log = Log::new(PathBuf::new(), stdout, options);
+    std::thread::spawn(move || {
+        log.copy_from(reader);
+        let _ = done_tx.send(());
+    });
+    Ok(())
+}
+
+fn open_log(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow!("could not open log file {}: {}", path.display(), e))
+}
+
+// Lets the log thread write out whatever's left. Gives
+// up after a bit since a script that's still running in
+// the background can keep the pipe open.
+pub fn close_log() {
+    let Some(done) = LOG_DONE.lock().unwrap().take() else {
+        return;
+    };
+    if let Ok(null) = File::options().write(true).open("/dev/null") {
+        let _ = std::io::stdout().flush();
+        let _ = dup2_stdout(&null);
+        let _ = dup2_stderr(&null);
+    }
+    let _ = done.recv_timeout(Duration::from_millis(500));
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Start,
+    // `ESC [ ... <letter>` for colors, cursor moves, etc.
+    Csi,
+    // `ESC ] ... BEL` (or `ESC \`) for titles and links
+    Osc,
+    OscEnd,
+}
+
+struct Log {
+    // where it is in an escape sequence since they can
+    // be split between reads
+    escape: Escape,
+    file: File,
+    options: LogOptions,
+    path: PathBuf,
+    size: u64,
+    started: SystemTime,
+}
+
+impl Log {
+    fn new(path: PathBuf, file: File, options: LogOptions) -> Log {
+        let metadata = file.metadata().ok();
+        Log {
+            escape: Escape::None,
+            size: metadata.as_ref().map_or(0, |m| m.len()),
+            // an empty log that's being appended to was
+            // just made
+            started: metadata
+                .filter(|m| m.len() > 0)
+                .and_then(|m| m.created().ok())
+                .unwrap_or_else(SystemTime::now),
+            file,
+            options,
+            path,
+        }
+    }
+
+    fn copy_from(&mut self, mut reader: PipeReader) {
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            let text = if self.options.colors {
+                buf[..n].to_vec()
+            } else {
+                self.strip(&buf[..n])
+            };
+            if self.is_due(text.len() as u64) {
+                self.rotate();
+            }
+            if self.file.write_all(&text).is_err() {
+                break;
+            }
+            self.size += text.len() as u64;
+        }
+    }
+
+    fn strip(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut text = Vec::with_capacity(bytes.len());
+        for &byte in bytes {
+            self.escape = match (self.escape, byte) {
+                (Escape::None, 0x1b) => Escape::Start,
+                (Escape::None, _) => {
+                    text.push(byte);
+                    Escape::None
+                }
+                (Escape::Start, b'[') => Escape::Csi,
+                (Escape::Start, b']') => Escape::Osc,
+                (Escape::Start, _) => Escape::None,
+                (Escape::Csi, 0x40..=0x7e) => Escape::None,
+                (Escape::Csi, _) => Escape::Csi,
+                (Escape::Osc | Escape::OscEnd, 0x07) => Escape::None,
+                (Escape::Osc | Escape::OscEnd, 0x1b) => Escape::OscEnd,
+                (Escape::OscEnd, b'\\') => Escape::None,
+                (Escape::Osc | Escape::OscEnd, _) => Escape::Osc,
+            };
+        }
+        text
+    }
+
+    // A log doesn't get rotated while it's empty so one
+    // big write can't leave a trail of empty files
+    fn is_due(&self, incoming: u64) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        self.options
+            .max_size
+            .is_some_and(|max| self.size + incoming > max)
+            || self
+                .options
+                .max_age
+                .is_some_and(|max| self.started.elapsed().unwrap_or_default() > max)
+    }
+
+    fn rotate(&mut self) {
+        let numbered = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        let _ = std::fs::remove_file(numbered(self.options.keep));
+        for n in (1..self.options.keep).rev() {
+            let _ = std::fs::rename(numbered(n), numbered(n + 1));
+        }
+        if self.options.keep > 0 {
+            let _ = std::fs::rename(&self.path, numbered(1));
+        } else {
+            let _ = std::fs::remove_file(&self.path);
+        }
+        // if a new file can't be made the old one gets
+        // written to (wherever it ended up)
+        if let Ok(file) = open_log(&self.path) {
+            self.file = file;
+            self.size = 0;
+            self.started = SystemTime::now();
+        }
+    }
+}
+
+// Tells systemd (or anything else that set NOTIFY_SOCKET)
+// how the watcher is doing. It's a no-op otherwise.
+pub fn sd_notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let path = PathBuf::from(&socket);
+    let Ok(sock) = std::os::unix::net::UnixDatagram::unbound() else {
+        return;
+    };
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.to_str().and_then(|p| p.strip_prefix('@')) {
+        use std::os::linux::net::SocketAddrExt;
+        if let Ok(addr) = std::os::unix::net::SocketAddr::from_abstract_name(name) {
+            let _ = sock.send_to_addr(state.as_bytes(), &addr);
+        }
+        return;
+    }
+    let _ = sock.send_to(state.as_bytes(), path);
+}
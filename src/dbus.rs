@@ -0,0 +1,703 @@
+use crate::control::ControlSocket;
+use crate::stream::RunStream;
+use anyhow::{Result, anyhow, bail};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::Mutex;
+
+// --dbus puts the watcher on the session bus so desktop
+// things (shortcut daemons, status bar widgets) can run
+// and watch it without parsing its output:
+//
+//   busctl --user call io.github.alanwsmith.WatchScripts \
+//     /io/github/alanwsmith/WatchScripts \
+//     io.github.alanwsmith.WatchScripts Trigger s build.sh
+//
+// Methods:
+//
+//   Trigger(s script)   run a script ("" re-runs the last one)
+//   Pause()
+//   Resume()
+//   Status() -> s       JSON like the control socket's status
+//
+// Signals:
+//
+//   RunStarted(s script, u run_number)
+//   RunFinished(s script, b success, s status, t duration_ms)
+//
+// Only the little bit of the protocol that takes is here
+// (EXTERNAL auth and the basic types) so there's no
+// libdbus to link against. A second watcher gets the
+// name with `.pid<pid>` on the end.
+const NAME: &str = "io.github.alanwsmith.WatchScripts";
+const PATH: &str = "/io/github/alanwsmith/WatchScripts";
+const INTERFACE: &str = "io.github.alanwsmith.WatchScripts";
+const ERROR: &str = "io.github.alanwsmith.WatchScripts.Error";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="io.github.alanwsmith.WatchScripts">
+    <method name="Trigger"><arg name="script" type="s" direction="in"/></method>
+    <method name="Pause"/>
+    <method name="Resume"/>
+    <method name="Status"><arg name="status" type="s" direction="out"/></method>
+    <signal name="RunStarted"><arg name="script" type="s"/><arg name="run_number" type="u"/></signal>
+    <signal name="RunFinished"><arg name="script" type="s"/><arg name="success" type="b"/><arg name="status" type="s"/><arg name="duration_ms" type="t"/></signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect"><arg name="xml" type="s" direction="out"/></method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#;
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR_REPLY: u8 = 3;
+const SIGNAL: u8 = 4;
+
+// the NO_REPLY_EXPECTED flag
+const NO_REPLY: u8 = 0x1;
+
+// header field codes
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+#[derive(Debug, Clone)]
+enum Field {
+    ObjectPath(String),
+    Signature(String),
+    String(String),
+    U32(u32),
+}
+
+#[derive(Debug, Default)]
+struct Message {
+    body: Vec<u8>,
+    flags: u8,
+    interface: Option<String>,
+    kind: u8,
+    member: Option<String>,
+    path: Option<String>,
+    reply_serial: Option<u32>,
+    sender: Option<String>,
+    serial: u32,
+    signature: String,
+    big_endian: bool,
+}
+
+// The body of an outgoing message
+#[derive(Default)]
+struct Body {
+    bytes: Vec<u8>,
+    signature: String,
+}
+
+impl Body {
+    fn align(&mut self, to: usize) {
+        while !self.bytes.len().is_multiple_of(to) {
+            self.bytes.push(0);
+        }
+    }
+
+    fn string(mut self, value: &str) -> Body {
+        self.signature.push('s');
+        write_string(&mut self.bytes, value);
+        self
+    }
+
+    fn u32(mut self, value: u32) -> Body {
+        self.signature.push('u');
+        self.align(4);
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn bool(self, value: bool) -> Body {
+        let mut body = self.u32(value as u32);
+        body.signature.pop();
+        body.signature.push('b');
+        body
+    }
+
+    fn u64(mut self, value: u64) -> Body {
+        self.signature.push('t');
+        self.align(8);
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+}
+
+fn pad(bytes: &mut Vec<u8>, to: usize) {
+    while !bytes.len().is_multiple_of(to) {
+        bytes.push(0);
+    }
+}
+
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    pad(bytes, 4);
+    bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+    bytes.push(0);
+}
+
+fn write_signature(bytes: &mut Vec<u8>, value: &str) {
+    bytes.push(value.len() as u8);
+    bytes.extend_from_slice(value.as_bytes());
+    bytes.push(0);
+}
+
+// Always little endian on the way out
+fn encode(kind: u8, flags: u8, serial: u32, fields: &[(u8, Field)], body: &Body) -> Vec<u8> {
+    let mut bytes = vec![b'l', kind, flags, 1];
+    bytes.extend_from_slice(&(body.bytes.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&serial.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    let mut fields = fields.to_vec();
+    if !body.signature.is_empty() {
+        fields.push((FIELD_SIGNATURE, Field::Signature(body.signature.clone())));
+    }
+    for (code, field) in &fields {
+        pad(&mut bytes, 8);
+        bytes.push(*code);
+        match field {
+            Field::ObjectPath(value) => {
+                write_signature(&mut bytes, "o");
+                write_string(&mut bytes, value);
+            }
+            Field::Signature(value) => {
+                write_signature(&mut bytes, "g");
+                write_signature(&mut bytes, value);
+            }
+            Field::String(value) => {
+                write_signature(&mut bytes, "s");
+                write_string(&mut bytes, value);
+            }
+            Field::U32(value) => {
+                write_signature(&mut bytes, "u");
+                pad(&mut bytes, 4);
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+    let fields_len = (bytes.len() - 16) as u32;
+    bytes[12..16].copy_from_slice(&fields_len.to_le_bytes());
+    pad(&mut bytes, 8);
+    bytes.extend_from_slice(&body.bytes);
+    bytes
+}
+
+// Reads values out of an incoming message
+struct Cursor<'a> {
+    big_endian: bool,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn align(&mut self, to: usize) {
+        self.pos = self.pos.next_multiple_of(to);
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| anyhow!("message cut short"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.align(4);
+        let big_endian = self.big_endian;
+        let bytes: [u8; 4] = self.take(4)?.try_into()?;
+        Ok(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        let value = String::from_utf8_lossy(self.take(len)?).to_string();
+        self.pos += 1;
+        Ok(value)
+    }
+
+    fn signature(&mut self) -> Result<String> {
+        let len = self.u8()? as usize;
+        let value = String::from_utf8_lossy(self.take(len)?).to_string();
+        self.pos += 1;
+        Ok(value)
+    }
+}
+
+// Nothing the watcher sends or expects comes close. The
+// lengths come from the other end so they're checked
+// before anything gets allocated for them.
+const MAX_MESSAGE: usize = 1 << 20;
+
+async fn read_message(reader: &mut (impl AsyncRead + Unpin)) -> Result<Message> {
+    let mut fixed = [0u8; 16];
+    reader.read_exact(&mut fixed).await?;
+    let big_endian = match fixed[0] {
+        b'l' => false,
+        b'B' => true,
+        _ => bail!("not a D-Bus message"),
+    };
+    let number = |bytes: &[u8]| {
+        let bytes: [u8; 4] = bytes.try_into().unwrap();
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    let body_len = number(&fixed[4..8]) as usize;
+    let fields_len = number(&fixed[12..16]) as usize;
+    let body_start = (16 + fields_len).next_multiple_of(8);
+    if body_start + body_len > MAX_MESSAGE {
+        bail!("message too big ({} bytes)", body_start + body_len);
+    }
+    let mut bytes = fixed.to_vec();
+    bytes.resize(body_start + body_len, 0);
+    reader.read_exact(&mut bytes[16..]).await?;
+    let mut message = Message {
+        big_endian,
+        flags: fixed[2],
+        kind: fixed[1],
+        serial: number(&fixed[8..12]),
+        ..Default::default()
+    };
+    let mut cursor = Cursor {
+        big_endian,
+        bytes: &bytes,
+        pos: 16,
+    };
+    while cursor.pos < 16 + fields_len {
+        cursor.align(8);
+        let code = cursor.u8()?;
+        let value = match cursor.signature()?.as_str() {
+            "s" | "o" => Field::String(cursor.string()?),
+            "g" => Field::Signature(cursor.signature()?),
+            "u" => Field::U32(cursor.u32()?),
+            other => bail!("unexpected header field type {}", other),
+        };
+        match (code, value) {
+            (FIELD_PATH, Field::String(value)) => message.path = Some(value),
+            (FIELD_INTERFACE, Field::String(value)) => message.interface = Some(value),
+            (FIELD_MEMBER, Field::String(value)) => message.member = Some(value),
+            (FIELD_REPLY_SERIAL, Field::U32(value)) => message.reply_serial = Some(value),
+            (FIELD_SENDER, Field::String(value)) => message.sender = Some(value),
+            (FIELD_SIGNATURE, Field::Signature(value)) => message.signature = value,
+            _ => {}
+        }
+    }
+    message.body = bytes[body_start..].to_vec();
+    Ok(message)
+}
+
+impl Message {
+    fn body(&self) -> Cursor<'_> {
+        Cursor {
+            big_endian: self.big_endian,
+            bytes: &self.body,
+            pos: 0,
+        }
+    }
+}
+
+struct Bus {
+    serial: AtomicU32,
+    writer: Mutex<OwnedWriteHalf>,
+}
+
+impl Bus {
+    async fn send(&self, kind: u8, fields: &[(u8, Field)], body: &Body) -> Result<u32> {
+        let serial = self.serial.fetch_add(1, Ordering::Relaxed);
+        let bytes = encode(kind, 0, serial, fields, body);
+        self.writer.lock().await.write_all(&bytes).await?;
+        Ok(serial)
+    }
+
+    async fn call_bus(&self, member: &str, body: &Body) -> Result<u32> {
+        self.send(
+            METHOD_CALL,
+            &[
+                (
+                    FIELD_PATH,
+                    Field::ObjectPath("/org/freedesktop/DBus".into()),
+                ),
+                (
+                    FIELD_INTERFACE,
+                    Field::String("org.freedesktop.DBus".into()),
+                ),
+                (FIELD_MEMBER, Field::String(member.into())),
+                (
+                    FIELD_DESTINATION,
+                    Field::String("org.freedesktop.DBus".into()),
+                ),
+            ],
+            body,
+        )
+        .await
+    }
+
+    async fn reply(&self, call: &Message, body: Result<Body, (&str, String)>) -> Result<()> {
+        if call.flags & NO_REPLY != 0 {
+            return Ok(());
+        }
+        let mut fields = vec![(FIELD_REPLY_SERIAL, Field::U32(call.serial))];
+        if let Some(sender) = &call.sender {
+            fields.push((FIELD_DESTINATION, Field::String(sender.clone())));
+        }
+        match body {
+            Ok(body) => self.send(METHOD_RETURN, &fields, &body).await?,
+            Err((name, message)) => {
+                fields.push((FIELD_ERROR_NAME, Field::String(name.into())));
+                self.send(ERROR_REPLY, &fields, &Body::default().string(&message))
+                    .await?
+            }
+        };
+        Ok(())
+    }
+
+    async fn signal(&self, member: &str, body: &Body) -> Result<()> {
+        self.send(
+            SIGNAL,
+            &[
+                (FIELD_PATH, Field::ObjectPath(PATH.into())),
+                (FIELD_INTERFACE, Field::String(INTERFACE.into())),
+                (FIELD_MEMBER, Field::String(member.into())),
+            ],
+            body,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+pub async fn serve(control: ControlSocket, stream: RunStream) -> Result<()> {
+    let address = std::env::var("DBUS_SESSION_BUS_ADDRESS")
+        .map_err(|_| anyhow!("there's no session bus (DBUS_SESSION_BUS_ADDRESS isn't set)"))?;
+    let socket = connect(&address).await?;
+    let (reader, writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+    let bus = Arc::new(Bus {
+        serial: AtomicU32::new(1),
+        writer: Mutex::new(writer),
+    });
+    authenticate(&mut reader, &bus).await?;
+    let hello = bus.call_bus("Hello", &Body::default()).await?;
+    wait_for_reply(&mut reader, hello).await?;
+    let mut name = NAME.to_string();
+    if !request_name(&mut reader, &bus, &name).await? {
+        name = format!("{}.pid{}", NAME, std::process::id());
+        if !request_name(&mut reader, &bus, &name).await? {
+            bail!("could not get the name {} on the session bus", name);
+        }
+        println!(
+            "\x1b[2mAnother watcher has {} so this one is {}\x1b[0m",
+            NAME, name
+        );
+    }
+    let calls = Arc::clone(&bus);
+    tokio::spawn(async move {
+        while let Ok(message) = read_message(&mut reader).await {
+            if message.kind == METHOD_CALL {
+                let reply = answer(&control, &message).await;
+                if calls.reply(&message, reply).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    let mut events = stream.follow(false);
+    tokio::spawn(async move {
+        while let Some(message) = events.next().await {
+            let run = &message["data"];
+            let script = run["script"].as_str().unwrap_or_default();
+            let sent = match message["type"].as_str() {
+                Some("run_started") => {
+                    let run_number = run["run_number"].as_u64().unwrap_or(0) as u32;
+                    let body = Body::default().string(script).u32(run_number);
+                    bus.signal("RunStarted", &body).await
+                }
+                Some("run_finished") => {
+                    let body = Body::default()
+                        .string(script)
+                        .bool(run["success"].as_bool().unwrap_or(false))
+                        .string(run["status"].as_str().unwrap_or_default())
+                        .u64(run["duration_ms"].as_u64().unwrap_or(0));
+                    bus.signal("RunFinished", &body).await
+                }
+                _ => Ok(()),
+            };
+            if sent.is_err() {
+                return;
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn answer(control: &ControlSocket, call: &Message) -> Result<Body, (&'static str, String)> {
+    let member = call.member.as_deref().unwrap_or_default();
+    match (call.interface.as_deref(), member) {
+        (Some("org.freedesktop.DBus.Introspectable") | None, "Introspect") => {
+            return Ok(Body::default().string(INTROSPECTION));
+        }
+        (Some("org.freedesktop.DBus.Peer") | None, "Ping") => return Ok(Body::default()),
+        _ => {}
+    }
+    if call.path.as_deref() != Some(PATH) {
+        return Err((
+            "org.freedesktop.DBus.Error.UnknownObject",
+            format!("no object at {}", call.path.as_deref().unwrap_or_default()),
+        ));
+    }
+    let command = match (member, call.signature.as_str()) {
+        ("Trigger", "s") => match call.body().string() {
+            Ok(script) if script.is_empty() => "run".to_string(),
+            Ok(script) => format!("run {}", script),
+            Err(e) => return Err(("org.freedesktop.DBus.Error.InvalidArgs", e.to_string())),
+        },
+        ("Pause", "") => "pause".to_string(),
+        ("Resume", "") => "resume".to_string(),
+        ("Status", "") => "status".to_string(),
+        _ => {
+            return Err((
+                "org.freedesktop.DBus.Error.UnknownMethod",
+                format!("no method {}({})", member, call.signature),
+            ));
+        }
+    };
+    let response = control.command(&command).await;
+    if let Some(error) = response.get("error") {
+        let error = error.as_str().map(str::to_string);
+        return Err((
+            ERROR,
+            error.unwrap_or_else(|| response["error"].to_string()),
+        ));
+    }
+    match member {
+        "Status" => Ok(Body::default().string(&response.to_string())),
+        _ => Ok(Body::default()),
+    }
+}
+
+// The first `unix:` address. Values can have %xx escapes.
+async fn connect(address: &str) -> Result<UnixStream> {
+    for entry in address.split(';') {
+        let Some(params) = entry.strip_prefix("unix:") else {
+            continue;
+        };
+        for param in params.split(',') {
+            match param.split_once('=') {
+                Some(("path", path)) => return Ok(UnixStream::connect(unescape(path)).await?),
+                #[cfg(target_os = "linux")]
+                Some(("abstract", name)) => {
+                    use std::os::linux::net::SocketAddrExt;
+                    let addr = std::os::unix::net::SocketAddr::from_abstract_name(unescape(name))?;
+                    let socket = std::os::unix::net::UnixStream::connect_addr(&addr)?;
+                    socket.set_nonblocking(true)?;
+                    return Ok(UnixStream::from_std(socket)?);
+                }
+                _ => {}
+            }
+        }
+    }
+    bail!("can't connect to the session bus at {}", address)
+}
+
+fn unescape(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = value
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(byte);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+async fn authenticate(reader: &mut BufReader<OwnedReadHalf>, bus: &Bus) -> Result<()> {
+    let uid = nix::unistd::getuid().to_string();
+    let hex: String = uid.bytes().map(|byte| format!("{:02x}", byte)).collect();
+    let mut writer = bus.writer.lock().await;
+    writer
+        .write_all(format!("\0AUTH EXTERNAL {}\r\n", hex).as_bytes())
+        .await?;
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    if !line.starts_with("OK ") {
+        bail!("the session bus didn't accept us: {}", line.trim());
+    }
+    writer.write_all(b"BEGIN\r\n").await?;
+    Ok(())
+}
+
+// Anything else that comes in before the reply (like
+// NameAcquired) doesn't matter yet
+async fn wait_for_reply(reader: &mut BufReader<OwnedReadHalf>, serial: u32) -> Result<Message> {
+    loop {
+        let message = read_message(reader).await?;
+        if message.reply_serial != Some(serial) {
+            continue;
+        }
+        if message.kind == ERROR_REPLY {
+            let error = message.body().string().unwrap_or_default();
+            bail!("the session bus said: {}", error);
+        }
+        return Ok(message);
+    }
+}
+
+// Whether it's ours. DO_NOT_QUEUE (4) so it fails right
+// away when another watcher has it.
+async fn request_name(
+    reader: &mut BufReader<OwnedReadHalf>,
+    bus: &Bus,
+    name: &str,
+) -> Result<bool> {
+    let serial = bus
+        .call_bus("RequestName", &Body::default().string(name).u32(4))
+        .await?;
+    let reply = wait_for_reply(reader, serial).await?;
+    // 1 is PRIMARY_OWNER and 4 is ALREADY_OWNER
+    Ok(matches!(reply.body().u32()?, 1 | 4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn round_trip(bytes: &[u8]) -> Message {
+        read_message(&mut &bytes[..]).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn method_call_round_trips() {
+        let body = Body::default().string("build.sh");
+        let bytes = encode(
+            METHOD_CALL,
+            NO_REPLY,
+            7,
+            &[
+                (FIELD_PATH, Field::ObjectPath(PATH.to_string())),
+                (FIELD_INTERFACE, Field::String(INTERFACE.to_string())),
+                (FIELD_MEMBER, Field::String("Trigger".to_string())),
+            ],
+            &body,
+        );
+        let message = round_trip(&bytes).await;
+        assert_eq!(message.kind, METHOD_CALL);
+        assert_eq!(message.flags, NO_REPLY);
+        assert_eq!(message.serial, 7);
+        assert_eq!(message.path.as_deref(), Some(PATH));
+        assert_eq!(message.interface.as_deref(), Some(INTERFACE));
+        assert_eq!(message.member.as_deref(), Some("Trigger"));
+        assert_eq!(message.signature, "s");
+        assert_eq!(message.body().string().unwrap(), "build.sh");
+    }
+
+    #[tokio::test]
+    async fn signal_body_round_trips() {
+        let body = Body::default()
+            .string("test.sh")
+            .bool(true)
+            .string("exit 0")
+            .u64(1234);
+        let bytes = encode(
+            SIGNAL,
+            0,
+            1,
+            &[(FIELD_MEMBER, Field::String("RunFinished".to_string()))],
+            &body,
+        );
+        let message = round_trip(&bytes).await;
+        assert_eq!(message.signature, "sbst");
+        let mut cursor = message.body();
+        assert_eq!(cursor.string().unwrap(), "test.sh");
+        assert_eq!(cursor.u32().unwrap(), 1);
+        assert_eq!(cursor.string().unwrap(), "exit 0");
+        cursor.align(8);
+        assert_eq!(cursor.take(8).unwrap(), 1234u64.to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn reply_serial_round_trips() {
+        let bytes = encode(
+            METHOD_RETURN,
+            0,
+            3,
+            &[(FIELD_REPLY_SERIAL, Field::U32(2))],
+            &Body::default(),
+        );
+        let message = round_trip(&bytes).await;
+        assert_eq!(message.reply_serial, Some(2));
+        assert!(message.body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn big_endian_is_read() {
+        let mut bytes = vec![b'B', METHOD_RETURN, 0, 1];
+        bytes.extend_from_slice(&4u32.to_be_bytes());
+        bytes.extend_from_slice(&9u32.to_be_bytes());
+        bytes.extend_from_slice(&0u32.to_be_bytes());
+        bytes.extend_from_slice(&42u32.to_be_bytes());
+        let message = round_trip(&bytes).await;
+        assert!(message.big_endian);
+        assert_eq!(message.serial, 9);
+        assert_eq!(message.body().u32().unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn oversized_messages_are_refused() {
+        let mut bytes = vec![b'l', SIGNAL, 0, 1];
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        assert!(read_message(&mut &bytes[..]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn short_and_foreign_messages_are_errors() {
+        let bytes = encode(SIGNAL, 0, 1, &[], &Body::default().string("cut"));
+        assert!(read_message(&mut &bytes[..bytes.len() - 2]).await.is_err());
+        assert!(read_message(&mut &[b'x'; 16][..]).await.is_err());
+    }
+
+    #[test]
+    fn addresses_are_unescaped() {
+        assert_eq!(unescape("/run/user/1000/bus"), "/run/user/1000/bus");
+        assert_eq!(unescape("/tmp/a%20b%2c"), "/tmp/a b,");
+        assert_eq!(unescape("100%"), "100%");
+    }
+}
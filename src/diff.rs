@@ -0,0 +1,83 @@
+use similar::TextDiff;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Keeps the last seen contents of each script that's been
+// run so the next change can be shown as a diff. When
+// there's nothing cached yet (i.e. the first change after
+// starting up) it falls back to `git diff` which covers
+// any script that lives in a repo.
+#[derive(Debug, Default)]
+pub struct ScriptCache {
+    contents: HashMap<PathBuf, String>,
+}
+
+impl ScriptCache {
+    pub fn new() -> ScriptCache {
+        ScriptCache {
+            contents: HashMap::new(),
+        }
+    }
+
+    pub fn diff_and_update(&mut self, path: &Path, root: &Path) -> Option<String> {
+        let key = fs::canonicalize(path).ok()?;
+        let current = fs::read_to_string(&key).ok()?;
+        let label = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .display()
+            .to_string();
+        let diff = match self.contents.get(&key) {
+            Some(previous) => {
+                let text = TextDiff::from_lines(previous, &current)
+                    .unified_diff()
+                    .context_radius(3)
+                    .header(&format!("a/{}", label), &format!("b/{}", label))
+                    .to_string();
+                if text.is_empty() {
+                    Some("(no changes since the last run)\n".to_string())
+                } else {
+                    Some(text)
+                }
+            }
+            None => git_diff(&key)
+                .or_else(|| Some("(no previous version to compare against)\n".to_string())),
+        };
+        self.contents.insert(key, current);
+        diff
+    }
+}
+
+fn git_diff(path: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("diff")
+        .arg("--no-color")
+        .arg("--")
+        .arg(path.file_name()?)
+        .current_dir(path.parent()?)
+        .output()
+        .ok()?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub fn print_diff(diff: &str) {
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("\x1b[1m{}\x1b[0m", line);
+        } else if line.starts_with('+') {
+            println!("\x1b[32m{}\x1b[0m", line);
+        } else if line.starts_with('-') {
+            println!("\x1b[31m{}\x1b[0m", line);
+        } else if line.starts_with("@@") {
+            println!("\x1b[36m{}\x1b[0m", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+    println!("------------------------------------------------------------");
+}
@@ -0,0 +1,233 @@
+use crate::notify;
+use crate::sections::Sections;
+
+const PREFIX: &[u8] = b"::ws::";
+
+// With --directives a script can tell the watcher things
+// by printing lines that start with `::ws::`:
+//
+//   ::ws::title Building the docs   sets the terminal's title
+//   ::ws::notify Deploy finished    sends a desktop notification
+//   ::ws::skip-then                 skips this run's then script
+//
+// --sections uses the same lines for `::ws::section
+// <name>` and `::ws::endsection` (see sections::Sections)
+// without turning the rest of them on.
+//
+// Those lines are taken out of the output before it goes
+// anywhere (the screen, --record, --tee, the stream).
+// Ones it doesn't know are left in so typos show up. A
+// line that starts out looking like one is held until
+// it's clear whether it is.
+#[derive(Debug, Clone, Default)]
+pub struct Directives {
+    // --directives itself
+    general: bool,
+    position: LinePosition,
+    pub sections: Option<Sections>,
+    skip_then: bool,
+    // the last ::ws::title that hasn't been shown yet
+    title: Option<String>,
+}
+
+// Where a stream is in its current line. A separate
+// stderr keeps its own (see output::OutputCapture) so a
+// directive on one isn't missed because the other was
+// partway through a line.
+#[derive(Debug, Clone)]
+pub struct LinePosition {
+    line_start: bool,
+    pending: Option<Vec<u8>>,
+}
+
+impl Default for LinePosition {
+    fn default() -> LinePosition {
+        LinePosition {
+            line_start: true,
+            pending: None,
+        }
+    }
+}
+
+impl Directives {
+    pub fn enable_general(&mut self) {
+        self.general = true;
+    }
+
+    // The same ones turned on without anything carried
+    // over, for a --jobs job
+    pub fn fresh(&self) -> Directives {
+        Directives {
+            general: self.general,
+            sections: self.sections.as_ref().map(|sections| sections.fresh()),
+            ..Default::default()
+        }
+    }
+
+    // Trades where the current stream is for where the
+    // other one was
+    pub fn swap_position(&mut self, other: &mut LinePosition) {
+        std::mem::swap(&mut self.position, other);
+    }
+
+    // What's left of the output to pass on
+    pub fn filter(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(bytes.len());
+        for byte in bytes {
+            if let Some(pending) = self.position.pending.as_mut() {
+                pending.push(*byte);
+                if *byte == b'\n' {
+                    let line = self.position.pending.take().unwrap_or_default();
+                    out.extend(self.apply(line));
+                    self.position.line_start = true;
+                } else if pending.len() <= PREFIX.len() && !PREFIX.starts_with(pending) {
+                    out.extend(self.position.pending.take().unwrap_or_default());
+                    self.position.line_start = false;
+                }
+                continue;
+            }
+            if self.position.line_start && *byte == PREFIX[0] {
+                self.position.pending = Some(vec![*byte]);
+                continue;
+            }
+            out.push(*byte);
+            self.position.line_start = *byte == b'\n';
+        }
+        out
+    }
+
+    // A last line that never got its newline
+    pub fn finish(&mut self) -> Vec<u8> {
+        self.position.line_start = true;
+        match self.position.pending.take() {
+            Some(line) => self.apply(line),
+            None => vec![],
+        }
+    }
+
+    // Whether the script asked to skip the then script
+    // since the last time
+    pub fn take_skip_then(&mut self) -> bool {
+        std::mem::take(&mut self.skip_then)
+    }
+
+    // A title the script asked for since the last time.
+    // It goes out with the job's output (see
+    // output::OutputCapture) so it lands in order with it.
+    // Control characters are taken out so it can't end the
+    // title early and slip its own escapes in.
+    pub fn take_title(&mut self) -> Option<String> {
+        self.title
+            .take()
+            .map(|title| title.chars().filter(|c| !c.is_control()).collect())
+    }
+
+    // Carries out a whole line and hands back what to
+    // show in its place
+    fn apply(&mut self, line: Vec<u8>) -> Vec<u8> {
+        let Some(rest) = line.strip_prefix(PREFIX) else {
+            return line;
+        };
+        let rest = String::from_utf8_lossy(rest);
+        let rest = rest.trim_end_matches(['\n', '\r']);
+        let (name, value) = rest.split_once(' ').unwrap_or((rest, ""));
+        if let Some(sections) = self.sections.as_mut() {
+            match name {
+                "section" => return sections.start_script(value),
+                "endsection" => return sections.end_script(),
+                _ => {}
+            }
+        }
+        if !self.general {
+            return line;
+        }
+        match name {
+            "title" => {
+                self.title = Some(value.to_string());
+                vec![]
+            }
+            "notify" => match notify::desktop(value) {
+                true => vec![],
+                false => format!("\x1b[33m🔔 {}\x1b[0m\n", value).into_bytes(),
+            },
+            "skip-then" => {
+                self.skip_then = true;
+                vec![]
+            }
+            _ => line,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sections::SectionStyle;
+
+    fn general() -> Directives {
+        let mut directives = Directives::default();
+        directives.enable_general();
+        directives
+    }
+
+    #[test]
+    fn off_without_directives() {
+        let mut directives = Directives::default();
+        assert_eq!(
+            directives.filter(b"::ws::skip-then\n"),
+            b"::ws::skip-then\n"
+        );
+        assert!(!directives.take_skip_then());
+    }
+
+    #[test]
+    fn takes_out_known_lines() {
+        let mut directives = general();
+        let out = directives.filter(b"one\n::ws::skip-then\n::ws::title Docs\x07\ntwo\n");
+        assert_eq!(out, b"one\ntwo\n");
+        assert!(directives.take_skip_then());
+        assert!(!directives.take_skip_then());
+        assert_eq!(directives.take_title().as_deref(), Some("Docs"));
+        assert_eq!(directives.take_title(), None);
+    }
+
+    #[test]
+    fn leaves_unknown_and_lookalike_lines() {
+        let mut directives = general();
+        let input = b"::ws::tittle x\n::wat\nsay ::ws::skip-then\n:\n";
+        assert_eq!(directives.filter(input), input);
+        assert!(!directives.take_skip_then());
+    }
+
+    #[test]
+    fn holds_a_directive_split_across_reads() {
+        let mut directives = general();
+        assert_eq!(directives.filter(b"ok\n::w"), b"ok\n");
+        assert_eq!(directives.filter(b"s::skip-th"), b"");
+        assert_eq!(directives.filter(b"en\r\nafter"), b"after");
+        assert!(directives.take_skip_then());
+    }
+
+    #[test]
+    fn finishes_a_last_line_without_a_newline() {
+        let mut directives = general();
+        assert_eq!(directives.filter(b"::ws::title Last"), b"");
+        assert_eq!(directives.finish(), b"");
+        assert_eq!(directives.take_title().as_deref(), Some("Last"));
+        assert_eq!(directives.filter(b"::nope"), b"::nope");
+    }
+
+    #[test]
+    fn sections_without_the_rest() {
+        let mut directives = Directives {
+            sections: Some(Sections::new(SectionStyle::Ci)),
+            ..Default::default()
+        };
+        let out =
+            directives.filter(b"::ws::section tests\nran\n::ws::endsection\n::ws::skip-then\n");
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "::group::tests\nran\n::ws::skip-then\n"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use crate::audit::AuditLog;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+// What `direnv export json` says to change for a
+// directory. A None means the variable gets unset.
+pub type Exported = BTreeMap<String, Option<String>>;
+
+// --direnv's exports for each script directory a job
+// has been set up for. They're all loaded again right
+// before a job starts (so edits to the .envrc get picked
+// up) since the spawn hook can't wait on direnv and only
+// reads what's here.
+#[derive(Debug, Clone, Default)]
+pub struct Direnv {
+    loaded: Arc<Mutex<HashMap<PathBuf, Exported>>>,
+}
+
+impl Direnv {
+    // From prepare_job, with the canonical directory
+    pub fn add(&self, dir: &Path) {
+        self.loaded
+            .lock()
+            .unwrap()
+            .entry(dir.to_path_buf())
+            .or_default();
+    }
+
+    pub async fn load(&self, audit_log: Option<&AuditLog>) {
+        let dirs: Vec<PathBuf> = self.loaded.lock().unwrap().keys().cloned().collect();
+        let audit_log = audit_log.cloned();
+        let exports = tokio::task::spawn_blocking(move || {
+            dirs.into_iter()
+                .map(|dir| {
+                    let exported = export(&dir, audit_log.as_ref());
+                    (dir, exported)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+        self.loaded.lock().unwrap().extend(exports);
+    }
+
+    pub fn get(&self, dir: &Path) -> Exported {
+        self.loaded
+            .lock()
+            .unwrap()
+            .get(dir)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+// It's empty when there's no .envrc at or above the
+// directory or direnv isn't installed or hasn't been
+// allowed to load it (`direnv allow`), in which case
+// direnv's own message shows up in the output.
+fn export(dir: &Path, audit_log: Option<&AuditLog>) -> Exported {
+    if !dir.ancestors().any(|dir| dir.join(".envrc").is_file()) {
+        return BTreeMap::new();
+    }
+    let mut command = Command::new("direnv");
+    command
+        .args(["export", "json"])
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit());
+    let id = audit_log.map(|audit_log| audit_log.spawned(&command));
+    let output = command.output();
+    if let Some((audit_log, id)) = audit_log.zip(id) {
+        match &output {
+            Ok(output) => audit_log.exited_with(id, &Ok(output.status)),
+            Err(e) => audit_log.exited(id, None, &format!("could not run: {}", e)),
+        }
+    }
+    output
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| serde_json::from_slice(&output.stdout).ok())
+        .unwrap_or_default()
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Deserializer};
+use std::time::Duration;
+
+// Parses durations like `500ms`, `10s`, `5m`, or `2h`.
+// A bare number is treated as seconds. This is used
+// directly as a clap value parser.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split_at = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split_at);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {}", value))?;
+    let secs = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 60.0 * 60.0,
+        "d" => number * 60.0 * 60.0 * 24.0,
+        _ => return Err(format!("invalid duration unit in: {}", value)),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("duration too long: {}", value))
+}
+
+// For durations in config files. Same format as the
+// command line.
+pub fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| parse_duration(&value).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_unit() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("10"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration(" 1.5s "), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        for value in ["", "s", "10w", "1.2.3s", "-5s"] {
+            assert!(parse_duration(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn refuses_durations_too_long_to_hold() {
+        let value = format!("{}d", "9".repeat(20));
+        assert!(parse_duration(&value).unwrap_err().contains("too long"));
+    }
+}
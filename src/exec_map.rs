@@ -0,0 +1,68 @@
+use crate::shell::ScriptShell;
+use anyhow::{Result, anyhow};
+use std::path::Path;
+
+// Commands to run files through by extension so they
+// don't need a shebang or to be executable:
+//
+//   --exec-map ".py=python3 {file}"
+//   --exec-map ".ts=deno run {file}"
+//
+// `{file}` is the full path to the file, `{dir}` is the
+// directory it's in, and `{stem}` is its name without
+// the extension. They're quoted for the shell.
+#[derive(Debug, Clone, Default)]
+pub struct ExecMap {
+    commands: Vec<(String, String)>,
+}
+
+impl ExecMap {
+    pub fn new(entries: &[String]) -> Result<ExecMap> {
+        let mut commands = vec![];
+        for entry in entries {
+            let (extension, command) = entry
+                .split_once('=')
+                .filter(|(extension, command)| {
+                    !extension.trim_start_matches('.').is_empty() && !command.trim().is_empty()
+                })
+                .ok_or_else(|| {
+                    anyhow!(
+                        "--exec-map takes .ext=command (e.g. \".py=python3 {{file}}\"), got: {}",
+                        entry
+                    )
+                })?;
+            commands.push((
+                extension.trim_start_matches('.').to_string(),
+                command.to_string(),
+            ));
+        }
+        Ok(ExecMap { commands })
+    }
+
+    pub fn handles(&self, path: &Path) -> bool {
+        self.template_for(path).is_some()
+    }
+
+    // The command for the file with the placeholders
+    // filled in if its extension is mapped
+    pub fn command_for(&self, path: &Path, shell: &ScriptShell) -> Option<String> {
+        let template = self.template_for(path)?;
+        let quoted = |part: Option<&std::ffi::OsStr>| {
+            shell.quote(&part.unwrap_or_default().to_string_lossy())
+        };
+        Some(
+            template
+                .replace("{file}", &quoted(Some(path.as_os_str())))
+                .replace("{dir}", &quoted(path.parent().map(|dir| dir.as_os_str())))
+                .replace("{stem}", &quoted(path.file_stem())),
+        )
+    }
+
+    fn template_for(&self, path: &Path) -> Option<&str> {
+        let extension = path.extension()?.to_str()?;
+        self.commands
+            .iter()
+            .find(|(mapped, _)| mapped == extension)
+            .map(|(_, command)| command.as_str())
+    }
+}
@@ -0,0 +1,358 @@
+use crate::scripts;
+use anyhow::{Result, anyhow};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::Gitignore;
+use permissions::is_executable;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use watchexec_events::Event;
+
+pub const WSIGNORE_NAME: &str = ".wsignore";
+
+// Decides whether a changed file should set off a run.
+// Filters are checked in order and the first one that
+// says no stops the file from running. Only changes
+// from the filesystem go through them. Scripts asked
+// for by name (keys, the socket, HTTP) skip them.
+pub trait EventFilter: Send + Sync {
+    // Shows up in FilterReason::Filter when a file
+    // gets rejected
+    fn name(&self) -> &'static str;
+
+    fn allows(&self, event: &Event, path: &Path) -> bool;
+}
+
+// The built-ins as they're listed in the config file:
+//
+//   [[filters]]
+//   kind = "glob"
+//   include = ["scripts/**"]
+//
+// Leaving `filters` out of the config is the same as
+// listing executable-only and no-dotfiles. With a task
+// (--just, --make, --npm) the changed files aren't what gets run so
+// it's gitignore and no-dotfiles instead, which also
+// keeps build output from setting off another run.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum FilterConfig {
+    ExecutableOnly,
+    Gitignore,
+    Glob {
+        #[serde(default)]
+        exclude: Vec<String>,
+        #[serde(default)]
+        include: Vec<String>,
+    },
+    NoDotfiles,
+}
+
+pub fn default_filters(tasks: bool) -> Vec<FilterConfig> {
+    if tasks {
+        vec![FilterConfig::Gitignore, FilterConfig::NoDotfiles]
+    } else {
+        vec![FilterConfig::ExecutableOnly, FilterConfig::NoDotfiles]
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Filters {
+    filters: Vec<Arc<dyn EventFilter>>,
+}
+
+impl std::fmt::Debug for Filters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.filters.iter().map(|filter| filter.name()))
+            .finish()
+    }
+}
+
+impl Filters {
+    // Globs and .gitignore files are relative to the root
+    pub fn from_config(configs: &[FilterConfig], root: &Path) -> Result<Filters> {
+        let mut filters = Filters::default();
+        for config in configs {
+            match config {
+                FilterConfig::ExecutableOnly => filters.push(Arc::new(ExecutableOnly)),
+                FilterConfig::Gitignore => filters.push(Arc::new(GitignoreFilter::new(root))),
+                FilterConfig::Glob { exclude, include } => {
+                    filters.push(Arc::new(GlobFilter::new(root, include, exclude)?))
+                }
+                FilterConfig::NoDotfiles => filters.push(Arc::new(NoDotfiles)),
+            }
+        }
+        Ok(filters)
+    }
+
+    pub fn push(&mut self, filter: Arc<dyn EventFilter>) {
+        self.filters.push(filter);
+    }
+
+    pub fn extend(&mut self, other: Filters) {
+        self.filters.extend(other.filters);
+    }
+
+    // The name of the first filter that turned the
+    // path down if there was one
+    pub fn rejected_by(&self, event: &Event, path: &Path) -> Option<&'static str> {
+        self.filters
+            .iter()
+            .find(|filter| !filter.allows(event, path))
+            .map(|filter| filter.name())
+    }
+
+    // The same but leaving one of them out for a file
+    // that gets to skip it
+    pub fn rejected_by_except(
+        &self,
+        event: &Event,
+        path: &Path,
+        skipped: &str,
+    ) -> Option<&'static str> {
+        self.filters
+            .iter()
+            .filter(|filter| filter.name() != skipped)
+            .find(|filter| !filter.allows(event, path))
+            .map(|filter| filter.name())
+    }
+}
+
+pub struct ExecutableOnly;
+
+impl EventFilter for ExecutableOnly {
+    fn name(&self) -> &'static str {
+        "executable-only"
+    }
+
+    // directories have the x bit too and creating one
+    // touches its parent
+    fn allows(&self, _event: &Event, path: &Path) -> bool {
+        path.is_file() && is_executable(path).unwrap_or(false)
+    }
+}
+
+// Hidden files, anything in a hidden directory, and
+// editor backups that end with `~`
+pub struct NoDotfiles;
+
+impl EventFilter for NoDotfiles {
+    fn name(&self) -> &'static str {
+        "no-dotfiles"
+    }
+
+    fn allows(&self, _event: &Event, path: &Path) -> bool {
+        !scripts::is_hidden(path)
+    }
+}
+
+// Paths have to match one of the include globs (if
+// there are any) and none of the exclude ones. Globs
+// are matched against the path relative to the root.
+pub struct GlobFilter {
+    exclude: GlobSet,
+    include: Option<GlobSet>,
+    root: PathBuf,
+}
+
+impl GlobFilter {
+    pub fn new(root: &Path, include: &[String], exclude: &[String]) -> Result<GlobFilter> {
+        Ok(GlobFilter {
+            exclude: build_globs(exclude)?,
+            include: if include.is_empty() {
+                None
+            } else {
+                Some(build_globs(include)?)
+            },
+            root: root.to_path_buf(),
+        })
+    }
+}
+
+pub fn build_globs(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).map_err(|e| anyhow!("invalid glob `{}`: {}", pattern, e))?);
+    }
+    Ok(builder.build()?)
+}
+
+impl EventFilter for GlobFilter {
+    fn name(&self) -> &'static str {
+        "glob"
+    }
+
+    fn allows(&self, _event: &Event, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(relative))
+            && !self.exclude.is_match(relative)
+    }
+}
+
+// Skips anything matched by a .gitignore between the
+// root and the file. Each .gitignore gets read again
+// when it changes so edits to it take effect right
+// away. The same thing reads .wsignore files, which
+// use the same syntax but only matter to the watcher,
+// and that one is always on.
+pub struct GitignoreFilter {
+    cache: Mutex<HashMap<PathBuf, (SystemTime, Gitignore)>>,
+    file_name: &'static str,
+    root: PathBuf,
+}
+
+impl GitignoreFilter {
+    pub fn new(root: &Path) -> GitignoreFilter {
+        GitignoreFilter {
+            cache: Mutex::new(HashMap::new()),
+            file_name: ".gitignore",
+            root: root.to_path_buf(),
+        }
+    }
+
+    pub fn wsignore(root: &Path) -> GitignoreFilter {
+        GitignoreFilter {
+            file_name: WSIGNORE_NAME,
+            ..GitignoreFilter::new(root)
+        }
+    }
+
+    fn ignores(&self, dir: &Path, path: &Path) -> bool {
+        let file = dir.join(self.file_name);
+        let Ok(modified) = file.metadata().and_then(|meta| meta.modified()) else {
+            return false;
+        };
+        let mut cache = self.cache.lock().unwrap();
+        let stale = cache
+            .get(&file)
+            .is_none_or(|(cached, _)| *cached != modified);
+        if stale {
+            let (gitignore, _) = Gitignore::new(&file);
+            cache.insert(file.clone(), (modified, gitignore));
+        }
+        cache[&file]
+            .1
+            .matched_path_or_any_parents(path, false)
+            .is_ignore()
+    }
+}
+
+impl EventFilter for GitignoreFilter {
+    fn name(&self) -> &'static str {
+        if self.file_name == WSIGNORE_NAME {
+            "wsignore"
+        } else {
+            "gitignore"
+        }
+    }
+
+    fn allows(&self, _event: &Event, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return true;
+        };
+        let mut dir = self.root.clone();
+        if self.ignores(&dir, path) {
+            return false;
+        }
+        for component in relative
+            .parent()
+            .into_iter()
+            .flat_map(|parent| parent.components())
+        {
+            dir.push(component);
+            if self.ignores(&dir, path) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Globs added with `ignore <glob>` on the control
+// socket or --stdin. They're relative to the root and
+// last until the watcher stops (a config reload keeps
+// them).
+#[derive(Debug, Clone, Default)]
+pub struct Ignores {
+    patterns: Arc<Mutex<Vec<String>>>,
+    root: PathBuf,
+    set: Arc<Mutex<GlobSet>>,
+}
+
+impl Ignores {
+    pub fn new(root: &Path) -> Ignores {
+        Ignores {
+            root: root.to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&self, pattern: &str) -> Result<()> {
+        let mut patterns = self.patterns.lock().unwrap();
+        let mut added = patterns.clone();
+        added.push(pattern.to_string());
+        *self.set.lock().unwrap() = build_globs(&added)?;
+        *patterns = added;
+        Ok(())
+    }
+
+    pub fn patterns(&self) -> Vec<String> {
+        self.patterns.lock().unwrap().clone()
+    }
+}
+
+impl EventFilter for Ignores {
+    fn name(&self) -> &'static str {
+        "ignore"
+    }
+
+    fn allows(&self, _event: &Event, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        !self.set.lock().unwrap().is_match(relative)
+    }
+}
+
+// Only the files from --files-from (one path per line,
+// relative to the root or absolute) can set off runs so
+// the list can come from find, fd, fzf, etc.
+pub struct FileList {
+    files: HashSet<PathBuf>,
+}
+
+impl FileList {
+    // `-` reads the list from stdin
+    pub fn read(source: &Path, root: &Path) -> Result<FileList> {
+        let mut listing = String::new();
+        if source == Path::new("-") {
+            std::io::stdin()
+                .read_to_string(&mut listing)
+                .map_err(|e| anyhow!("could not read --files-from from stdin: {}", e))?;
+        } else {
+            listing = std::fs::read_to_string(source)
+                .map_err(|e| anyhow!("could not read {}: {}", source.display(), e))?;
+        }
+        let files = listing
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| std::fs::canonicalize(root.join(line)).ok())
+            .collect();
+        Ok(FileList { files })
+    }
+}
+
+impl EventFilter for FileList {
+    fn name(&self) -> &'static str {
+        "files-from"
+    }
+
+    fn allows(&self, _event: &Event, path: &Path) -> bool {
+        std::fs::canonicalize(path).is_ok_and(|path| self.files.contains(&path))
+    }
+}
@@ -0,0 +1,93 @@
+use crate::config::GroupConfig;
+use crate::filters::build_globs;
+use crate::scripts;
+use anyhow::{Result, anyhow};
+use globset::GlobSet;
+use std::path::{Path, PathBuf};
+
+// Separate sets of scripts that one watcher looks after,
+// each with its own directories, globs, and then script:
+//
+//   [[groups]]
+//   name = "deploy"
+//   paths = ["scripts"]
+//   then = "scripts/deploy.sh"
+//
+//   [[groups]]
+//   name = "lint"
+//   paths = ["checks"]
+//   exclude = ["checks/slow/**"]
+//
+// `paths`, `then`, and the `include`/`exclude` globs are
+// relative to the watch root. Once there are groups only
+// scripts in one of them run from changes (the first
+// group that has a script wins) and their runs are
+// labeled with the group's name. A sidecar's then script
+// still beats the group's.
+#[derive(Debug, Clone, Default)]
+pub struct Groups {
+    groups: Vec<Group>,
+    root: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct Group {
+    dirs: Vec<PathBuf>,
+    exclude: GlobSet,
+    include: Option<GlobSet>,
+    pub name: String,
+    pub then: Option<PathBuf>,
+}
+
+impl Groups {
+    pub fn new(configs: &[GroupConfig], root: &Path) -> Result<Groups> {
+        let mut groups = vec![];
+        for config in configs {
+            let then = match &config.then {
+                Some(then) => {
+                    let then = root.join(then);
+                    if !scripts::is_runnable(&then) {
+                        return Err(anyhow!(
+                            "the then script for group {} isn't runnable: {}",
+                            config.name,
+                            then.display()
+                        ));
+                    }
+                    Some(std::fs::canonicalize(then)?)
+                }
+                None => None,
+            };
+            groups.push(Group {
+                dirs: config.paths.iter().map(|path| root.join(path)).collect(),
+                exclude: build_globs(&config.exclude)?,
+                include: if config.include.is_empty() {
+                    None
+                } else {
+                    Some(build_globs(&config.include)?)
+                },
+                name: config.name.clone(),
+                then,
+            });
+        }
+        Ok(Groups {
+            groups,
+            root: root.to_path_buf(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    pub fn group_for(&self, script: &Path) -> Option<&Group> {
+        let relative = script.strip_prefix(&self.root).ok()?;
+        self.groups.iter().find(|group| {
+            group.dirs.iter().any(|dir| script.starts_with(dir))
+                && group
+                    .include
+                    .as_ref()
+                    .is_none_or(|include| include.is_match(relative))
+                && !group.exclude.is_match(relative)
+        })
+    }
+}
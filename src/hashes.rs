@@ -0,0 +1,79 @@
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub const HASHES_NAME: &str = ".watch_scripts.hashes";
+
+// A hash of the contents of each file that's set off a
+// run so saves that didn't change anything (a touch, a
+// format-on-save that had nothing to do, checking out
+// the same contents) can be skipped instead of clearing
+// the screen and running again.
+//
+// They're saved in the watch root on the way out (in
+// the same format as `sha1sum` with paths relative to
+// the root) and read back at startup so the first
+// no-op save after a restart gets skipped too.
+#[derive(Debug, Clone, Default)]
+pub struct ContentHashes {
+    hashes: Arc<Mutex<HashMap<PathBuf, String>>>,
+    root: PathBuf,
+}
+
+impl ContentHashes {
+    // A missing or unreadable file just means starting
+    // over with nothing cached
+    pub fn load(root: &Path) -> ContentHashes {
+        let contents = std::fs::read_to_string(root.join(HASHES_NAME)).unwrap_or_default();
+        let hashes = contents
+            .lines()
+            .filter_map(|line| line.split_once("  "))
+            .map(|(hash, path)| (root.join(path), hash.to_string()))
+            .collect();
+        ContentHashes {
+            hashes: Arc::new(Mutex::new(hashes)),
+            root: root.to_path_buf(),
+        }
+    }
+
+    // Files that have gone away are left out
+    pub fn save(&self) -> std::io::Result<()> {
+        let hashes = self.hashes.lock().unwrap();
+        let mut entries: Vec<(&Path, &String)> = hashes
+            .iter()
+            .filter(|(path, _)| path.is_file())
+            .filter_map(|(path, hash)| Some((path.strip_prefix(&self.root).ok()?, hash)))
+            .collect();
+        entries.sort();
+        let mut contents = String::new();
+        for (path, hash) in entries {
+            let _ = writeln!(contents, "{}  {}", hash, path.display());
+        }
+        std::fs::write(self.root.join(HASHES_NAME), contents)
+    }
+
+    // True the first time a file shows up and whenever
+    // its contents are different from the last time.
+    // Files that can't be read count as changed so the
+    // run can report what's wrong with them.
+    pub fn changed(&self, path: &Path) -> bool {
+        let Ok(contents) = std::fs::read(path) else {
+            self.hashes.lock().unwrap().remove(path);
+            return true;
+        };
+        let hash = Sha1::digest(&contents)
+            .iter()
+            .fold(String::new(), |mut hex, byte| {
+                let _ = write!(hex, "{:02x}", byte);
+                hex
+            });
+        let previous = self
+            .hashes
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), hash.clone());
+        previous != Some(hash)
+    }
+}
@@ -0,0 +1,96 @@
+use crate::Payload;
+use crate::run;
+use crate::session::Session;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use watchexec::job::Job;
+
+// --health for --service. Once the new version of a
+// script has started the command gets run from the
+// watch root every interval until it exits 0 or the
+// retries run out. With --health-rollback the version
+// that was running is left alone until the new one
+// passes and the new one gets stopped instead if it
+// never does.
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    command: String,
+    interval: Duration,
+    retries: u32,
+    pub rollback: bool,
+}
+
+impl HealthCheck {
+    pub fn new(command: String, interval: Duration, retries: u32, rollback: bool) -> HealthCheck {
+        HealthCheck {
+            command,
+            interval,
+            retries,
+            rollback,
+        }
+    }
+
+    // Gives up early if the job gets replaced while it
+    // waits. The result goes out on the run stream as a
+    // `health` message too.
+    pub async fn wait_for(
+        &self,
+        job: &Job,
+        script: &Path,
+        payload: &Payload,
+        session: &Arc<Mutex<Session>>,
+    ) -> bool {
+        for attempt in 1..=self.retries {
+            tokio::time::sleep(self.interval).await;
+            if job.is_dead() {
+                return false;
+            }
+            if self.passes(payload).await {
+                println!(
+                    "\x1b[32mHealthy: {} passed `{}` (try {})\x1b[0m",
+                    script.display(),
+                    self.command,
+                    attempt
+                );
+                self.report(script, true, attempt, session);
+                return true;
+            }
+        }
+        println!(
+            "\x1b[31mUnhealthy: {} didn't pass `{}` after {} tries\x1b[0m",
+            script.display(),
+            self.command,
+            self.retries
+        );
+        self.report(script, false, self.retries, session);
+        false
+    }
+
+    async fn passes(&self, payload: &Payload) -> bool {
+        let mut command = payload.shell.command(&self.command);
+        command
+            .envs(payload.run_vars.get())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        if let Some(dir) = &payload.initial_dir {
+            command.current_dir(dir);
+        }
+        run::audited(&mut command, payload)
+            .await
+            .is_ok_and(|status| status.success())
+    }
+
+    fn report(&self, script: &Path, healthy: bool, tries: u32, session: &Arc<Mutex<Session>>) {
+        session.lock().unwrap().stream().send(
+            "health",
+            serde_json::json!({
+                "script": script,
+                "healthy": healthy,
+                "tries": tries,
+            }),
+        );
+    }
+}
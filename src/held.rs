@@ -0,0 +1,48 @@
+use crate::trigger;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use watchexec::Watchexec;
+use watchexec_events::Priority;
+
+// Scripts set off while runs are being held back (by
+// --max-runs-per-minute or quiet hours). They're kept as
+// one pending run and more triggers while it's waiting
+// get added to it instead of queueing up. Once the wait
+// is over they go back through the action handler the
+// same as the changes that set them off so pausing and
+// the rest still apply.
+#[derive(Debug, Clone, Default)]
+pub struct HeldRuns {
+    pending: Arc<Mutex<BTreeSet<PathBuf>>>,
+}
+
+impl HeldRuns {
+    // Returns false if there was already a pending run
+    // they were added to
+    pub fn hold(
+        &self,
+        scripts: impl IntoIterator<Item = PathBuf>,
+        wait: Duration,
+        wx: Arc<Watchexec>,
+    ) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        let waiting = !pending.is_empty();
+        pending.extend(scripts);
+        if waiting {
+            return false;
+        }
+        let pending = Arc::clone(&self.pending);
+        tokio::spawn(async move {
+            tokio::time::sleep(wait).await;
+            let scripts: Vec<PathBuf> = std::mem::take(&mut *pending.lock().unwrap())
+                .into_iter()
+                .collect();
+            let _ = wx
+                .send_event(trigger::released_event(&scripts), Priority::Normal)
+                .await;
+        });
+        true
+    }
+}
@@ -0,0 +1,100 @@
+use crate::config::HighlightConfig;
+use crate::problems::strip_ansi;
+use anyhow::{Result, anyhow};
+use regex::Regex;
+
+// Styles lines of output that match a pattern so the
+// important ones stand out in a long log:
+//
+//   [[highlight]]
+//   pattern = "(?i)\\berror\\b"
+//   style = "bold red"
+//
+// or `--highlight "bold red=(?i)\berror\b"` for one-offs.
+// The first rule that matches a line wins. Rules from
+// the command line are tried before the ones in the
+// config.
+#[derive(Debug, Clone)]
+pub struct Highlights {
+    rules: Vec<(Regex, String)>,
+}
+
+const COLORS: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+impl Highlights {
+    pub fn new(config: &[HighlightConfig], extra: &[String]) -> Result<Option<Highlights>> {
+        let mut rules = vec![];
+        for arg in extra {
+            let (style, pattern) = arg.split_once('=').ok_or_else(|| {
+                anyhow!(
+                    "--highlight takes style=pattern (e.g. \"red=error\"), got: {}",
+                    arg
+                )
+            })?;
+            rules.push(rule(pattern, style)?);
+        }
+        for highlight in config {
+            rules.push(rule(&highlight.pattern, &highlight.style)?);
+        }
+        Ok((!rules.is_empty()).then_some(Highlights { rules }))
+    }
+
+    // The line with the style of the first rule that
+    // matches it. Colors the script already put in the
+    // line are matched without their escape codes and
+    // the style comes back after any resets in it.
+    pub fn apply(&self, line: &[u8]) -> Option<Vec<u8>> {
+        let text = String::from_utf8_lossy(line);
+        let (text, newline) = match text.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (text.as_ref(), ""),
+        };
+        let stripped = strip_ansi(text);
+        let (_, code) = self
+            .rules
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(&stripped))?;
+        let start = format!("\x1b[{}m", code);
+        let text = text
+            .replace("\x1b[0m", &format!("\x1b[0m{}", start))
+            .replace("\x1b[m", &format!("\x1b[m{}", start));
+        Some(format!("{}{}\x1b[0m{}", start, text, newline).into_bytes())
+    }
+}
+
+fn rule(pattern: &str, style: &str) -> Result<(Regex, String)> {
+    let pattern =
+        Regex::new(pattern).map_err(|e| anyhow!("invalid highlight pattern {}: {}", pattern, e))?;
+    Ok((pattern, sgr_code(style)?))
+}
+
+// "bold red", "bright-yellow on-blue", "dim,underline",
+// etc. as the codes for an escape sequence
+fn sgr_code(style: &str) -> Result<String> {
+    let mut codes = vec![];
+    for word in style.split([' ', ',', '+']).filter(|word| !word.is_empty()) {
+        let color = |name: &str| COLORS.iter().position(|color| *color == name);
+        let code = match word {
+            "bold" => Some(1),
+            "dim" => Some(2),
+            "italic" => Some(3),
+            "underline" => Some(4),
+            "reverse" => Some(7),
+            _ => match word.strip_prefix("on-") {
+                Some(name) => color(name).map(|n| 40 + n),
+                None => match word.strip_prefix("bright-") {
+                    Some(name) => color(name).map(|n| 90 + n),
+                    None => color(word).map(|n| 30 + n),
+                },
+            },
+        };
+        let code = code.ok_or_else(|| anyhow!("unknown highlight style: {}", word))?;
+        codes.push(code.to_string());
+    }
+    if codes.is_empty() {
+        return Err(anyhow!("highlight style is empty"));
+    }
+    Ok(codes.join(";"))
+}
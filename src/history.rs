@@ -0,0 +1,66 @@
+use crate::session::RunInfo;
+use crate::stream::RunStream;
+use anyhow::{Result, anyhow};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const HISTORY_NAME: &str = ".watch_scripts.history.jsonl";
+
+// How much of a failed run's output goes in with it
+pub const TAIL_LINES: usize = 40;
+
+// Once the file gets this big it's moved to the side
+// (with `.1` on the end, replacing the one from before)
+// and a new one is started, so it never takes up more
+// than about twice this
+const MAX_BYTES: u64 = 5 << 20;
+
+// --keep-history adds a line to the history file in the
+// watch root for every run that finishes (the same JSON
+// the webhook gets plus the end of the output for
+// failed runs) so `ws report` has something to go on
+// after the watcher's gone.
+pub fn follow(path: PathBuf, stream: &RunStream) {
+    let mut events = stream.follow(false);
+    tokio::spawn(async move {
+        while let Some(message) = events.next().await {
+            if message["type"] != "run_finished" {
+                continue;
+            }
+            if std::fs::metadata(&path).is_ok_and(|metadata| metadata.len() >= MAX_BYTES)
+                && let Err(e) = std::fs::rename(&path, rotated(&path))
+            {
+                eprintln!("\x1b[33mcould not rotate {}: {}\x1b[0m", path.display(), e);
+            }
+            let written = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| writeln!(file, "{}", message["data"]));
+            if let Err(e) = written {
+                eprintln!("\x1b[33mcould not add to {}: {}\x1b[0m", path.display(), e);
+            }
+        }
+    });
+}
+
+fn rotated(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+// The rotated file's runs and then the current ones.
+// Lines that don't parse (like one that got cut off when
+// the disk filled up) are skipped.
+pub fn load(path: &Path) -> Result<Vec<RunInfo>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("could not read {}: {}", path.display(), e))?;
+    let older = std::fs::read_to_string(rotated(path)).unwrap_or_default();
+    Ok(older
+        .lines()
+        .chain(contents.lines())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
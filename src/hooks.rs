@@ -0,0 +1,72 @@
+use crate::session::RunInfo;
+use std::path::Path;
+use std::sync::Arc;
+
+type PathHook = Arc<dyn Fn(&Path) + Send + Sync>;
+type FilterHook = Arc<dyn Fn(&Path, FilterReason) + Send + Sync>;
+type RunHook = Arc<dyn Fn(&RunInfo) + Send + Sync>;
+
+// Why a changed file didn't set off a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterReason {
+    // Set off again before its cooldown was up
+    Cooldown,
+    // Written by a script that's running or just finished
+    // (see loop_guard::LoopGuard)
+    Loop,
+    // Turned down by the EventFilter with this name
+    Filter(&'static str),
+    // --confirm-new and it hasn't been run by name yet
+    New,
+    // Asked for by name but not executable, hidden, or
+    // an editor backup
+    NotRunnable,
+    Paused,
+    // A WASM plugin's should_trigger said no
+    Plugin,
+    // Saved without its contents changing
+    Unchanged,
+    // --tracked-only and git doesn't know about it
+    Untracked,
+}
+
+// Closures from an embedding application that get called
+// as things happen. They're called right from the
+// watcher's own tasks so they need to be quick and
+// can't block.
+#[derive(Clone, Default)]
+pub struct Hooks {
+    pub(crate) on_filtered: Vec<FilterHook>,
+    pub(crate) on_run_end: Vec<RunHook>,
+    pub(crate) on_run_start: Vec<RunHook>,
+    pub(crate) on_trigger: Vec<PathHook>,
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_filtered", &self.on_filtered.len())
+            .field("on_run_end", &self.on_run_end.len())
+            .field("on_run_start", &self.on_run_start.len())
+            .field("on_trigger", &self.on_trigger.len())
+            .finish()
+    }
+}
+
+impl Hooks {
+    pub fn filtered(&self, path: &Path, reason: FilterReason) {
+        self.on_filtered.iter().for_each(|hook| hook(path, reason));
+    }
+
+    pub fn run_end(&self, info: &RunInfo) {
+        self.on_run_end.iter().for_each(|hook| hook(info));
+    }
+
+    pub fn run_start(&self, info: &RunInfo) {
+        self.on_run_start.iter().for_each(|hook| hook(info));
+    }
+
+    pub fn trigger(&self, path: &Path) {
+        self.on_trigger.iter().for_each(|hook| hook(path));
+    }
+}
@@ -0,0 +1,594 @@
+use crate::inventory::Inventory;
+use crate::session::Session;
+use crate::trigger;
+use anyhow::Result;
+use base64::Engine;
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use watchexec::Watchexec;
+use watchexec_events::{Priority, Source};
+
+// A client gets this long to send its request line and
+// headers, each line this long at most, before it's
+// dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_LINE: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+// A tiny HTTP server so editors and other tools can
+// drive the watcher:
+//
+//   POST /run?script=build.sh   trigger a run
+//   POST /run                   re-run the last script
+//   GET  /status                JSON about the current
+//                               and last run
+//   GET  /events                WebSocket that streams
+//                               runs and output lines
+//
+// It only speaks enough HTTP/1.1 for curl and friends.
+//
+// Web pages the user has open can reach it too so it
+// only answers ones served from the watcher's own address
+// (or clients that don't send an Origin, like a terminal
+// or an editor). Otherwise any page could start scripts
+// with a form or read every script's output. The Host
+// has to be the watcher's too so a page can't get around
+// that by pointing a name of its own at 127.0.0.1.
+pub struct HttpTrigger {
+    pub inventory: Inventory,
+    pub session: Arc<Mutex<Session>>,
+    pub wx: Arc<Watchexec>,
+}
+
+struct Request {
+    headers: Vec<(String, String)>,
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+}
+
+impl HttpTrigger {
+    pub async fn serve(self, addr: String) -> Result<()> {
+        let listener = TcpListener::bind(&addr).await?;
+        println!("Listening for HTTP triggers on http://{}", addr);
+        let bound = listener.local_addr()?;
+        let server = Arc::new(self);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let server = Arc::clone(&server);
+                tokio::spawn(async move {
+                    let _ = server.handle(stream, bound).await;
+                });
+            }
+        });
+        Ok(())
+    }
+
+    async fn handle(&self, stream: TcpStream, bound: SocketAddr) -> Result<()> {
+        let mut reader = BufReader::new(stream);
+        let Ok(request) = tokio::time::timeout(READ_TIMEOUT, read_request(&mut reader)).await
+        else {
+            return Ok(());
+        };
+        let Some(request) = request? else {
+            return Ok(());
+        };
+        if !request.is_local_to(bound) {
+            return respond(
+                reader.get_mut(),
+                "403 Forbidden",
+                json!({ "error": "not from this origin" }),
+            )
+            .await;
+        }
+        if request.path == "/events"
+            && let Some(key) = request.websocket_key()
+        {
+            return self.stream_events(reader, key).await;
+        }
+        let (code, body) = self.route(&request).await;
+        respond(reader.get_mut(), code, body).await
+    }
+
+    // Upgrades the connection and sends every message from
+    // the run stream as a text frame. The first message is
+    // the same status that GET /status returns so clients
+    // don't have to wait for the next run to show something.
+    async fn stream_events(&self, reader: BufReader<TcpStream>, key: &str) -> Result<()> {
+        let mut hasher = Sha1::new();
+        hasher.update(key.as_bytes());
+        hasher.update(b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11");
+        let accept = base64::engine::general_purpose::STANDARD.encode(hasher.finalize());
+        let (mut events, status) = {
+            let session = self.session.lock().unwrap();
+            (
+                session.stream().subscribe(),
+                session.status_json(&self.inventory),
+            )
+        };
+        let (mut read_half, mut write_half) = reader.into_inner().into_split();
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept
+        );
+        write_half.write_all(response.as_bytes()).await?;
+        let hello = json!({ "type": "status", "data": status }).to_string();
+        write_frame(&mut write_half, 0x1, hello.as_bytes()).await?;
+        let (pong_tx, mut pong_rx) = tokio::sync::mpsc::unbounded_channel();
+        // reads what the client sends just to answer pings
+        // and notice when it goes away
+        let reading = tokio::spawn(async move {
+            while let Ok((opcode, payload)) = read_frame(&mut read_half).await {
+                match opcode {
+                    0x8 => break,
+                    0x9 if pong_tx.send(payload).is_err() => break,
+                    _ => {}
+                }
+            }
+        });
+        loop {
+            tokio::select! {
+                message = events.recv() => match message {
+                    Ok(message) => write_frame(&mut write_half, 0x1, message.as_bytes()).await?,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                pong = pong_rx.recv() => match pong {
+                    Some(payload) => write_frame(&mut write_half, 0xA, &payload).await?,
+                    None => break,
+                },
+            }
+        }
+        reading.abort();
+        let _ = write_frame(&mut write_half, 0x8, &[]).await;
+        Ok(())
+    }
+
+    async fn route(&self, request: &Request) -> (&'static str, serde_json::Value) {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/status") => (
+                "200 OK",
+                self.session.lock().unwrap().status_json(&self.inventory),
+            ),
+            ("POST", "/run") => {
+                let requested = request
+                    .query
+                    .iter()
+                    .find(|(key, _)| key == "script")
+                    .map(|(_, value)| value.clone());
+                let path = match requested {
+                    Some(requested) => match trigger::resolve_script(&self.inventory, &requested) {
+                        Ok(path) => path,
+                        Err(e) => return ("404 Not Found", json!({ "error": e })),
+                    },
+                    None => match self.session.lock().unwrap().last_script.clone() {
+                        Some(path) => path,
+                        None => {
+                            return (
+                                "400 Bad Request",
+                                json!({ "error": "no script given and nothing has run yet" }),
+                            );
+                        }
+                    },
+                };
+                let event = trigger::manual_event(&path, Source::Internal);
+                match self.wx.send_event(event, Priority::Urgent).await {
+                    Ok(_) => ("202 Accepted", json!({ "triggered": path })),
+                    Err(e) => (
+                        "500 Internal Server Error",
+                        json!({ "error": e.to_string() }),
+                    ),
+                }
+            }
+            (_, "/run") | (_, "/status") => (
+                "405 Method Not Allowed",
+                json!({ "error": "method not allowed" }),
+            ),
+            _ => ("404 Not Found", json!({ "error": "not found" })),
+        }
+    }
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Only a GET that asks for the upgrade
+    fn websocket_key(&self) -> Option<&str> {
+        let upgrade = self
+            .header("upgrade")
+            .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+        (self.method == "GET" && upgrade)
+            .then(|| self.header("sec-websocket-key"))
+            .flatten()
+    }
+
+    // Browsers always send the page's origin with a
+    // WebSocket request or a POST
+    fn is_local_to(&self, bound: SocketAddr) -> bool {
+        let origin = match self.header("origin") {
+            None => true,
+            Some(origin) => origin
+                .strip_prefix("http://")
+                .is_some_and(|authority| is_local(authority, bound)),
+        };
+        origin && self.header("host").is_none_or(|host| is_local(host, bound))
+    }
+}
+
+// Whether a `host:port` is the address the server's
+// bound to. localhost counts for a loopback address and
+// any of the machine's addresses could be the one meant
+// when it's bound to all of them.
+fn is_local(authority: &str, bound: SocketAddr) -> bool {
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.ends_with(']') => (host, port.parse().ok()),
+        _ => (authority, Some(80)),
+    };
+    if port != Some(bound.port()) {
+        return false;
+    }
+    match host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        Ok(ip) => {
+            ip == bound.ip()
+                || bound.ip().is_unspecified()
+                || (ip.is_loopback() && bound.ip().is_loopback())
+        }
+        Err(_) => {
+            host.eq_ignore_ascii_case("localhost")
+                && (bound.ip().is_loopback() || bound.ip().is_unspecified())
+        }
+    }
+}
+
+async fn respond(stream: &mut TcpStream, code: &str, body: serde_json::Value) -> Result<()> {
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn read_request(reader: &mut BufReader<TcpStream>) -> Result<Option<Request>> {
+    let Some(line) = read_line(reader).await? else {
+        return Ok(None);
+    };
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+    let mut headers = vec![];
+    while let Some(header) = read_line(reader).await? {
+        if header.trim().is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            anyhow::bail!("too many headers");
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0usize);
+    // nothing uses the body but it gets read so the
+    // client doesn't see a reset
+    let mut body = vec![0u8; content_length.min(64 * 1024)];
+    reader.read_exact(&mut body).await?;
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), parse_query(query)),
+        None => (target, vec![]),
+    };
+    Ok(Some(Request {
+        headers,
+        method,
+        path,
+        query,
+    }))
+}
+
+// None once the client's done sending
+async fn read_line(reader: &mut BufReader<TcpStream>) -> Result<Option<String>> {
+    let mut line = String::new();
+    let read = (&mut *reader).take(MAX_LINE).read_line(&mut line).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') && read as u64 == MAX_LINE {
+        anyhow::bail!("request line too long");
+    }
+    Ok(Some(line))
+}
+
+async fn write_frame(writer: &mut OwnedWriteHalf, opcode: u8, payload: &[u8]) -> Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame).await?;
+    Ok(())
+}
+
+// Frames from the client are always masked. Fragmented
+// messages aren't put back together since nothing the
+// client sends is used for more than a ping.
+async fn read_frame(reader: &mut OwnedReadHalf) -> Result<(u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).await?;
+    let opcode = head[0] & 0x0f;
+    let len = match head[1] & 0x7f {
+        126 => reader.read_u16().await? as u64,
+        127 => reader.read_u64().await?,
+        len => len as u64,
+    };
+    if len > 64 * 1024 {
+        anyhow::bail!("websocket frame too large");
+    }
+    let mut mask = [0u8; 4];
+    if head[1] & 0x80 != 0 {
+        reader.read_exact(&mut mask).await?;
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    for (idx, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[idx % 4];
+    }
+    Ok((opcode, payload))
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (percent_decode(key), percent_decode(value)),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'+' => decoded.push(b' '),
+            b'%' if idx + 2 < bytes.len() => {
+                match std::str::from_utf8(&bytes[idx + 1..idx + 3])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        idx += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        idx += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::Hooks;
+    use crate::stream::RunStream;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    // A watch root of its own with one script in it
+    fn server(name: &str) -> (HttpTrigger, PathBuf) {
+        let root = std::env::temp_dir().join(format!("ws-http-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        let script = root.join("build.sh");
+        std::fs::write(&script, "#!/bin/bash\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let server = HttpTrigger {
+            inventory: Inventory::scan(&root),
+            session: Arc::new(Mutex::new(Session::new(RunStream::new(), Hooks::default()))),
+            wx: Arc::new(Watchexec::default()),
+        };
+        (server, root)
+    }
+
+    fn with_query(mut request: Request, query: &[(&str, &str)]) -> Request {
+        request.query = query
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        request
+    }
+
+    fn request(method: &str, path: &str, headers: &[(&str, &str)]) -> Request {
+        Request {
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            method: method.to_string(),
+            path: path.to_string(),
+            query: vec![],
+        }
+    }
+
+    fn upgrade(origin: Option<&str>) -> Request {
+        let mut headers = vec![
+            ("upgrade", "websocket"),
+            ("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="),
+        ];
+        headers.extend(origin.map(|origin| ("origin", origin)));
+        request("GET", "/events", &headers)
+    }
+
+    fn bound(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn only_a_get_with_upgrade_is_a_websocket() {
+        assert!(upgrade(None).websocket_key().is_some());
+        let mut post = upgrade(None);
+        post.method = "POST".to_string();
+        assert_eq!(post.websocket_key(), None);
+        let key_only = request("GET", "/events", &[("sec-websocket-key", "abc")]);
+        assert_eq!(key_only.websocket_key(), None);
+    }
+
+    #[test]
+    fn rejects_websockets_from_other_origins() {
+        let local = bound("127.0.0.1:7878");
+        for origin in [
+            "https://example.com",
+            "http://example.com:7878",
+            "http://127.0.0.1:8080",
+            "http://localhost",
+            "null",
+        ] {
+            assert!(!upgrade(Some(origin)).is_local_to(local), "{}", origin);
+        }
+    }
+
+    #[test]
+    fn rejects_form_posts_from_other_origins() {
+        let local = bound("127.0.0.1:7878");
+        let form = request(
+            "POST",
+            "/run",
+            &[
+                ("host", "127.0.0.1:7878"),
+                ("origin", "https://example.com"),
+            ],
+        );
+        assert!(!form.is_local_to(local));
+        let curl = request("POST", "/run", &[("host", "127.0.0.1:7878")]);
+        assert!(curl.is_local_to(local));
+    }
+
+    #[test]
+    fn rejects_other_hosts() {
+        let local = bound("127.0.0.1:7878");
+        let rebound = request("GET", "/status", &[("host", "evil.test:7878")]);
+        assert!(!rebound.is_local_to(local));
+        let localhost = request("GET", "/status", &[("host", "localhost:7878")]);
+        assert!(localhost.is_local_to(local));
+    }
+
+    #[test]
+    fn accepts_websockets_from_the_watcher_or_without_an_origin() {
+        let local = bound("127.0.0.1:7878");
+        assert!(upgrade(None).is_local_to(local));
+        assert!(upgrade(Some("http://127.0.0.1:7878")).is_local_to(local));
+        assert!(upgrade(Some("http://localhost:7878")).is_local_to(local));
+        let everywhere = bound("[::]:7878");
+        assert!(upgrade(Some("http://192.168.1.5:7878")).is_local_to(everywhere));
+        assert!(upgrade(Some("http://[::1]:7878")).is_local_to(everywhere));
+        assert!(!upgrade(Some("http://evil.test:7878")).is_local_to(everywhere));
+    }
+
+    #[tokio::test]
+    async fn status_reports_the_watch_root() {
+        let (server, root) = server("status");
+        let (code, body) = server.route(&request("GET", "/status", &[])).await;
+        assert_eq!(code, "200 OK");
+        assert_eq!(body["root"], json!(root));
+        assert_eq!(body["scripts"], json!(1));
+        assert_eq!(body["runs"], json!(0));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn runs_a_script_by_name() {
+        let (server, root) = server("run");
+        let run = with_query(request("POST", "/run", &[]), &[("script", "build.sh")]);
+        let (code, body) = server.route(&run).await;
+        assert_eq!(code, "202 Accepted");
+        assert_eq!(body["triggered"], json!(root.join("build.sh")));
+        let missing = with_query(request("POST", "/run", &[]), &[("script", "nope.sh")]);
+        let (code, body) = server.route(&missing).await;
+        assert_eq!(code, "404 Not Found");
+        assert_eq!(body["error"], json!("nope.sh does not exist"));
+        let outside = with_query(request("POST", "/run", &[]), &[("script", "../x.sh")]);
+        assert_eq!(server.route(&outside).await.0, "404 Not Found");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn reruns_the_last_script_without_a_name() {
+        let (server, root) = server("rerun");
+        let (code, _) = server.route(&request("POST", "/run", &[])).await;
+        assert_eq!(code, "400 Bad Request");
+        let last = root.join("build.sh");
+        server.session.lock().unwrap().last_script = Some(last.clone());
+        let (code, body) = server.route(&request("POST", "/run", &[])).await;
+        assert_eq!(code, "202 Accepted");
+        assert_eq!(body["triggered"], json!(last));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn other_methods_and_paths() {
+        let (server, root) = server("methods");
+        for (method, path, expected) in [
+            ("GET", "/run", "405 Method Not Allowed"),
+            ("POST", "/status", "405 Method Not Allowed"),
+            ("GET", "/nope", "404 Not Found"),
+            ("GET", "/events", "404 Not Found"),
+        ] {
+            let (code, _) = server.route(&request(method, path, &[])).await;
+            assert_eq!(code, expected, "{} {}", method, path);
+        }
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn decodes_queries() {
+        assert_eq!(
+            parse_query("script=a%20b.sh&x&&y=1+2"),
+            [
+                ("script".to_string(), "a b.sh".to_string()),
+                ("x".to_string(), String::new()),
+                ("y".to_string(), "1 2".to_string()),
+            ]
+        );
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+}
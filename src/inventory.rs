@@ -0,0 +1,132 @@
+use crate::scripts;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use watchexec_events::filekind::{FileEventKind, ModifyKind};
+use watchexec_events::{Event, Tag};
+
+// How long after a new directory shows up it gets
+// looked through again. Files copied in right behind
+// it (`mkdir new && cp run.sh new/`) can land before
+// the watch on it is in place and never get an event.
+const NEW_DIR_SETTLE: Duration = Duration::from_secs(1);
+
+// Every runnable script under the watch root. It's
+// scanned once at startup, kept current from create,
+// remove, rename, and permission change events, and
+// rescanned every so often in case an event got
+// missed (or when asked to with the `rescan` command).
+// The picker, `list`, and scripts asked for by name go
+// off of it instead of walking or probing the
+// filesystem each time.
+#[derive(Debug, Clone)]
+pub struct Inventory {
+    root: PathBuf,
+    scripts: Arc<Mutex<BTreeSet<PathBuf>>>,
+}
+
+impl Inventory {
+    pub fn scan(root: &Path) -> Inventory {
+        Inventory {
+            root: root.to_path_buf(),
+            scripts: Arc::new(Mutex::new(
+                scripts::find_scripts(root).into_iter().collect(),
+            )),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.scripts.lock().unwrap().contains(path)
+    }
+
+    pub fn scripts(&self) -> Vec<PathBuf> {
+        self.scripts.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn rescan(&self) {
+        *self.scripts.lock().unwrap() = scripts::find_scripts(&self.root).into_iter().collect();
+    }
+
+    fn rescan_dir_later(&self, dir: PathBuf) {
+        let inventory = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(NEW_DIR_SETTLE).await;
+            let _ = tokio::task::spawn_blocking(move || inventory.refresh(&dir)).await;
+        });
+    }
+
+    // A zero period turns rescanning off
+    pub fn rescan_every(&self, period: Duration) {
+        if period.is_zero() {
+            return;
+        }
+        let inventory = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // the first tick is right away and the startup
+            // scan just happened
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let inventory = inventory.clone();
+                let _ = tokio::task::spawn_blocking(move || inventory.rescan()).await;
+            }
+        });
+    }
+
+    // Picks up files and directories that showed up, went
+    // away, were renamed, or had their permissions changed.
+    // Content changes don't change what's runnable so
+    // they're left alone.
+    pub fn update(&self, events: &[Event]) {
+        for event in events {
+            let affects_inventory = event.tags.iter().any(|tag| {
+                matches!(
+                    tag,
+                    Tag::FileEventKind(
+                        FileEventKind::Create(_)
+                            | FileEventKind::Remove(_)
+                            | FileEventKind::Modify(ModifyKind::Name(_))
+                            | FileEventKind::Modify(ModifyKind::Metadata(_))
+                    )
+                )
+            });
+            if !affects_inventory {
+                continue;
+            }
+            let created = event
+                .tags
+                .iter()
+                .any(|tag| matches!(tag, Tag::FileEventKind(FileEventKind::Create(_))));
+            for (path, _) in event.paths() {
+                self.refresh(path);
+                if created && path.is_dir() && path.starts_with(&self.root) {
+                    self.rescan_dir_later(path.to_path_buf());
+                }
+            }
+        }
+    }
+
+    fn refresh(&self, path: &Path) {
+        if !path.starts_with(&self.root) {
+            return;
+        }
+        let mut scripts = self.scripts.lock().unwrap();
+        if path.is_dir() {
+            if !scripts::is_hidden(path) {
+                scripts.extend(scripts::find_scripts(path));
+            }
+        } else if path.is_file() && scripts::is_runnable(path) {
+            scripts.insert(path.to_path_buf());
+        } else {
+            // a directory that went away takes everything
+            // under it along with it
+            scripts.retain(|script| !script.starts_with(path));
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::problems::strip_ansi;
+use crate::session::RunInfo;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+// --junit <path> keeps a JUnit XML file up to date with
+// the latest results so CI systems and editors can show
+// them. A single run is written as a suite with one test
+// case and an --all run as a suite with a case for each
+// script (written as each one finishes so a long run can
+// be followed). Failures carry the run's output.
+#[derive(Debug, Clone)]
+pub struct JunitReport {
+    path: PathBuf,
+    state: Arc<Mutex<Batch>>,
+}
+
+#[derive(Debug, Default)]
+struct Batch {
+    cases: Vec<TestCase>,
+    // set for the length of an --all run
+    open: bool,
+}
+
+#[derive(Debug)]
+struct TestCase {
+    duration_ms: u128,
+    name: String,
+    output: Vec<String>,
+    status: Option<String>,
+    success: Option<bool>,
+}
+
+impl JunitReport {
+    pub fn new(path: PathBuf) -> JunitReport {
+        JunitReport {
+            path,
+            state: Arc::new(Mutex::new(Batch::default())),
+        }
+    }
+
+    pub fn begin(&self) {
+        let mut batch = self.state.lock().unwrap();
+        batch.cases.clear();
+        batch.open = true;
+    }
+
+    pub fn end(&self) {
+        self.state.lock().unwrap().open = false;
+    }
+
+    pub fn add(&self, info: &RunInfo, output: Vec<String>) {
+        let mut batch = self.state.lock().unwrap();
+        if !batch.open {
+            batch.cases.clear();
+        }
+        batch.cases.push(TestCase {
+            duration_ms: info.duration_ms.unwrap_or(0),
+            name: info.script.display().to_string(),
+            output,
+            status: info.status.clone(),
+            success: info.success,
+        });
+        if let Err(e) = std::fs::write(&self.path, render(&batch.cases)) {
+            eprintln!(
+                "\x1b[33mcould not write {}: {}\x1b[0m",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+fn render(cases: &[TestCase]) -> String {
+    let failures = cases.iter().filter(|c| c.success == Some(false)).count();
+    let skipped = cases.iter().filter(|c| c.success.is_none()).count();
+    let seconds = |ms: u128| format!("{:.3}", ms as f64 / 1000.0);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuite name=\"ws\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{}\" timestamp=\"{}\">",
+        cases.len(),
+        failures,
+        skipped,
+        seconds(cases.iter().map(|c| c.duration_ms).sum()),
+        chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+    for case in cases {
+        let _ = write!(
+            xml,
+            "  <testcase classname=\"ws\" name=\"{}\" time=\"{}\"",
+            escape(&case.name),
+            seconds(case.duration_ms)
+        );
+        let status = escape(case.status.as_deref().unwrap_or("unknown"));
+        match case.success {
+            Some(true) => xml.push_str("/>\n"),
+            Some(false) => {
+                let _ = writeln!(
+                    xml,
+                    ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>",
+                    status,
+                    escape(&case.output.join("\n"))
+                );
+            }
+            None => {
+                let _ = writeln!(
+                    xml,
+                    ">\n    <skipped message=\"{}\"/>\n  </testcase>",
+                    status
+                );
+            }
+        }
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+// Colors come out and so do the control characters XML
+// doesn't allow
+fn escape(text: &str) -> String {
+    strip_ansi(text)
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+        .collect::<String>()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,203 @@
+use crate::inventory::Inventory;
+use anyhow::{Result, anyhow};
+use nix::sys::termios::{self, LocalFlags, SetArg, Termios};
+use std::collections::BTreeMap;
+use std::io::{BufRead, IsTerminal, Read};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::sync::mpsc::UnboundedSender;
+
+// The terminal settings from before the key handler
+// took over stdin so they can be put back on the
+// way out.
+static ORIGINAL_TERMIOS: Mutex<Option<Termios>> = Mutex::new(None);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyAction {
+    Clear,
+    Kill,
+    Pause,
+    Pick,
+    Quit,
+    Rerun,
+    // Sent once a script has been chosen with the picker
+    Run(PathBuf),
+    Stats,
+    Then,
+    // Runs the next script with -x
+    Xtrace,
+}
+
+impl KeyAction {
+    fn from_name(name: &str) -> Option<KeyAction> {
+        match name {
+            "clear" => Some(KeyAction::Clear),
+            "kill" => Some(KeyAction::Kill),
+            "pause" => Some(KeyAction::Pause),
+            "pick" => Some(KeyAction::Pick),
+            "quit" => Some(KeyAction::Quit),
+            "rerun" => Some(KeyAction::Rerun),
+            "stats" => Some(KeyAction::Stats),
+            "then" => Some(KeyAction::Then),
+            "xtrace" => Some(KeyAction::Xtrace),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            KeyAction::Clear => "clear",
+            KeyAction::Kill => "kill",
+            KeyAction::Pause => "pause",
+            KeyAction::Pick => "pick",
+            KeyAction::Quit => "quit",
+            KeyAction::Rerun => "rerun",
+            KeyAction::Run(_) => "run",
+            KeyAction::Stats => "stats",
+            KeyAction::Then => "then",
+            KeyAction::Xtrace => "xtrace",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    keys: BTreeMap<u8, KeyAction>,
+}
+
+impl KeyBindings {
+    // Starts with the default bindings and then applies
+    // any `key=action` overrides from the command line.
+    // Using `none` as the action removes a binding.
+    // Binding an action moves it, so it's taken off the
+    // keys it had before (both of them for the ones with
+    // two, like Enter and `r`).
+    pub fn new(overrides: &[String]) -> Result<KeyBindings> {
+        let mut keys = BTreeMap::from([
+            (b'\n', KeyAction::Rerun),
+            (b' ', KeyAction::Pause),
+            (0x0b, KeyAction::Kill),
+            (b'c', KeyAction::Clear),
+            (b'k', KeyAction::Kill),
+            (b'p', KeyAction::Pick),
+            (b'q', KeyAction::Quit),
+            (b'r', KeyAction::Rerun),
+            (b's', KeyAction::Stats),
+            (b't', KeyAction::Then),
+            (b'x', KeyAction::Xtrace),
+        ]);
+        for binding in overrides {
+            let (key, action) = binding
+                .split_once('=')
+                .ok_or_else(|| anyhow!("key bindings look like key=action, got: {}", binding))?;
+            let key = parse_key(key)?;
+            if action == "none" {
+                keys.remove(&key);
+                continue;
+            }
+            let action = KeyAction::from_name(action)
+                .ok_or_else(|| anyhow!("unknown key action: {}", action))?;
+            keys.retain(|_, existing| *existing != action);
+            keys.insert(key, action);
+        }
+        Ok(KeyBindings { keys })
+    }
+
+    pub fn help(&self) -> String {
+        self.keys
+            .iter()
+            .map(|(key, action)| format!("{} {}", key_name(*key), action.name()))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    // Puts the terminal in cbreak mode (no line buffering
+    // or echo, but output processing and Ctrl+c still
+    // work) and sends bound keys to the channel from
+    // a background thread. The picker runs right on that
+    // thread since it needs to read stdin too.
+    pub fn listen(&self, tx: UnboundedSender<KeyAction>, inventory: Inventory) -> Result<()> {
+        let stdin = std::io::stdin();
+        if !stdin.is_terminal() {
+            return Ok(());
+        }
+        let original = termios::tcgetattr(&stdin)?;
+        let mut cbreak = original.clone();
+        cbreak
+            .local_flags
+            .remove(LocalFlags::ICANON | LocalFlags::ECHO);
+        termios::tcsetattr(&stdin, SetArg::TCSANOW, &cbreak)?;
+        *ORIGINAL_TERMIOS.lock().unwrap() = Some(original);
+        let keys = self.keys.clone();
+        std::thread::spawn(move || {
+            let mut stdin = std::io::stdin();
+            let mut buf = [0u8; 1];
+            while let Ok(1) = stdin.read(&mut buf) {
+                let action = match keys.get(&buf[0]) {
+                    Some(KeyAction::Pick) => match crate::picker::pick(&inventory) {
+                        Some(path) => KeyAction::Run(path),
+                        None => continue,
+                    },
+                    Some(action) => action.clone(),
+                    None => continue,
+                };
+                if tx.send(action).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+// The lighter weight option when full key bindings
+// aren't turned on. stdin stays line buffered and
+// every Enter asks for a re-run.
+pub fn listen_for_enter(tx: UnboundedSender<KeyAction>) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            if line.is_err() || tx.send(KeyAction::Rerun).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+pub fn restore_terminal() {
+    if let Some(original) = ORIGINAL_TERMIOS.lock().unwrap().take() {
+        let _ = termios::tcsetattr(std::io::stdin(), SetArg::TCSANOW, &original);
+    }
+}
+
+// Keys are either a single character, `enter`, `space`,
+// or `ctrl-<letter>`
+fn parse_key(key: &str) -> Result<u8> {
+    if key == "enter" {
+        return Ok(b'\n');
+    }
+    if key == "space" {
+        return Ok(b' ');
+    }
+    if let Some(letter) = key.strip_prefix("ctrl-")
+        && letter.len() == 1
+        && letter.as_bytes()[0].is_ascii_alphabetic()
+    {
+        return Ok(letter.as_bytes()[0].to_ascii_lowercase() - b'a' + 1);
+    }
+    if key.len() == 1 && key.is_ascii() {
+        return Ok(key.as_bytes()[0]);
+    }
+    Err(anyhow!("unsupported key: {}", key))
+}
+
+fn key_name(key: u8) -> String {
+    if key == b'\n' {
+        "enter".to_string()
+    } else if key == b' ' {
+        "space".to_string()
+    } else if (1..=26).contains(&key) {
+        format!("ctrl-{}", (key - 1 + b'a') as char)
+    } else {
+        (key as char).to_string()
+    }
+}
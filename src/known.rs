@@ -0,0 +1,87 @@
+use crate::inventory::Inventory;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub const KNOWN_NAME: &str = ".watch_scripts.known";
+
+// The scripts --confirm-new lets run on their own. One
+// that isn't on the list gets skipped when a change
+// sets it off until it's been run by name once (`ws
+// trigger`, the picker, HTTP, etc.) so something that
+// gets dropped into the directory doesn't run just
+// because it showed up.
+//
+// The list is kept in the watch root with paths relative
+// to it. When there isn't one yet everything that's
+// already there is taken as known.
+#[derive(Debug, Clone)]
+pub struct KnownScripts {
+    root: PathBuf,
+    scripts: Arc<Mutex<BTreeSet<PathBuf>>>,
+}
+
+impl KnownScripts {
+    pub fn load(root: &Path, inventory: &Inventory) -> KnownScripts {
+        let existing = std::fs::read_to_string(root.join(KNOWN_NAME)).ok();
+        let known = KnownScripts {
+            root: root.to_path_buf(),
+            scripts: Arc::default(),
+        };
+        match existing {
+            Some(contents) => {
+                *known.scripts.lock().unwrap() = contents
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(PathBuf::from)
+                    .collect();
+            }
+            None => {
+                let mut scripts = known.scripts.lock().unwrap();
+                scripts.extend(
+                    inventory
+                        .scripts()
+                        .iter()
+                        .filter_map(|script| known.relative(script)),
+                );
+                known.save(&scripts);
+            }
+        }
+        known
+    }
+
+    pub fn contains(&self, script: &Path) -> bool {
+        self.relative(script)
+            .is_some_and(|relative| self.scripts.lock().unwrap().contains(&relative))
+    }
+
+    // Saved right away so it sticks even if the watcher
+    // doesn't get to shut down cleanly
+    pub fn allow(&self, script: &Path) {
+        let Some(relative) = self.relative(script) else {
+            return;
+        };
+        let mut scripts = self.scripts.lock().unwrap();
+        if scripts.insert(relative) {
+            self.save(&scripts);
+        }
+    }
+
+    fn save(&self, scripts: &BTreeSet<PathBuf>) {
+        let contents: String = scripts
+            .iter()
+            .map(|script| format!("{}\n", script.display()))
+            .collect();
+        if let Err(e) = std::fs::write(self.root.join(KNOWN_NAME), contents) {
+            eprintln!("\x1b[33mcould not save {}: {}\x1b[0m", KNOWN_NAME, e);
+        }
+    }
+
+    fn relative(&self, script: &Path) -> Option<PathBuf> {
+        let script = std::fs::canonicalize(script).ok()?;
+        script
+            .strip_prefix(&self.root)
+            .ok()
+            .map(|relative| relative.to_path_buf())
+    }
+}
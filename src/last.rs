@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// What the watcher keeps between runs that doesn't
+// belong in the project itself. It's one file for every
+// directory in XDG_STATE_HOME (~/.local/state) so a
+// repo can't come with its own.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    // the last script a change (or a trigger) set off in
+    // each watch root, relative to it
+    #[serde(default)]
+    last: BTreeMap<PathBuf, PathBuf>,
+}
+
+fn state_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(dir.join("watch_scripts").join("state.json"))
+}
+
+fn load_state(path: &Path) -> State {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+// Kept so --resume can pick the edit loop back up after
+// a restart. It's saved when it changes instead of on
+// the way out so it's still there after a crash or a
+// reboot.
+pub fn save(root: &Path, script: &Path) {
+    let Ok(relative) = script.strip_prefix(root) else {
+        return;
+    };
+    let Some(path) = state_path() else {
+        return;
+    };
+    let mut state = load_state(&path);
+    if state.last.get(root).is_some_and(|last| last == relative) {
+        return;
+    }
+    state
+        .last
+        .insert(root.to_path_buf(), relative.to_path_buf());
+    // written next to it and moved into place so another
+    // watcher reading it never gets half a file
+    let partial = path.with_extension(format!("json.{}", std::process::id()));
+    let written = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| {
+            std::fs::write(
+                &partial,
+                serde_json::to_string_pretty(&state).unwrap_or_default(),
+            )
+        })
+        .and_then(|()| std::fs::rename(&partial, &path));
+    if let Err(e) = written {
+        eprintln!("\x1b[33mcould not save {}: {}\x1b[0m", path.display(), e);
+    }
+}
+
+// Only if the script is still there and inside the root
+pub fn load(root: &Path) -> Option<PathBuf> {
+    let relative = load_state(&state_path()?).last.remove(root)?;
+    let path = root.join(relative).canonicalize().ok()?;
+    (path.starts_with(root) && path.is_file()).then_some(path)
+}
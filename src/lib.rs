@@ -0,0 +1,3696 @@
+mod allow;
+mod artifacts;
+mod audit;
+mod ci;
+pub mod completions;
+mod config;
+pub mod control;
+mod cooldown;
+pub mod daemon;
+mod dbus;
+mod diff;
+mod directives;
+mod direnv;
+mod duration;
+mod exec_map;
+mod filters;
+mod groups;
+mod hashes;
+mod health;
+mod held;
+mod highlight;
+mod history;
+mod hooks;
+mod http;
+mod inventory;
+mod junit;
+pub mod keys;
+mod known;
+mod last;
+mod lifecycle;
+mod locks;
+pub mod logs;
+mod loop_guard;
+mod notify;
+mod order;
+mod output;
+mod picker;
+mod placeholders;
+mod plugins;
+mod problems;
+mod quiet;
+pub mod record;
+mod registry;
+pub mod report;
+mod rules;
+mod run;
+pub mod scaffold;
+mod schedule;
+mod screen;
+mod scripts;
+mod sections;
+pub mod service;
+mod session;
+mod shell;
+mod sidecar;
+mod sourced;
+mod stats;
+mod stream;
+mod syslog;
+mod tags;
+mod tasks;
+mod throttle;
+mod tmux;
+mod touched;
+mod tracked;
+mod trigger;
+mod truncate;
+mod watch_errors;
+mod webhook;
+pub mod workspace;
+mod wsl;
+
+use allow::SpawnPolicy;
+use anyhow::{Result, anyhow};
+use artifacts::Artifacts;
+use audit::AuditLog;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Command, arg, command};
+use clap_complete::engine::ArgValueCompleter;
+use config::{Config, CwdPolicy};
+use control::ControlSocket;
+use cooldown::Cooldowns;
+use diff::ScriptCache;
+use duration::parse_duration;
+use exec_map::ExecMap;
+pub use filters::{EventFilter, FilterConfig, Filters};
+use filters::{FileList, GitignoreFilter, Ignores};
+use groups::Groups;
+use hashes::ContentHashes;
+use health::HealthCheck;
+use highlight::Highlights;
+pub use hooks::{FilterReason, Hooks};
+use http::HttpTrigger;
+use inventory::Inventory;
+use itertools::Itertools;
+use junit::JunitReport;
+use keys::{KeyAction, KeyBindings};
+use known::KnownScripts;
+use locks::Locks;
+use loop_guard::LoopGuard;
+use order::RunOrder;
+use output::{OutputCapture, StderrStyle, TeeFile};
+use permissions::is_executable;
+use placeholders::RunVars;
+use plugins::Plugins;
+use problems::{Diagnostics, ProblemMatchers};
+use quiet::QuietHours;
+use regex::Regex;
+use rules::Rules;
+use run::{PipeEnd, ScriptRun, ThenOn};
+use screen::{Banner, ClearMode};
+use scripts::SymlinkMode;
+use sections::SectionStyle;
+pub use session::RunInfo;
+use session::Session;
+use shell::ScriptShell;
+use sourced::Sourced;
+use stats::Stats;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::time::Instant;
+use stream::RunStream;
+use syslog::SystemLog;
+use tasks::Task;
+use throttle::RunThrottle;
+use tmux::{Tmux, TmuxSend};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracked::TrackedFiles;
+use trigger::TriggerKinds;
+use truncate::{OutputLimit, parse_output_limit, parse_size};
+use watch_errors::WatchErrors;
+use watchexec::WatchedPath;
+use watchexec::Watchexec;
+use watchexec::command::Command as WatchCommand;
+use watchexec::command::Program;
+use watchexec::job::Job;
+use watchexec::sources::fs::Watcher;
+use watchexec_events::Event;
+use watchexec_events::Priority;
+use watchexec_events::Source;
+use watchexec_events::Tag;
+use watchexec_signals::Signal;
+use wsl::WslMode;
+
+#[derive(Debug, Clone)]
+pub struct Payload {
+    all: bool,
+    audit_log: Option<AuditLog>,
+    auto_chmod: bool,
+    banner: Option<Banner>,
+    before_path: Option<PathBuf>,
+    ci: bool,
+    clean_env: bool,
+    clear: ClearMode,
+    config: Config,
+    config_path: Option<PathBuf>,
+    confirm_new: bool,
+    container: Option<String>,
+    // skip runs when a save didn't change the contents
+    content_check: bool,
+    cooldowns: Cooldowns,
+    control_socket: bool,
+    daemon: bool,
+    dbus: bool,
+    // EventFilters added through the library builder.
+    // These stay put when the config gets reloaded.
+    custom_filters: Filters,
+    diagnostics: Option<Diagnostics>,
+    directives: bool,
+    direnv: Option<direnv::Direnv>,
+    enter: bool,
+    env_args: BTreeMap<String, String>,
+    exec_map: ExecMap,
+    exit_on_failure: bool,
+    extend_path: bool,
+    fifo: bool,
+    filters: Filters,
+    // false with --no-sourced
+    follow_sourced: bool,
+    // --github or --ci under GitHub Actions
+    github: bool,
+    grace: Duration,
+    // the name of the script's group (see groups::Groups)
+    group: Option<String>,
+    // --jobs-output grouped
+    group_output: bool,
+    groups: Groups,
+    guard: Option<String>,
+    health: Option<HealthCheck>,
+    highlight_args: Vec<String>,
+    highlights: Option<Highlights>,
+    hooks: Hooks,
+    idle_timeout: Option<Duration>,
+    // from `ignore <glob>` while it's running
+    ignores: Ignores,
+    initial_dir: Option<PathBuf>,
+    jobs: usize,
+    journal: Option<AuditLog>,
+    junit: Option<JunitReport>,
+    keep_alive: bool,
+    keep_history: bool,
+    keep_stats: bool,
+    keep_tail: usize,
+    keys: Option<KeyBindings>,
+    latency: Option<Duration>,
+    locks: Locks,
+    max_output: Option<OutputLimit>,
+    run_throttle: Option<RunThrottle>,
+    listen: Option<String>,
+    log_colors: bool,
+    log_file: Option<PathBuf>,
+    log_keep: usize,
+    log_max_age: Option<Duration>,
+    log_max_size: Option<u64>,
+    loop_guard: LoopGuard,
+    // the directory with the flake.nix for --nix
+    nix: Option<PathBuf>,
+    notify_errors: bool,
+    // --notify-over
+    notify_over: Option<Duration>,
+    once: bool,
+    pick: bool,
+    pidfile: Option<PathBuf>,
+    plugin_args: Vec<PathBuf>,
+    plugins: Option<Plugins>,
+    problem_args: Vec<String>,
+    problems: Option<ProblemMatchers>,
+    pipe_then: bool,
+    poll: Option<Duration>,
+    queue: bool,
+    quiet: bool,
+    // the config's no_run
+    quiet_hours: Option<QuietHours>,
+    quiet_over: Option<Duration>,
+    raw_then_path: Option<PathBuf>,
+    record: Option<PathBuf>,
+    report: bool,
+    rescan: Duration,
+    resume: bool,
+    rules: Rules,
+    run_order: RunOrder,
+    // filled in as a run goes for the placeholders
+    run_vars: RunVars,
+    // everything after `--` on the command line
+    script_args: Vec<String>,
+    sections: Option<SectionStyle>,
+    // --session's name for `ws attach`
+    session_name: Option<String>,
+    service: bool,
+    shell: ScriptShell,
+    spawn_policy: SpawnPolicy,
+    show_diff: bool,
+    spinner: bool,
+    stagger: Option<Duration>,
+    // --stderr and --stderr-log
+    stderr_log: Option<PathBuf>,
+    stderr_style: Option<StderrStyle>,
+    stdin_commands: bool,
+    start_instant: Option<Instant>,
+    symlinks: SymlinkMode,
+    system_log: Option<SystemLog>,
+    summary: bool,
+    // only scripts with one of these run (see tags.rs)
+    tags: Option<Vec<String>>,
+    // --just, --make, or --npm's task to run in place of
+    // scripts
+    task: Option<Task>,
+    tee: Option<PathBuf>,
+    then_after_output: Option<Regex>,
+    // --then-if-changed
+    then_artifacts: Option<Artifacts>,
+    then_on: ThenOn,
+    then_wait_file: Option<PathBuf>,
+    then_wait_port: Option<u16>,
+    then_wait_timeout: Duration,
+    // what came after the script in --then
+    then_args: Option<String>,
+    then_guard: Option<String>,
+    // false with --no-shell
+    through_shell: bool,
+    tmux: Option<Tmux>,
+    touched: bool,
+    tracked_only: bool,
+    // --events
+    trigger_kinds: TriggerKinds,
+    venv: bool,
+    webhook: Option<String>,
+    // watching a Windows drive from WSL
+    wsl: bool,
+    // --xtrace or the x key for the next run
+    xtrace: bool,
+}
+
+impl Payload {
+    pub fn get_args() -> ArgMatches {
+        Payload::cli().get_matches()
+    }
+
+    pub fn cli() -> Command {
+        command!()
+            .arg(arg!(
+    --all
+                "Run every script under the watched directory (in order) when any of them changes"))
+            .arg(
+                arg!(
+    --"audit-log" <path>
+                "Add a line to the file for every command that gets started and another when it's done")
+                .value_parser(clap::value_parser!(PathBuf)),
+            )
+            .arg(arg!(
+    --"auto-chmod"
+                "Make a saved file that starts with a shebang executable (with a note) so it runs instead of being skipped"))
+            .arg(arg!(
+    --banner <template>
+                "Line to show at the top of the screen instead of the default. Can use {script}, {run_number}, {time}, {date}, and {root}"))
+            .arg(
+                arg!(
+    --before <before_path>
+                "Script to run before each run. The run is called off if it fails")
+                .value_parser(clap::value_parser!(PathBuf))
+                .add(ArgValueCompleter::new(completions::script_candidates)),
+            )
+            .arg(arg!(
+    --ci
+                "For running inside CI: no clearing or colors, sections as ::group:: lines, a key=value line after each run, --exit-on-failure, and --idle-timeout 5m unless it's set"))
+            .arg(arg!(
+    --"clean-env"
+                "Start scripts with only PATH, HOME, and the --env and config variables instead of the watcher's environment"))
+            .arg(arg!(
+    -e --enter
+                "Re-run the last script when Enter is pressed"))
+            .arg(
+                arg!(
+    --env <var>
+                "Set an environment variable for the scripts, as NAME=VALUE")
+                .action(ArgAction::Append),
+            )
+            .arg(
+                arg!(
+    --events <kinds>
+                "Which file events set off a run (comma separated)")
+                .value_delimiter(',')
+                .value_parser(["modify", "create", "remove", "chmod", "rename"])
+                .default_value("modify"),
+            )
+            .arg(
+                arg!(
+    --"exec-map" <mapping>
+                "Run files with an extension through a command, e.g. \".py=python3 {file}\" ({file}, {dir}, and {stem} get filled in)")
+                .action(ArgAction::Append),
+            )
+            .arg(arg!(
+    --"exit-on-failure"
+                "Stop watching the first time a run fails and exit with the script's exit code"))
+            .arg(arg!(
+    --"extend-path"
+                "Put the script's directory and the watch root's bin directory at the front of PATH so scripts can call each other by name"))
+            .arg(
+                arg!(
+    --"files-from" <path>
+                "Only run the files listed in this file, one per line (- reads the list from stdin, e.g. find . -name '*.sh' | ws --files-from -)")
+                .value_parser(clap::value_parser!(PathBuf)),
+            )
+            .arg(
+                arg!(
+    -t --then <then_path>
+                "Script to run after the main process is done. Args after it can use {trigger}, {trigger_dir}, {exit}, and {duration_ms}")
+                .value_parser(clap::value_parser!(PathBuf))
+                .add(ArgValueCompleter::new(completions::script_candidates)),
+            )
+            .arg(
+                arg!(
+    --bind <binding>
+                "Change a key binding for --keys (e.g. x=rerun or q=none)")
+                .action(ArgAction::Append),
+            )
+            .arg(arg!(
+    --"confirm-new"
+                "Skip scripts that haven't been seen before when a change sets them off until they've been run by name once (e.g. with `ws trigger`)"))
+            .arg(
+                arg!(
+    --config <path>
+                "Config file to use instead of .watch_scripts.toml")
+                .value_parser(clap::value_parser!(PathBuf)),
+            )
+            .arg(arg!(
+    --"content-check"
+                "Skip runs for saves that didn't change a file's contents (e.g. touch)"))
+            .arg(
+                arg!(
+    --container <image>
+                "Run the scripts in a docker container from the image with the watch root mounted at the same path"),
+            )
+            .arg(
+                arg!(
+    --clear <mode>
+                "How to clear the terminal before each run")
+                .value_parser(["screen", "reset", "none"])
+                .default_value("screen"),
+            )
+            .arg(
+                arg!(
+    --daemon
+                "Run in the background with output going to a log file")
+                .conflicts_with_all(["enter", "keys", "pick"]),
+            )
+            .arg(arg!(
+    --dbus
+                "Take Trigger, Pause, Resume, and Status calls on the D-Bus session bus and send RunStarted and RunFinished signals"))
+            .arg(arg!(
+    --diagnostics
+                "Add file:line:col: message lines under error locations so terminals can link to them"))
+            .arg(arg!(
+    --directives
+                "Let scripts print ::ws::title <text>, ::ws::notify <message>, and ::ws::skip-then lines to set the terminal title, send a desktop notification, or skip the then script"))
+            .arg(arg!(
+    -d --diff
+                "Show a diff of the changed script before running it"))
+            .arg(arg!(
+    --direnv
+                "Load what direnv exports for the script's directory when there's an .envrc in it or above"))
+            .arg(arg!(
+    --github
+                "For GitHub Actions logs: each run in a ::group:: (like --sections ci) and ::error/::warning annotations for what --problems picks up. On by default with --ci when GITHUB_ACTIONS is set"))
+            .arg(
+                arg!(
+    --grace <duration>
+                "How long running scripts get to exit after Ctrl+c before they're killed")
+                .value_parser(parse_duration)
+                .default_value("5s"),
+            )
+            .arg(arg!(
+    --guard <command>
+                "Only run a script if this command exits 0 (e.g. 'git diff --quiet')"))
+            .arg(
+                arg!(
+    --health <command>
+                "With --service, a command that has to exit 0 once the new version has started (e.g. 'curl -sf localhost:3000/health')")
+                .requires("service"),
+            )
+            .arg(
+                arg!(
+    --"health-interval" <duration>
+                "How long to wait between --health tries")
+                .value_parser(parse_duration)
+                .default_value("1s"),
+            )
+            .arg(
+                arg!(
+    --"health-retries" <count>
+                "How many times to try --health before calling the new version unhealthy")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .default_value("10"),
+            )
+            .arg(
+                arg!(
+    --"health-rollback"
+                "Keep the version that was running until the new one passes --health and stop the new one if it never does")
+                .requires("health"),
+            )
+            .arg(
+                arg!(
+    --highlight <rule>
+                "Style lines of output matching a pattern, as style=pattern (e.g. \"bold red=error\")")
+                .action(ArgAction::Append),
+            )
+            .arg(
+                arg!(
+    --"idle-timeout" <duration>
+                "Stop watching (exiting 0) once nothing has set a script off for this long, e.g. 2h so forgotten watchers don't run forever")
+                .value_parser(parse_duration),
+            )
+            .arg(
+                arg!(
+    --jobs <count>
+                "How many scripts triggered together (--all or a batch of changes) can run at once. Output lines get the script's name in front of them when it's more than 1")
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .default_value("1"),
+            )
+            .arg(
+                arg!(
+    --"jobs-output" <mode>
+                "How the output of scripts running at once with --jobs is shown: prefix (lines as they come with the script's name in front) or grouped (each script's output all together once it's done)")
+                .value_parser(["prefix", "grouped"])
+                .default_value("prefix"),
+            )
+            .arg(arg!(
+    --journal
+                "Write down each run in .watch_scripts.journal.jsonl before it starts and again when it's done so runs cut off by a crash get listed (and re-run with --resume) next time"))
+            .arg(
+                arg!(
+    --just <recipe>
+                "Run this recipe from the justfile in the watched directory when anything changes instead of the changed script")
+                .conflicts_with_all(["all", "make", "npm"]),
+            )
+            .arg(
+                arg!(
+    --junit <path>
+                "Write the latest results as JUnit XML (a test case for each script with --all)")
+                .value_parser(clap::value_parser!(PathBuf)),
+            )
+            .arg(
+                arg!(
+    --"keep-alive"
+                "Start a script again (waiting longer each time) when it exits on its own so long running ones stay up until the next change")
+                .conflicts_with("queue"),
+            )
+            .arg(arg!(
+    --"keep-history"
+                "Add every finished run to .watch_scripts.history.jsonl for `ws report`"))
+            .arg(arg!(
+    --"keep-stats"
+                "Save the per-script stats (see `ws stats`) on the way out and pick them back up at startup"))
+            .arg(
+                arg!(
+    --"keep-tail" <lines>
+                "Keep the last lines of the previous run pinned at the top after clearing")
+                .value_parser(clap::value_parser!(usize)),
+            )
+            .arg(arg!(
+    -k --keys
+                "Enable single key commands (r rerun, t then, p pick, space pause, k kill, c clear, q quit)"))
+            .arg(arg!(
+    --listen <addr>
+                "Start an HTTP server (e.g. 127.0.0.1:7878) with POST /run?script=..., GET /status, and a WebSocket at /events"))
+            .arg(
+                arg!(
+    --latency <duration>
+                "How long a burst of changes gets to settle before a run starts (50ms by default). Lower is snappier on editors that save a lot")
+                .value_parser(parse_duration),
+            )
+            .arg(arg!(
+    --"log-colors"
+                "Keep the color codes in the --daemon log file instead of stripping them"))
+            .arg(
+                arg!(
+    --"log-file" <path>
+                "Where --daemon sends output (defaults to .watch_scripts.log)")
+                .value_parser(clap::value_parser!(PathBuf)),
+            )
+            .arg(
+                arg!(
+    --"log-keep" <count>
+                "How many rotated --daemon log files to keep")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("5"),
+            )
+            .arg(
+                arg!(
+    --"log-max-age" <duration>
+                "Start a new --daemon log file once the current one is this old (e.g. 7d)")
+                .value_parser(parse_duration),
+            )
+            .arg(
+                arg!(
+    --"log-max-size" <size>
+                "Start a new --daemon log file once the current one gets this big (e.g. 10m)")
+                .value_parser(parse_size),
+            )
+            .arg(arg!(
+    --"loop-guard"
+                "Learn which files each script writes and don't let changes to them set off runs while it's running (see outputs in the config for declaring them)"))
+            .arg(
+                arg!(
+    --make <target>
+                "Run `make <target>` from the watched directory when anything changes instead of the changed script")
+                .conflicts_with_all(["all", "npm"]),
+            )
+            .arg(
+                arg!(
+    --matcher <regex>
+                "Extra --diagnostics pattern with file, line, and optional col and message groups")
+                .action(ArgAction::Append),
+            )
+            .arg(
+                arg!(
+    --"max-output" <limit>
+                "Only show the start and end of a job's output past this many bytes (64k, 1m) or lines (500 lines)")
+                .value_parser(parse_output_limit),
+            )
+            .arg(
+                arg!(
+    --"max-runs-per-minute" <count>
+                "Hold runs past this many in a minute and start the scripts that got set off meanwhile together once there's room")
+                .value_parser(clap::value_parser!(u32).range(1..)),
+            )
+            .arg(
+                arg!(
+    --nix
+                "Run the scripts in the nix develop shell from the closest flake.nix at or above the watch root")
+                .conflicts_with("container"),
+            )
+            .arg(arg!(
+    --"no-clear"
+                "Same as --clear none"))
+            .arg(arg!(
+    --"no-permission-check"
+                "Run scripts even if they're world-writable or owned by another user"))
+            .arg(
+                arg!(
+    --"no-shell"
+                "Start scripts directly (going by their #! line) instead of through a shell. Scripts handled by --exec-map and the then script still go through one")
+                .conflicts_with_all(["container", "nix", "plugin", "shell"]),
+            )
+            .arg(arg!(
+    --"no-sourced"
+                "Don't re-run shell scripts when a file they source (source lib.sh or . lib.sh) changes"))
+            .arg(arg!(
+    --fifo
+                "Create a .watch_scripts.fifo named pipe where writing a script's path runs it and writing an empty line re-runs the last one"))
+            .arg(arg!(
+    --"notify-errors"
+                "Send a desktop notification when the file watcher runs into a problem (it always gets a banner)"))
+            .arg(
+                arg!(
+    --"notify-over" <duration>
+                "Ring the bell when a run that took at least this long finishes and leave quicker runs out of the chat notifications")
+                .value_parser(parse_duration),
+            )
+            .arg(arg!(
+    --once
+                "Stop watching after the first run finishes and exit with its exit code"))
+            .arg(
+                arg!(
+    --npm <script>
+                "Run a package.json script (with npm, pnpm, yarn, or bun going by the lockfile) when anything changes instead of the changed script")
+                .conflicts_with("all"),
+            )
+            .arg(
+                arg!(
+    -p --pick
+                "Pick a script to run from a fuzzy finder at startup")
+                .conflicts_with("resume"),
+            )
+            .arg(
+                arg!(
+    --"pipe-then"
+                "Feed the main script's stdout to the then script's stdin instead of showing it")
+                .requires("then")
+                .conflicts_with_all(["all", "then-after-output"]),
+            )
+            .arg(
+                arg!(
+    --pidfile <path>
+                "Where --daemon writes its pid (defaults to .watch_scripts.pid)")
+                .value_parser(clap::value_parser!(PathBuf)),
+            )
+            .arg(
+                arg!(
+    --poll <interval>
+                "Poll for changes this often instead of using FSEvents/inotify. Can be quicker for small trees on macOS")
+                .value_parser(parse_duration),
+            )
+            .arg(
+                arg!(
+    --plugin <path>
+                "Load a WASM plugin that can filter triggers, rewrite commands, or change output")
+                .value_parser(clap::value_parser!(PathBuf))
+                .action(ArgAction::Append),
+            )
+            .arg(
+                arg!(
+    --problems <matcher>
+                "List the errors and warnings after each run, found with a preset (rustc, pytest, eslint) or a pattern with file and line groups")
+                .action(ArgAction::Append),
+            )
+            .arg(
+                arg!(
+    --"project-origin" <mode>
+                "Where the watch root is: the current directory (cwd) or the closest directory at or above it with a .watch_scripts.toml or .git (auto)")
+                .value_parser(["cwd", "auto"])
+                .default_value("cwd"),
+            )
+            .arg(arg!(
+    --queue
+                "Let a script that's running finish when it's triggered again and run it after instead of restarting it. Triggers that come in while it waits replace each other so only the latest one runs"))
+            .arg(arg!(
+    -q --quiet
+                "Only show a script's output if it fails"))
+            .arg(
+                arg!(
+    --"quiet-over" <duration>
+                "With --quiet, show the output anyway once a run goes longer than this")
+                .value_parser(parse_duration),
+            )
+            .arg(
+                arg!(
+    --record <dir>
+                "Save each run's output with its timing (as an asciinema cast) for `ws replay`")
+                .value_parser(clap::value_parser!(PathBuf)),
+            )
+            .arg(arg!(
+    --report
+                "With --all, print a pass/fail table with durations once every script has run"))
+            .arg(arg!(
+    --resume
+                "Run the script that ran last time in this directory as soon as the watcher starts. With --journal it runs the ones the last watcher left unfinished instead when there are any"))
+            .arg(
+                arg!(
+    --rescan <duration>
+                "How often to rescan the watched directory for scripts in case a change was missed")
+                .value_parser(parse_duration)
+                .default_value("60s"),
+            )
+            .arg(
+                arg!(
+    --sections <style>
+                "Mark off each script run (and parts a script starts with ::ws::section <name>) for the terminal to jump between, or as ::group:: lines for CI logs to fold")
+                .value_parser(["terminal", "ci"]),
+            )
+            .arg(
+                arg!(
+    --session <name>
+                "Name this watcher so `ws attach <name>` can follow it from another terminal (it gets a control socket like --socket does)"),
+            )
+            .arg(
+                arg!(
+    --service
+                "Treat scripts as long running (like a dev server): a change stops the running one with SIGTERM (waiting up to --grace) before starting the new one")
+                .conflicts_with("queue"),
+            )
+            .arg(arg!(
+    --shell <shell>
+                "Shell that scripts are run through (defaults to the login shell from $SHELL if it's a POSIX shell or fish and bash otherwise)"))
+            .arg(arg!(
+    --socket
+                "Listen on a .watch_scripts.sock control socket in the watch root for ws trigger, ws status, ws list, and other tools"))
+            .arg(arg!(
+    --spinner
+                "Show a spinner with the elapsed time while a script is running"))
+            .arg(
+                arg!(
+    --stagger <duration>
+                "Wait this long between starting scripts that run together (--all or a batch with --jobs)")
+                .value_parser(parse_duration),
+            )
+            .arg(
+                arg!(
+    --stdin
+                "Take commands a line at a time on stdin (run <script>, pause, resume, status, ignore <glob>, quit) the same as the control socket")
+                .conflicts_with_all(["daemon", "enter", "keys"]),
+            )
+            .arg(
+                arg!(
+    --stderr <style>
+                "Show what scripts print to stderr in red (color) or with a red bar in front of each line (gutter)")
+                .value_parser(["plain", "color", "gutter"])
+                .default_value("plain"),
+            )
+            .arg(
+                arg!(
+    --"stderr-log" <path>
+                "Also add what scripts print to stderr to the end of this file")
+                .value_parser(clap::value_parser!(PathBuf)),
+            )
+            .arg(arg!(
+    --summary
+                "Print a summary line after each run"))
+            .arg(
+                arg!(
+    --symlinks <mode>
+                "Whether a symlinked script runs as the file it points to (from that file's directory) or through the link")
+                .value_parser(["target", "link"])
+                .default_value("target"),
+            )
+            .arg(
+                arg!(
+    --"system-log" <target>
+                "Send each run's start and finish to the systemd journal or syslog")
+                .value_parser(["journald", "syslog"]),
+            )
+            .arg(
+                arg!(
+    --tags <tags>
+                "Only run scripts tagged with one of these (comma separated) in a `ws-tags:` comment, their sidecar, or the config")
+                .value_delimiter(','),
+            )
+            .arg(
+                arg!(
+    --tee <path>
+                "Also add everything the scripts print to the end of this file")
+                .value_parser(clap::value_parser!(PathBuf)),
+            )
+            .arg(arg!(
+    --"then-after-output" <regex>
+                "Start the then script once the main script prints a line matching this instead of when it exits"))
+            .arg(
+                arg!(
+    --"then-on" <result>
+                "Which results of the main script the then script runs after")
+                .value_parser(["success", "failure", "always"])
+                .default_value("success")
+                .conflicts_with_all(["then-after-output", "then-wait-port"]),
+            )
+            .arg(
+                arg!(
+    --"then-wait-file" <path>
+                "Only run the then script once the main script has written this file (relative to the watch root) during the run")
+                .value_parser(clap::value_parser!(PathBuf)),
+            )
+            .arg(
+                arg!(
+    --"then-wait-port" <port>
+                "Start the then script once something accepts connections on this port on localhost instead of when the main script exits")
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .conflicts_with("then-after-output"),
+            )
+            .arg(
+                arg!(
+    --"then-wait-timeout" <duration>
+                "How long --then-wait-port and --then-wait-file wait before skipping the then script")
+                .value_parser(parse_duration)
+                .default_value("30s"),
+            )
+            .arg(arg!(
+    --"then-guard" <command>
+                "Only run the then script if this command exits 0"))
+            .arg(
+                arg!(
+    --"then-if-changed" <glob>
+                "Skip the then script when the files matching this (relative to the watch root) are byte for byte the same as after the last run")
+                .action(ArgAction::Append),
+            )
+            .arg(arg!(
+    --tmux
+                "Show the status of the latest run in the tmux window name"))
+            .arg(arg!(
+    --"tmux-pane" <target>
+                "Send runs to another tmux pane (e.g. 1 or ws:0.1). Implies --tmux"))
+            .arg(
+                arg!(
+    --"tmux-send" <what>
+                "What --tmux-pane gets")
+                .value_parser(["summary", "output"])
+                .default_value("summary"),
+            )
+            .arg(arg!(
+    --touched
+                "After the main script runs list the files under the watch root it created, modified, or deleted (and give them to the then script in WS_TOUCHED)"))
+            .arg(arg!(
+    --"tracked-only"
+                "Only run scripts that are tracked by git"))
+            .arg(arg!(
+    --venv
+                "Run scripts with the closest .venv or venv between their directory and the watch root activated"))
+            .arg(arg!(
+    --webhook <url>
+                "POST a JSON summary of each finished run to this url"))
+            .arg(
+                arg!(
+    --workspace <dirs>...
+                "Watch each of these project roots (e.g. ~/sites/*/scripts) with its own config and then script, labeling the output by root")
+                .value_delimiter(':')
+                .conflicts_with_all(["daemon", "enter", "keys", "stdin"]),
+            )
+            .arg(
+                arg!(
+    --wsl <mode>
+                "Poll and take Windows style paths when watching a Windows drive (/mnt/c) from WSL")
+                .value_parser(["auto", "on", "off"])
+                .default_value("auto"),
+            )
+            .arg(arg!(
+    --xtrace
+                "Run shell scripts with -x so every command gets printed before it runs (the x key does it for the next run only)"))
+            .arg(
+                arg!(
+    [script_args] ...
+                "Args after -- get added to every script run (e.g. ws --then deploy.sh -- --env=staging --fast)")
+                .id("script-args")
+                .last(true),
+            )
+            .subcommand(
+                Command::new("attach")
+                    .about("Follow the output of a watcher started with --session (Ctrl+\\ detaches)")
+                    .arg(
+                        arg!(<name> "Session to attach to")
+                            .add(ArgValueCompleter::new(completions::session_candidates)),
+                    ),
+            )
+            .subcommand(
+                Command::new("bench")
+                    .about("Run a script a number of times the same way a change would and show how long it took")
+                    .arg(
+                        arg!(<script> "Script to time")
+                            .value_parser(clap::value_parser!(PathBuf))
+                            .add(ArgValueCompleter::new(completions::script_candidates)),
+                    )
+                    .arg(
+                        arg!(-n --runs <count> "How many times to run it")
+                            .value_parser(clap::value_parser!(u16).range(1..))
+                            .default_value("10"),
+                    ),
+            )
+            .subcommand(
+                Command::new("completions")
+                    .about("Print shell completions to source from the shell's startup file")
+                    .arg(
+                        arg!(<shell> "Shell to complete for")
+                            .value_parser(["bash", "elvish", "fish", "powershell", "zsh"]),
+                    ),
+            )
+            .subcommand(
+                Command::new("init")
+                    .about("Write a commented .watch_scripts.toml for this directory with the shell and build directories filled in")
+                    .arg(arg!(--wsignore "Put the build directories in a .wsignore instead of the config"))
+                    .arg(arg!(--force "Replace files that are already there")),
+            )
+            .subcommand(
+                Command::new("install-service")
+                    .about("Print a systemd user unit (or launchd agent on macOS) that runs ws with the options given before this subcommand")
+                    .arg(arg!(--install "Write the service file and start it")),
+            )
+            .subcommand(
+                Command::new("list")
+                    .about("List the scripts that can be run (from the running watcher if there is one)")
+                    .arg(arg!(
+    -v --verbose
+                        "List every file and why the ones that can't run are left out")),
+            )
+            .subcommand(
+                Command::new("logs")
+                    .about("Show past runs from --keep-history with their output from --record")
+                    .arg(
+                        arg!([script] "Only show runs of this script")
+                            .value_parser(clap::value_parser!(PathBuf))
+                            .add(ArgValueCompleter::new(completions::script_candidates)),
+                    )
+                    .arg(
+                        arg!(
+    --last <count>
+                            "How many runs to show")
+                            .value_parser(clap::value_parser!(usize))
+                            .default_value("10"),
+                    )
+                    .arg(arg!(
+    -f --follow
+                        "Keep showing runs as they finish"))
+                    .arg(
+                        arg!(
+    --history <path>
+                            "History file to read (defaults to the .watch_scripts.history.jsonl here)")
+                            .value_parser(clap::value_parser!(PathBuf)),
+                    )
+                    .arg(
+                        arg!(
+    --dir <dir>
+                            "Where the recordings are (defaults to the --record dir)")
+                            .value_parser(clap::value_parser!(PathBuf)),
+                    ),
+            )
+            .subcommand(
+                Command::new("new")
+                    .about("Create an executable script with a starter header")
+                    .arg(
+                        arg!(<name> "Name for the script (the extension picks the shebang)")
+                            .value_parser(clap::value_parser!(PathBuf)),
+                    ),
+            )
+            .subcommand(
+                Command::new("replay")
+                    .about("Play back a run saved with --record (or list them when no run is given)")
+                    .arg(arg!([run] "Run id or path to a .cast file"))
+                    .arg(
+                        arg!(
+    --dir <dir>
+                            "Where the recordings are (defaults to the --record dir or the current one)")
+                            .value_parser(clap::value_parser!(PathBuf)),
+                    ),
+            )
+            .subcommand(
+                Command::new("report")
+                    .about("Render the runs saved with --keep-history as a single HTML page")
+                    .arg(
+                        arg!(
+    --html <path>
+                            "Where to write the page")
+                            .required(true)
+                            .value_parser(clap::value_parser!(PathBuf)),
+                    )
+                    .arg(
+                        arg!(
+    --history <path>
+                            "History file to read (defaults to the .watch_scripts.history.jsonl here)")
+                            .value_parser(clap::value_parser!(PathBuf)),
+                    ),
+            )
+            .subcommand(
+                Command::new("run")
+                    .about("Run a script once the same way a change would and exit with its result")
+                    .arg(
+                        arg!([script] "Script to run (leave off with --all to run every script or --just/--make/--npm to run the task)")
+                            .value_parser(clap::value_parser!(PathBuf))
+                            .add(ArgValueCompleter::new(completions::script_candidates)),
+                    ),
+            )
+            .subcommand(
+                Command::new("stats")
+                    .about("Show how often each script has run and failed and how long it takes (from the running watcher or --keep-stats)"),
+            )
+            .subcommand(
+                Command::new("status")
+                    .about("Show what the watcher running in this directory (or a parent) is watching, what's running, and the last few results"),
+            )
+            .subcommand(
+                Command::new("trigger")
+                    .about("Ask the watcher running in this directory (or a parent) to run a script")
+                    .arg(
+                        arg!([script] "Script to run (defaults to re-running the last one)")
+                            .add(ArgValueCompleter::new(completions::script_candidates)),
+                    ),
+            )
+            .mut_args(with_env_var)
+    }
+
+    pub fn captures_output(&self) -> bool {
+        self.diagnostics.is_some()
+            || self.highlights.is_some()
+            || self.problems.is_some()
+            || self.max_output.is_some()
+            || self.junit.is_some()
+            || self.keep_history
+            || self.jobs > 1
+            || self.keep_tail > 0
+            || self.plugins.is_some()
+            || self.listen.is_some()
+            || self.spinner
+            || self.then_after_output.is_some()
+            || self.quiet
+            || self.record.is_some()
+            || self.tee.is_some()
+            || self.stderr_style.is_some()
+            || self.stderr_log.is_some()
+            || self.directives
+            || self.sections.is_some()
+            || self.session_name.is_some()
+            || self
+                .tmux
+                .as_ref()
+                .is_some_and(|t| t.send == TmuxSend::Output)
+    }
+
+    pub fn mark_time(&mut self) {
+        self.start_instant = Some(Instant::now());
+    }
+
+    pub fn new(matches: &ArgMatches) -> Result<Payload> {
+        let cwd = std::env::current_dir()?;
+        let dir = match matches
+            .get_one::<String>("project-origin")
+            .map(String::as_str)
+        {
+            Some("auto") => project_root(&cwd),
+            _ => cwd,
+        };
+        Payload::from_matches(matches, dir)
+    }
+
+    // Scripts and the config file are looked up relative
+    // to the directory that gets watched.
+    pub fn from_matches(matches: &ArgMatches, dir: PathBuf) -> Result<Payload> {
+        let keys = if matches.get_flag("keys") {
+            let overrides: Vec<String> = matches
+                .get_many::<String>("bind")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            Some(KeyBindings::new(&overrides)?)
+        } else {
+            None
+        };
+        let daemon = matches.get_flag("daemon");
+        let ci = matches.get_flag("ci");
+        let github = matches.get_flag("github")
+            || (ci && std::env::var_os("GITHUB_ACTIONS").is_some_and(|value| value == "true"));
+        let wsl_mode = WslMode::from_arg(matches.get_one::<String>("wsl").unwrap());
+        let in_wsl = wsl_mode != WslMode::Off && wsl::is_wsl();
+        // Windows style paths only mean something under WSL
+        let path_arg = |name: &str| {
+            matches.get_one::<PathBuf>(name).map(|path| {
+                if in_wsl {
+                    wsl::normalize(path)
+                } else {
+                    path.clone()
+                }
+            })
+        };
+        let wsl = wsl_mode == WslMode::On || (in_wsl && wsl::on_windows_drive(&dir));
+        let config_path = path_arg("config");
+        let nix = if matches.get_flag("nix") {
+            Some(find_flake(&dir)?)
+        } else {
+            None
+        };
+        let plugin_args: Vec<PathBuf> = matches
+            .get_many::<PathBuf>("plugin")
+            .unwrap_or_default()
+            .cloned()
+            .collect();
+        let env_args = matches
+            .get_many::<String>("env")
+            .unwrap_or_default()
+            .map(|var| match var.split_once('=') {
+                Some((name, value)) if !name.is_empty() => {
+                    Ok((name.to_string(), value.to_string()))
+                }
+                _ => Err(anyhow!("--env needs NAME=VALUE, got `{}`", var)),
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        let highlight_args: Vec<String> = matches
+            .get_many::<String>("highlight")
+            .unwrap_or_default()
+            .cloned()
+            .collect();
+        let task = if let Some(recipe) = matches.get_one::<String>("just") {
+            Some(Task::just(&dir, recipe)?)
+        } else if let Some(target) = matches.get_one::<String>("make") {
+            Some(Task::make(&dir, target)?)
+        } else if let Some(script) = matches.get_one::<String>("npm") {
+            Some(Task::npm(&dir, script)?)
+        } else {
+            None
+        };
+        let (config, mut filters, plugins, rules) =
+            load_config(config_path.as_ref(), &plugin_args, &dir, task.is_some())?;
+        let ignores = Ignores::new(&dir);
+        let mut custom_filters = Filters::default();
+        custom_filters.push(Arc::new(ignores.clone()));
+        if let Some(source) = matches.get_one::<PathBuf>("files-from") {
+            // clap's conflicts_with can't go by the value so
+            // the list coming from stdin is checked here
+            if source.as_os_str() == "-"
+                && let Some(other) = ["stdin", "enter", "keys"]
+                    .into_iter()
+                    .find(|flag| matches.get_flag(flag))
+            {
+                return Err(anyhow!(
+                    "--files-from - can't be used with --{} since they both read stdin",
+                    other
+                ));
+            }
+            custom_filters.push(Arc::new(FileList::read(source, &dir)?));
+        }
+        filters.extend(custom_filters.clone());
+        let diagnostics = if matches.get_flag("diagnostics") {
+            let matchers: Vec<String> = matches
+                .get_many::<String>("matcher")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            Some(Diagnostics::new(&matchers)?)
+        } else {
+            None
+        };
+        let highlights = Highlights::new(&config.highlight, &highlight_args)?;
+        let problem_args: Vec<String> = matches
+            .get_many::<String>("problems")
+            .unwrap_or_default()
+            .cloned()
+            .collect();
+        let problems = problem_matchers(&config, &problem_args)?;
+        let locks = Locks::new(&config.locks, &dir)?;
+        let run_order = RunOrder::new(&config.order, config.order_by, &dir)?;
+        let quiet_hours = config.no_run.as_deref().map(QuietHours::new).transpose()?;
+        let groups = Groups::new(&config.groups, &dir)?;
+        let poll = matches
+            .get_one::<Duration>("poll")
+            .copied()
+            .or(wsl.then_some(wsl::POLL_INTERVAL));
+        let journal = matches.get_flag("journal").then(|| AuditLog::journal(&dir));
+        let loop_guard = LoopGuard::new(matches.get_flag("loop-guard"), &dir, poll);
+        let then_artifacts = matches
+            .get_many::<String>("then-if-changed")
+            .map(|globs| Artifacts::new(&globs.cloned().collect::<Vec<_>>(), &dir))
+            .transpose()?;
+        let spawn_policy = SpawnPolicy::new(
+            config.allow.as_deref(),
+            &config.deny,
+            &dir,
+            !matches.get_flag("no-permission-check"),
+        )?;
+        let tmux_pane = matches.get_one::<String>("tmux-pane").cloned();
+        let tmux = (matches.get_flag("tmux") || tmux_pane.is_some()).then(|| Tmux {
+            pane: tmux_pane,
+            send: TmuxSend::from_arg(matches.get_one::<String>("tmux-send").unwrap()),
+        });
+        let clear = from_cli_or_env(matches, "clear")
+            .or(config.clear.as_ref())
+            .unwrap_or_else(|| matches.get_one::<String>("clear").unwrap())
+            .clone();
+        // the login shell unless the scripts are going to
+        // be run by sh in a container or nix develop anyway
+        let shell = match from_cli_or_env(matches, "shell").or(config.shell.as_ref()) {
+            Some(shell) => ScriptShell::new(shell),
+            None if nix.is_some() || matches.contains_id("container") => ScriptShell::bash(),
+            None => ScriptShell::login(),
+        };
+        let (then_path, then_args) = match path_arg("then").or(config.then.clone()) {
+            Some(then) => {
+                let (path, args) = placeholders::split_then(&dir, then);
+                (Some(path), args)
+            }
+            None => (None, None),
+        };
+        // scripts change the working directory so it has
+        // to be absolute
+        let record = path_arg("record").map(|path| dir.join(path));
+        if let Some(record) = &record {
+            fs::create_dir_all(record)
+                .map_err(|e| anyhow!("could not create {}: {}", record.display(), e))?;
+        }
+        let junit = path_arg("junit").map(|path| JunitReport::new(dir.join(path)));
+        let mut payload = Payload {
+            all: matches.get_flag("all"),
+            audit_log: path_arg("audit-log").map(|path| AuditLog::new(dir.join(path))),
+            auto_chmod: matches.get_flag("auto-chmod"),
+            banner: matches
+                .get_one::<String>("banner")
+                .map(|template| Banner::new(template)),
+            before_path: path_arg("before"),
+            clean_env: matches.get_flag("clean-env"),
+            // escape codes just clutter up the log
+            ci,
+            clear: if daemon || ci || matches.get_flag("no-clear") {
+                ClearMode::None
+            } else {
+                ClearMode::from_arg(&clear)
+            },
+            config,
+            config_path,
+            confirm_new: matches.get_flag("confirm-new"),
+            container: matches.get_one::<String>("container").cloned(),
+            content_check: matches.get_flag("content-check"),
+            cooldowns: Cooldowns::default(),
+            control_socket: matches.get_flag("socket") || matches.contains_id("session"),
+            daemon,
+            dbus: matches.get_flag("dbus"),
+            diagnostics,
+            directives: matches.get_flag("directives"),
+            direnv: matches.get_flag("direnv").then(direnv::Direnv::default),
+            custom_filters,
+            enter: matches.get_flag("enter"),
+            env_args,
+            exit_on_failure: ci || matches.get_flag("exit-on-failure"),
+            extend_path: matches.get_flag("extend-path"),
+            exec_map: ExecMap::new(
+                &matches
+                    .get_many::<String>("exec-map")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect::<Vec<_>>(),
+            )?,
+            fifo: matches.get_flag("fifo"),
+            filters,
+            follow_sourced: !matches.get_flag("no-sourced"),
+            github,
+            grace: *matches.get_one::<Duration>("grace").unwrap(),
+            group: None,
+            groups,
+            guard: matches.get_one::<String>("guard").cloned(),
+            health: matches.get_one::<String>("health").map(|command| {
+                HealthCheck::new(
+                    command.clone(),
+                    *matches.get_one::<Duration>("health-interval").unwrap(),
+                    *matches.get_one::<u32>("health-retries").unwrap(),
+                    matches.get_flag("health-rollback"),
+                )
+            }),
+            highlights,
+            highlight_args,
+            hooks: Hooks::default(),
+            idle_timeout: matches
+                .get_one::<Duration>("idle-timeout")
+                .copied()
+                .or(ci.then_some(Duration::from_secs(300))),
+            ignores,
+            initial_dir: Some(dir),
+            jobs: *matches.get_one::<u16>("jobs").unwrap() as usize,
+            group_output: matches.get_one::<String>("jobs-output").unwrap() == "grouped",
+            journal,
+            junit,
+            keep_alive: matches.get_flag("keep-alive"),
+            keep_history: matches.get_flag("keep-history"),
+            keep_stats: matches.get_flag("keep-stats"),
+            keep_tail: matches.get_one::<usize>("keep-tail").copied().unwrap_or(0),
+            keys,
+            latency: matches.get_one::<Duration>("latency").copied(),
+            locks,
+            max_output: matches.get_one::<OutputLimit>("max-output").copied(),
+            run_throttle: matches
+                .get_one::<u32>("max-runs-per-minute")
+                .map(|max| RunThrottle::new(*max as usize)),
+            listen: matches.get_one::<String>("listen").cloned(),
+            log_colors: matches.get_flag("log-colors"),
+            log_file: matches.get_one::<PathBuf>("log-file").cloned(),
+            log_keep: *matches.get_one::<usize>("log-keep").unwrap(),
+            log_max_age: matches.get_one::<Duration>("log-max-age").copied(),
+            log_max_size: matches.get_one::<u64>("log-max-size").copied(),
+            loop_guard,
+            nix,
+            notify_errors: matches.get_flag("notify-errors"),
+            notify_over: matches.get_one::<Duration>("notify-over").copied(),
+            once: matches.get_flag("once"),
+            pick: matches.get_flag("pick"),
+            resume: matches.get_flag("resume"),
+            pidfile: matches.get_one::<PathBuf>("pidfile").cloned(),
+            plugin_args,
+            plugins,
+            problem_args,
+            problems,
+            pipe_then: matches.get_flag("pipe-then"),
+            poll,
+            queue: matches.get_flag("queue"),
+            quiet: matches.get_flag("quiet"),
+            quiet_hours,
+            quiet_over: matches.get_one::<Duration>("quiet-over").copied(),
+            raw_then_path: then_path,
+            record,
+            report: matches.get_flag("report"),
+            rescan: *matches.get_one::<Duration>("rescan").unwrap(),
+            rules,
+            run_order,
+            run_vars: RunVars::default(),
+            script_args: matches
+                .get_many::<String>("script-args")
+                .unwrap_or_default()
+                .cloned()
+                .collect(),
+            sections: matches
+                .get_one::<String>("sections")
+                .map(|style| SectionStyle::from_arg(style))
+                .or((ci || github).then_some(SectionStyle::Ci)),
+            session_name: matches.get_one::<String>("session").cloned(),
+            service: matches.get_flag("service"),
+            shell,
+            spawn_policy,
+            show_diff: matches.get_flag("diff"),
+            spinner: matches.get_flag("spinner"),
+            stagger: matches.get_one::<Duration>("stagger").copied(),
+            stderr_log: path_arg("stderr-log"),
+            stderr_style: StderrStyle::from_arg(matches.get_one::<String>("stderr").unwrap()),
+            stdin_commands: matches.get_flag("stdin"),
+            start_instant: None,
+            symlinks: SymlinkMode::from_arg(matches.get_one::<String>("symlinks").unwrap()),
+            system_log: matches
+                .get_one::<String>("system-log")
+                .map(|target| SystemLog::from_arg(target)),
+            summary: matches.get_flag("summary"),
+            tags: matches
+                .get_many::<String>("tags")
+                .map(|tags| tags.cloned().collect()),
+            task,
+            tee: path_arg("tee"),
+            then_after_output: matches
+                .get_one::<String>("then-after-output")
+                .map(|pattern| {
+                    Regex::new(pattern)
+                        .map_err(|e| anyhow!("invalid --then-after-output pattern: {}", e))
+                })
+                .transpose()?,
+            then_artifacts,
+            then_on: ThenOn::from_arg(matches.get_one::<String>("then-on").unwrap()),
+            then_wait_file: path_arg("then-wait-file"),
+            then_wait_port: matches.get_one::<u16>("then-wait-port").copied(),
+            then_wait_timeout: *matches.get_one::<Duration>("then-wait-timeout").unwrap(),
+            then_args,
+            then_guard: matches.get_one::<String>("then-guard").cloned(),
+            tmux,
+            through_shell: !matches.get_flag("no-shell"),
+            touched: matches.get_flag("touched"),
+            tracked_only: matches.get_flag("tracked-only"),
+            trigger_kinds: TriggerKinds::from_args(matches.get_many::<String>("events").unwrap()),
+            venv: matches.get_flag("venv"),
+            webhook: matches.get_one::<String>("webhook").cloned(),
+            wsl,
+            xtrace: matches.get_flag("xtrace"),
+        };
+        payload.validate_paths()?;
+        Ok(payload)
+    }
+
+    // Reads the config file again for a SIGHUP. Nothing
+    // changes unless the whole thing loads.
+    pub fn reload_config(&mut self) -> Result<()> {
+        let (config, mut filters, plugins, rules) = load_config(
+            self.config_path.as_ref(),
+            &self.plugin_args,
+            self.initial_dir.as_ref().unwrap(),
+            self.task.is_some(),
+        )?;
+        filters.extend(self.custom_filters.clone());
+        self.highlights = Highlights::new(&config.highlight, &self.highlight_args)?;
+        self.problems = problem_matchers(&config, &self.problem_args)?;
+        self.locks = self.locks.reloaded(&config.locks)?;
+        self.run_order = RunOrder::new(
+            &config.order,
+            config.order_by,
+            self.initial_dir.as_ref().unwrap(),
+        )?;
+        self.groups = Groups::new(&config.groups, self.initial_dir.as_ref().unwrap())?;
+        self.quiet_hours = config.no_run.as_deref().map(QuietHours::new).transpose()?;
+        self.spawn_policy = self
+            .spawn_policy
+            .reloaded(config.allow.as_deref(), &config.deny)?;
+        self.config = config;
+        self.filters = filters;
+        self.plugins = plugins;
+        self.rules = rules;
+        Ok(())
+    }
+
+    // The payload a run of the script goes with. The
+    // script's sidecar file and .env file (if it has
+    // them) get merged into the `[scripts]` settings and
+    // the sidecar can swap out the then script. One that
+    // can't be read is reported and the defaults are used.
+    pub fn for_script(&self, script: &Path) -> Payload {
+        let mut payload = self.clone();
+        // each run gets its own so ones going at the same
+        // time (--jobs, --queue) don't see each other's
+        payload.run_vars = RunVars::default();
+        if let Some(group) = self.groups.group_for(script) {
+            payload.group = Some(group.name.clone());
+            if let Some(then) = &group.then {
+                payload.raw_then_path = Some(then.clone());
+            }
+        }
+        match sidecar::settings_for(script, &self.config.scripts) {
+            Ok(settings) => {
+                if let Some(then) = &settings.then {
+                    match fs::canonicalize(then) {
+                        Ok(then) if scripts::is_runnable(&then) => {
+                            payload.raw_then_path = Some(then)
+                        }
+                        _ => report_error(format!(
+                            "the then script from {} isn't runnable: {}",
+                            sidecar::sidecar_path(script).display(),
+                            then.display()
+                        )),
+                    }
+                }
+                payload.config.scripts = settings;
+            }
+            Err(e) => report_error(e),
+        }
+        payload
+    }
+
+    // Whether --all should include the script given the
+    // groups and --tags
+    pub fn selects(&self, script: &Path) -> bool {
+        (self.groups.is_empty() || self.groups.group_for(script).is_some()) && self.has_tags(script)
+    }
+
+    // Whether --tags lets the script run. The sidecar
+    // gets read for it so it's only done once the
+    // cheaper checks have passed.
+    pub fn has_tags(&self, script: &Path) -> bool {
+        let Some(wanted) = &self.tags else {
+            return true;
+        };
+        let settings = sidecar::settings_for(script, &self.config.scripts)
+            .unwrap_or_else(|_| self.config.scripts.clone());
+        tags::has_any(script, &settings, wanted)
+    }
+
+    // A runnable script or a file --exec-map has a
+    // command for
+    pub fn can_run(&self, path: &Path) -> bool {
+        scripts::is_runnable(path) || (self.exec_map.handles(path) && !scripts::is_hidden(path))
+    }
+
+    // The path that actually gets run (see SymlinkMode)
+    pub fn run_path(&self, script: &Path) -> PathBuf {
+        if self.symlinks == SymlinkMode::Target
+            && scripts::is_symlink(script)
+            && let Ok(target) = fs::canonicalize(script)
+        {
+            return target;
+        }
+        script.to_path_buf()
+    }
+
+    // Where a script runs from (see CwdPolicy)
+    pub fn script_dir(&self, script: &Path) -> Option<PathBuf> {
+        match self.config.scripts.cwd {
+            Some(CwdPolicy::Root) => self.initial_dir.clone(),
+            _ => script.parent().map(|dir| dir.to_path_buf()),
+        }
+    }
+
+    // What --extend-path puts at the front of PATH for
+    // the script
+    pub fn path_dirs(&self, script: &Path) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = script.parent().map(Path::to_path_buf).into_iter().collect();
+        let bin = self.config.bin.as_deref().unwrap_or(Path::new("bin"));
+        let bin = self.watch_path().join(bin);
+        if bin.is_dir() && !dirs.contains(&bin) {
+            dirs.push(bin);
+        }
+        dirs
+    }
+
+    // The Python virtualenv --venv activates for the
+    // script. The closest one wins and it doesn't look
+    // above the watch root.
+    pub fn venv_dir(&self, script: &Path) -> Option<PathBuf> {
+        let root = self.watch_path();
+        script
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&root))
+            .flat_map(|dir| [dir.join(".venv"), dir.join("venv")])
+            .find(|venv| venv.join("pyvenv.cfg").is_file())
+    }
+
+    pub fn log_file_path(&self) -> PathBuf {
+        self.log_file
+            .clone()
+            .unwrap_or_else(|| self.initial_dir.as_ref().unwrap().join(daemon::LOG_NAME))
+    }
+
+    pub fn pidfile_path(&self) -> PathBuf {
+        self.pidfile
+            .clone()
+            .unwrap_or_else(|| self.initial_dir.as_ref().unwrap().join(daemon::PID_NAME))
+    }
+
+    // How a run gets shown. Tasks go by their command.
+    pub fn script_label(&self, script: &Path) -> PathBuf {
+        match &self.task {
+            Some(task) if task.file() == script => PathBuf::from(task.command(&self.shell)),
+            _ => self.relative_path(script),
+        }
+    }
+
+    pub fn relative_path(&self, path: &Path) -> PathBuf {
+        self.initial_dir
+            .as_ref()
+            .and_then(|dir| path.strip_prefix(dir).ok())
+            .unwrap_or(path)
+            .to_path_buf()
+    }
+
+    pub fn then_command(&self) -> Option<String> {
+        let then_path = self.raw_then_path.as_ref()?;
+        let name = then_path.file_name()?;
+        let args = match &self.then_args {
+            Some(args) => format!(" {}", placeholders::expand(args)),
+            None => String::new(),
+        };
+        if !then_path.is_dir() {
+            let then_script = format!("./{}", name.to_string_lossy());
+            return Some(format!("{}{}", self.shell.quote(&then_script), args));
+        }
+        // a directory of hooks (then.d/) runs each one in
+        // turn like run-parts. They all get a go and the
+        // then step fails if any of them did.
+        let mut command = "status=0".to_string();
+        for hook in then_hooks(then_path) {
+            let Some(hook_name) = hook.file_name() else {
+                continue;
+            };
+            let relative = Path::new(name).join(hook_name);
+            command.push_str(&format!(
+                "; ./{}{} || status=$?",
+                self.shell.posix().quote(&relative.to_string_lossy()),
+                args
+            ));
+        }
+        command.push_str("; exit $status");
+        Some(command)
+    }
+
+    // The then script or one of the hooks when --then is
+    // a directory
+    pub fn is_then(&self, script: &Path) -> bool {
+        self.raw_then_path
+            .as_ref()
+            .is_some_and(|then_path| script.starts_with(then_path))
+    }
+
+    // --container runs the command in a throwaway
+    // container with the watch root mounted where it is
+    // on the host so the script's directory is the same
+    // in there. The run's variables and the configured
+    // env are passed through but not the rest of the
+    // environment. Guards and the before script still
+    // run on the host.
+    fn in_container(&self, command: String) -> String {
+        let Some(image) = &self.container else {
+            return command;
+        };
+        let root = self.shell.quote(&self.watch_path().to_string_lossy());
+        let mut names: BTreeSet<&str> = placeholders::var_names().collect();
+        names.extend(self.config.scripts.env.keys().map(String::as_str));
+        names.extend(self.env_args.keys().map(String::as_str));
+        let env: String = names
+            .iter()
+            .map(|name| format!(" -e {}", self.shell.quote(name)))
+            .collect();
+        format!(
+            "docker run --rm -i --init --user \"$(id -u):$(id -g)\" -v {0}:{0} -w \"$PWD\"{1} {2} sh -c {3}",
+            root,
+            env,
+            self.shell.quote(image),
+            self.shell.quote(&command)
+        )
+    }
+
+    // --nix gets the scripts the toolchain the project's
+    // flake declares. nix develop keeps the environment
+    // so the run's variables get through on their own.
+    fn in_nix(&self, command: String) -> String {
+        let Some(flake_dir) = &self.nix else {
+            return command;
+        };
+        format!(
+            "nix develop {} --command sh -c {}",
+            self.shell.quote(&flake_dir.to_string_lossy()),
+            self.shell.quote(&command)
+        )
+    }
+
+    fn wrap_command(&self, command: String) -> String {
+        self.in_container(self.in_nix(command))
+    }
+
+    pub fn then_job(&self) -> Option<Arc<WatchCommand>> {
+        // a then.d directory's loop is written for sh
+        let shell = match self
+            .raw_then_path
+            .as_ref()
+            .is_some_and(|path| path.is_dir())
+        {
+            true => self.shell.posix(),
+            false => self.shell.clone(),
+        };
+        self.then_command().map(|then_command| {
+            Arc::new(WatchCommand {
+                program: Program::Shell {
+                    shell: shell.program(),
+                    command: self.wrap_command(then_command),
+                    args: vec![],
+                },
+                options: run::spawn_options(),
+            })
+        })
+    }
+
+    // Errors come back instead of exiting so this works
+    // for embedders too. The binary prints them and exits.
+    pub fn validate_paths(&mut self) -> Result<()> {
+        let Some(initial_dir) = &self.initial_dir else {
+            return Err(anyhow!(
+                "could not get current directory. Can not continue."
+            ));
+        };
+        if let Some(then_path) = &self.raw_then_path {
+            self.raw_then_path = Some(check_script_arg(initial_dir, then_path)?);
+        }
+        if let Some(before_path) = &self.before_path {
+            self.before_path = Some(check_script_arg(initial_dir, before_path)?);
+        }
+        Ok(())
+    }
+
+    pub fn is_daemon(&self) -> bool {
+        self.daemon
+    }
+
+    pub fn is_ci(&self) -> bool {
+        self.ci
+    }
+
+    pub fn log_options(&self) -> daemon::LogOptions {
+        daemon::LogOptions {
+            colors: self.log_colors,
+            keep: self.log_keep,
+            max_age: self.log_max_age,
+            max_size: self.log_max_size,
+        }
+    }
+
+    pub fn watch_path(&self) -> PathBuf {
+        self.initial_dir.clone().unwrap()
+    }
+
+    // For `ws list --verbose`. Every file under the watch
+    // root relative to it with the reason it wouldn't run
+    // when it changes (if there is one) including the
+    // filters from the config.
+    pub fn explain_scripts(&self) -> Result<Vec<(PathBuf, Option<String>)>> {
+        let root = fs::canonicalize(self.watch_path())?;
+        Ok(scripts::explain_files(&root)
+            .into_iter()
+            .map(|(path, reason)| {
+                let reason = reason.map(|reason| reason.to_string()).or_else(|| {
+                    self.filters
+                        .rejected_by(&trigger::manual_event(&path, Source::Filesystem), &path)
+                        .map(|name| format!("left out by the {} filter", name))
+                        .or_else(|| {
+                            (!self.groups.is_empty() && self.groups.group_for(&path).is_none())
+                                .then(|| "not in any group".to_string())
+                        })
+                        .or_else(|| {
+                            (!self.has_tags(&path)).then(|| "doesn't have a --tags tag".to_string())
+                        })
+                });
+                let relative = path.strip_prefix(&root).unwrap_or(&path).to_path_buf();
+                (relative, reason)
+            })
+            .collect())
+    }
+}
+
+pub struct Runner {
+    output: OutputCapture,
+    payload: Payload,
+    script_cache: Arc<Mutex<ScriptCache>>,
+    session: Arc<Mutex<Session>>,
+}
+
+impl Runner {
+    pub fn new(payload: Payload) -> Result<Runner> {
+        let stream = RunStream::new();
+        let mut session = Session::new(stream.clone(), payload.hooks.clone());
+        if payload.keep_stats {
+            session.stats = Stats::load(payload.initial_dir.as_ref().unwrap());
+        }
+        let output = OutputCapture::new(
+            payload.keep_tail,
+            stream,
+            payload.diagnostics.clone(),
+            payload.highlights.clone(),
+            payload.plugins.clone(),
+            payload.problems.clone(),
+            payload.max_output,
+        );
+        // --keep-history saves the end of it for failed
+        // runs
+        if payload.junit.is_some() || payload.keep_history {
+            output.keep_transcript();
+        }
+        if payload.directives {
+            output.enable_directives();
+        }
+        if let Some(style) = payload.sections {
+            output.enable_sections(style);
+        }
+        if let Some(path) = &payload.tee {
+            output.add_sink(output::TEE_SINK, Box::new(TeeFile::open(path)?));
+        }
+        output.split_stderr(
+            payload.stderr_style,
+            payload
+                .stderr_log
+                .as_deref()
+                .map(output::open_log)
+                .transpose()?,
+        );
+        Ok(Runner {
+            output,
+            payload,
+            script_cache: Arc::new(Mutex::new(ScriptCache::new())),
+            session: Arc::new(Mutex::new(session)),
+        })
+    }
+
+    // `ws run <script>` goes through the same steps as a
+    // triggered run (its own directory, bash, the then
+    // script, output handling, and the summary) without
+    // watching anything. With --all and no script it runs
+    // everything the way a change would with --all.
+    // Returns whether the run passed.
+    pub async fn run_once(&self, script: Option<&Path>) -> Result<bool> {
+        let payload = &self.payload;
+        let mut jobs = vec![];
+        let mut handles = vec![];
+        let mut start_job = |command: Arc<WatchCommand>,
+                             payload: &Payload,
+                             output: &OutputCapture,
+                             script: &Path,
+                             dir: Option<&Path>,
+                             pipe: Option<PipeEnd>| {
+            let (job, handle) = watchexec::job::start_job(command);
+            run::prepare_job(&job, payload, output, script, dir, pipe);
+            jobs.push(job.clone());
+            handles.push(handle);
+            job
+        };
+        let then_dir = |payload: &Payload| {
+            payload
+                .raw_then_path
+                .as_ref()
+                .and_then(|path| path.parent())
+                .map(|dir| dir.to_path_buf())
+        };
+        let work: Pin<Box<dyn Future<Output = bool> + Send>> = match script {
+            Some(script) => {
+                let joined = payload.watch_path().join(script);
+                let path = fs::canonicalize(&joined)
+                    .map_err(|_| anyhow!("{} does not exist", script.display()))?;
+                if !payload.can_run(&path) {
+                    return Err(anyhow!("{} is not a runnable script", script.display()));
+                }
+                let payload = payload.for_script(&path);
+                let run_path = payload.run_path(&joined);
+                let command = script_command(&run_path, &payload)
+                    .ok_or_else(|| anyhow!("{} is not a runnable script", script.display()))?;
+                let job = start_job(
+                    command,
+                    &payload,
+                    &self.output,
+                    &run_path,
+                    payload.script_dir(&run_path).as_deref(),
+                    Some(PipeEnd::Main),
+                );
+                let then_job = if payload.is_then(&path) {
+                    None
+                } else {
+                    payload.raw_then_path.clone().zip(payload.then_job()).map(
+                        |(then_path, then_command)| {
+                            start_job(
+                                then_command,
+                                &payload,
+                                &self.output,
+                                &then_path,
+                                then_dir(&payload).as_deref(),
+                                Some(PipeEnd::Then),
+                            )
+                        },
+                    )
+                };
+                let run = ScriptRun {
+                    job,
+                    output: self.output.clone(),
+                    script: payload.relative_path(&path),
+                    path: path.clone(),
+                    then_job,
+                    priority: payload.config.scripts.priority.unwrap_or(0),
+                    run_vars: payload.run_vars.clone(),
+                    timeout: payload.config.scripts.timeout,
+                    trigger_path: path,
+                };
+                let work = run.execute(payload.clone(), Arc::clone(&self.session));
+                Box::pin(async move { work.await.is_some_and(|info| info.success == Some(true)) })
+            }
+            None if payload.all => {
+                let root = fs::canonicalize(payload.watch_path())?;
+                let mut runs = vec![];
+                for script in Inventory::scan(&root).scripts() {
+                    if payload.is_then(&script)
+                        || payload.before_path.as_ref() == Some(&script)
+                        || !payload.selects(&script)
+                    {
+                        continue;
+                    }
+                    let script_payload = payload.for_script(&script);
+                    let run_path = script_payload.run_path(&script);
+                    let Some(command) = script_command(&run_path, &script_payload) else {
+                        continue;
+                    };
+                    let dir = script_payload.script_dir(&run_path);
+                    let label = payload.relative_path(&script);
+                    let output = job_output(&self.output, payload, &label);
+                    runs.push(ScriptRun {
+                        job: start_job(
+                            command,
+                            &script_payload,
+                            &output,
+                            &run_path,
+                            dir.as_deref(),
+                            None,
+                        ),
+                        output,
+                        script: label,
+                        path: script.clone(),
+                        then_job: None,
+                        priority: script_payload.config.scripts.priority.unwrap_or(0),
+                        run_vars: script_payload.run_vars.clone(),
+                        timeout: script_payload.config.scripts.timeout,
+                        trigger_path: script,
+                    });
+                }
+                let then_run = payload.raw_then_path.clone().zip(payload.then_job()).map(
+                    |(then_path, then_command)| ScriptRun {
+                        job: start_job(
+                            then_command,
+                            payload,
+                            &self.output,
+                            &then_path,
+                            then_dir(payload).as_deref(),
+                            None,
+                        ),
+                        output: self.output.clone(),
+                        script: payload.relative_path(&then_path),
+                        path: then_path.clone(),
+                        then_job: None,
+                        priority: 0,
+                        run_vars: payload.run_vars.clone(),
+                        timeout: None,
+                        trigger_path: then_path,
+                    },
+                );
+                let work = run::run_all(runs, then_run, payload.clone(), Arc::clone(&self.session));
+                Box::pin(async move { work.await.unwrap_or(false) })
+            }
+            None => {
+                let Some(task) = &payload.task else {
+                    return Err(anyhow!("give run a script or use --all"));
+                };
+                let command = shell_command(task.command(&payload.shell), payload);
+                let job = start_job(
+                    command,
+                    payload,
+                    &self.output,
+                    task.file(),
+                    payload.initial_dir.as_deref(),
+                    Some(PipeEnd::Main),
+                );
+                let then_job = payload.raw_then_path.clone().zip(payload.then_job()).map(
+                    |(then_path, then_command)| {
+                        start_job(
+                            then_command,
+                            payload,
+                            &self.output,
+                            &then_path,
+                            then_dir(payload).as_deref(),
+                            Some(PipeEnd::Then),
+                        )
+                    },
+                );
+                let run = ScriptRun {
+                    job,
+                    output: self.output.clone(),
+                    script: payload.script_label(task.file()),
+                    path: task.file().to_path_buf(),
+                    then_job,
+                    priority: payload.config.scripts.priority.unwrap_or(0),
+                    run_vars: payload.run_vars.clone(),
+                    timeout: payload.config.scripts.timeout,
+                    trigger_path: task.file().to_path_buf(),
+                };
+                let work = run.execute(payload.clone(), Arc::clone(&self.session));
+                Box::pin(async move { work.await.is_some_and(|info| info.success == Some(true)) })
+            }
+        };
+        payload.clear.clear();
+        if let Some(banner) = &payload.banner {
+            let label = match (script, &payload.task) {
+                (Some(script), _) => script.display().to_string(),
+                (None, Some(task)) => task.command(&payload.shell),
+                (None, None) => "all".to_string(),
+            };
+            banner.print(&[label], 1, &payload.watch_path());
+        }
+        // the scripts have their own process groups so
+        // Ctrl+c has to be passed along
+        let passed = tokio::select! {
+            passed = work => passed,
+            _ = tokio::signal::ctrl_c() => {
+                for job in &jobs {
+                    job.stop_with_signal(Signal::Terminate, payload.grace);
+                    job.to_wait().await;
+                }
+                false
+            }
+        };
+        // the supervisor tasks have to be shut down before
+        // the runtime goes away
+        for job in &jobs {
+            job.delete_now().await;
+        }
+        for handle in handles {
+            let _ = handle.await;
+        }
+        placeholders::remove_handoff_dir();
+        Ok(passed)
+    }
+
+    // `ws bench <script> -n 10` runs a script over and
+    // over the way a change would (minus the then script)
+    // and shows the fastest, median, and slowest times.
+    // It stops at the first run that fails since its time
+    // doesn't say much. Returns whether they all passed.
+    pub async fn bench(&self, script: &Path, count: usize) -> Result<bool> {
+        let joined = self.payload.watch_path().join(script);
+        let path = fs::canonicalize(&joined)
+            .map_err(|_| anyhow!("{} does not exist", script.display()))?;
+        if !self.payload.can_run(&path) {
+            return Err(anyhow!("{} is not a runnable script", script.display()));
+        }
+        let payload = self.payload.for_script(&path);
+        let run_path = payload.run_path(&joined);
+        let label = payload.relative_path(&path);
+        let mut times = vec![];
+        for n in 1..=count {
+            let command = script_command(&run_path, &payload)
+                .ok_or_else(|| anyhow!("{} is not a runnable script", script.display()))?;
+            let (job, handle) = watchexec::job::start_job(command);
+            run::prepare_job(
+                &job,
+                &payload,
+                &self.output,
+                &run_path,
+                payload.script_dir(&run_path).as_deref(),
+                None,
+            );
+            let run = ScriptRun {
+                job: job.clone(),
+                output: self.output.clone(),
+                script: label.clone(),
+                path: path.clone(),
+                then_job: None,
+                priority: payload.config.scripts.priority.unwrap_or(0),
+                run_vars: payload.run_vars.clone(),
+                timeout: payload.config.scripts.timeout,
+                trigger_path: path.clone(),
+            };
+            let info = tokio::select! {
+                info = run.execute(payload.clone(), Arc::clone(&self.session)) => info,
+                _ = tokio::signal::ctrl_c() => {
+                    job.stop_with_signal(Signal::Terminate, payload.grace);
+                    job.to_wait().await;
+                    None
+                }
+            };
+            job.delete_now().await;
+            let _ = handle.await;
+            match info.filter(|info| info.success == Some(true)) {
+                Some(info) => {
+                    let time = Duration::from_millis(info.duration_ms.unwrap_or(0) as u64);
+                    println!(
+                        "\x1b[2mRun {} of {}: {}\x1b[0m",
+                        n,
+                        count,
+                        run::format_duration(&time)
+                    );
+                    times.push(time);
+                }
+                None => {
+                    println!("\x1b[31mStopped: run {} of {} didn't pass\x1b[0m", n, count);
+                    break;
+                }
+            }
+        }
+        times.sort();
+        if let (Some(fastest), Some(slowest)) = (times.first(), times.last()) {
+            let middle = times.len() / 2;
+            let median = if times.len() % 2 == 0 {
+                (times[middle - 1] + times[middle]) / 2
+            } else {
+                times[middle]
+            };
+            println!(
+                "{} ({} runs): fastest {} | median {} | slowest {}",
+                label.display(),
+                times.len(),
+                run::format_duration(fastest),
+                run::format_duration(&median),
+                run::format_duration(slowest)
+            );
+        }
+        placeholders::remove_handoff_dir();
+        Ok(times.len() == count)
+    }
+
+    pub async fn run(&self) -> Result<()> {
+        self.payload.clear.clear();
+        match (&self.payload.banner, &self.payload.task) {
+            (Some(banner), _) => banner.print(&[], 0, &self.payload.watch_path()),
+            (None, Some(task)) => {
+                println!(
+                    "Running `{}` when anything changes",
+                    task.command(&self.payload.shell)
+                )
+            }
+            (None, None) => println!("Watching for script changes"),
+        }
+        if let Some(then_path) = self.payload.raw_then_path.as_ref() {
+            println!("Then Running: {}", then_path.display());
+        }
+        let wx = Arc::new(Watchexec::default());
+        let root = fs::canonicalize(self.payload.watch_path())?;
+        let inventory = Inventory::scan(&root);
+        inventory.rescan_every(self.payload.rescan);
+        // this has to happen before the key handler starts
+        // reading stdin
+        if self.payload.pick
+            && let Some(path) = picker::pick(&inventory)
+        {
+            wx.send_event(
+                trigger::manual_event(&path, Source::Keyboard),
+                Priority::Urgent,
+            )
+            .await?;
+        }
+        // a journal with runs in it that never finished
+        // gets them re-run with --resume instead of the
+        // last script
+        let interrupted: Vec<PathBuf> = match &self.payload.journal {
+            Some(journal) => {
+                report_interrupted(&journal.take_interrupted(), &self.payload, &inventory)
+            }
+            None => vec![],
+        };
+        if self.payload.resume && !interrupted.is_empty() {
+            wx.send_event(trigger::released_event(&interrupted), Priority::Urgent)
+                .await?;
+        } else if self.payload.resume {
+            match last::load(&root).filter(|path| inventory.contains(path)) {
+                Some(path) => {
+                    println!(
+                        "\x1b[2mPicking up where it left off with {}\x1b[0m",
+                        self.payload.relative_path(&path).display()
+                    );
+                    wx.send_event(trigger::released_event(&[path]), Priority::Urgent)
+                        .await?;
+                }
+                None => println!("\x1b[2mThere's no last script to resume\x1b[0m"),
+            }
+        }
+        if let Some(tmux) = &self.payload.tmux {
+            tmux.clone().follow(self.session.lock().unwrap().stream());
+        }
+        let (hooks_tx, hooks_rx) = tokio::sync::watch::channel(self.payload.config.hooks.clone());
+        let quit_hooks = hooks_tx.subscribe();
+        lifecycle::follow(
+            hooks_rx,
+            self.payload.clone(),
+            self.session.lock().unwrap().stream(),
+        );
+        let (notify_tx, notify_rx) =
+            tokio::sync::watch::channel(self.payload.config.notify.clone());
+        notify::follow(
+            notify_rx,
+            self.payload.notify_over,
+            self.session.lock().unwrap().stream(),
+        );
+        if self.payload.exit_on_failure || self.payload.once {
+            self.stop_when_done(Arc::clone(&wx));
+        }
+        if let Some(limit) = self.payload.idle_timeout {
+            self.stop_when_idle(Arc::clone(&wx), limit);
+        }
+        if self.payload.ci {
+            ci::follow(self.session.lock().unwrap().finished_runs());
+        }
+        let (schedule_tx, schedule_rx) =
+            tokio::sync::watch::channel(self.payload.config.schedules.clone());
+        schedule::follow(schedule_rx, root.clone(), Arc::clone(&wx));
+        if let Some(url) = &self.payload.webhook {
+            webhook::follow(url.clone(), self.session.lock().unwrap().stream());
+        }
+        if self.payload.keep_history {
+            history::follow(
+                root.join(history::HISTORY_NAME),
+                self.session.lock().unwrap().stream(),
+            );
+        }
+        if let Some(system_log) = self.payload.system_log {
+            let socket = system_log
+                .connect()
+                .map_err(|e| anyhow!("--system-log: {}", e))?;
+            system_log.follow(socket, self.session.lock().unwrap().stream());
+        }
+        let control = ControlSocket {
+            ignores: self.payload.ignores.clone(),
+            inventory: inventory.clone(),
+            root: root.clone(),
+            session: Arc::clone(&self.session),
+            wx: Arc::clone(&wx),
+        };
+        if self.payload.stdin_commands {
+            control.clone().read_stdin();
+        }
+        if self.payload.dbus {
+            let stream = self.session.lock().unwrap().stream().clone();
+            dbus::serve(control.clone(), stream)
+                .await
+                .map_err(|e| anyhow!("--dbus: {}", e))?;
+        }
+        if self.payload.fifo {
+            control.clone().read_fifo()?;
+        }
+        if self.payload.control_socket {
+            control.serve().await?;
+            if let Some(name) = &self.payload.session_name {
+                registry::register(name, &ControlSocket::path(&root))?;
+            }
+        }
+        if let Some(addr) = &self.payload.listen {
+            HttpTrigger {
+                inventory: inventory.clone(),
+                session: Arc::clone(&self.session),
+                wx: Arc::clone(&wx),
+            }
+            .serve(addr.clone())
+            .await?;
+        }
+        if let Some(bindings) = &self.payload.keys {
+            println!("Keys: {}", bindings.help());
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            bindings.listen(tx, inventory.clone())?;
+            self.handle_keys(rx, Arc::clone(&wx));
+        } else if self.payload.enter {
+            println!("Press Enter to re-run the last script");
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            keys::listen_for_enter(tx);
+            self.handle_keys(rx, Arc::clone(&wx));
+        }
+        let tracked = if self.payload.tracked_only {
+            match TrackedFiles::new(&root) {
+                Ok(tracked) => Some(Arc::new(Mutex::new(tracked))),
+                Err(e) => {
+                    eprintln!("ERROR: --tracked-only needs a git repo: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            None
+        };
+        let hashes = self
+            .payload
+            .content_check
+            .then(|| ContentHashes::load(&root));
+        let saved_hashes = hashes.clone();
+        let sourced = self.payload.follow_sourced.then(Sourced::default);
+        let last_root = root.clone();
+        let known = self
+            .payload
+            .confirm_new
+            .then(|| KnownScripts::load(&root, &inventory));
+        // shared so a SIGHUP can swap in a reloaded config
+        let shared_payload = Arc::new(Mutex::new(self.payload.clone()));
+        let script_cache = Arc::clone(&self.script_cache);
+        let output = self.output.clone();
+        let session = Arc::clone(&self.session);
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let throttle_wx = Arc::clone(&wx);
+        let watch_path = WatchedPath::recursive(self.payload.watch_path());
+        wx.config.pathset(vec![watch_path]);
+        // FSEvents batches changes up on its own and the
+        // throttle adds to that so both can be turned down
+        // for save-heavy workflows
+        if let Some(latency) = self.payload.latency {
+            wx.config.throttle(latency);
+        }
+        if let Some(interval) = self.payload.poll {
+            if self.payload.wsl {
+                println!(
+                    "Watching a Windows drive from WSL. Polling every {}",
+                    run::format_duration(&interval)
+                );
+            }
+            wx.config.file_watcher(Watcher::Poll(interval));
+        }
+        let watch_errors = WatchErrors::new(
+            self.payload.notify_errors,
+            self.session.lock().unwrap().stream().clone(),
+        );
+        let overflow_errors = watch_errors.clone();
+        wx.config.on_error(move |hook| watch_errors.handle(hook));
+        wx.config.on_action(move |mut action| {
+            let mut payload = shared_payload.lock().unwrap().clone();
+            payload.run_vars = RunVars::default();
+            if session.lock().unwrap().xtrace_next {
+                payload.xtrace = true;
+            }
+            inventory.update(&action.events);
+            // the list of scripts could be out of date
+            // after the kernel dropped events
+            if watch_errors::overflowed(&action.events) {
+                inventory.rescan();
+                overflow_errors.overflowed();
+            }
+            // SIGUSR1 re-runs the last script like Enter does
+            // so hooks and editors can poke the watcher with
+            // `kill -USR1 $(cat .watch_scripts.pid)`
+            if action.signals().any(|sig| sig == Signal::User1) {
+                match session.lock().unwrap().last_script.clone() {
+                    Some(script) => {
+                        action.events =
+                            Arc::from([trigger::manual_event(&script, Source::Internal)]);
+                    }
+                    None => println!("\x1b[2mNothing has run yet to re-run\x1b[0m"),
+                }
+            }
+            // SIGTERM too so --daemon can be stopped by a service
+            // manager. The signal is passed on to the running
+            // scripts' process groups first so their own
+            // traps get to clean up.
+            let stop_signal = action
+                .signals()
+                .find(|sig| matches!(sig, Signal::Interrupt | Signal::Terminate | Signal::Quit));
+            if let Some(signal) = stop_signal {
+                // a second Ctrl+c skips the wait
+                if shutting_down.swap(true, Ordering::SeqCst) {
+                    action.quit();
+                    return action;
+                }
+                if session.lock().unwrap().is_running() {
+                    println!(
+                        "\x1b[33mSent {} to the running scripts (waiting up to {} before killing them)\x1b[0m",
+                        signal,
+                        run::format_duration(&payload.grace)
+                    );
+                }
+                action.quit_gracefully(signal, payload.grace);
+            } else if let Some(command) = trigger::command(&action.events) {
+                match command {
+                    "kill" => {
+                        println!("\x1b[33mStopping the running script\x1b[0m");
+                        action.list_jobs().for_each(|(_, job)| {
+                            job.stop();
+                        });
+                    }
+                    "pause" | "resume" => {
+                        let mut session = session.lock().unwrap();
+                        if session.set_paused(command == "pause") {
+                            report_pause(&session);
+                        }
+                    }
+                    _ => {}
+                }
+            } else if action.signals().any(|sig| sig == Signal::Hangup) {
+                // a run that's going keeps going with what it
+                // started with
+                inventory.rescan();
+                let mut payload = shared_payload.lock().unwrap();
+                match payload.reload_config() {
+                    Ok(()) => {
+                        output.reconfigure(
+                            payload.highlights.clone(),
+                            payload.plugins.clone(),
+                            payload.problems.clone(),
+                        );
+                        notify_tx.send_replace(payload.config.notify.clone());
+                        hooks_tx.send_replace(payload.config.hooks.clone());
+                        schedule_tx.send_replace(payload.config.schedules.clone());
+                        println!(
+                            "\x1b[33mReloaded the config ({} scripts)\x1b[0m",
+                            inventory.scripts().len()
+                        );
+                    }
+                    Err(e) => {
+                        eprintln!("ERROR: could not reload the config: {}", e);
+                    }
+                }
+            } else if action.signals().any(|sig| sig == Signal::User2) {
+                let mut session = session.lock().unwrap();
+                session.toggle_pause();
+                report_pause(&session);
+            } else if let [triggered, others @ ..] = &get_commands(
+                &action.events,
+                &payload,
+                &inventory,
+                tracked.as_deref(),
+                hashes.as_ref(),
+                known.as_ref(),
+                sourced.as_ref(),
+            )[..]
+            {
+                let batch = || std::iter::once(triggered).chain(others);
+                session.lock().unwrap().triggered();
+                // manual triggers still go through while paused
+                if !action.events.iter().any(trigger::is_manual) {
+                    let mut session = session.lock().unwrap();
+                    if session.paused {
+                        session.ignored_while_paused += 1;
+                        for triggered in batch() {
+                            payload.hooks.filtered(&triggered.trigger_path, FilterReason::Paused);
+                        }
+                        return action;
+                    }
+                }
+                if let Some(quiet_hours) = &payload.quiet_hours
+                    && !action.events.iter().any(trigger::is_forced)
+                    && let Some(wait) = quiet_hours.remaining()
+                {
+                    quiet_hours.hold(
+                        batch().map(|triggered| triggered.script.clone()),
+                        wait,
+                        Arc::clone(&throttle_wx),
+                    );
+                    return action;
+                }
+                if let Some(throttle) = &payload.run_throttle
+                    && let Err(wait) = throttle.admit()
+                {
+                    throttle.hold(
+                        batch().map(|triggered| triggered.script.clone()),
+                        wait,
+                        Arc::clone(&throttle_wx),
+                    );
+                    return action;
+                }
+                // the x key only lasts for one run
+                session.lock().unwrap().xtrace_next = false;
+                for triggered in batch() {
+                    payload.hooks.trigger(&triggered.script);
+                }
+                payload.clear.clear();
+                if let Some(banner) = &payload.banner {
+                    let scripts: Vec<String> = batch()
+                        .map(|triggered| payload.script_label(&triggered.script).display().to_string())
+                        .collect();
+                    let next_run = session.lock().unwrap().next_run();
+                    banner.print(&scripts, next_run, &payload.watch_path());
+                }
+                if payload.keep_tail > 0 {
+                    output::print_pinned_tail(&output.take_tail());
+                }
+                if payload.show_diff {
+                    let mut script_cache = script_cache.lock().unwrap();
+                    for triggered in batch() {
+                        if let Some(diff) = script_cache.diff_and_update(&triggered.script, &payload.watch_path()) {
+                            diff::print_diff(&diff);
+                        }
+                    }
+                }
+                // with --queue the runs clean up after
+                // themselves instead and with --service the
+                // new run takes care of it
+                let stopping: Vec<Job> = if payload.service {
+                    action.list_jobs().map(|(_, job)| job).collect()
+                } else {
+                    vec![]
+                };
+                if !payload.queue && !payload.service {
+                    action.list_jobs().for_each(|(_, job)| {
+                        job.delete_now();
+                    });
+                }
+                {
+                    let mut session = session.lock().unwrap();
+                    if !payload.queue {
+                        session.replace_running();
+                    }
+                    if session.last_script.as_ref() != Some(&triggered.script) {
+                        last::save(&last_root, &triggered.script);
+                    }
+                    session.last_script = Some(triggered.script.clone());
+                }
+                // --all and a batch of scripts with --jobs both
+                // get run as a set with the then script once
+                // at the end
+                if payload.all || !others.is_empty() {
+                    let mut runs = vec![];
+                    if payload.all {
+                        for script in inventory.scripts() {
+                            if payload.is_then(&script)
+                                || payload.before_path.as_ref() == Some(&script)
+                            {
+                                continue;
+                            }
+                            if !payload.selects(&script) {
+                                continue;
+                            }
+                            if let Some(known) = &known
+                                && !known.contains(&script)
+                            {
+                                report_new(&script, &payload);
+                                continue;
+                            }
+                            let script_payload = payload.for_script(&script);
+                            let run_path = script_payload.run_path(&script);
+                            let Some(command) = script_command(&run_path, &script_payload) else {
+                                continue;
+                            };
+                            let (_, job) = action.create_job(command);
+                            let dir = script_payload.script_dir(&run_path);
+                            let label = payload.relative_path(&script);
+                            let output = job_output(&output, &script_payload, &label);
+                            run::prepare_job(
+                                &job,
+                                &script_payload,
+                                &output,
+                                &run_path,
+                                dir.as_deref(),
+                                None,
+                            );
+                            runs.push(ScriptRun {
+                                job,
+                                output,
+                                script: label,
+                                path: script,
+                                then_job: None,
+                                priority: script_payload.config.scripts.priority.unwrap_or(0),
+                                run_vars: script_payload.run_vars.clone(),
+                                timeout: script_payload.config.scripts.timeout,
+                                trigger_path: triggered.trigger_path.clone(),
+                            });
+                        }
+                    } else {
+                        for triggered in batch() {
+                            let script_payload = &triggered.payload;
+                            let (_, job) = action.create_job(Arc::clone(&triggered.command));
+                            let label = script_payload.script_label(&triggered.script);
+                            let output = job_output(&output, script_payload, &label);
+                            run::prepare_job(
+                                &job,
+                                script_payload,
+                                &output,
+                                &triggered.script,
+                                triggered.dir.as_deref(),
+                                None,
+                            );
+                            runs.push(ScriptRun {
+                                job,
+                                output,
+                                path: triggered.script.clone(),
+                                script: label,
+                                then_job: None,
+                                priority: script_payload.config.scripts.priority.unwrap_or(0),
+                                run_vars: script_payload.run_vars.clone(),
+                                timeout: script_payload.config.scripts.timeout,
+                                trigger_path: triggered.trigger_path.clone(),
+                            });
+                        }
+                    }
+                    // the then script can be one of the batch
+                    // itself in which case it's already there
+                    let then_run = payload
+                        .raw_then_path
+                        .clone()
+                        .filter(|_| payload.all || batch().all(|triggered| triggered.run_then))
+                        .zip(payload.then_job())
+                        .map(|(then_path, then_command)| {
+                            let (_, job) = action.create_job(then_command);
+                            run::prepare_job(
+                                &job,
+                                &payload,
+                                &output,
+                                &then_path,
+                                then_path.parent(),
+                                None,
+                            );
+                            ScriptRun {
+                                job,
+                                output: output.clone(),
+                                script: payload.relative_path(&then_path),
+                                path: then_path,
+                                then_job: None,
+                                priority: 0,
+                                run_vars: payload.run_vars.clone(),
+                                timeout: None,
+                                trigger_path: triggered.trigger_path.clone(),
+                            }
+                        });
+                    tokio::spawn(run::restart(
+                        stopping,
+                        None,
+                        payload.clone(),
+                        Arc::clone(&session),
+                        run::run_all(runs, then_run, payload.clone(), Arc::clone(&session)),
+                    ));
+                    return action;
+                }
+                // from here on the script's own settings apply
+                let payload = &triggered.payload;
+                if let Some(group) = &payload.group {
+                    println!(
+                        "\x1b[2m[{}] {}\x1b[0m",
+                        group,
+                        payload.script_label(&triggered.script).display()
+                    );
+                }
+                let (_, job) = action.create_job(Arc::clone(&triggered.command));
+                let then_job = if triggered.run_then {
+                    payload
+                        .then_job()
+                        .map(|then_job| action.create_job(then_job).1)
+                } else {
+                    None
+                };
+                run::prepare_job(
+                    &job,
+                    payload,
+                    &output,
+                    &triggered.script,
+                    triggered.dir.as_deref(),
+                    Some(PipeEnd::Main),
+                );
+                if let Some(then_job) = &then_job
+                    && let Some(then_path) = &payload.raw_then_path
+                {
+                    run::prepare_job(
+                        then_job,
+                        payload,
+                        &output,
+                        then_path,
+                        then_path.parent(),
+                        Some(PipeEnd::Then),
+                    );
+                }
+                let started = (job.clone(), payload.script_label(&triggered.script));
+                let run = ScriptRun {
+                    job,
+                    output: output.clone(),
+                    path: triggered.script.clone(),
+                    script: payload.script_label(&triggered.script),
+                    then_job,
+                    priority: payload.config.scripts.priority.unwrap_or(0),
+                    run_vars: payload.run_vars.clone(),
+                    timeout: payload.config.scripts.timeout,
+                    trigger_path: triggered.trigger_path.clone(),
+                };
+                tokio::spawn(run::restart(
+                    stopping,
+                    Some(started),
+                    payload.clone(),
+                    Arc::clone(&session),
+                    run.execute(payload.clone(), Arc::clone(&session)),
+                ));
+
+            }
+            action
+        });
+        daemon::sd_notify("READY=1");
+        let result = wx.main().await;
+        daemon::sd_notify("STOPPING=1");
+        lifecycle::on_quit(&quit_hooks, &self.payload).await;
+        if let Some(hashes) = &saved_hashes
+            && let Err(e) = hashes.save()
+        {
+            report_error(format!("could not save {}: {}", hashes::HASHES_NAME, e));
+        }
+        if self.payload.keep_stats
+            && let Err(e) = self.session.lock().unwrap().stats.save(&root)
+        {
+            report_error(format!("could not save {}: {}", stats::STATS_NAME, e));
+        }
+        if self.payload.control_socket {
+            let _ = fs::remove_file(ControlSocket::path(&root));
+        }
+        if self.payload.fifo {
+            let _ = fs::remove_file(ControlSocket::fifo_path(&root));
+        }
+        if let Some(name) = &self.payload.session_name {
+            registry::unregister(name);
+        }
+        if self.payload.tmux.is_some() {
+            tmux::restore_window_name();
+        }
+        placeholders::remove_handoff_dir();
+        keys::restore_terminal();
+        println!("{}", self.session.lock().unwrap().final_summary());
+        let _ = result?;
+        Ok(())
+    }
+
+    // What the watcher should exit with if something
+    // other than Ctrl+c stopped it
+    pub fn exit_code(&self) -> Option<i32> {
+        self.session.lock().unwrap().exit_code
+    }
+
+    // --exit-on-failure and --once quit the same way Ctrl+c
+    // does (anything else running gets to finish up) and
+    // keep the exit code for main.rs. Scripts that were
+    // killed or never got going count as 1.
+    fn stop_when_done(&self, wx: Arc<Watchexec>) {
+        let once = self.payload.once;
+        let on_failure = self.payload.exit_on_failure;
+        let session = Arc::clone(&self.session);
+        let mut runs = session.lock().unwrap().finished_runs();
+        tokio::spawn(async move {
+            while let Some(run) = runs.recv().await {
+                let failed = run.success == Some(false);
+                if !(once || failed && on_failure) {
+                    continue;
+                }
+                let code = match failed {
+                    true => run
+                        .exit_code
+                        .and_then(|code| i32::try_from(code).ok())
+                        .filter(|code| *code != 0)
+                        .unwrap_or(1),
+                    false => 0,
+                };
+                // only the first one counts
+                let first = {
+                    let mut session = session.lock().unwrap();
+                    let first = session.exit_code.is_none();
+                    session.exit_code.get_or_insert(code);
+                    first
+                };
+                if !first {
+                    continue;
+                }
+                let script = run.script.display();
+                match (failed, on_failure) {
+                    (true, true) => println!(
+                        "\x1b[31mStopping since {} failed (--exit-on-failure)\x1b[0m",
+                        script
+                    ),
+                    _ => println!("\x1b[2mStopping after {} (--once)\x1b[0m", script),
+                }
+                let _ = wx.send_event(trigger::quit_event(), Priority::Urgent).await;
+            }
+        });
+    }
+
+    // --idle-timeout. The time starts over whenever
+    // something sets a script off (even while paused or
+    // if it gets skipped) or a run finishes, and doesn't
+    // count while one's going. Quitting goes the same way
+    // as Ctrl+c so the on_quit hook and saving the stats
+    // still happen.
+    fn stop_when_idle(&self, wx: Arc<Watchexec>, limit: Duration) {
+        let session = Arc::clone(&self.session);
+        tokio::spawn(async move {
+            loop {
+                let idle = session.lock().unwrap().idle_for();
+                match idle {
+                    Some(idle) if idle >= limit => break,
+                    Some(idle) => tokio::time::sleep(limit - idle).await,
+                    None => tokio::time::sleep(limit).await,
+                }
+            }
+            println!(
+                "\x1b[2mStopping after {} without anything to run (--idle-timeout)\x1b[0m",
+                run::format_duration(&limit)
+            );
+            let _ = wx.send_event(trigger::quit_event(), Priority::Urgent).await;
+        });
+    }
+
+    // Turns key presses into the same events that file
+    // changes produce so they go through the regular
+    // action handler.
+    fn handle_keys(&self, mut rx: UnboundedReceiver<KeyAction>, wx: Arc<Watchexec>) {
+        let payload = self.payload.clone();
+        let session = Arc::clone(&self.session);
+        tokio::spawn(async move {
+            while let Some(key) = rx.recv().await {
+                let event = match key {
+                    KeyAction::Clear => {
+                        payload.clear.clear();
+                        None
+                    }
+                    KeyAction::Kill => Some(trigger::command_event("kill")),
+                    KeyAction::Pause => Some(trigger::pause_event()),
+                    KeyAction::Pick => None,
+                    KeyAction::Quit => Some(trigger::quit_event()),
+                    KeyAction::Rerun => session
+                        .lock()
+                        .unwrap()
+                        .last_script
+                        .as_ref()
+                        .map(|path| trigger::manual_event(path, Source::Keyboard)),
+                    KeyAction::Run(path) => Some(trigger::manual_event(&path, Source::Keyboard)),
+                    KeyAction::Stats => {
+                        let session = session.lock().unwrap();
+                        println!("{}", session.stats.table());
+                        if let Some(status) = session.idle_status() {
+                            println!("\x1b[2m{}\x1b[0m", status);
+                        }
+                        None
+                    }
+                    KeyAction::Then => payload
+                        .raw_then_path
+                        .as_ref()
+                        .map(|path| trigger::manual_event(path, Source::Keyboard)),
+                    KeyAction::Xtrace => {
+                        let mut session = session.lock().unwrap();
+                        session.xtrace_next = !session.xtrace_next;
+                        match session.xtrace_next {
+                            true => println!("\x1b[33mTracing the next run (set -x)\x1b[0m"),
+                            false => println!("\x1b[33mNot tracing the next run\x1b[0m"),
+                        }
+                        None
+                    }
+                };
+                if let Some(event) = event {
+                    let _ = wx.send_event(event, Priority::Urgent).await;
+                }
+            }
+        });
+    }
+}
+
+// Sets up a watcher from Rust instead of the command
+// line. Options without their own method are passed the
+// same way they would be on the command line.
+//
+//     WatchScripts::builder()
+//         .watch("scripts")
+//         .then("deploy.sh")
+//         .arg("--summary")
+//         .build()?
+//         .run()
+//         .await
+pub struct WatchScripts {
+    runner: Runner,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WatchScriptsBuilder {
+    args: Vec<String>,
+    dir: Option<PathBuf>,
+    filters: Filters,
+    hooks: Hooks,
+}
+
+impl WatchScripts {
+    pub fn builder() -> WatchScriptsBuilder {
+        WatchScriptsBuilder::default()
+    }
+
+    pub fn payload(&self) -> &Payload {
+        &self.runner.payload
+    }
+
+    pub async fn run(&self) -> Result<()> {
+        let result = self.runner.run().await;
+        keys::restore_terminal();
+        result
+    }
+}
+
+impl WatchScriptsBuilder {
+    // The directory to watch. Defaults to the current one.
+    pub fn watch(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    // Relative paths are relative to the watched directory
+    pub fn then(self, script: impl Into<PathBuf>) -> Self {
+        let script = script.into();
+        self.arg("--then").arg(script.display().to_string())
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    // Adds a filter that changed files have to get past
+    // after the ones from the config file
+    pub fn filter(mut self, filter: impl EventFilter + 'static) -> Self {
+        self.filters.push(Arc::new(filter));
+        self
+    }
+
+    // Called with the path of each script that's about to
+    // run, whether from a change or a manual trigger.
+    pub fn on_trigger(mut self, hook: impl Fn(&Path) + Send + Sync + 'static) -> Self {
+        self.hooks.on_trigger.push(Arc::new(hook));
+        self
+    }
+
+    pub fn on_run_start(mut self, hook: impl Fn(&RunInfo) + Send + Sync + 'static) -> Self {
+        self.hooks.on_run_start.push(Arc::new(hook));
+        self
+    }
+
+    // Called once the results are in, including for runs
+    // that were skipped by a guard or cancelled.
+    pub fn on_run_end(mut self, hook: impl Fn(&RunInfo) + Send + Sync + 'static) -> Self {
+        self.hooks.on_run_end.push(Arc::new(hook));
+        self
+    }
+
+    // Called with changed files that didn't set off a run
+    // and why.
+    pub fn on_filtered(
+        mut self,
+        hook: impl Fn(&Path, FilterReason) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.on_filtered.push(Arc::new(hook));
+        self
+    }
+
+    pub fn build(self) -> Result<WatchScripts> {
+        let dir = match self.dir {
+            Some(dir) => dir,
+            None => std::env::current_dir()?,
+        };
+        let dir = fs::canonicalize(&dir)
+            .map_err(|e| anyhow!("could not watch {}: {}", dir.display(), e))?;
+        let matches = Payload::cli()
+            .try_get_matches_from(std::iter::once("ws".to_string()).chain(self.args))?;
+        let mut payload = Payload::from_matches(&matches, dir)?;
+        payload.hooks = self.hooks;
+        payload.filters.extend(self.filters.clone());
+        payload.custom_filters.extend(self.filters);
+        Ok(WatchScripts {
+            runner: Runner::new(payload)?,
+        })
+    }
+}
+
+// Every option can also be set with a WATCH_SCRIPTS_*
+// environment variable named after it (--then is
+// WATCH_SCRIPTS_THEN, --no-clear is
+// WATCH_SCRIPTS_NO_CLEAR, etc.). The command line wins
+// over the environment which wins over the config file.
+fn with_env_var(arg: clap::Arg) -> clap::Arg {
+    match arg.get_long() {
+        Some("help" | "version") | None => arg,
+        Some(long) => {
+            let name = format!("WATCH_SCRIPTS_{}", long.to_uppercase().replace('-', "_"));
+            // so flags can be turned on with 1, yes, on, etc.
+            if matches!(arg.get_action(), ArgAction::SetTrue) {
+                arg.env(name)
+                    .value_parser(clap::builder::BoolishValueParser::new())
+            } else {
+                arg.env(name)
+            }
+        }
+    }
+}
+
+// The value of an option if it was given on the command
+// line or through its environment variable so the config
+// file only fills in what's left
+fn from_cli_or_env<'a>(matches: &'a ArgMatches, id: &str) -> Option<&'a String> {
+    match matches.value_source(id) {
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable) => matches.get_one::<String>(id),
+        _ => None,
+    }
+}
+
+// --nix's flake, the closest one at or above the
+// script directory
+fn find_flake(dir: &Path) -> Result<PathBuf> {
+    dir.ancestors()
+        .find(|dir| dir.join("flake.nix").is_file())
+        .map(Path::to_path_buf)
+        .ok_or_else(|| anyhow!("--nix: there's no flake.nix in {} or above", dir.display()))
+}
+
+// --project-origin auto's root. The closest marker wins
+// so a config in a subproject of a bigger repo gets
+// used over the repo itself. It stays where it started
+// when there's neither.
+fn project_root(dir: &Path) -> PathBuf {
+    dir.ancestors()
+        .find(|dir| dir.join(config::CONFIG_NAME).is_file() || dir.join(".git").exists())
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+// The executables right in a --then directory in
+// lexical order. Hidden files and editor backups are
+// left out. It's read for every run so hooks can be
+// added and removed while watching.
+fn then_hooks(dir: &Path) -> Vec<PathBuf> {
+    let mut hooks: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| !scripts::is_hidden(Path::new(&entry.file_name())))
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && is_executable(path).unwrap_or(false))
+        .collect();
+    hooks.sort();
+    hooks
+}
+
+// Scripts given on the command line (--then, --before)
+// have to be there and executable when starting up
+fn check_script_arg(initial_dir: &Path, path: &Path) -> Result<PathBuf> {
+    let path = initial_dir.join(path);
+    if !path.exists() {
+        return Err(anyhow!("{} does not exist", path.display()));
+    }
+    match is_executable(&path) {
+        Ok(check) => {
+            if !check {
+                return Err(anyhow!("{} is not executable", path.display()));
+            }
+        }
+        Err(_) => {
+            return Err(anyhow!(
+                "Could not determine permissions for {} ",
+                path.display()
+            ));
+        }
+    }
+    Ok(fs::canonicalize(path)?)
+}
+
+// For things that go wrong with a single run (a path that
+// can't be resolved, a directory that went away, etc.).
+// They get shown and the watcher keeps going. Under
+// --daemon stderr is the log file so they end up
+// there too.
+pub(crate) fn report_error(message: impl std::fmt::Display) {
+    eprintln!("\x1b[31mERROR: {}\x1b[0m", message);
+}
+
+fn report_pause(session: &Session) {
+    if session.paused {
+        println!(
+            "\x1b[33m⏸ Paused. Changes are ignored until resumed (SIGUSR2 or the pause key)\x1b[0m"
+        );
+    } else {
+        println!(
+            "\x1b[32m▶ Resumed watching ({} changes ignored while paused)\x1b[0m",
+            session.ignored_while_paused
+        );
+    }
+}
+
+// The parts of the payload that come from the config
+// file (along with any --plugin paths since those get
+// loaded together with the ones from the config).
+fn load_config(
+    config_path: Option<&PathBuf>,
+    plugin_args: &[PathBuf],
+    dir: &Path,
+    tasks: bool,
+) -> Result<(Config, Filters, Option<Plugins>, Rules)> {
+    let config = Config::load(config_path, dir)?;
+    let plugin_paths: Vec<PathBuf> = config
+        .plugins
+        .iter()
+        .chain(plugin_args)
+        .map(|path| dir.join(path))
+        .collect();
+    let plugins = if plugin_paths.is_empty() {
+        None
+    } else {
+        Some(Plugins::load(&plugin_paths)?)
+    };
+    let mut filters = Filters::from_config(
+        config
+            .filters
+            .as_deref()
+            .unwrap_or(&filters::default_filters(tasks)),
+        dir,
+    )?;
+    filters.push(Arc::new(GitignoreFilter::wsignore(dir)));
+    let rules = Rules::new(&config.rules, dir)?;
+    schedule::validate(&config.schedules)?;
+    Ok((config, filters, plugins, rules))
+}
+
+// --problems matchers are tried before the config's
+fn problem_matchers(config: &Config, args: &[String]) -> Result<Option<ProblemMatchers>> {
+    let names: Vec<String> = args.iter().chain(&config.problems).cloned().collect();
+    ProblemMatchers::new(&names)
+}
+
+// With --jobs each script in a set that runs together
+// gets its own capture with its name in front of its
+// lines
+fn job_output(output: &OutputCapture, payload: &Payload, script: &Path) -> OutputCapture {
+    if payload.jobs > 1 {
+        let label = match &payload.group {
+            Some(group) => format!("{} {}", group, script.display()),
+            None => script.display().to_string(),
+        };
+        output.for_job(format!("\x1b[2m[{}]\x1b[0m ", label))
+    } else {
+        output.clone()
+    }
+}
+
+// The paths from content changes and manual triggers in
+// an action. One save usually shows up as several events
+// for the same file so each path is only listed once
+// (with a manual trigger winning over a change since
+// it skips the filters). Order is kept so the first
+// path that changed is still the one that runs.
+fn changed_paths<'a>(events: &'a [Event], kinds: &TriggerKinds) -> Vec<(&'a Event, &'a Path)> {
+    let mut paths: Vec<(&Event, &Path)> = vec![];
+    for event in events {
+        let manual = trigger::is_manual(event);
+        if !manual && !trigger::is_released(event) && !kinds.matches(event) {
+            continue;
+        }
+        let event_paths = event.tags.iter().filter_map(|tag| match tag {
+            Tag::Path { path, .. } => Some(path.as_path()),
+            _ => None,
+        });
+        // a released batch has all of its scripts in one
+        // event
+        let event_paths: Vec<&Path> = match trigger::is_released(event) {
+            true => event_paths.collect(),
+            false => event_paths.take(1).collect(),
+        };
+        for path in event_paths {
+            match paths.iter_mut().find(|(_, seen)| *seen == path) {
+                Some(entry) if manual => entry.0 = event,
+                Some(_) => {}
+                None => paths.push((event, path)),
+            }
+        }
+    }
+    paths
+}
+
+// What a batch of events turned into
+struct Triggered {
+    command: Arc<WatchCommand>,
+    // where the script gets run from
+    dir: Option<PathBuf>,
+    // false when the script is the then script itself
+    // so it doesn't get run twice
+    run_then: bool,
+    script: PathBuf,
+    // the file that changed (the script itself unless a
+    // rule matched or it was asked for by name)
+    trigger_path: PathBuf,
+    // with the script's own settings merged in
+    payload: Payload,
+}
+
+// Just the first script the batch of changes calls for
+// unless --jobs lets more than one run at a time in
+// which case it's all of them.
+fn get_commands(
+    events: &Arc<[Event]>,
+    payload: &Payload,
+    inventory: &Inventory,
+    tracked: Option<&Mutex<TrackedFiles>>,
+    hashes: Option<&ContentHashes>,
+    known: Option<&KnownScripts>,
+    sourced: Option<&Sourced>,
+) -> Vec<Triggered> {
+    // only checked once everything else has let the file
+    // through so files that were never going to run don't
+    // get read
+    let unchanged = |event: &Event, path: &Path| {
+        // other --events kinds don't change what's in it
+        let unchanged =
+            trigger::is_write(event) && hashes.is_some_and(|hashes| !hashes.changed(path));
+        if unchanged {
+            payload.hooks.filtered(path, FilterReason::Unchanged);
+        }
+        unchanged
+    };
+    // what a script has to get through to be set off by
+    // a change (to itself or a file it sources). A script
+    // --auto-chmod is about to fix skips executable-only.
+    let passes = |event: &Event, path: &Path, chmod: bool| {
+        let hooks = &payload.hooks;
+        // files run through --exec-map don't need to be
+        // executable
+        let rejected_by = match chmod || payload.exec_map.handles(path) {
+            true => payload
+                .filters
+                .rejected_by_except(event, path, "executable-only"),
+            false => payload.filters.rejected_by(event, path),
+        };
+        if let Some(name) = rejected_by {
+            hooks.filtered(path, FilterReason::Filter(name));
+            return false;
+        }
+        if !payload.groups.is_empty() && payload.groups.group_for(path).is_none() {
+            hooks.filtered(path, FilterReason::Filter("groups"));
+            return false;
+        }
+        if !payload.has_tags(path) {
+            hooks.filtered(path, FilterReason::Filter("tags"));
+            return false;
+        }
+        if let Some(tracked) = tracked
+            && !tracked.lock().unwrap().contains(path)
+        {
+            hooks.filtered(path, FilterReason::Untracked);
+            return false;
+        }
+        if let Some(plugins) = &payload.plugins
+            && !plugins.should_trigger(path)
+        {
+            hooks.filtered(path, FilterReason::Plugin);
+            return false;
+        }
+        true
+    };
+    let pick = |(event, path): (&Event, &Path)| {
+        let hooks = &payload.hooks;
+        // asking for a script by name skips the
+        // filters and works whether or not git knows
+        // about it
+        if trigger::is_manual(event) {
+            if let Some(task) = &payload.task {
+                return vec![(task.file().to_path_buf(), path.to_path_buf())];
+            }
+            if !inventory.contains(path) && !payload.can_run(path) {
+                hooks.filtered(path, FilterReason::NotRunnable);
+                return vec![];
+            }
+            // running it by name is what --confirm-new
+            // asks for
+            if let Some(known) = known {
+                known.allow(path);
+            }
+            return vec![(path.to_path_buf(), path.to_path_buf())];
+        }
+        // rules are for files that aren't scripts so they
+        // don't go through the filters either
+        if let Some(script) = payload.rules.script_for(path) {
+            if unchanged(event, path) {
+                return vec![];
+            }
+            return vec![(script.to_path_buf(), path.to_path_buf())];
+        }
+        // a file that scripts source (see sourced::Sourced)
+        // runs every one of them that would run on its own
+        if let Some(sourced) = sourced
+            && !payload.can_run(path)
+        {
+            let scripts: Vec<(PathBuf, PathBuf)> = sourced
+                .scripts_sourcing(path, inventory)
+                .into_iter()
+                .filter(|script| passes(event, script, false))
+                .filter(|script| known.is_none_or(|known| known.contains(script)))
+                .map(|script| (script, path.to_path_buf()))
+                .collect();
+            if !scripts.is_empty() {
+                return if unchanged(event, path) {
+                    vec![]
+                } else {
+                    scripts
+                };
+            }
+        }
+        // --auto-chmod waits until everything else has let
+        // the script through so it doesn't touch files that
+        // weren't going to run anyway
+        let chmod = payload.auto_chmod && payload.task.is_none() && scripts::needs_chmod(path);
+        if !passes(event, path, chmod) {
+            return vec![];
+        }
+        if unchanged(event, path) {
+            return vec![];
+        }
+        if let Some(task) = &payload.task {
+            return vec![(task.file().to_path_buf(), path.to_path_buf())];
+        }
+        if let Some(known) = known
+            && !known.contains(path)
+        {
+            hooks.filtered(path, FilterReason::New);
+            report_new(path, payload);
+            return vec![];
+        }
+        if chmod {
+            match scripts::make_executable(path) {
+                Ok(()) => println!(
+                    "\x1b[33mMade {} executable (it has a shebang)\x1b[0m",
+                    payload.relative_path(path).display()
+                ),
+                Err(e) => report_error(format!(
+                    "could not make {} executable: {}",
+                    payload.relative_path(path).display(),
+                    e
+                )),
+            }
+            // only executable-only could have changed its
+            // mind
+            if payload.filters.rejected_by(event, path) == Some("executable-only") {
+                hooks.filtered(path, FilterReason::Filter("executable-only"));
+                return vec![];
+            }
+        }
+        vec![(path.to_path_buf(), path.to_path_buf())]
+    };
+    let mut changed = changed_paths(events, &payload.trigger_kinds);
+    payload.run_order.sort_changed(&mut changed);
+    let changed = changed.into_iter();
+    let picked: Vec<(PathBuf, PathBuf)> = if payload.jobs > 1 {
+        changed.flat_map(pick).collect()
+    } else {
+        changed
+            .map(pick)
+            .find(|picked| !picked.is_empty())
+            .unwrap_or_default()
+    };
+    let manual = events.iter().any(trigger::is_manual);
+    picked
+        .into_iter()
+        .unique_by(|(script, _)| script.clone())
+        .filter(|(script, trigger_path)| {
+            if manual {
+                return true;
+            }
+            let Some(writer) = payload.loop_guard.written_by(trigger_path) else {
+                return true;
+            };
+            payload.hooks.filtered(trigger_path, FilterReason::Loop);
+            println!(
+                "\x1b[2mSkipped {}: {} was written by {}\x1b[0m",
+                payload.relative_path(script).display(),
+                payload.relative_path(trigger_path).display(),
+                payload.relative_path(&writer).display()
+            );
+            false
+        })
+        .filter_map(|(script, trigger_path)| triggered(script, trigger_path, payload))
+        .filter(|triggered| {
+            let cooldown = triggered.payload.config.scripts.cooldown;
+            let Some(left) = payload
+                .cooldowns
+                .hold_back(&triggered.script, cooldown, manual)
+            else {
+                return true;
+            };
+            payload
+                .hooks
+                .filtered(&triggered.trigger_path, FilterReason::Cooldown);
+            println!(
+                "\x1b[2mSkipped {}: it's cooling down for another {}\x1b[0m",
+                payload.relative_path(&triggered.script).display(),
+                run::format_duration(&left)
+            );
+            false
+        })
+        .collect()
+}
+
+// Lists the runs --journal found cut off and hands back
+// the scripts (not then scripts) that can run again.
+// Only scripts in the watch root's inventory count since
+// the journal could have come from anywhere.
+fn report_interrupted(
+    interrupted: &[audit::Unfinished],
+    payload: &Payload,
+    inventory: &Inventory,
+) -> Vec<PathBuf> {
+    if interrupted.is_empty() {
+        return vec![];
+    }
+    println!("\x1b[33mThese were still running when the last watcher went down:\x1b[0m");
+    for run in interrupted {
+        let started = chrono::DateTime::parse_from_rfc3339(&run.time)
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|_| run.time.clone());
+        println!(
+            "  \x1b[33m{}\x1b[0m \x1b[2mstarted {} as `{}` in {}\x1b[0m",
+            run.script
+                .as_deref()
+                .map(|script| payload.relative_path(script))
+                .unwrap_or_default()
+                .display(),
+            started,
+            run.argv.join(" "),
+            run.cwd
+                .as_ref()
+                .map(|cwd| cwd.display().to_string())
+                .unwrap_or_default()
+        );
+    }
+    interrupted
+        .iter()
+        .filter_map(|run| fs::canonicalize(run.script.as_ref()?).ok())
+        .filter(|script| script.starts_with(inventory.root()) && inventory.contains(script))
+        .filter(|script| !payload.is_then(script))
+        .unique()
+        .collect()
+}
+
+fn report_new(script: &Path, payload: &Payload) {
+    let script = payload.relative_path(script);
+    println!(
+        "\x1b[33mSkipped {0}: it hasn't run before (--confirm-new). Run it by name once to allow it: ws trigger {0}\x1b[0m",
+        script.display()
+    );
+}
+
+fn triggered(script: PathBuf, trigger_path: PathBuf, payload: &Payload) -> Option<Triggered> {
+    let full_path = match fs::canonicalize(&script) {
+        Ok(full_path) => full_path,
+        Err(e) => {
+            report_error(format!("could not resolve {}: {}", script.display(), e));
+            return None;
+        }
+    };
+    let payload = payload.for_script(&full_path);
+    let run_path = payload.run_path(&script);
+    let (command, dir) = match &payload.task {
+        Some(task) => (
+            shell_command(task.command(&payload.shell), &payload),
+            payload.initial_dir.clone(),
+        ),
+        // running a --then directory by itself (the then
+        // key) runs its hooks
+        None if full_path.is_dir() && payload.raw_then_path.as_ref() == Some(&full_path) => (
+            payload.then_job()?,
+            full_path.parent().map(Path::to_path_buf),
+        ),
+        None => (
+            script_command(&run_path, &payload)?,
+            payload.script_dir(&run_path),
+        ),
+    };
+    Some(Triggered {
+        command,
+        dir,
+        run_then: payload.raw_then_path.is_some() && !payload.is_then(&full_path),
+        script,
+        trigger_path,
+        payload,
+    })
+}
+
+// Scripts are run by name (or through their --exec-map
+// command) through the shell with any args from their
+// settings (after plugin rewrites)
+fn script_command(path: &Path, payload: &Payload) -> Option<Arc<WatchCommand>> {
+    // scripts run from the root get called by their path
+    // from there instead
+    let mut command = match payload.config.scripts.cwd {
+        _ if payload.exec_map.handles(path) => {
+            payload.exec_map.command_for(path, &payload.shell)?
+        }
+        Some(CwdPolicy::Root) => match payload
+            .initial_dir
+            .as_ref()
+            .and_then(|dir| path.strip_prefix(dir).ok())
+        {
+            Some(relative) => payload
+                .shell
+                .quote(&format!("./{}", relative.to_string_lossy())),
+            None => payload.shell.quote(&path.to_string_lossy()),
+        },
+        _ => payload
+            .shell
+            .quote(&format!("./{}", path.file_name()?.to_string_lossy())),
+    };
+    // the config's args and then the ones after `--`
+    let args = payload
+        .config
+        .scripts
+        .args
+        .iter()
+        .flatten()
+        .chain(&payload.script_args);
+    // --xtrace starts shell scripts with the shell from
+    // their #! line and -x in front of them
+    let traced = match payload.xtrace && !payload.exec_map.handles(path) {
+        true => {
+            let traced = shell::traceable(path);
+            if traced.is_none() {
+                println!(
+                    "\x1b[2mNot tracing {} since it isn't a shell script\x1b[0m",
+                    path.display()
+                );
+            }
+            traced
+        }
+        false => None,
+    };
+    // with --no-shell there's nothing to quote for
+    if !payload.through_shell && !payload.exec_map.handles(path) {
+        let program = match traced {
+            Some(mut words) => Program::Exec {
+                prog: PathBuf::from(words.remove(0)),
+                args: words
+                    .into_iter()
+                    .chain(["-x".to_string(), path.to_string_lossy().to_string()])
+                    .chain(args.cloned())
+                    .collect(),
+            },
+            None => Program::Exec {
+                prog: path.to_path_buf(),
+                args: args.cloned().collect(),
+            },
+        };
+        return Some(Arc::new(WatchCommand {
+            program,
+            options: run::spawn_options(),
+        }));
+    }
+    if let Some(words) = traced {
+        let words: Vec<String> = words.iter().map(|word| payload.shell.quote(word)).collect();
+        command = format!("{} -x {}", words.join(" "), command);
+    }
+    for arg in args {
+        command.push(' ');
+        command.push_str(&payload.shell.quote(arg));
+    }
+    Some(shell_command(command, payload))
+}
+
+fn shell_command(command: String, payload: &Payload) -> Arc<WatchCommand> {
+    Arc::new(WatchCommand {
+        program: Program::Shell {
+            shell: payload.shell.program(),
+            command: payload.wrap_command(match &payload.plugins {
+                Some(plugins) => plugins.rewrite_command(command),
+                None => command,
+            }),
+            args: vec![],
+        },
+        options: run::spawn_options(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use watchexec_events::filekind::{DataChange, FileEventKind, ModifyKind};
+
+    type Filtered = Arc<Mutex<Vec<(PathBuf, FilterReason)>>>;
+
+    // Turns down scripts with `skip` in their names
+    struct SkipNamed;
+
+    impl EventFilter for SkipNamed {
+        fn name(&self) -> &'static str {
+            "skip-named"
+        }
+
+        fn allows(&self, _event: &Event, path: &Path) -> bool {
+            !path.to_string_lossy().contains("skip")
+        }
+    }
+
+    // A watch root of its own with the files given
+    // (executable or not) and a config file if there is one
+    fn root(name: &str, files: &[(&str, bool)], config: Option<&str>) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("ws-commands-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        for (file, executable) in files {
+            let path = root.join(file);
+            fs::write(&path, "#!/bin/bash\n").unwrap();
+            let mode = if *executable { 0o755 } else { 0o644 };
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+        if let Some(config) = config {
+            fs::write(root.join(config::CONFIG_NAME), config).unwrap();
+        }
+        root
+    }
+
+    fn payload(root: &Path, args: &[&str]) -> (Payload, Filtered) {
+        let filtered: Filtered = Arc::default();
+        let record = Arc::clone(&filtered);
+        let watcher = WatchScripts::builder()
+            .watch(root)
+            .args(args.iter().copied())
+            .filter(SkipNamed)
+            .on_filtered(move |path, reason| {
+                record.lock().unwrap().push((path.to_path_buf(), reason));
+            })
+            .build()
+            .unwrap();
+        (watcher.payload().clone(), filtered)
+    }
+
+    fn changed(path: PathBuf) -> Event {
+        Event {
+            tags: vec![
+                Tag::Source(Source::Filesystem),
+                Tag::Path {
+                    path,
+                    file_type: None,
+                },
+                Tag::FileEventKind(FileEventKind::Modify(ModifyKind::Data(DataChange::Content))),
+            ],
+            metadata: Default::default(),
+        }
+    }
+
+    fn commands(root: &Path, payload: &Payload, events: Vec<Event>) -> Vec<PathBuf> {
+        let events: Arc<[Event]> = events.into();
+        let inventory = Inventory::scan(root);
+        get_commands(&events, payload, &inventory, None, None, None, None)
+            .into_iter()
+            .map(|triggered| triggered.script)
+            .collect()
+    }
+
+    fn names(root: &Path, filtered: &Filtered) -> Vec<(String, FilterReason)> {
+        filtered
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(path, reason)| {
+                let name = path.strip_prefix(root).unwrap_or(path);
+                (name.display().to_string(), *reason)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_first_filter_that_says_no_is_the_reason() {
+        let root = root(
+            "order",
+            &[
+                ("run.sh", true),
+                ("notes.txt", false),
+                (".hidden.sh", true),
+                (".notes", false),
+                ("skip.sh", true),
+            ],
+            None,
+        );
+        let (payload, filtered) = payload(&root, &["--jobs", "5"]);
+        let events = ["run.sh", "notes.txt", ".hidden.sh", ".notes", "skip.sh"]
+            .map(|name| changed(root.join(name)))
+            .to_vec();
+        assert_eq!(commands(&root, &payload, events), [root.join("run.sh")]);
+        // sorted by path before they're checked
+        assert_eq!(
+            names(&root, &filtered),
+            [
+                (
+                    ".hidden.sh".to_string(),
+                    FilterReason::Filter("no-dotfiles")
+                ),
+                (
+                    ".notes".to_string(),
+                    FilterReason::Filter("executable-only")
+                ),
+                (
+                    "notes.txt".to_string(),
+                    FilterReason::Filter("executable-only")
+                ),
+                ("skip.sh".to_string(), FilterReason::Filter("skip-named")),
+            ]
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn the_config_decides_the_order() {
+        let config =
+            "[[filters]]\nkind = \"no-dotfiles\"\n\n[[filters]]\nkind = \"executable-only\"\n";
+        let root = root("config-order", &[(".notes", false)], Some(config));
+        let (payload, filtered) = payload(&root, &[]);
+        let events = vec![changed(root.join(".notes"))];
+        assert!(commands(&root, &payload, events).is_empty());
+        assert_eq!(
+            names(&root, &filtered),
+            [(".notes".to_string(), FilterReason::Filter("no-dotfiles"))]
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn scripts_asked_for_by_name_skip_the_filters() {
+        let root = root("manual", &[("skip.sh", true), ("notes.txt", false)], None);
+        let (payload, filtered) = payload(&root, &[]);
+        let manual = |name: &str| trigger::manual_event(&root.join(name), Source::Internal);
+        assert_eq!(
+            commands(&root, &payload, vec![manual("skip.sh")]),
+            [root.join("skip.sh")]
+        );
+        assert!(filtered.lock().unwrap().is_empty());
+        assert!(commands(&root, &payload, vec![manual("notes.txt")]).is_empty());
+        assert_eq!(
+            names(&root, &filtered),
+            [("notes.txt".to_string(), FilterReason::NotRunnable)]
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn without_jobs_only_the_first_script_that_passes_runs() {
+        let root = root(
+            "first",
+            &[("b.sh", true), ("a.txt", false), ("c.sh", true)],
+            None,
+        );
+        let (payload, filtered) = payload(&root, &[]);
+        let events = ["c.sh", "b.sh", "a.txt"]
+            .map(|name| changed(root.join(name)))
+            .to_vec();
+        assert_eq!(commands(&root, &payload, events), [root.join("b.sh")]);
+        // c.sh never got looked at
+        assert_eq!(
+            names(&root, &filtered),
+            [("a.txt".to_string(), FilterReason::Filter("executable-only"))]
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::Payload;
+use crate::config::HooksConfig;
+use crate::placeholders;
+use crate::run;
+use crate::stream::RunStream;
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use tokio::sync::watch;
+
+// The config's [hooks] are commands run from the watch
+// root through bash as runs start and finish and when
+// the watcher quits:
+//
+//   [hooks]
+//   on_start = "tmux set status-style bg=yellow"
+//   on_success = "notify-send {script} passed"
+//   on_failure = "./collect-logs.sh {script} {exit}"
+//   on_cancel = "echo {script} was replaced"
+//   on_quit = "docker compose down"
+//
+// The run ones can use the placeholders (and get the
+// variables) from placeholders.rs for the run. Skipped
+// runs don't set any of them off. They run alongside the watcher without
+// holding up the next run, except on_quit which gets to
+// finish before it exits.
+pub fn follow(config: watch::Receiver<HooksConfig>, payload: Payload, stream: &RunStream) {
+    let mut events = stream.follow(false);
+    tokio::spawn(async move {
+        while let Some(message) = events.next().await {
+            let run = &message["data"];
+            // read fresh each time since a SIGHUP can
+            // reload it
+            let hooks = config.borrow().clone();
+            let hook = match (message["type"].as_str(), run["success"].as_bool()) {
+                (Some("run_started"), _) => hooks.on_start.map(|hook| ("on_start", hook)),
+                (Some("run_finished"), Some(true)) => {
+                    hooks.on_success.map(|hook| ("on_success", hook))
+                }
+                (Some("run_finished"), Some(false)) => {
+                    hooks.on_failure.map(|hook| ("on_failure", hook))
+                }
+                (Some("run_cancelled"), _) => hooks.on_cancel.map(|hook| ("on_cancel", hook)),
+                (Some("run_finished"), None) if run["status"] == "cancelled" => {
+                    hooks.on_cancel.map(|hook| ("on_cancel", hook))
+                }
+                _ => None,
+            };
+            if let Some((name, hook)) = hook {
+                let vars = placeholders::from_run(run);
+                let payload = payload.clone();
+                tokio::spawn(async move { run_hook(name, &hook, vars, &payload).await });
+            }
+        }
+    });
+}
+
+pub async fn on_quit(config: &watch::Receiver<HooksConfig>, payload: &Payload) {
+    let hook = config.borrow().on_quit.clone();
+    if let Some(hook) = hook {
+        run_hook("on_quit", &hook, BTreeMap::new(), payload).await;
+    }
+}
+
+// Output goes to the terminal along with the scripts'.
+// Only a hook that fails gets a note.
+async fn run_hook(name: &str, hook: &str, vars: BTreeMap<&'static str, String>, payload: &Payload) {
+    let mut command = payload.shell.command(&placeholders::expand(hook));
+    command.envs(vars).stdin(Stdio::null());
+    if let Some(dir) = &payload.initial_dir {
+        command.current_dir(dir);
+    }
+    let problem = match run::audited(&mut command, payload).await {
+        Ok(status) if status.success() => return,
+        Ok(status) => match status.code() {
+            Some(code) => format!("exited {}", code),
+            None => "was killed".to_string(),
+        },
+        Err(e) => format!("could not run: {}", e),
+    };
+    println!("\x1b[33mThe {} hook `{}` {}\x1b[0m", name, hook, problem);
+}
@@ -0,0 +1,130 @@
+use crate::config::Patterns;
+use anyhow::{Result, anyhow};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as RunLock, OwnedMutexGuard};
+
+// A script never runs alongside another run of itself
+// (a newer run waits for the one it replaced to be
+// gone) and scripts in the same config lock group wait
+// for each other too:
+//
+//   [locks]
+//   database = ["db/*.sh", "migrate.sh"]
+//   port-3000 = "server*.sh"
+//
+// The globs are matched against the script's path
+// relative to the watch root like the rules' are.
+//
+// A run that's still waiting when a newer one for the
+// same script comes along gets dropped so a burst of
+// saves behind a long run (with --queue or a busy
+// group) ends up as one more run instead of a backlog.
+#[derive(Debug, Clone, Default)]
+pub struct Locks {
+    groups: Vec<(String, GlobSet)>,
+    // one for each script and group that's had a run
+    held: Arc<Mutex<HashMap<String, Arc<RunLock<()>>>>>,
+    root: PathBuf,
+    tickets: Arc<Mutex<Tickets>>,
+}
+
+#[derive(Debug, Default)]
+struct Tickets {
+    issued: usize,
+    // the newest run that's asked for each script
+    latest: HashMap<PathBuf, usize>,
+}
+
+impl Locks {
+    pub fn new(config: &BTreeMap<String, Patterns>, root: &Path) -> Result<Locks> {
+        let mut groups = vec![];
+        for (name, patterns) in config {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in patterns.patterns() {
+                builder.add(Glob::new(pattern).map_err(|e| {
+                    anyhow!("invalid glob `{}` in the `{}` lock: {}", pattern, name, e)
+                })?);
+            }
+            groups.push((name.clone(), builder.build()?));
+        }
+        Ok(Locks {
+            groups,
+            held: Arc::default(),
+            root: root.to_path_buf(),
+            tickets: Arc::default(),
+        })
+    }
+
+    // New groups from a reloaded config. Runs that are
+    // going keep what they're holding.
+    pub fn reloaded(&self, config: &BTreeMap<String, Patterns>) -> Result<Locks> {
+        let mut locks = Locks::new(config, &self.root)?;
+        locks.held = Arc::clone(&self.held);
+        locks.tickets = Arc::clone(&self.tickets);
+        Ok(locks)
+    }
+
+    // Waits until the script and all of its groups are
+    // free. They're taken in the same order every time
+    // so two runs can't end up waiting on each other.
+    // The run holds on to them until it drops them.
+    // Returns None if a newer run of the script came in
+    // while it was waiting. With `alongside` it doesn't
+    // wait for other runs of the script itself (for
+    // --health-rollback where the new version starts
+    // while the old one's still up).
+    pub async fn acquire(
+        &self,
+        script: &Path,
+        label: &Path,
+        alongside: bool,
+    ) -> Option<Vec<OwnedMutexGuard<()>>> {
+        let ticket = {
+            let mut tickets = self.tickets.lock().unwrap();
+            tickets.issued += 1;
+            let ticket = tickets.issued;
+            tickets.latest.insert(script.to_path_buf(), ticket);
+            ticket
+        };
+        let relative = script.strip_prefix(&self.root).unwrap_or(script);
+        let mut names = vec![];
+        if !alongside {
+            names.push(format!("script:{}", script.display()));
+        }
+        for (name, globs) in &self.groups {
+            if globs.is_match(relative) {
+                names.push(format!("group:{}", name));
+            }
+        }
+        names.sort();
+        let mut guards = vec![];
+        for name in names {
+            let lock = Arc::clone(self.held.lock().unwrap().entry(name.clone()).or_default());
+            let guard = match lock.clone().try_lock_owned() {
+                Ok(guard) => guard,
+                Err(_) => {
+                    // the wait for a replaced run is usually
+                    // just it getting stopped so only groups
+                    // get a mention
+                    if let Some(group) = name.strip_prefix("group:") {
+                        println!(
+                            "\x1b[2m{} is waiting for the `{}` lock\x1b[0m",
+                            label.display(),
+                            group
+                        );
+                    }
+                    lock.lock_owned().await
+                }
+            };
+            guards.push(guard);
+        }
+        let superseded = self.tickets.lock().unwrap().latest.get(script) != Some(&ticket);
+        if superseded {
+            return None;
+        }
+        Some(guards)
+    }
+}
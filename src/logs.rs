@@ -0,0 +1,118 @@
+use crate::history;
+use crate::record;
+use crate::run::format_duration;
+use crate::session::RunInfo;
+use anyhow::Result;
+use chrono::DateTime;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// How often --follow looks for new runs. It also gives
+// a recording that's still being finished a moment to
+// get the last of the output.
+const FOLLOW_POLL: Duration = Duration::from_millis(500);
+
+// `ws logs` shows the runs in the --keep-history file
+// (just the ones for `script` when it's given) with
+// their output from the --record dir when there's a
+// recording of them. --follow keeps showing runs as
+// they finish until it's stopped.
+pub struct Logs {
+    pub follow: bool,
+    pub history: PathBuf,
+    pub last: usize,
+    pub record: Option<PathBuf>,
+    pub script: Option<PathBuf>,
+}
+
+impl Logs {
+    pub fn show(&self) -> Result<()> {
+        let runs = match self.runs() {
+            Ok(runs) => runs,
+            // the history file isn't there until the first
+            // run finishes
+            Err(_) if self.follow => vec![],
+            Err(e) => return Err(e),
+        };
+        for run in runs.iter().skip(runs.len().saturating_sub(self.last)) {
+            self.print(run);
+        }
+        if !self.follow {
+            return Ok(());
+        }
+        // the history file gets rotated so where it left
+        // off goes by the last run it showed instead of a
+        // count
+        let mut shown = runs.last().map(key);
+        loop {
+            std::thread::sleep(FOLLOW_POLL);
+            let Ok(runs) = self.runs() else {
+                continue;
+            };
+            let new = match &shown {
+                Some(shown) => runs
+                    .iter()
+                    .rposition(|run| key(run) == *shown)
+                    .map_or(0, |n| n + 1),
+                None => 0,
+            };
+            for run in &runs[new..] {
+                self.print(run);
+            }
+            if let Some(run) = runs.last() {
+                shown = Some(key(run));
+            }
+        }
+    }
+
+    fn runs(&self) -> Result<Vec<RunInfo>> {
+        Ok(history::load(&self.history)?
+            .into_iter()
+            .filter(|run| {
+                self.script
+                    .as_ref()
+                    .is_none_or(|script| run.script.ends_with(script))
+            })
+            .collect())
+    }
+
+    fn print(&self, run: &RunInfo) {
+        let started = DateTime::parse_from_rfc3339(&run.started_at).ok();
+        let color = match run.success {
+            Some(true) => "32",
+            Some(false) => "31",
+            None => "33",
+        };
+        println!(
+            "\x1b[2m{}\x1b[0m {} \x1b[{}m{}\x1b[0m{}",
+            started
+                .map(|started| started.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| run.started_at.clone()),
+            run.script.display(),
+            color,
+            run.status.as_deref().unwrap_or("unknown"),
+            run.duration_ms
+                .map(|ms| format!(" in {}", format_duration(&Duration::from_millis(ms as u64))))
+                .unwrap_or_default(),
+        );
+        let recording = self
+            .record
+            .as_ref()
+            .zip(run.recording.as_ref())
+            .and_then(|(dir, id)| record::find(dir, id));
+        if let Some(text) = recording.and_then(|path| record::output(&path).ok()) {
+            print!("{}", text);
+            if !text.is_empty() && !text.ends_with('\n') {
+                println!();
+            }
+        } else {
+            for line in &run.output_tail {
+                println!("{}", line);
+            }
+        }
+    }
+}
+
+fn key(run: &RunInfo) -> (String, usize) {
+    (run.started_at.clone(), run.run_number)
+}
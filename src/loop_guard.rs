@@ -0,0 +1,135 @@
+use crate::filters::build_globs;
+use crate::report_error;
+use globset::GlobSet;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How long after a run finishes its writes still count
+// as its own. Events can show up a bit after the fact
+// (and a poll later with --poll).
+const GRACE: Duration = Duration::from_secs(1);
+
+// Keeps a script that writes into the watched tree from
+// setting itself (or another script) off over and over.
+// While a script is running, and for a moment after,
+// changes to the files it writes don't trigger anything.
+// Those come from the `outputs` globs in `[scripts]` or
+// its sidecar:
+//
+//   # build.sh.ws.toml
+//   outputs = ["dist/**", "build.log"]
+//
+// and with --loop-guard from what it actually wrote the
+// last time it ran (a before and after look at the
+// watch root like --touched takes). That look walks the
+// whole tree before and after each run so it adds to
+// every run on a big root. Running a script by name
+// still works either way.
+#[derive(Debug, Clone)]
+pub struct LoopGuard {
+    grace: Duration,
+    // --loop-guard
+    learn: bool,
+    root: PathBuf,
+    state: Arc<Mutex<GuardState>>,
+}
+
+#[derive(Debug, Default)]
+struct GuardState {
+    // scripts that are running (finished is None) or
+    // finished within the grace
+    active: HashMap<PathBuf, Active>,
+    // what each script wrote the last time it ran
+    written: HashMap<PathBuf, BTreeSet<PathBuf>>,
+}
+
+#[derive(Debug)]
+struct Active {
+    finished: Option<Instant>,
+    outputs: Option<GlobSet>,
+    runs: usize,
+}
+
+impl LoopGuard {
+    pub fn new(learn: bool, root: &Path, poll: Option<Duration>) -> LoopGuard {
+        LoopGuard {
+            grace: GRACE + poll.unwrap_or_default(),
+            learn,
+            root: root.to_path_buf(),
+            state: Arc::default(),
+        }
+    }
+
+    // Whether runs need a snapshot for it
+    pub fn learns(&self) -> bool {
+        self.learn
+    }
+
+    pub fn started(&self, script: &Path, outputs: Option<&Vec<String>>) {
+        let outputs = outputs.and_then(|patterns| match build_globs(patterns) {
+            Ok(globs) => Some(globs),
+            Err(e) => {
+                report_error(format!("outputs for {}: {}", script.display(), e));
+                None
+            }
+        });
+        let mut state = self.state.lock().unwrap();
+        let active = state.active.entry(script.to_path_buf()).or_insert(Active {
+            finished: None,
+            outputs: None,
+            runs: 0,
+        });
+        active.finished = None;
+        active.outputs = outputs;
+        active.runs += 1;
+    }
+
+    // The files from a --loop-guard snapshot. They take
+    // the place of the last run's so something the script
+    // stopped writing (or that only changed alongside it
+    // once) doesn't stay muted.
+    pub fn wrote(&self, script: &Path, files: impl Iterator<Item = PathBuf>) {
+        self.state
+            .lock()
+            .unwrap()
+            .written
+            .insert(script.to_path_buf(), files.collect());
+    }
+
+    // --jobs can have more than one run of a script going
+    pub fn finished(&self, script: &Path) {
+        if let Some(active) = self.state.lock().unwrap().active.get_mut(script) {
+            active.runs = active.runs.saturating_sub(1);
+            if active.runs == 0 {
+                active.finished = Some(Instant::now());
+            }
+        }
+    }
+
+    // The running (or just finished) script a change to
+    // the path probably came from
+    pub fn written_by(&self, path: &Path) -> Option<PathBuf> {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let mut state = self.state.lock().unwrap();
+        let grace = self.grace;
+        state
+            .active
+            .retain(|_, active| active.finished.is_none_or(|done| done.elapsed() < grace));
+        state
+            .active
+            .iter()
+            .find(|(script, active)| {
+                active
+                    .outputs
+                    .as_ref()
+                    .is_some_and(|globs| globs.is_match(relative))
+                    || state
+                        .written
+                        .get(*script)
+                        .is_some_and(|written| written.contains(path))
+            })
+            .map(|(script, _)| script.clone())
+    }
+}
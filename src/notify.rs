@@ -0,0 +1,118 @@
+use crate::config::{ChatNotifier, NotifyConfig, NotifyOn};
+use crate::placeholders;
+use crate::stream::RunStream;
+use crate::webhook;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tokio::sync::watch;
+
+// Posts to the Slack and Discord webhooks from the
+// config when a run fails or when a script that was
+// failing passes again. Runs that were skipped or
+// cancelled don't count either way.
+//
+// With --notify-over only runs that took at least that
+// long get posted and they ring the terminal's bell
+// when they finish (pass or fail) so quick runs stay
+// quiet and long ones get noticed from another window.
+pub fn follow(config: watch::Receiver<NotifyConfig>, over: Option<Duration>, stream: &RunStream) {
+    let mut events = stream.follow(false);
+    tokio::spawn(async move {
+        let mut failing: HashMap<String, bool> = HashMap::new();
+        while let Some(message) = events.next().await {
+            if message["type"] != "run_finished" {
+                continue;
+            }
+            let run = &message["data"];
+            let Some(success) = run["success"].as_bool() else {
+                continue;
+            };
+            let script = run["script"].as_str().unwrap_or_default().to_string();
+            let was_failing = failing.insert(script, !success).unwrap_or(false);
+            if let Some(over) = over {
+                let took = Duration::from_millis(run["duration_ms"].as_u64().unwrap_or_default());
+                if took < over {
+                    continue;
+                }
+                ring_bell();
+            }
+            let event = match (success, was_failing) {
+                (false, _) => NotifyOn::Failure,
+                (true, true) => NotifyOn::Recovery,
+                (true, false) => continue,
+            };
+            // read fresh each time since a SIGHUP can
+            // reload it
+            let config = config.borrow().clone();
+            let mut notifiers = vec![];
+            if let Some(slack) = config.slack {
+                notifiers.push((slack, "text"));
+            }
+            if let Some(discord) = config.discord {
+                notifiers.push((discord, "content"));
+            }
+            for (notifier, field) in &notifiers {
+                if notifier.on.contains(&event) {
+                    let body = json!({ *field: render(notifier, event, run) });
+                    webhook::post(notifier.webhook_url.clone(), body.to_string());
+                }
+            }
+        }
+    });
+}
+
+// Only when there's a terminal to hear it. With
+// --daemon it'd just end up in the log.
+fn ring_bell() {
+    let mut stdout = std::io::stdout();
+    if stdout.is_terminal() {
+        let _ = stdout.write_all(b"\x07");
+        let _ = stdout.flush();
+    }
+}
+
+// With the placeholders from placeholders.rs and
+// `{event}` (`failed` or `recovered`)
+fn render(notifier: &ChatNotifier, event: NotifyOn, run: &Value) -> String {
+    let event = match event {
+        NotifyOn::Failure => "failed",
+        NotifyOn::Recovery => "recovered",
+    };
+    placeholders::fill(
+        &notifier.template.replace("{event}", event),
+        &placeholders::from_run(run),
+    )
+}
+
+// notify-send on Linux and Notification Center on macOS.
+// Returns false when there isn't a way to send one.
+pub fn desktop(message: &str) -> bool {
+    let mut command = if cfg!(target_os = "macos") {
+        let quoted = message.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification \"{}\" with title \"ws\"",
+            quoted
+        ));
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command.arg("ws").arg(message);
+        command
+    };
+    let spawned = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match spawned {
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+            true
+        }
+        Err(_) => false,
+    }
+}
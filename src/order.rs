@@ -0,0 +1,159 @@
+use anyhow::{Result, anyhow};
+use globset::{Glob, GlobMatcher};
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Which scripts go first when several are triggered
+// together (--all or a batch of changes with --jobs) so
+// the quick checks can fail fast before the slow ones
+// get going:
+//
+//   order = ["lint.sh", "check/*.sh"]
+//
+// Scripts matching an earlier glob go before ones that
+// match a later one and anything that doesn't match
+// goes after all of them. A `priority` in `[scripts]` or
+// a sidecar sorts the scripts with the same place in the
+// list (higher first, 0 by default). Otherwise they go
+// by `order_by`.
+#[derive(Debug, Clone, Default)]
+pub struct RunOrder {
+    globs: Vec<GlobMatcher>,
+    order_by: OrderBy,
+    root: PathBuf,
+}
+
+// How the files changed in the same batch of events
+// are lined up before anything else sorts them. That
+// also picks which one runs when only one can (no
+// --jobs).
+//
+//   order_by = "path"
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OrderBy {
+    // the order the events came in, which can change
+    // from one save to the next
+    Arrival,
+    // most recently modified first
+    Modified,
+    // sorted by path (the default)
+    #[default]
+    Path,
+}
+
+impl RunOrder {
+    pub fn new(patterns: &[String], order_by: OrderBy, root: &Path) -> Result<RunOrder> {
+        let globs = patterns
+            .iter()
+            .map(|pattern| {
+                Glob::new(pattern)
+                    .map(|glob| glob.compile_matcher())
+                    .map_err(|e| anyhow!("invalid glob `{}` in the order: {}", pattern, e))
+            })
+            .collect::<Result<_>>()?;
+        Ok(RunOrder {
+            globs,
+            order_by,
+            root: root.to_path_buf(),
+        })
+    }
+
+    // A stable sort so a path that shows up more than
+    // once keeps the event it was first seen with
+    pub fn sort_changed<T>(&self, changed: &mut [(T, &Path)]) {
+        match self.order_by {
+            OrderBy::Arrival => {}
+            OrderBy::Modified => changed.sort_by_cached_key(|(_, path)| {
+                Reverse(
+                    path.metadata()
+                        .and_then(|meta| meta.modified())
+                        .unwrap_or(SystemTime::UNIX_EPOCH),
+                )
+            }),
+            OrderBy::Path => changed.sort_by_key(|(_, path)| *path),
+        }
+    }
+
+    // Sorts by this with a stable sort
+    pub fn rank(&self, script: &Path, priority: i64) -> (usize, Reverse<i64>) {
+        let relative = script.strip_prefix(&self.root).unwrap_or(script);
+        let place = self
+            .globs
+            .iter()
+            .position(|glob| glob.is_match(relative))
+            .unwrap_or(self.globs.len());
+        (place, Reverse(priority))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_by_the_first_matching_glob_then_priority() {
+        let order = RunOrder::new(
+            &["lint.sh".to_string(), "check/*.sh".to_string()],
+            OrderBy::Path,
+            Path::new("/project"),
+        )
+        .unwrap();
+        let mut scripts = [
+            (Path::new("/project/deploy.sh"), 0),
+            (Path::new("/project/check/slow.sh"), 0),
+            (Path::new("/project/check/fast.sh"), 5),
+            (Path::new("/project/lint.sh"), 0),
+            (Path::new("/project/build.sh"), 1),
+        ];
+        scripts.sort_by_key(|(script, priority)| order.rank(script, *priority));
+        let names: Vec<_> = scripts
+            .iter()
+            .map(|(script, _)| script.strip_prefix("/project").unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                Path::new("lint.sh"),
+                Path::new("check/fast.sh"),
+                Path::new("check/slow.sh"),
+                Path::new("build.sh"),
+                Path::new("deploy.sh"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_globs() {
+        assert!(RunOrder::new(&["[".to_string()], OrderBy::Path, Path::new("/")).is_err());
+    }
+
+    #[test]
+    fn sorts_changes_by_path_keeping_the_first_event() {
+        let order = RunOrder::default();
+        let mut changed = [
+            (1, Path::new("b.sh")),
+            (2, Path::new("a.sh")),
+            (3, Path::new("b.sh")),
+        ];
+        order.sort_changed(&mut changed);
+        assert_eq!(
+            changed,
+            [
+                (2, Path::new("a.sh")),
+                (1, Path::new("b.sh")),
+                (3, Path::new("b.sh")),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_changes_in_arrival_order() {
+        let order = RunOrder::new(&[], OrderBy::Arrival, Path::new("/")).unwrap();
+        let mut changed = [(1, Path::new("b.sh")), (2, Path::new("a.sh"))];
+        order.sort_changed(&mut changed);
+        assert_eq!(changed, [(1, Path::new("b.sh")), (2, Path::new("a.sh"))]);
+    }
+}
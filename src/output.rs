@@ -0,0 +1,966 @@
+use crate::directives::{Directives, LinePosition};
+use crate::highlight::Highlights;
+use crate::plugins::{LineChange, Plugins};
+use crate::problems::{Diagnostics, Problem, ProblemMatchers};
+use crate::record::Recording;
+use crate::sections::{SectionStyle, Sections};
+use crate::stream::RunStream;
+use crate::truncate::{OutputLimit, Truncator};
+use anyhow::{Result, anyhow};
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, PipeReader, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, oneshot};
+use watchexec::job::Job;
+
+const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+// How long the output has to be quiet before the spinner
+// shows up. Keeps it from flickering between lines of
+// a chatty script.
+const SPINNER_DELAY: Duration = Duration::from_millis(500);
+
+// The most of a run --junit holds on to
+const TRANSCRIPT_LINES: usize = 5000;
+
+// Routes a job's stdout and stderr through the watcher
+// instead of letting the child write to the terminal
+// directly. The bytes are passed along untouched and
+// are also split into lines so the end of a run can
+// be kept around after the screen gets cleared.
+#[derive(Debug, Clone)]
+pub struct OutputCapture {
+    relays_done: Arc<Notify>,
+    state: Arc<Mutex<CaptureState>>,
+}
+
+// Where a stream is in the line it's on. stdout and a
+// separate stderr (--stderr, --stderr-log) each keep
+// their own so a line from one doesn't get joined onto
+// what the other had printed of its line so far.
+#[derive(Debug, Default)]
+struct LineState {
+    directives: LinePosition,
+    line_partial: Vec<u8>,
+    partial: String,
+}
+
+#[derive(Debug)]
+struct CaptureState {
+    active_relays: usize,
+    at_line_start: bool,
+    // with --diagnostics, highlights, or plugins output is
+    // shown a line at a time so the normalized locations
+    // can go right under the lines they came from and
+    // whole lines can be styled or rewritten
+    diagnostics: Option<Diagnostics>,
+    // --directives' and --sections' `::ws::` lines get
+    // pulled out first
+    directives: Option<Directives>,
+    highlights: Option<Highlights>,
+    line_partial: Vec<u8>,
+    // output is collected here instead of being shown
+    // while a quiet run is in progress
+    held: Option<Vec<u8>>,
+    // with --pipe-then the main script's stdout ends up
+    // here for the then script to read
+    piped: Vec<u8>,
+    keep: usize,
+    plugins: Option<Plugins>,
+    // what each line starts with when the job runs
+    // alongside others (--jobs)
+    prefix: Option<String>,
+    // --problems' matchers and what they've found in the
+    // run in progress
+    problems: Option<ProblemMatchers>,
+    found: Vec<Problem>,
+    last_output: Instant,
+    lines: VecDeque<String>,
+    partial: String,
+    // where the stream that isn't coming through right
+    // now is in its line (see LineState)
+    other_stream: LineState,
+    // --then-after-output's pattern and who to tell
+    // when a line matches it
+    ready: Option<(Regex, oneshot::Sender<()>)>,
+    // everywhere else the output goes (see OutputSink)
+    // by what it is. They get everything the scripts
+    // print whether it's shown or held back.
+    sinks: BTreeMap<&'static str, Box<dyn OutputSink>>,
+    spinner_shown: bool,
+    // the watcher's stream, which jobs' captures share
+    stream: RunStream,
+    // --stderr-log's file. It gets stderr as is.
+    stderr_log: Option<File>,
+    // --stderr's style and whether what's coming through
+    // right now is from stderr. With either of them
+    // stderr gets a pipe of its own.
+    stderr_style: Option<StderrStyle>,
+    on_stderr: bool,
+    // every line of the run in progress for --junit
+    transcript: Option<VecDeque<String>>,
+    // --max-output's cut of each job's output
+    truncator: Option<Truncator>,
+}
+
+const STREAM_SINK: &str = "stream";
+const RECORD_SINK: &str = "record";
+pub const TEE_SINK: &str = "tee";
+
+// How --stderr shows what scripts print to stderr so
+// it stands out from their regular output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StderrStyle {
+    // the lines in red
+    Color,
+    // a red bar in front of each line
+    Gutter,
+}
+
+impl StderrStyle {
+    // `plain` leaves it alone
+    pub fn from_arg(value: &str) -> Option<StderrStyle> {
+        match value {
+            "color" => Some(StderrStyle::Color),
+            "gutter" => Some(StderrStyle::Gutter),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut styled = Vec::with_capacity(bytes.len() + 16);
+        for line in bytes.split_inclusive(|byte| *byte == b'\n') {
+            let (text, newline) = match line.strip_suffix(b"\n") {
+                Some(text) => (text, &b"\n"[..]),
+                None => (line, &b""[..]),
+            };
+            match self {
+                StderrStyle::Color => {
+                    styled.extend_from_slice(b"\x1b[31m");
+                    styled.extend_from_slice(text);
+                    styled.extend_from_slice(b"\x1b[0m");
+                }
+                StderrStyle::Gutter => {
+                    styled.extend_from_slice("\x1b[31m▌\x1b[0m ".as_bytes());
+                    styled.extend_from_slice(text);
+                }
+            }
+            styled.extend_from_slice(newline);
+        }
+        styled
+    }
+}
+
+// Somewhere a job's output goes besides the terminal:
+// the stream (the control socket's and HTTP's clients),
+// --record's file, --tee's file. The capture reads the
+// pipes once and hands every sink the same output so
+// none of them has to capture it on its own.
+pub trait OutputSink: Send + std::fmt::Debug {
+    // The bytes as they come from the job, before they're
+    // held back or styled for the terminal
+    fn write(&mut self, _bytes: &[u8]) {}
+
+    // Each whole line without its line ending
+    fn line(&mut self, _line: &str) {}
+
+    // One for a job's own capture with --jobs if it
+    // should get that job's output too
+    fn for_job(&self) -> Option<Box<dyn OutputSink>> {
+        None
+    }
+
+    // The run it's only for, if there is one
+    fn run_id(&self) -> Option<&str> {
+        None
+    }
+}
+
+// --tee's file. Everything the scripts print gets added
+// to the end of it as is (colors and all) to keep or
+// follow with tail -f.
+#[derive(Debug)]
+pub struct TeeFile {
+    file: File,
+    // a job's copy (see for_job) that writes whole lines
+    // instead of the bytes as they come
+    by_line: bool,
+}
+
+impl TeeFile {
+    pub fn open(path: &Path) -> Result<TeeFile> {
+        Ok(TeeFile {
+            file: open_log(path)?,
+            by_line: false,
+        })
+    }
+}
+
+// A file output gets added to the end of
+pub fn open_log(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow!("could not open {}: {}", path.display(), e))
+}
+
+impl OutputSink for TeeFile {
+    fn write(&mut self, bytes: &[u8]) {
+        if !self.by_line {
+            let _ = self.file.write_all(bytes);
+        }
+    }
+
+    // Each line goes in with one write so jobs sharing the
+    // file (it's opened for appending) don't get theirs
+    // mixed together partway through a line
+    fn line(&mut self, line: &str) {
+        if self.by_line {
+            let _ = self.file.write_all(format!("{}\n", line).as_bytes());
+        }
+    }
+
+    fn for_job(&self) -> Option<Box<dyn OutputSink>> {
+        let file = self.file.try_clone().ok()?;
+        Some(Box::new(TeeFile {
+            file,
+            by_line: true,
+        }))
+    }
+}
+
+impl CaptureState {
+    // Everything that comes from a job goes through here
+    fn take_in(&mut self, stdout: &mut impl Write, bytes: &[u8]) {
+        match self.directives.as_mut() {
+            Some(directives) => {
+                let bytes = directives.filter(bytes);
+                self.truncate(stdout, &bytes);
+                self.show_title(stdout);
+            }
+            None => self.truncate(stdout, bytes),
+        }
+    }
+
+    // --directives' ::ws::title, only for a terminal
+    fn show_title(&mut self, stdout: &mut impl Write) {
+        if let Some(title) = self.directives.as_mut().and_then(|d| d.take_title())
+            && std::io::stdout().is_terminal()
+        {
+            let _ = write!(stdout, "\x1b]2;{}\x07", title);
+            let _ = stdout.flush();
+        }
+    }
+
+    // What a job printed to stderr when it has a pipe of
+    // its own
+    fn take_in_stderr(&mut self, stdout: &mut impl Write, bytes: &[u8]) {
+        if let Some(log) = self.stderr_log.as_mut() {
+            let _ = log.write_all(bytes);
+        }
+        self.on_stderr = true;
+        self.swap_streams();
+        self.take_in(stdout, bytes);
+        self.swap_streams();
+        self.on_stderr = false;
+    }
+
+    // Puts the other stream's line state in place of the
+    // current one's
+    fn swap_streams(&mut self) {
+        let other = &mut self.other_stream;
+        std::mem::swap(&mut self.partial, &mut other.partial);
+        std::mem::swap(&mut self.line_partial, &mut other.line_partial);
+        if let Some(directives) = self.directives.as_mut() {
+            directives.swap_position(&mut other.directives);
+        }
+    }
+
+    fn splits_stderr(&self) -> bool {
+        self.stderr_style.is_some() || self.stderr_log.is_some()
+    }
+
+    // --stderr's style for what's coming through from
+    // stderr
+    fn styled<'a>(&self, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+        match self.stderr_style {
+            Some(style) if self.on_stderr => Cow::Owned(style.apply(bytes)),
+            _ => Cow::Borrowed(bytes),
+        }
+    }
+
+    // The end of a job's output that --directives was
+    // still holding onto
+    fn finish_directives(&mut self, stdout: &mut impl Write) {
+        if let Some(rest) = self.directives.as_mut().map(|d| d.finish()) {
+            self.truncate(stdout, &rest);
+            self.show_title(stdout);
+        }
+    }
+
+    fn truncate(&mut self, stdout: &mut impl Write, bytes: &[u8]) {
+        match self.truncator.as_mut() {
+            Some(truncator) => {
+                let bytes = truncator.feed(bytes);
+                self.pass_on(stdout, &bytes);
+            }
+            None => self.pass_on(stdout, bytes),
+        }
+    }
+
+    fn pass_on(&mut self, stdout: &mut impl Write, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        for sink in self.sinks.values_mut() {
+            sink.write(bytes);
+        }
+        match self.held.is_some() {
+            true => {
+                let styled = self.styled(bytes).into_owned();
+                if let Some(held) = self.held.as_mut() {
+                    held.extend(styled);
+                }
+            }
+            false => self.show_output(stdout, bytes),
+        }
+        self.push(bytes);
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.partial.push_str(&String::from_utf8_lossy(bytes));
+        while let Some(idx) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=idx).collect();
+            self.push_line(line.trim_end_matches(['\n', '\r']).to_string());
+        }
+    }
+
+    fn push_line(&mut self, line: String) {
+        for sink in self.sinks.values_mut() {
+            sink.line(&line);
+        }
+        if let Some(problem) = self.problems.as_mut().and_then(|p| p.check(&line))
+            && !self.found.contains(&problem)
+        {
+            self.found.push(problem);
+        }
+        if self
+            .ready
+            .as_ref()
+            .is_some_and(|(pattern, _)| pattern.is_match(&line))
+            && let Some((_, ready)) = self.ready.take()
+        {
+            let _ = ready.send(());
+        }
+        if let Some(transcript) = self.transcript.as_mut() {
+            transcript.push_back(line.clone());
+            while transcript.len() > TRANSCRIPT_LINES {
+                transcript.pop_front();
+            }
+        }
+        if self.keep == 0 {
+            return;
+        }
+        self.lines.push_back(line);
+        while self.lines.len() > self.keep {
+            self.lines.pop_front();
+        }
+    }
+
+    fn flush_partial(&mut self) {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.push_line(line);
+        }
+    }
+
+    fn show_output(&mut self, stdout: &mut impl Write, bytes: &[u8]) {
+        if self.diagnostics.is_none() && self.highlights.is_none() && self.plugins.is_none() {
+            self.show(stdout, bytes);
+            return;
+        }
+        self.line_partial.extend_from_slice(bytes);
+        while let Some(idx) = self.line_partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.line_partial.drain(..=idx).collect();
+            self.show_line(stdout, line);
+        }
+    }
+
+    fn show_line(&mut self, stdout: &mut impl Write, mut line: Vec<u8>) {
+        if let Some(plugins) = &self.plugins {
+            let text = String::from_utf8_lossy(&line);
+            match plugins.process_line(text.trim_end_matches(['\n', '\r'])) {
+                LineChange::Drop => return,
+                LineChange::Keep => {}
+                LineChange::Replace(replacement) => {
+                    let newline = line.ends_with(b"\n");
+                    line = replacement.into_bytes();
+                    if newline {
+                        line.push(b'\n');
+                    }
+                }
+            }
+        }
+        match self.highlights.as_ref().and_then(|h| h.apply(&line)) {
+            Some(highlighted) => self.show(stdout, &highlighted),
+            None => self.show(stdout, &line),
+        }
+        let normalized = self
+            .diagnostics
+            .as_mut()
+            .and_then(|d| d.normalize(&String::from_utf8_lossy(&line)));
+        if let Some(normalized) = normalized {
+            self.show(
+                stdout,
+                format!("\x1b[36m{}\x1b[0m\n", normalized).as_bytes(),
+            );
+        }
+    }
+
+    fn flush_output(&mut self, stdout: &mut impl Write) {
+        let rest = std::mem::take(&mut self.line_partial);
+        if !rest.is_empty() {
+            self.show_line(stdout, rest);
+        }
+    }
+
+    fn show(&mut self, stdout: &mut impl Write, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        self.clear_spinner(stdout);
+        let styled = self.styled(bytes);
+        match &self.prefix {
+            Some(prefix) => {
+                let _ = stdout.write_all(&prefix_lines(prefix, &styled, self.at_line_start));
+            }
+            None => {
+                let _ = stdout.write_all(&styled);
+            }
+        }
+        let _ = stdout.flush();
+        self.at_line_start = bytes[bytes.len() - 1] == b'\n';
+        self.last_output = Instant::now();
+    }
+
+    fn clear_spinner(&mut self, stdout: &mut impl Write) {
+        if self.spinner_shown {
+            let _ = write!(stdout, "\r\x1b[2K");
+            let _ = stdout.flush();
+            self.spinner_shown = false;
+        }
+    }
+}
+
+impl OutputCapture {
+    pub fn new(
+        keep: usize,
+        stream: RunStream,
+        diagnostics: Option<Diagnostics>,
+        highlights: Option<Highlights>,
+        plugins: Option<Plugins>,
+        problems: Option<ProblemMatchers>,
+        max_output: Option<OutputLimit>,
+    ) -> OutputCapture {
+        OutputCapture {
+            relays_done: Arc::new(Notify::new()),
+            state: Arc::new(Mutex::new(CaptureState {
+                active_relays: 0,
+                at_line_start: true,
+                diagnostics,
+                directives: None,
+                highlights,
+                line_partial: vec![],
+                held: None,
+                piped: vec![],
+                keep,
+                plugins,
+                prefix: None,
+                problems,
+                found: vec![],
+                last_output: Instant::now(),
+                lines: VecDeque::new(),
+                partial: String::new(),
+                other_stream: LineState::default(),
+                ready: None,
+                sinks: BTreeMap::from([(
+                    STREAM_SINK,
+                    Box::new(stream.clone()) as Box<dyn OutputSink>,
+                )]),
+                spinner_shown: false,
+                stream,
+                stderr_log: None,
+                stderr_style: None,
+                on_stderr: false,
+                transcript: None,
+                truncator: max_output.map(Truncator::new),
+            })),
+        }
+    }
+
+    // A capture of its own for a job that runs alongside
+    // others (--jobs) with each of its lines marked with
+    // the prefix. It has the same settings and sends its
+    // lines to the same sinks that follow jobs (the stream
+    // and --tee) but holds its output and
+    // collects problems and its transcript separately so
+    // one job finishing doesn't take the others' output
+    // with it.
+    pub fn for_job(&self, prefix: String) -> OutputCapture {
+        let state = self.state.lock().unwrap();
+        let capture = OutputCapture::new(
+            0,
+            state.stream.clone(),
+            state.diagnostics.clone(),
+            state.highlights.clone(),
+            state.plugins.clone(),
+            state.problems.clone(),
+            state.truncator.as_ref().map(|t| t.limit()),
+        );
+        {
+            let mut job_state = capture.state.lock().unwrap();
+            job_state.prefix = Some(prefix);
+            job_state.sinks = state
+                .sinks
+                .iter()
+                .filter_map(|(kind, sink)| sink.for_job().map(|sink| (*kind, sink)))
+                .collect();
+            if state.transcript.is_some() {
+                job_state.transcript = Some(VecDeque::new());
+            }
+            job_state.directives = state.directives.as_ref().map(|d| d.fresh());
+            job_state.stderr_style = state.stderr_style;
+            job_state.stderr_log = state.stderr_log.as_ref().and_then(|f| f.try_clone().ok());
+        }
+        capture
+    }
+
+    // Called from a job's spawn hook to point the child's
+    // stdout and stderr at a pipe that gets relayed.
+    // With --stderr or --stderr-log stderr gets one of
+    // its own.
+    pub fn capture(&self, command: &mut tokio::process::Command) {
+        if let Err(e) = self.try_capture(command) {
+            eprintln!("ERROR: could not capture output: {}", e);
+        }
+    }
+
+    fn try_capture(&self, command: &mut tokio::process::Command) -> std::io::Result<()> {
+        let (reader, writer) = std::io::pipe()?;
+        let err_reader = if self.state.lock().unwrap().splits_stderr() {
+            let (err_reader, err_writer) = std::io::pipe()?;
+            command.stderr(err_writer);
+            Some(err_reader)
+        } else {
+            command.stderr(writer.try_clone()?);
+            None
+        };
+        command.stdout(writer);
+        let mut state = self.state.lock().unwrap();
+        state.active_relays += 1;
+        let capture = self.clone();
+        std::thread::spawn(move || capture.relay(reader, false));
+        if let Some(err_reader) = err_reader {
+            state.active_relays += 1;
+            let capture = self.clone();
+            std::thread::spawn(move || capture.relay(err_reader, true));
+        }
+        Ok(())
+    }
+
+    // --pipe-then's side of the main script. This goes
+    // after capture() so only stderr is relayed and
+    // stdout is kept for the then script.
+    pub fn pipe_stdout(&self, command: &mut tokio::process::Command) {
+        let (mut reader, writer) = match std::io::pipe() {
+            Ok(pipe) => pipe,
+            Err(e) => {
+                eprintln!("ERROR: could not pipe output: {}", e);
+                return;
+            }
+        };
+        command.stdout(writer);
+        let mut state = self.state.lock().unwrap();
+        state.piped.clear();
+        state.active_relays += 1;
+        let capture = self.clone();
+        std::thread::spawn(move || {
+            let mut piped = vec![];
+            let _ = reader.read_to_end(&mut piped);
+            let mut state = capture.state.lock().unwrap();
+            state.piped = piped;
+            state.active_relays -= 1;
+            capture.relays_done.notify_waiters();
+        });
+    }
+
+    // --pipe-then's side of the then script. It gets what
+    // the main script printed the last time it ran.
+    pub fn pipe_into(&self, command: &mut tokio::process::Command) {
+        let (reader, mut writer) = match std::io::pipe() {
+            Ok(pipe) => pipe,
+            Err(e) => {
+                eprintln!("ERROR: could not pipe output: {}", e);
+                return;
+            }
+        };
+        command.stdin(reader);
+        let piped = std::mem::take(&mut self.state.lock().unwrap().piped);
+        // the then script doesn't have to read all of it
+        std::thread::spawn(move || {
+            let _ = writer.write_all(&piped);
+        });
+    }
+
+    fn relay(&self, mut reader: PipeReader, from_stderr: bool) {
+        let mut buf = [0u8; 8192];
+        let mut stdout = std::io::stdout();
+        let take_in = |state: &mut CaptureState, stdout: &mut std::io::Stdout, bytes: &[u8]| {
+            match from_stderr {
+                true => state.take_in_stderr(stdout, bytes),
+                false => state.take_in(stdout, bytes),
+            }
+        };
+        // a job running alongside others only hands over
+        // whole lines so theirs don't get mixed together.
+        // A separate stderr does the same so its lines
+        // don't land in the middle of stdout's.
+        let whole_lines = from_stderr || self.state.lock().unwrap().prefix.is_some();
+        let mut pending = vec![];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) if whole_lines => {
+                    pending.extend_from_slice(&buf[..n]);
+                    if let Some(end) = pending.iter().rposition(|b| *b == b'\n') {
+                        let lines: Vec<u8> = pending.drain(..=end).collect();
+                        take_in(&mut self.state.lock().unwrap(), &mut stdout, &lines);
+                    }
+                }
+                Ok(n) => {
+                    take_in(&mut self.state.lock().unwrap(), &mut stdout, &buf[..n]);
+                }
+            }
+        }
+        let mut state = self.state.lock().unwrap();
+        if !pending.is_empty() {
+            pending.push(b'\n');
+            take_in(&mut state, &mut stdout, &pending);
+        }
+        // only this stream's partial line is finished off
+        if from_stderr {
+            state.swap_streams();
+        }
+        state.finish_directives(&mut stdout);
+        state.flush_partial();
+        if state.held.is_none() {
+            state.flush_output(&mut stdout);
+        }
+        if from_stderr {
+            state.swap_streams();
+        }
+        state.active_relays -= 1;
+        self.relays_done.notify_waiters();
+    }
+
+    // Waits for the output of jobs that have exited to
+    // finish coming through. Gives up after a bit since
+    // a script can leave something running in the
+    // background that keeps the pipe open.
+    pub async fn drain(&self) {
+        let wait = async {
+            loop {
+                let done = self.relays_done.notified();
+                tokio::pin!(done);
+                done.as_mut().enable();
+                if self.state.lock().unwrap().active_relays == 0 {
+                    return;
+                }
+                done.await;
+            }
+        };
+        let _ = tokio::time::timeout(Duration::from_millis(500), wait).await;
+    }
+
+    // Resolves the first time a line of output matches
+    // the pattern. Lines that came in before this was
+    // called don't count.
+    pub fn wait_for_line(&self, pattern: Regex) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        self.state.lock().unwrap().ready = Some((pattern, tx));
+        rx
+    }
+
+    // Lets the end of a job's output through once it's
+    // done when --max-output cut some of it
+    pub fn finish_truncated(&self) {
+        let mut state = self.state.lock().unwrap();
+        let Some(rest) = state.truncator.as_mut().map(|t| t.finish()) else {
+            return;
+        };
+        let mut stdout = std::io::stdout();
+        state.pass_on(&mut stdout, &rest);
+        state.flush_partial();
+        if state.held.is_none() {
+            state.flush_output(&mut stdout);
+        }
+    }
+
+    // Returns the run id. A newer recording replaces one
+    // that's still going since only the latest run's
+    // output comes through.
+    pub fn start_recording(&self, recording: Recording) -> String {
+        let id = recording.id().to_string();
+        self.add_sink(RECORD_SINK, Box::new(recording));
+        id
+    }
+
+    // Leaves a recording that's already been replaced
+    // alone
+    pub fn stop_recording(&self, id: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .sinks
+            .get(RECORD_SINK)
+            .is_some_and(|recording| recording.run_id() == Some(id))
+        {
+            state.sinks.remove(RECORD_SINK);
+        }
+    }
+
+    // Sends the output somewhere else too from now on. It
+    // replaces a sink of the same kind.
+    pub fn add_sink(&self, kind: &'static str, sink: Box<dyn OutputSink>) {
+        self.state.lock().unwrap().sinks.insert(kind, sink);
+    }
+
+    // Swaps in the highlights, plugins, and problem
+    // matchers from a reloaded config
+    pub fn reconfigure(
+        &self,
+        highlights: Option<Highlights>,
+        plugins: Option<Plugins>,
+        problems: Option<ProblemMatchers>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.highlights = highlights;
+        state.plugins = plugins;
+        state.problems = problems;
+    }
+
+    // Hands back the problems found since the last time
+    // and starts fresh for the next run. The last line
+    // can still be waiting for its newline.
+    pub fn take_problems(&self) -> Vec<Problem> {
+        let mut state = self.state.lock().unwrap();
+        state.flush_partial();
+        if let Some(problems) = state.problems.as_mut() {
+            problems.reset();
+        }
+        std::mem::take(&mut state.found)
+    }
+
+    // Starts collecting output instead of showing it.
+    pub fn hold(&self) {
+        self.state.lock().unwrap().held = Some(vec![]);
+    }
+
+    // Shows anything that's been held and goes back to
+    // showing output as it comes in. Stdout stays locked
+    // so it comes out in one block even when other jobs
+    // are printing.
+    pub fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(held) = state.held.take() {
+            let mut stdout = std::io::stdout().lock();
+            state.show_output(&mut stdout, &held);
+            state.flush_output(&mut stdout);
+        }
+    }
+
+    // Throws away anything that's been held and goes
+    // back to showing output as it comes in.
+    pub fn discard(&self) {
+        self.state.lock().unwrap().held = None;
+    }
+
+    // Shows a spinner with the elapsed time on a status
+    // line while the job is running. It only draws when
+    // the output has gone quiet and the cursor is at
+    // the start of a line and gets wiped as soon as
+    // more output shows up or the job finishes. The
+    // job needs to have been started already since
+    // to_wait() resolves right away otherwise.
+    pub fn spin(&self, job: &Job, label: String) {
+        // there's no one status line for jobs running
+        // side by side
+        if self.state.lock().unwrap().prefix.is_some() {
+            return;
+        }
+        let capture = self.clone();
+        let job = job.clone();
+        tokio::spawn(async move {
+            let started = Instant::now();
+            capture.state.lock().unwrap().last_output = started;
+            let mut ticker = tokio::time::interval(Duration::from_millis(100));
+            let mut frame = 0;
+            let wait = job.to_wait();
+            tokio::pin!(wait);
+            loop {
+                tokio::select! {
+                    _ = &mut wait => break,
+                    _ = ticker.tick() => {
+                        let mut state = capture.state.lock().unwrap();
+                        if !state.at_line_start || state.last_output.elapsed() < SPINNER_DELAY {
+                            continue;
+                        }
+                        let mut stdout = std::io::stdout();
+                        let _ = write!(
+                            stdout,
+                            "\r\x1b[2K\x1b[2m{} {} ({:.1}s)\x1b[0m",
+                            SPINNER_FRAMES[frame % SPINNER_FRAMES.len()],
+                            label,
+                            started.elapsed().as_secs_f64()
+                        );
+                        let _ = stdout.flush();
+                        state.spinner_shown = true;
+                        frame += 1;
+                    }
+                }
+            }
+            capture
+                .state
+                .lock()
+                .unwrap()
+                .clear_spinner(&mut std::io::stdout());
+        });
+    }
+
+    // Starts looking for --directives' lines
+    pub fn enable_directives(&self) {
+        self.state
+            .lock()
+            .unwrap()
+            .directives
+            .get_or_insert_default()
+            .enable_general();
+    }
+
+    // Starts marking off --sections
+    pub fn enable_sections(&self, style: SectionStyle) {
+        self.state
+            .lock()
+            .unwrap()
+            .directives
+            .get_or_insert_default()
+            .sections = Some(Sections::new(style));
+    }
+
+    // The section for a script or then script run
+    pub fn start_section(&self, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        let Some(sections) = state.directives.as_mut().and_then(|d| d.sections.as_mut()) else {
+            return;
+        };
+        let bytes = sections.start_run(name);
+        state.pass_on(&mut std::io::stdout().lock(), &bytes);
+    }
+
+    // Ends the run's section and any the script left open
+    pub fn end_sections(&self) {
+        let mut state = self.state.lock().unwrap();
+        let Some(sections) = state.directives.as_mut().and_then(|d| d.sections.as_mut()) else {
+            return;
+        };
+        let bytes = sections.end_all();
+        state.pass_on(&mut std::io::stdout().lock(), &bytes);
+    }
+
+    // Whether a `::ws::skip-then` came through since the
+    // last time
+    pub fn take_skip_then(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .directives
+            .as_mut()
+            .is_some_and(|directives| directives.take_skip_then())
+    }
+
+    // Gives stderr a pipe of its own for --stderr and
+    // --stderr-log
+    pub fn split_stderr(&self, style: Option<StderrStyle>, log: Option<File>) {
+        let mut state = self.state.lock().unwrap();
+        state.stderr_style = style;
+        state.stderr_log = log;
+    }
+
+    // Starts keeping every line for take_transcript()
+    pub fn keep_transcript(&self) {
+        self.state.lock().unwrap().transcript = Some(VecDeque::new());
+    }
+
+    // Hands back the lines since the last time and starts
+    // fresh for the next run
+    pub fn take_transcript(&self) -> Vec<String> {
+        let mut state = self.state.lock().unwrap();
+        state.flush_partial();
+        state
+            .transcript
+            .as_mut()
+            .map(|transcript| transcript.drain(..).collect())
+            .unwrap_or_default()
+    }
+
+    // The last few lines of the transcript, leaving it
+    // there for take_transcript()
+    pub fn transcript_tail(&self, lines: usize) -> Vec<String> {
+        let mut state = self.state.lock().unwrap();
+        state.flush_partial();
+        state.transcript.as_ref().map_or(vec![], |transcript| {
+            transcript
+                .iter()
+                .skip(transcript.len().saturating_sub(lines))
+                .cloned()
+                .collect()
+        })
+    }
+
+    // Hands back the tail of everything captured so far
+    // and starts fresh for the next run.
+    pub fn take_tail(&self) -> Vec<String> {
+        let mut state = self.state.lock().unwrap();
+        state.flush_partial();
+        state.lines.drain(..).collect()
+    }
+}
+
+fn prefix_lines(prefix: &str, bytes: &[u8], at_line_start: bool) -> Vec<u8> {
+    let mut prefixed = Vec::with_capacity(bytes.len() + prefix.len());
+    let mut line_start = at_line_start;
+    for byte in bytes {
+        if line_start {
+            prefixed.extend_from_slice(prefix.as_bytes());
+        }
+        prefixed.push(*byte);
+        line_start = *byte == b'\n';
+    }
+    prefixed
+}
+
+pub fn print_pinned_tail(lines: &[String]) {
+    if lines.is_empty() {
+        return;
+    }
+    println!("\x1b[2m---------------------- last run ----------------------");
+    for line in lines {
+        println!("{}", line);
+    }
+    println!("------------------------------------------------------\x1b[0m");
+}
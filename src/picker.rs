@@ -0,0 +1,156 @@
+use crate::inventory::Inventory;
+use nix::poll::{PollFd, PollFlags, poll};
+use nix::sys::termios::{self, LocalFlags, SetArg};
+use std::io::{Read, Write};
+use std::os::fd::AsFd;
+use std::path::PathBuf;
+
+const MAX_SHOWN: usize = 20;
+
+// Lists every runnable script under the root and lets
+// the user narrow it down by typing. Returns the path
+// of the one that was picked or None if the picker
+// was cancelled.
+pub fn pick(inventory: &Inventory) -> Option<PathBuf> {
+    let root = inventory.root();
+    let scripts = inventory.scripts();
+    if scripts.is_empty() {
+        println!("No scripts found to pick from");
+        return None;
+    }
+    let labels: Vec<String> = scripts
+        .iter()
+        .map(|script| {
+            script
+                .strip_prefix(root)
+                .unwrap_or(script)
+                .display()
+                .to_string()
+        })
+        .collect();
+    let stdin = std::io::stdin();
+    let original = termios::tcgetattr(&stdin).ok()?;
+    let mut picker_mode = original.clone();
+    picker_mode
+        .local_flags
+        .remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG);
+    termios::tcsetattr(&stdin, SetArg::TCSANOW, &picker_mode).ok()?;
+    let picked = run_picker(&labels);
+    let _ = termios::tcsetattr(&stdin, SetArg::TCSANOW, &original);
+    print!("\x1b[H\x1b[2J");
+    let _ = std::io::stdout().flush();
+    picked.map(|idx| scripts[idx].clone())
+}
+
+fn run_picker(labels: &[String]) -> Option<usize> {
+    let mut query = String::new();
+    let mut selected = 0;
+    let mut stdin = std::io::stdin();
+    loop {
+        let matches = rank(labels, &query);
+        selected = selected.min(matches.len().saturating_sub(1));
+        render(&query, labels, &matches, selected);
+        let mut buf = [0u8; 1];
+        if stdin.read(&mut buf).ok()? == 0 {
+            return None;
+        }
+        match buf[0] {
+            b'\n' | b'\r' => return matches.get(selected).copied(),
+            // ctrl-c and ctrl-g
+            0x03 | 0x07 => return None,
+            0x1b => match read_escape(&mut stdin) {
+                Some(b'A') => selected = selected.saturating_sub(1),
+                Some(b'B') => selected += 1,
+                Some(_) => {}
+                None => return None,
+            },
+            // ctrl-p and ctrl-n
+            0x10 => selected = selected.saturating_sub(1),
+            0x0e => selected += 1,
+            // backspace and delete
+            0x08 | 0x7f => {
+                query.pop();
+                selected = 0;
+            }
+            c if c.is_ascii_graphic() || c == b' ' => {
+                query.push(c as char);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+// Arrow keys show up as `ESC [ A` and friends. A bare
+// escape (i.e. nothing else arrives right after it)
+// cancels the picker.
+fn read_escape(stdin: &mut std::io::Stdin) -> Option<u8> {
+    let mut buf = [0u8; 2];
+    let mut fds = [PollFd::new(stdin.as_fd(), PollFlags::POLLIN)];
+    match poll(&mut fds, 50u16) {
+        Ok(n) if n > 0 => {}
+        _ => return None,
+    }
+    stdin.read_exact(&mut buf).ok()?;
+    Some(buf[1])
+}
+
+fn render(query: &str, labels: &[String], matches: &[usize], selected: usize) {
+    let mut stdout = std::io::stdout();
+    let _ = write!(stdout, "\x1b[H\x1b[2J");
+    let _ = writeln!(
+        stdout,
+        "\x1b[2mPick a script (type to filter, enter to run, esc to cancel)\x1b[0m"
+    );
+    let _ = writeln!(stdout, "> {}", query);
+    let start = selected.saturating_sub(MAX_SHOWN - 1);
+    for (position, idx) in matches.iter().enumerate().skip(start).take(MAX_SHOWN) {
+        if position == selected {
+            let _ = writeln!(stdout, "\x1b[7m> {}\x1b[0m", labels[*idx]);
+        } else {
+            let _ = writeln!(stdout, "  {}", labels[*idx]);
+        }
+    }
+    let _ = writeln!(stdout, "\x1b[2m{}/{}\x1b[0m", matches.len(), labels.len());
+    let _ = stdout.flush();
+}
+
+// Indexes of the labels that match the query with the
+// best matches first.
+fn rank(labels: &[String], query: &str) -> Vec<usize> {
+    let mut scored: Vec<(i64, usize)> = labels
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, label)| fuzzy_score(query, label).map(|score| (score, idx)))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored.into_iter().map(|(_, idx)| idx).collect()
+}
+
+// Every character of the query has to show up in the
+// candidate in order. Runs of consecutive characters and
+// matches at the start of a word score higher.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let query: Vec<char> = query.to_lowercase().chars().collect();
+    let chars: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut qi = 0;
+    let mut previous: Option<usize> = None;
+    for (idx, ch) in chars.iter().enumerate() {
+        if qi < query.len() && *ch == query[qi] {
+            score += 1;
+            if idx > 0 && previous == Some(idx - 1) {
+                score += 5;
+            }
+            if idx == 0 || matches!(chars[idx - 1], '/' | '_' | '-' | '.' | ' ') {
+                score += 3;
+            }
+            previous = Some(idx);
+            qi += 1;
+        }
+    }
+    if qi < query.len() {
+        return None;
+    }
+    Some(score * 10 - chars.len() as i64)
+}
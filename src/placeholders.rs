@@ -0,0 +1,281 @@
+use crate::run::{JobResult, describe_status, exit_code};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+// What a run's commands can be told about it. Every job
+// and guard gets these as environment variables and the
+// args to --then and the --guard/--then-guard commands
+// can use them as placeholders:
+//
+//   {script}        WS_SCRIPT        the script that's running
+//   {trigger}       WS_TRIGGER       the file that changed
+//   {trigger_dir}   WS_TRIGGER_DIR   the directory it's in
+//   {exit}          WS_EXIT          the main script's exit code
+//   {status}        WS_STATUS        how it finished (`exit 0`, ...)
+//   {duration_ms}   WS_DURATION_MS   how long it ran
+//   {run_number}    WS_RUN_NUMBER    which run it is this session
+//
+// The [hooks], the chat notification templates, and
+// --banner use the same names (see fill and from_run).
+//
+// {exit}, {status}, and {duration_ms} are only there
+// once the main script's done (so for the then script
+// and --then-guard). The rest are set as the run starts.
+//
+// The then script also gets how the main run went
+// under names of its own, for scripts that log or
+// branch on it without taking args:
+//
+//   WS_MAIN_SCRIPT        the script it follows
+//   WS_MAIN_EXIT_CODE     its exit code (unset if it
+//                         was killed by a signal)
+//   WS_MAIN_DURATION_MS   how long it ran
+//
+// WS_HANDOFF is the path of a JSON file the main script
+// can write (artifact lists, versions, etc.) for the
+// then script to read. Both get the same path. It's in
+// a directory of the watcher's own under the temp
+// directory so writing it doesn't set off anything and
+// it's removed once the run's done.
+//
+// With --touched WS_TOUCHED has the files the main
+// script created or modified, a path a line relative to
+// the watch root. Like WS_MAIN_EXIT_CODE and
+// WS_MAIN_DURATION_MS it's only set once the main
+// script's done.
+const PLACEHOLDERS: [(&str, &str); 7] = [
+    ("{script}", "WS_SCRIPT"),
+    ("{trigger}", "WS_TRIGGER"),
+    ("{trigger_dir}", "WS_TRIGGER_DIR"),
+    ("{exit}", "WS_EXIT"),
+    ("{status}", "WS_STATUS"),
+    ("{duration_ms}", "WS_DURATION_MS"),
+    ("{run_number}", "WS_RUN_NUMBER"),
+];
+
+const MAIN_VARS: [&str; 5] = [
+    "WS_MAIN_SCRIPT",
+    "WS_MAIN_EXIT_CODE",
+    "WS_MAIN_DURATION_MS",
+    "WS_HANDOFF",
+    "WS_TOUCHED",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct RunVars {
+    vars: Arc<Mutex<BTreeMap<&'static str, String>>>,
+}
+
+impl RunVars {
+    pub fn start(&self, trigger: &Path, script: &Path, run_number: usize) {
+        let mut vars = self.vars.lock().unwrap();
+        vars.clear();
+        if let Some(handoff) = handoff_path(run_number) {
+            vars.insert("WS_HANDOFF", handoff.display().to_string());
+        }
+        vars.insert("WS_MAIN_SCRIPT", script.display().to_string());
+        vars.insert("WS_SCRIPT", script.display().to_string());
+        vars.insert("WS_RUN_NUMBER", run_number.to_string());
+        vars.insert("WS_TRIGGER", trigger.display().to_string());
+        if let Some(dir) = trigger.parent() {
+            vars.insert("WS_TRIGGER_DIR", dir.display().to_string());
+        }
+    }
+
+    pub fn finish(&self, result: &JobResult) {
+        let mut vars = self.vars.lock().unwrap();
+        if let Some(code) = exit_code(&result.status) {
+            vars.insert("WS_EXIT", code.to_string());
+            vars.insert("WS_MAIN_EXIT_CODE", code.to_string());
+        }
+        vars.insert("WS_STATUS", describe_status(&result.status));
+        let duration_ms = result.duration.as_millis().to_string();
+        vars.insert("WS_DURATION_MS", duration_ms.clone());
+        vars.insert("WS_MAIN_DURATION_MS", duration_ms);
+    }
+
+    pub fn touched(&self, files: String) {
+        self.vars.lock().unwrap().insert("WS_TOUCHED", files);
+    }
+
+    pub fn get(&self) -> BTreeMap<&'static str, String> {
+        self.vars.lock().unwrap().clone()
+    }
+}
+
+// The watcher's own directory for the handoff files,
+// made the first time one's needed. Only its owner can
+// get into it so nobody else can put a file (or a
+// symlink) where a script is about to write.
+static HANDOFF_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+// Unique to the run so two runs with --jobs don't share
+// one
+pub fn handoff_path(run_number: usize) -> Option<PathBuf> {
+    let dir = HANDOFF_DIR.get_or_init(|| {
+        nix::unistd::mkdtemp(&std::env::temp_dir().join("ws-handoff-XXXXXX"))
+            .map_err(|e| {
+                eprintln!(
+                    "\x1b[33mcould not make a directory for WS_HANDOFF: {}\x1b[0m",
+                    e
+                )
+            })
+            .ok()
+    });
+    dir.as_ref()
+        .map(|dir| dir.join(format!("{}.json", run_number)))
+}
+
+// On the way out
+pub fn remove_handoff_dir() {
+    if let Some(Some(dir)) = HANDOFF_DIR.get() {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+// Every variable a run can get
+pub fn var_names() -> impl Iterator<Item = &'static str> {
+    PLACEHOLDERS.iter().map(|(_, var)| *var).chain(MAIN_VARS)
+}
+
+// The placeholders become references to the variables so
+// the values are quoted by the shell and can be filled
+// in when the job starts instead of when it's created
+pub fn expand(command: &str) -> String {
+    PLACEHOLDERS
+        .iter()
+        .fold(command.to_string(), |command, (placeholder, var)| {
+            command.replace(placeholder, &format!("\"${}\"", var))
+        })
+}
+
+// For templates that aren't run through the shell. Any
+// that there isn't a value for become `-`.
+pub fn fill(template: &str, vars: &BTreeMap<&'static str, String>) -> String {
+    PLACEHOLDERS
+        .iter()
+        .fold(template.to_string(), |filled, (placeholder, var)| {
+            filled.replace(placeholder, vars.get(var).map_or("-", String::as_str))
+        })
+}
+
+// The variables for a run from the stream's run events
+// (for the followers that only see those)
+pub fn from_run(run: &Value) -> BTreeMap<&'static str, String> {
+    let mut vars = BTreeMap::new();
+    for (field, var) in [
+        ("script", "WS_SCRIPT"),
+        ("trigger_path", "WS_TRIGGER"),
+        ("exit_code", "WS_EXIT"),
+        ("status", "WS_STATUS"),
+        ("duration_ms", "WS_DURATION_MS"),
+        ("run_number", "WS_RUN_NUMBER"),
+    ] {
+        match &run[field] {
+            Value::String(value) => vars.insert(var, value.clone()),
+            Value::Null => None,
+            value => vars.insert(var, value.to_string()),
+        };
+    }
+    if let Some(dir) = vars
+        .get("WS_TRIGGER")
+        .and_then(|trigger| Path::new(trigger).parent())
+    {
+        vars.insert("WS_TRIGGER_DIR", dir.display().to_string());
+    }
+    vars
+}
+
+// `--then "notify.sh {exit}"` is the script and its args.
+// A path that exists is taken as a whole so scripts with
+// spaces in their names still work.
+pub fn split_then(dir: &Path, then: PathBuf) -> (PathBuf, Option<String>) {
+    if dir.join(&then).exists() {
+        return (then, None);
+    }
+    let value = then.to_string_lossy().to_string();
+    match value.trim().split_once(char::is_whitespace) {
+        Some((path, args)) => (PathBuf::from(path), Some(args.trim().to_string())),
+        None => (then, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn expands_to_quoted_variables() {
+        assert_eq!(
+            expand("notify.sh {exit} {script} {exit}"),
+            r#"notify.sh "$WS_EXIT" "$WS_SCRIPT" "$WS_EXIT""#
+        );
+        assert_eq!(expand("{unknown}"), "{unknown}");
+    }
+
+    #[test]
+    fn fills_missing_values_with_a_dash() {
+        let vars = BTreeMap::from([
+            ("WS_SCRIPT", "build.sh".to_string()),
+            ("WS_DURATION_MS", "12".to_string()),
+        ]);
+        assert_eq!(
+            fill("{script} took {duration_ms}ms ({exit})", &vars),
+            "build.sh took 12ms (-)"
+        );
+    }
+
+    #[test]
+    fn reads_the_variables_from_a_run_event() {
+        let vars = from_run(&json!({
+            "script": "build.sh",
+            "trigger_path": "src/lib.rs",
+            "exit_code": 0,
+            "status": "exit 0",
+            "duration_ms": 12,
+            "run_number": 3,
+        }));
+        assert_eq!(
+            vars,
+            BTreeMap::from([
+                ("WS_SCRIPT", "build.sh".to_string()),
+                ("WS_TRIGGER", "src/lib.rs".to_string()),
+                ("WS_TRIGGER_DIR", "src".to_string()),
+                ("WS_EXIT", "0".to_string()),
+                ("WS_STATUS", "exit 0".to_string()),
+                ("WS_DURATION_MS", "12".to_string()),
+                ("WS_RUN_NUMBER", "3".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn leaves_out_what_a_run_event_does_not_have() {
+        let vars = from_run(&json!({ "script": "build.sh", "exit_code": null }));
+        assert_eq!(
+            vars,
+            BTreeMap::from([("WS_SCRIPT", "build.sh".to_string())])
+        );
+    }
+
+    #[test]
+    fn splits_then_into_the_script_and_its_args() {
+        let dir = std::env::temp_dir().join(format!("ws-split-then-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("my notify.sh"), "").unwrap();
+        let split = |then: &str| split_then(&dir, PathBuf::from(then));
+        assert_eq!(
+            split("notify.sh {exit} {script}"),
+            (
+                PathBuf::from("notify.sh"),
+                Some("{exit} {script}".to_string())
+            )
+        );
+        assert_eq!(split("notify.sh"), (PathBuf::from("notify.sh"), None));
+        assert_eq!(split("my notify.sh"), (PathBuf::from("my notify.sh"), None));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
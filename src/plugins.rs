@@ -0,0 +1,238 @@
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use wasmi::{Config, Engine, Instance, Linker, Memory, Module, Store};
+
+// WASM modules loaded with --plugin (or `plugins` in the
+// config) that can change what the watcher does. A plugin
+// exports `memory` and `alloc(len: i32) -> i32` for the
+// watcher to write strings into, plus any of:
+//
+//   should_trigger(ptr, len) -> i32
+//       gets the changed path, returning 0 skips the run
+//   rewrite_command(ptr, len) -> i64
+//       gets the command about to run
+//   process_line(ptr, len) -> i64
+//       gets each line of output
+//
+// The i64 results are `(ptr << 32) | len` of a
+// replacement string, 0 to keep the input as is, or -1
+// (process_line only) to drop the line. After each call
+// the watcher hands the strings back with
+// `dealloc(ptr, len)` or calls `reset()` to start the
+// allocator over, if the plugin exports either. Plugins
+// don't get any imports so they can't touch anything
+// outside of their own memory, and each call gets a
+// fixed amount of fuel so one that loops forever gets
+// stopped (and reported) instead of hanging the watcher.
+// Roughly how many instructions a call can run
+const FUEL: u64 = 10_000_000;
+
+#[derive(Debug, Clone)]
+pub struct Plugins {
+    plugins: Arc<Vec<Mutex<Plugin>>>,
+}
+
+struct Plugin {
+    instance: Instance,
+    memory: Memory,
+    path: PathBuf,
+    store: Store<()>,
+}
+
+impl std::fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Plugin").field("path", &self.path).finish()
+    }
+}
+
+// What process_line wants done with a line.
+pub enum LineChange {
+    Drop,
+    Keep,
+    Replace(String),
+}
+
+impl Plugins {
+    pub fn load(paths: &[PathBuf]) -> Result<Plugins> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let linker = Linker::<()>::new(&engine);
+        let mut plugins = vec![];
+        for path in paths {
+            let bytes = std::fs::read(path)
+                .map_err(|e| anyhow!("could not read plugin {}: {}", path.display(), e))?;
+            let module = Module::new(&engine, bytes)
+                .map_err(|e| anyhow!("invalid plugin {}: {}", path.display(), e))?;
+            let mut store = Store::new(&engine, ());
+            store
+                .set_fuel(FUEL)
+                .map_err(|e| anyhow!("could not start plugin {}: {}", path.display(), e))?;
+            let instance = linker
+                .instantiate_and_start(&mut store, &module)
+                .map_err(|e| anyhow!("could not start plugin {}: {}", path.display(), e))?;
+            let memory = instance
+                .get_memory(&store, "memory")
+                .ok_or_else(|| anyhow!("plugin {} doesn't export memory", path.display()))?;
+            plugins.push(Mutex::new(Plugin {
+                instance,
+                memory,
+                path: path.clone(),
+                store,
+            }));
+        }
+        Ok(Plugins {
+            plugins: Arc::new(plugins),
+        })
+    }
+
+    // Every plugin that has an opinion has to agree
+    pub fn should_trigger(&self, path: &Path) -> bool {
+        let path = path.display().to_string();
+        self.plugins.iter().all(|plugin| {
+            let mut plugin = plugin.lock().unwrap();
+            match plugin.call_i32("should_trigger", &path) {
+                Some(result) => result != 0,
+                None => true,
+            }
+        })
+    }
+
+    // Each plugin gets the command the one before it
+    // settled on.
+    pub fn rewrite_command(&self, command: String) -> String {
+        self.plugins.iter().fold(command, |command, plugin| {
+            let mut plugin = plugin.lock().unwrap();
+            match plugin.call_string("rewrite_command", &command) {
+                LineChange::Replace(rewritten) => rewritten,
+                _ => command,
+            }
+        })
+    }
+
+    pub fn process_line(&self, line: &str) -> LineChange {
+        let mut current: Option<String> = None;
+        for plugin in self.plugins.iter() {
+            let mut plugin = plugin.lock().unwrap();
+            match plugin.call_string("process_line", current.as_deref().unwrap_or(line)) {
+                LineChange::Drop => return LineChange::Drop,
+                LineChange::Keep => {}
+                LineChange::Replace(replacement) => current = Some(replacement),
+            }
+        }
+        match current {
+            Some(replacement) => LineChange::Replace(replacement),
+            None => LineChange::Keep,
+        }
+    }
+}
+
+impl Plugin {
+    // Copies the input into the plugin's memory and calls
+    // the export with it. Returns None if the plugin
+    // doesn't have that export or something went wrong
+    // (which gets reported).
+    fn call_i32(&mut self, name: &str, input: &str) -> Option<i32> {
+        let func = self
+            .instance
+            .get_typed_func::<(i32, i32), i32>(&self.store, name)
+            .ok()?;
+        self.refuel()?;
+        let ptr = self.write_input(input)?;
+        let result = func.call(&mut self.store, (ptr, input.len() as i32));
+        let result = self.report(result);
+        self.free(&[(ptr, input.len() as i32)]);
+        result
+    }
+
+    fn call_string(&mut self, name: &str, input: &str) -> LineChange {
+        let Ok(func) = self
+            .instance
+            .get_typed_func::<(i32, i32), i64>(&self.store, name)
+        else {
+            return LineChange::Keep;
+        };
+        if self.refuel().is_none() {
+            return LineChange::Keep;
+        }
+        let Some(ptr) = self.write_input(input) else {
+            return LineChange::Keep;
+        };
+        let result = func.call(&mut self.store, (ptr, input.len() as i32));
+        let mut buffers = vec![(ptr, input.len() as i32)];
+        let change = match self.report(result) {
+            Some(-1) => LineChange::Drop,
+            Some(0) | None => LineChange::Keep,
+            Some(packed) => {
+                let ptr = (packed as u64 >> 32) as usize;
+                let len = (packed as u64 & 0xffff_ffff) as usize;
+                buffers.push((ptr as i32, len as i32));
+                let mut buf = vec![0u8; len];
+                match self.memory.read(&self.store, ptr, &mut buf) {
+                    Ok(()) => LineChange::Replace(String::from_utf8_lossy(&buf).to_string()),
+                    Err(e) => {
+                        self.report::<()>(Err(e.into()));
+                        LineChange::Keep
+                    }
+                }
+            }
+        };
+        self.free(&buffers);
+        change
+    }
+
+    // Each call starts with a full tank
+    fn refuel(&mut self) -> Option<()> {
+        let refueled = self.store.set_fuel(FUEL);
+        self.report(refueled)
+    }
+
+    // Hands the strings from a call back to the plugin
+    fn free(&mut self, buffers: &[(i32, i32)]) {
+        if let Ok(reset) = self.instance.get_typed_func::<(), ()>(&self.store, "reset") {
+            let result = reset.call(&mut self.store, ());
+            self.report(result);
+            return;
+        }
+        let Ok(dealloc) = self
+            .instance
+            .get_typed_func::<(i32, i32), ()>(&self.store, "dealloc")
+        else {
+            return;
+        };
+        for (ptr, len) in buffers {
+            let result = dealloc.call(&mut self.store, (*ptr, *len));
+            self.report(result);
+        }
+    }
+
+    fn write_input(&mut self, input: &str) -> Option<i32> {
+        let alloc = self
+            .instance
+            .get_typed_func::<i32, i32>(&self.store, "alloc")
+            .ok()?;
+        let ptr = alloc.call(&mut self.store, input.len() as i32);
+        let ptr = self.report(ptr)?;
+        let written = self
+            .memory
+            .write(&mut self.store, ptr as usize, input.as_bytes())
+            .map_err(wasmi::Error::from);
+        self.report(written)?;
+        Some(ptr)
+    }
+
+    fn report<T>(&self, result: Result<T, wasmi::Error>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                eprintln!(
+                    "\x1b[33mplugin {} failed: {}\x1b[0m",
+                    self.path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,508 @@
+use anyhow::{Result, anyhow};
+use regex::{Captures, Regex};
+
+// Matchers that find the file and line of errors in a
+// run's output.
+//
+// --problems picks out the errors and warnings in a run's
+// output so they can be listed together once it's done
+// instead of being scrolled back for. It takes a preset
+// (rustc, pytest, or eslint) or a pattern with `file`
+// and `line` groups and optional `col`, `message`, and
+// `severity` ones.
+//
+// Some tools split a problem over more than one line.
+// A preset's heading is a line that holds part of the
+// problem (rustc's `error[E0308]: ...` or the file name
+// eslint puts above its list) that the location lines
+// after it fill in the rest of.
+#[derive(Debug, Clone)]
+pub struct ProblemMatchers {
+    matchers: Matchers,
+}
+
+// --diagnostics goes through the same matchers to add a
+// `file:line:col: message` line under each location in
+// compiler and linter output so terminals can turn it
+// into a link. For tools that print the message on a
+// line before the location (like rustc) the last line
+// that looked like an error or warning is used.
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    matchers: Matchers,
+}
+
+// Locations for tools that don't already print
+// `file:line:col: message`, for --diagnostics
+const DIAGNOSTIC_LOCATIONS: [&str; 4] = [
+    // rustc and cargo: `  --> src/main.rs:4:18`
+    r"^\s*--> (?P<file>[^:\s]+):(?P<line>\d+):(?P<col>\d+)",
+    // python tracebacks: `  File "app.py", line 12, in main`
+    r#"^\s*File "(?P<file>[^"]+)", line (?P<line>\d+)"#,
+    // node stack traces: `    at run (/app/index.js:10:5)`
+    r"^\s*at (?:.+ \()?(?P<file>[^():\s]+):(?P<line>\d+):(?P<col>\d+)\)?$",
+    // eslint and friends: `  src/app.ts(10,5): error TS2322: ...`
+    r"^(?P<file>[^():\s]+)\((?P<line>\d+),(?P<col>\d+)\): (?P<message>.+)$",
+];
+
+// The whole line is the message for the location after it
+const DIAGNOSTIC_HEADING: &str =
+    r"^(?P<message>(?:error|warning)(?:\[\w+\])?: .+|\w*(?:Error|Exception): .+)$";
+
+// The lines each matcher is after, tried in order
+#[derive(Debug, Clone)]
+struct Matchers {
+    matchers: Vec<Matcher>,
+}
+
+#[derive(Debug, Clone)]
+struct Matcher {
+    heading: Option<Regex>,
+    location: Regex,
+    // rustc's heading goes with the one location under
+    // it but eslint's goes with everything until the
+    // next file
+    heading_once: bool,
+    // whether a location without a heading counts
+    heading_required: bool,
+    last_heading: Option<Problem>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Problem {
+    pub col: Option<String>,
+    pub file: String,
+    pub line: String,
+    pub message: String,
+    pub severity: String,
+}
+
+pub const PRESETS: [&str; 3] = ["rustc", "pytest", "eslint"];
+
+impl ProblemMatchers {
+    pub fn new(names: &[String]) -> Result<Option<ProblemMatchers>> {
+        let mut matchers = vec![];
+        for name in names {
+            matchers.push(Matcher::problems(name)?);
+        }
+        Ok((!matchers.is_empty()).then(|| ProblemMatchers {
+            matchers: Matchers { matchers },
+        }))
+    }
+
+    pub fn check(&mut self, line: &str) -> Option<Problem> {
+        let mut problem = self.matchers.check(line)?;
+        if problem.severity.is_empty() {
+            problem.severity = "error".to_string();
+        }
+        Some(problem)
+    }
+
+    pub fn reset(&mut self) {
+        self.matchers.reset();
+    }
+}
+
+impl Diagnostics {
+    // Matchers from the command line are tried before
+    // the built in ones.
+    pub fn new(extra: &[String]) -> Result<Diagnostics> {
+        let heading = Regex::new(DIAGNOSTIC_HEADING).unwrap();
+        let mut matchers = vec![];
+        for pattern in extra.iter().map(|p| p.as_str()).chain(DIAGNOSTIC_LOCATIONS) {
+            matchers.push(Matcher {
+                heading: Some(heading.clone()),
+                location: location(pattern, "diagnostics matcher")?,
+                heading_once: true,
+                heading_required: false,
+                last_heading: None,
+            });
+        }
+        Ok(Diagnostics {
+            matchers: Matchers { matchers },
+        })
+    }
+
+    // Returns the normalized line if this one points at
+    // a location. A heading only gets used once.
+    pub fn normalize(&mut self, line: &str) -> Option<String> {
+        let problem = self.matchers.check(line)?;
+        Some(match problem.message.is_empty() {
+            true => problem.location(),
+            false => format!("{}: {}", problem.location(), problem.message),
+        })
+    }
+}
+
+impl Matchers {
+    fn check(&mut self, line: &str) -> Option<Problem> {
+        let line = strip_ansi(line);
+        let line = line.trim_end();
+        let problem = self
+            .matchers
+            .iter_mut()
+            .find_map(|matcher| matcher.check(line))?;
+        // every matcher saw the heading so the others'
+        // copies of one that's been used up go too
+        for matcher in &mut self.matchers {
+            if matcher.heading_once {
+                matcher.last_heading = None;
+            }
+        }
+        Some(problem)
+    }
+
+    fn reset(&mut self) {
+        for matcher in &mut self.matchers {
+            matcher.last_heading = None;
+        }
+    }
+}
+
+// A location pattern of the user's own, which has to
+// have `file` and `line` groups
+fn location(pattern: &str, what: &str) -> Result<Regex> {
+    let location =
+        Regex::new(pattern).map_err(|e| anyhow!("invalid {} {}: {}", what, pattern, e))?;
+    let names: Vec<&str> = location.capture_names().flatten().collect();
+    if !names.contains(&"file") || !names.contains(&"line") {
+        return Err(anyhow!("{} needs file and line groups: {}", what, pattern));
+    }
+    Ok(location)
+}
+
+impl Matcher {
+    fn problems(name: &str) -> Result<Matcher> {
+        let preset = |heading: &str, location: &str, heading_once: bool| Matcher {
+            heading: Some(Regex::new(heading).unwrap()),
+            location: Regex::new(location).unwrap(),
+            heading_once,
+            heading_required: true,
+            last_heading: None,
+        };
+        let matcher = match name {
+            // error[E0308]: mismatched types
+            //  --> src/main.rs:4:18
+            "rustc" => preset(
+                r"^(?P<severity>error|warning)(?:\[\w+\])?: (?P<message>.+)$",
+                r"^\s*--> (?P<file>[^:\s]+):(?P<line>\d+):(?P<col>\d+)",
+                true,
+            ),
+            // tests/test_app.py:12: AssertionError
+            "pytest" => Matcher {
+                heading: None,
+                location: Regex::new(r"^(?P<file>[^:\s]+\.py):(?P<line>\d+): (?P<message>\w+)$")
+                    .unwrap(),
+                heading_once: false,
+                heading_required: false,
+                last_heading: None,
+            },
+            // /app/src/index.js
+            //   10:5  error  'x' is not defined  no-undef
+            "eslint" => preset(
+                r"^(?P<file>/\S+|[^\s:]+\.\w+)$",
+                r"^\s+(?P<line>\d+):(?P<col>\d+)\s+(?P<severity>error|warning)\s+(?P<message>.+?)$",
+                false,
+            ),
+            pattern => Matcher {
+                heading: None,
+                location: location(pattern, "problem matcher").map_err(|e| {
+                    anyhow!(
+                        "--problems takes one of {} or a pattern ({})",
+                        PRESETS.join(", "),
+                        e
+                    )
+                })?,
+                heading_once: false,
+                heading_required: false,
+                last_heading: None,
+            },
+        };
+        Ok(matcher)
+    }
+
+    fn check(&mut self, line: &str) -> Option<Problem> {
+        if let Some(caps) = self.heading.as_ref().and_then(|h| h.captures(line)) {
+            self.last_heading = Some(Problem::default().fill(&caps));
+            return None;
+        }
+        let caps = self.location.captures(line)?;
+        let heading = if self.heading_once {
+            self.last_heading.take()
+        } else {
+            self.last_heading.clone()
+        };
+        // a location without its heading (like rustc's
+        // notes) isn't a problem of its own
+        if self.heading_required && heading.is_none() {
+            return None;
+        }
+        Some(heading.unwrap_or_default().fill(&caps))
+    }
+}
+
+impl Problem {
+    fn fill(mut self, caps: &Captures) -> Problem {
+        let group = |name: &str| caps.name(name).map(|m| m.as_str().to_string());
+        if let Some(file) = group("file") {
+            self.file = file;
+        }
+        if let Some(line) = group("line") {
+            self.line = line;
+        }
+        if let Some(col) = group("col") {
+            self.col = Some(col);
+        }
+        if let Some(message) = group("message") {
+            self.message = message;
+        }
+        if let Some(severity) = group("severity") {
+            self.severity = severity.to_lowercase();
+        }
+        self
+    }
+
+    fn location(&self) -> String {
+        match &self.col {
+            Some(col) => format!("{}:{}:{}", self.file, self.line, col),
+            None => format!("{}:{}", self.file, self.line),
+        }
+    }
+}
+
+// The block that goes after a run that had problems
+pub fn print_summary(problems: &[Problem]) {
+    if problems.is_empty() {
+        return;
+    }
+    let width = problems
+        .iter()
+        .map(|problem| problem.location().chars().count())
+        .max()
+        .unwrap_or(0);
+    println!("\x1b[2m---------------------- problems ----------------------\x1b[0m");
+    for problem in problems {
+        let color = if problem.severity == "error" {
+            "\x1b[31m"
+        } else {
+            "\x1b[33m"
+        };
+        println!(
+            "{}{:<7}\x1b[0m {:<width$}  {}",
+            color,
+            problem.severity,
+            problem.location(),
+            problem.message,
+            width = width
+        );
+    }
+    let errors = problems.iter().filter(|p| p.severity == "error").count();
+    let plural = |n: usize, word: &str| format!("{} {}{}", n, word, if n == 1 { "" } else { "s" });
+    println!(
+        "\x1b[2m{}, {}\x1b[0m",
+        plural(errors, "error"),
+        plural(problems.len() - errors, "warning")
+    );
+}
+
+// GitHub Actions workflow commands for --github so the
+// problems show up on the run's summary page and next to
+// the lines in the diff:
+//
+//   ::error file=src/main.rs,line=4,col=9::mismatched types
+//
+// Anything that isn't an error goes out as a warning.
+pub fn print_annotations(problems: &[Problem]) {
+    for problem in problems {
+        let level = if problem.severity == "error" {
+            "error"
+        } else {
+            "warning"
+        };
+        let mut properties = format!(
+            "file={},line={}",
+            escape_property(&problem.file),
+            escape_property(&problem.line)
+        );
+        if let Some(col) = &problem.col {
+            properties.push_str(&format!(",col={}", escape_property(col)));
+        }
+        println!(
+            "::{} {}::{}",
+            level,
+            properties,
+            escape_data(&problem.message)
+        );
+    }
+}
+
+// The escapes the runner undoes in a command's message
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+// and in its properties, where `:` and `,` mean something
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+pub fn strip_ansi(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\x1b' {
+            if chars.next() == Some('[') {
+                for ch in chars.by_ref() {
+                    if ch.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        stripped.push(ch);
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matchers(names: &[&str]) -> ProblemMatchers {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        ProblemMatchers::new(&names).unwrap().unwrap()
+    }
+
+    fn problem(
+        file: &str,
+        line: &str,
+        col: Option<&str>,
+        message: &str,
+        severity: &str,
+    ) -> Problem {
+        Problem {
+            col: col.map(str::to_string),
+            file: file.to_string(),
+            line: line.to_string(),
+            message: message.to_string(),
+            severity: severity.to_string(),
+        }
+    }
+
+    #[test]
+    fn none_without_names() {
+        assert!(ProblemMatchers::new(&[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn rustc_headings_go_with_one_location() {
+        let mut rustc = matchers(&["rustc"]);
+        assert_eq!(rustc.check("error[E0308]: mismatched types"), None);
+        assert_eq!(
+            rustc.check("  --> src/main.rs:4:18"),
+            Some(problem(
+                "src/main.rs",
+                "4",
+                Some("18"),
+                "mismatched types",
+                "error"
+            ))
+        );
+        // a note's location after it
+        assert_eq!(rustc.check("  --> src/lib.rs:1:1"), None);
+    }
+
+    #[test]
+    fn pytest_failures() {
+        let mut pytest = matchers(&["pytest"]);
+        assert_eq!(
+            pytest.check("tests/test_app.py:12: AssertionError"),
+            Some(problem(
+                "tests/test_app.py",
+                "12",
+                None,
+                "AssertionError",
+                "error"
+            ))
+        );
+        assert_eq!(pytest.check("tests/test_app.py::test_it PASSED"), None);
+    }
+
+    #[test]
+    fn eslint_headings_go_with_every_location_under_them() {
+        let mut eslint = matchers(&["eslint"]);
+        assert_eq!(eslint.check("/app/src/index.js"), None);
+        assert_eq!(
+            eslint.check("  10:5  error  'x' is not defined  no-undef"),
+            Some(problem(
+                "/app/src/index.js",
+                "10",
+                Some("5"),
+                "'x' is not defined  no-undef",
+                "error"
+            ))
+        );
+        assert_eq!(
+            eslint.check("  12:1  warning  Unexpected console statement  no-console"),
+            Some(problem(
+                "/app/src/index.js",
+                "12",
+                Some("1"),
+                "Unexpected console statement  no-console",
+                "warning"
+            ))
+        );
+    }
+
+    #[test]
+    fn custom_patterns() {
+        let mut custom = matchers(&[r"^(?P<file>[^:]+):(?P<line>\d+): (?P<message>.+)$"]);
+        assert_eq!(
+            custom.check("\x1b[31mmain.c:3: oops\x1b[0m"),
+            Some(problem("main.c", "3", None, "oops", "error"))
+        );
+        assert!(ProblemMatchers::new(&["(?P<file>.+)".to_string()]).is_err());
+        assert!(ProblemMatchers::new(&["(".to_string()]).is_err());
+    }
+
+    #[test]
+    fn reset_forgets_the_last_heading() {
+        let mut rustc = matchers(&["rustc"]);
+        rustc.check("warning: unused variable");
+        rustc.reset();
+        assert_eq!(rustc.check("  --> src/main.rs:2:9"), None);
+    }
+
+    #[test]
+    fn normalizes_locations_with_the_heading_before_them() {
+        let mut diagnostics = Diagnostics::new(&[]).unwrap();
+        assert_eq!(
+            diagnostics.normalize("error[E0425]: cannot find value `x`"),
+            None
+        );
+        assert_eq!(
+            diagnostics.normalize("  --> src/main.rs:2:5").as_deref(),
+            Some("src/main.rs:2:5: error[E0425]: cannot find value `x`")
+        );
+        assert_eq!(
+            diagnostics.normalize("  --> src/lib.rs:1:1").as_deref(),
+            Some("src/lib.rs:1:1")
+        );
+        assert_eq!(
+            diagnostics
+                .normalize(r#"  File "app.py", line 12, in main"#)
+                .as_deref(),
+            Some("app.py:12")
+        );
+        assert_eq!(diagnostics.normalize("all good"), None);
+    }
+
+    #[test]
+    fn strips_ansi_escapes() {
+        assert_eq!(strip_ansi("\x1b[1;31merror\x1b[0m: bad"), "error: bad");
+        assert_eq!(strip_ansi("plain"), "plain");
+    }
+}
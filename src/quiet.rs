@@ -0,0 +1,110 @@
+use crate::held::HeldRuns;
+use anyhow::{Result, anyhow};
+use chrono::{Local, NaiveTime, TimeDelta};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use watchexec::Watchexec;
+
+// `no_run = "22:00-07:00"` in the config keeps changes
+// from running anything between those times (local,
+// and it can wrap past midnight). The scripts that get
+// set off meanwhile are held (see held::HeldRuns) until
+// the window ends. Scripts run by name (keys, the
+// socket, HTTP) still go right away.
+#[derive(Debug, Clone)]
+pub struct QuietHours {
+    end: NaiveTime,
+    held: HeldRuns,
+    start: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn new(window: &str) -> Result<QuietHours> {
+        let invalid = || anyhow!("invalid no_run `{}` (expected HH:MM-HH:MM)", window);
+        let (start, end) = window.split_once('-').ok_or_else(invalid)?;
+        let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M");
+        Ok(QuietHours {
+            end: parse(end).map_err(|_| invalid())?,
+            held: HeldRuns::default(),
+            start: parse(start).map_err(|_| invalid())?,
+        })
+    }
+
+    // How long until the window ends if it's on now
+    pub fn remaining(&self) -> Option<Duration> {
+        self.remaining_at(Local::now().time())
+    }
+
+    fn remaining_at(&self, now: NaiveTime) -> Option<Duration> {
+        let inside = if self.start <= self.end {
+            self.start <= now && now < self.end
+        } else {
+            now >= self.start || now < self.end
+        };
+        if !inside {
+            return None;
+        }
+        let mut left = self.end - now;
+        if left < TimeDelta::zero() {
+            left += TimeDelta::days(1);
+        }
+        left.to_std().ok()
+    }
+
+    pub fn hold(
+        &self,
+        scripts: impl IntoIterator<Item = PathBuf>,
+        wait: Duration,
+        wx: Arc<Watchexec>,
+    ) {
+        if self.held.hold(scripts, wait, wx) {
+            println!(
+                "\x1b[33m🌙 Quiet hours until {}. The latest changes run then (or run a script by name now)\x1b[0m",
+                self.end.format("%H:%M")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn inside_a_window_in_the_same_day() {
+        let quiet = QuietHours::new("12:00-13:30").unwrap();
+        assert_eq!(
+            quiet.remaining_at(time(12, 0)),
+            Some(Duration::from_secs(90 * 60))
+        );
+        assert_eq!(quiet.remaining_at(time(11, 59)), None);
+        assert_eq!(quiet.remaining_at(time(13, 30)), None);
+    }
+
+    #[test]
+    fn wraps_past_midnight() {
+        let quiet = QuietHours::new("22:00 - 07:00").unwrap();
+        assert_eq!(
+            quiet.remaining_at(time(23, 0)),
+            Some(Duration::from_secs(8 * 60 * 60))
+        );
+        assert_eq!(
+            quiet.remaining_at(time(6, 30)),
+            Some(Duration::from_secs(30 * 60))
+        );
+        assert_eq!(quiet.remaining_at(time(7, 0)), None);
+        assert_eq!(quiet.remaining_at(time(21, 59)), None);
+    }
+
+    #[test]
+    fn rejects_bad_windows() {
+        for window in ["22:00", "22:00-7", "25:00-07:00", "night"] {
+            assert!(QuietHours::new(window).is_err(), "{}", window);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::output::OutputSink;
+use anyhow::{Result, anyhow};
+use chrono::Local;
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// --record <dir> saves each run's output in the dir as
+// an asciinema (v2) cast: a JSON header line and then
+// `[seconds, "o", text]` for each chunk of output as it
+// came in. The file name without `.cast` is the run id
+// that `ws replay` takes. asciinema can play them too.
+pub const EXTENSION: &str = "cast";
+
+// Long gaps (like a script waiting on the network) get
+// cut down to this on replay
+const MAX_PAUSE: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+pub struct Recording {
+    file: File,
+    id: String,
+    // the start of a character that got split between
+    // two reads
+    pending: Vec<u8>,
+    started: Instant,
+}
+
+impl Recording {
+    // Ids are the start time and the script's name so
+    // they sort in the order they ran
+    pub fn create(dir: &Path, script: &Path) -> Result<Recording> {
+        let name = script
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let stamp = Local::now();
+        let base = format!("{}-{}", stamp.format("%Y%m%d-%H%M%S"), name);
+        let mut id = base.clone();
+        let mut n = 1;
+        while cast_path(dir, &id).exists() {
+            n += 1;
+            id = format!("{}-{}", base, n);
+        }
+        let path = cast_path(dir, &id);
+        let mut file = File::create(&path)
+            .map_err(|e| anyhow!("could not record to {}: {}", path.display(), e))?;
+        let (width, height) = terminal_size();
+        let header = json!({
+            "version": 2,
+            "width": width,
+            "height": height,
+            "timestamp": stamp.timestamp(),
+            "title": script.display().to_string(),
+            "env": { "TERM": std::env::var("TERM").unwrap_or_default() },
+        });
+        writeln!(file, "{}", header)?;
+        Ok(Recording {
+            file,
+            id,
+            pending: vec![],
+            started: Instant::now(),
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl OutputSink for Recording {
+    fn write(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+        let complete = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        if complete == 0 {
+            return;
+        }
+        let chunk: Vec<u8> = self.pending.drain(..complete).collect();
+        let event = json!([
+            self.started.elapsed().as_secs_f64(),
+            "o",
+            String::from_utf8_lossy(&chunk)
+        ]);
+        let _ = writeln!(self.file, "{}", event);
+    }
+
+    fn run_id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+}
+
+fn cast_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.{}", id, EXTENSION))
+}
+
+// There's no good way to ask for the size when the
+// output is a pipe so this goes by what the shell
+// exported and falls back to 80x24
+fn terminal_size() -> (u16, u16) {
+    let var = |name: &str, default: u16| {
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    };
+    (var("COLUMNS", 80), var("LINES", 24))
+}
+
+// The run ids in the dir, oldest first
+pub fn list(dir: &Path) -> Result<Vec<String>> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| anyhow!("could not read {}: {}", dir.display(), e))?;
+    let mut ids: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+        .filter_map(|path| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+        })
+        .collect();
+    ids.sort();
+    Ok(ids)
+}
+
+// Plays a run back with the timing it had. `run` can
+// be an id from the dir or the path to a cast file.
+pub fn replay(dir: &Path, run: &str) -> Result<()> {
+    let path = if Path::new(run).is_file() {
+        PathBuf::from(run)
+    } else {
+        cast_path(dir, run)
+    };
+    let file = File::open(&path).map_err(|_| anyhow!("no recording for {}", run))?;
+    let mut lines = BufReader::new(file).lines();
+    // the header only matters to asciinema
+    lines.next().transpose()?;
+    let mut stdout = std::io::stdout();
+    let mut last = 0.0;
+    for line in lines {
+        let event: serde_json::Value = serde_json::from_str(&line?)
+            .map_err(|e| anyhow!("{} isn't a cast file: {}", path.display(), e))?;
+        let (Some(time), Some("o"), Some(text)) =
+            (event[0].as_f64(), event[1].as_str(), event[2].as_str())
+        else {
+            continue;
+        };
+        let pause = Duration::from_secs_f64((time - last).max(0.0));
+        std::thread::sleep(pause.min(MAX_PAUSE));
+        last = time;
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+// The recording of a run that the history has (by the
+// run id it kept) if it's still in the dir
+pub fn find(dir: &Path, id: &str) -> Option<PathBuf> {
+    let path = cast_path(dir, id);
+    (!id.contains('/') && path.is_file()).then_some(path)
+}
+
+// All of a recording's output at once
+pub fn output(path: &Path) -> Result<String> {
+    let file = File::open(path).map_err(|e| anyhow!("could not read {}: {}", path.display(), e))?;
+    let mut text = String::new();
+    for line in BufReader::new(file).lines().skip(1) {
+        let event: serde_json::Value = serde_json::from_str(&line?)
+            .map_err(|e| anyhow!("{} isn't a cast file: {}", path.display(), e))?;
+        if let (Some("o"), Some(chunk)) = (event[1].as_str(), event[2].as_str()) {
+            text.push_str(chunk);
+        }
+    }
+    Ok(text)
+}
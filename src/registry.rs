@@ -0,0 +1,97 @@
+use anyhow::{Result, anyhow};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+// Watchers started with --session <name> get a file
+// here holding the path to their control socket so
+// `ws attach <name>` can find them from anywhere. It
+// lives in XDG_RUNTIME_DIR when there is one so it goes
+// away on logout.
+fn sessions_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("watch_scripts"),
+        None => std::env::temp_dir().join(format!("watch_scripts-{}", nix::unistd::getuid())),
+    }
+}
+
+// Without XDG_RUNTIME_DIR it's under the shared temp
+// directory where anyone could have made it first (and
+// pointed the names at sockets of their own), so it's
+// only used when it's a real directory of ours that
+// nobody else can get into.
+fn own_sessions_dir() -> Result<PathBuf> {
+    let dir = sessions_dir();
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(anyhow!("could not make {}: {}", dir.display(), e)),
+    }
+    let metadata = std::fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir()
+        || metadata.uid() != nix::unistd::getuid().as_raw()
+        || metadata.mode() & 0o077 != 0
+    {
+        return Err(anyhow!(
+            "not using {} for sessions: it has to be a directory only you can get into",
+            dir.display()
+        ));
+    }
+    Ok(dir)
+}
+
+fn entry_path(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(anyhow!("invalid session name: {}", name));
+    }
+    Ok(own_sessions_dir()?.join(name))
+}
+
+// Names that were left behind by a watcher that didn't
+// get to clean up can be taken over
+pub fn register(name: &str, socket: &Path) -> Result<()> {
+    let path = entry_path(name)?;
+    if let Ok(socket) = lookup(name)
+        && UnixStream::connect(&socket).is_ok()
+    {
+        return Err(anyhow!(
+            "there's already a session named {} (watching {})",
+            name,
+            socket.parent().unwrap_or(&socket).display()
+        ));
+    }
+    std::fs::write(&path, socket.display().to_string())
+        .map_err(|e| anyhow!("could not register session {}: {}", name, e))?;
+    Ok(())
+}
+
+pub fn unregister(name: &str) {
+    if let Ok(path) = entry_path(name) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+pub fn lookup(name: &str) -> Result<PathBuf> {
+    let path = entry_path(name)?;
+    let socket = std::fs::read_to_string(&path).map_err(|_| match names().as_slice() {
+        [] => anyhow!("no session named {} (nothing is running)", name),
+        running => anyhow!(
+            "no session named {} (running: {})",
+            name,
+            running.join(", ")
+        ),
+    })?;
+    Ok(PathBuf::from(socket.trim()))
+}
+
+pub fn names() -> Vec<String> {
+    let Ok(entries) = own_sessions_dir().and_then(|dir| Ok(std::fs::read_dir(dir)?)) else {
+        return vec![];
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str().map(|name| name.to_string()))
+        .collect();
+    names.sort();
+    names
+}
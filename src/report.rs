@@ -0,0 +1,207 @@
+use crate::history;
+use crate::run::format_duration;
+use crate::session::RunInfo;
+use crate::stats::percentile;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, FixedOffset};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+pub use crate::history::HISTORY_NAME;
+
+// `ws report --html out.html` turns the --keep-history
+// file into one page that doesn't need anything else to
+// open: a timeline of the runs for each script, how long
+// each script takes, and the details of the failures.
+pub fn write_html(history_path: &Path, out: &Path) -> Result<()> {
+    let runs: Vec<RunInfo> = history::load(history_path)?
+        .into_iter()
+        .filter(|run| started(run).is_some())
+        .collect();
+    if runs.is_empty() {
+        return Err(anyhow!("no runs in {}", history_path.display()));
+    }
+    std::fs::write(out, render(&runs, history_path))
+        .map_err(|e| anyhow!("could not write {}: {}", out.display(), e))
+}
+
+const STYLE: &str = "
+body { font: 14px/1.4 system-ui, sans-serif; margin: 2em auto; max-width: 1100px; color: #222; }
+h1 { font-size: 1.4em; } h2 { font-size: 1.1em; margin-top: 2em; }
+.summary { color: #666; }
+table { border-collapse: collapse; width: 100%; }
+th, td { text-align: left; padding: 4px 8px; border-bottom: 1px solid #eee; }
+td.num, th.num { text-align: right; font-variant-numeric: tabular-nums; }
+.lane { display: flex; align-items: center; margin: 2px 0; }
+.lane .name { width: 200px; overflow: hidden; text-overflow: ellipsis; white-space: nowrap; }
+.track { position: relative; flex: 1; height: 18px; background: #f6f6f6; }
+.track div { position: absolute; top: 2px; bottom: 2px; min-width: 3px; border-radius: 2px; }
+.pass { background: #3a3; } .fail { background: #d33; } .other { background: #e90; }
+.bar { background: #89b; height: 10px; }
+code { font-size: 0.95em; }
+pre { margin: 0; max-height: 20em; overflow: auto; font-size: 12px; background: #f6f6f6; padding: 6px; }
+";
+
+fn render(runs: &[RunInfo], history_path: &Path) -> String {
+    let first = runs.iter().filter_map(started).min().unwrap();
+    let last = runs
+        .iter()
+        .filter_map(|run| {
+            Some(started(run)? + chrono::Duration::milliseconds(duration_ms(run) as i64))
+        })
+        .max()
+        .unwrap();
+    let span_ms = (last - first).num_milliseconds().max(1) as f64;
+    let failures: Vec<&RunInfo> = runs
+        .iter()
+        .filter(|run| run.success == Some(false))
+        .collect();
+    let mut by_script: BTreeMap<String, Vec<&RunInfo>> = BTreeMap::new();
+    for run in runs {
+        by_script
+            .entry(run.script.display().to_string())
+            .or_default()
+            .push(run);
+    }
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>ws report</title><style>{}</style></head><body>\n",
+        STYLE
+    );
+    let _ = writeln!(
+        html,
+        "<h1>Runs from <code>{}</code></h1>",
+        escape(&history_path.display().to_string())
+    );
+    let _ = writeln!(
+        html,
+        "<p class=\"summary\">{} to {} &middot; {} runs &middot; {} failed &middot; {} scripts</p>",
+        first.format("%Y-%m-%d %H:%M:%S"),
+        last.format("%Y-%m-%d %H:%M:%S"),
+        runs.len(),
+        failures.len(),
+        by_script.len()
+    );
+
+    html.push_str("<h2>Timeline</h2>\n");
+    for (script, runs) in &by_script {
+        let _ = write!(
+            html,
+            "<div class=\"lane\"><div class=\"name\" title=\"{0}\">{0}</div><div class=\"track\">",
+            escape(script)
+        );
+        for run in runs {
+            let Some(start) = started(run) else {
+                continue;
+            };
+            let left = (start - first).num_milliseconds() as f64 / span_ms * 100.0;
+            let width = duration_ms(run) as f64 / span_ms * 100.0;
+            let class = match run.success {
+                Some(true) => "pass",
+                Some(false) => "fail",
+                None => "other",
+            };
+            let _ = write!(
+                html,
+                "<div class=\"{}\" style=\"left:min({:.3}%, calc(100% - 3px));width:{:.3}%\" title=\"#{} {} &middot; {} &middot; {}\"></div>",
+                class,
+                left,
+                width,
+                run.run_number,
+                start.format("%H:%M:%S"),
+                escape(run.status.as_deref().unwrap_or("unknown")),
+                format_duration(&Duration::from_millis(duration_ms(run)))
+            );
+        }
+        html.push_str("</div></div>\n");
+    }
+
+    html.push_str("<h2>Durations</h2>\n<table><tr><th>Script</th><th class=\"num\">Runs</th><th class=\"num\">Failed</th><th class=\"num\">p50</th><th class=\"num\">p90</th><th class=\"num\">Max</th><th></th></tr>\n");
+    let medians: Vec<u64> = by_script
+        .values()
+        .map(|runs| percentile(&durations(runs), 50).unwrap_or(0))
+        .collect();
+    let longest = medians.iter().copied().max().unwrap_or(0).max(1);
+    for ((script, runs), median) in by_script.iter().zip(medians) {
+        let durations = durations(runs);
+        let failed = runs.iter().filter(|run| run.success == Some(false)).count();
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td style=\"width:30%\"><div class=\"bar\" style=\"width:{:.1}%\"></div></td></tr>",
+            escape(script),
+            runs.len(),
+            failed,
+            ms(median),
+            ms(percentile(&durations, 90).unwrap_or(0)),
+            ms(durations.last().copied().unwrap_or(0)),
+            median as f64 / longest as f64 * 100.0
+        );
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Failures</h2>\n");
+    if failures.is_empty() {
+        html.push_str("<p>None</p>\n");
+    } else {
+        html.push_str("<table><tr><th>Run</th><th>Started</th><th>Script</th><th>Status</th><th>Then</th><th>Trigger</th><th class=\"num\">Took</th></tr>\n");
+        for run in failures.iter().rev() {
+            let _ = writeln!(
+                html,
+                "<tr><td>#{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><code>{}</code></td><td class=\"num\">{}</td></tr>",
+                run.run_number,
+                started(run)
+                    .map(|start| start.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default(),
+                escape(&run.script.display().to_string()),
+                escape(run.status.as_deref().unwrap_or("unknown")),
+                escape(run.then_status.as_deref().unwrap_or("-")),
+                escape(&run.trigger_path.display().to_string()),
+                ms(duration_ms(run))
+            );
+            if !run.output_tail.is_empty() {
+                let _ = writeln!(
+                    html,
+                    "<tr><td></td><td colspan=\"6\"><pre>{}</pre></td></tr>",
+                    escape(&run.output_tail.join("\n"))
+                );
+            }
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+fn started(run: &RunInfo) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(&run.started_at).ok()
+}
+
+fn duration_ms(run: &RunInfo) -> u64 {
+    run.duration_ms.unwrap_or(0) as u64
+}
+
+// Sorted so the last one is the longest
+fn durations(runs: &[&RunInfo]) -> Vec<u64> {
+    let mut durations: Vec<u64> = runs
+        .iter()
+        .filter(|run| run.duration_ms.is_some())
+        .map(|run| duration_ms(run))
+        .collect();
+    durations.sort_unstable();
+    durations
+}
+
+fn ms(ms: u64) -> String {
+    format_duration(&Duration::from_millis(ms))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
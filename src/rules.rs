@@ -0,0 +1,57 @@
+use crate::config::RuleConfig;
+use crate::scripts;
+use anyhow::{Result, anyhow};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::path::{Path, PathBuf};
+
+// Config rules that run a script when other files
+// change:
+//
+//   [[rules]]
+//   on = "src/**/*.py"
+//   run = "run_tests.sh"
+//
+// `on` takes a glob or a list of them and is matched
+// against the changed path relative to the watch root.
+// `run` is relative to the root too. The first rule
+// that matches wins.
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    root: PathBuf,
+    rules: Vec<(GlobSet, PathBuf)>,
+}
+
+impl Rules {
+    pub fn new(configs: &[RuleConfig], root: &Path) -> Result<Rules> {
+        let mut rules = vec![];
+        for config in configs {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in config.on.patterns() {
+                builder.add(
+                    Glob::new(pattern)
+                        .map_err(|e| anyhow!("invalid glob `{}` in a rule: {}", pattern, e))?,
+                );
+            }
+            let script = root.join(&config.run);
+            if !scripts::is_runnable(&script) {
+                return Err(anyhow!(
+                    "the script for a rule isn't runnable: {}",
+                    script.display()
+                ));
+            }
+            rules.push((builder.build()?, std::fs::canonicalize(script)?));
+        }
+        Ok(Rules {
+            root: root.to_path_buf(),
+            rules,
+        })
+    }
+
+    pub fn script_for(&self, changed: &Path) -> Option<&Path> {
+        let relative = changed.strip_prefix(&self.root).ok()?;
+        self.rules
+            .iter()
+            .find(|(globs, _)| globs.is_match(relative))
+            .map(|(_, script)| script.as_path())
+    }
+}
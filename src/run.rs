@@ -0,0 +1,1231 @@
+use crate::audit::AuditLog;
+use crate::history;
+use crate::output::OutputCapture;
+use crate::placeholders::{self, RunVars};
+use crate::problems;
+use crate::record::Recording;
+use crate::session::{RunInfo, Session};
+use crate::touched::Snapshot;
+use crate::{Payload, report_error};
+use permissions::is_executable;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use watchexec::command::{Command as WatchCommand, Program, SpawnOptions};
+use watchexec::job::{CommandState, Job};
+use watchexec_events::ProcessEnd;
+use watchexec_signals::Signal;
+
+// How long --keep-alive waits before starting a script
+// again. It doubles each time the script exits right
+// away and goes back to the start once it stays up for
+// KEEP_ALIVE_STEADY.
+const KEEP_ALIVE_FIRST: Duration = Duration::from_secs(1);
+const KEEP_ALIVE_MAX: Duration = Duration::from_secs(30);
+const KEEP_ALIVE_STEADY: Duration = Duration::from_secs(60);
+
+// --then-on picks which results of the main script (or
+// the whole set with --all) the then script follows.
+// Failure and always are for post steps that have to
+// happen either way like collecting logs or tearing
+// down services.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThenOn {
+    Success,
+    Failure,
+    Always,
+}
+
+impl ThenOn {
+    pub fn from_arg(value: &str) -> ThenOn {
+        match value {
+            "failure" => ThenOn::Failure,
+            "always" => ThenOn::Always,
+            _ => ThenOn::Success,
+        }
+    }
+
+    pub fn follows(&self, passed: bool) -> bool {
+        match self {
+            ThenOn::Success => passed,
+            ThenOn::Failure => !passed,
+            ThenOn::Always => true,
+        }
+    }
+}
+
+// A single triggered run: the main script's job and
+// the then script's job if it should be chained.
+pub struct ScriptRun {
+    pub job: Job,
+    // what the job was prepared with (its own one when
+    // it runs alongside others)
+    pub output: OutputCapture,
+    // the full path to check before starting
+    pub path: PathBuf,
+    // from the script's settings for sorting the ones
+    // that run together
+    pub priority: i64,
+    // what the jobs were prepared with. The payload the
+    // run goes with can be shared with other runs.
+    pub run_vars: RunVars,
+    // how the script gets shown
+    pub script: PathBuf,
+    pub then_job: Option<Job>,
+    // from the script's sidecar or the config's `[scripts]`
+    pub timeout: Option<Duration>,
+    pub trigger_path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct JobResult {
+    pub duration: Duration,
+    pub status: ProcessEnd,
+}
+
+impl JobResult {
+    pub fn success(&self) -> bool {
+        matches!(self.status, ProcessEnd::Success)
+    }
+}
+
+impl ScriptRun {
+    pub async fn execute(self, payload: Payload, session: Arc<Mutex<Session>>) -> Option<RunInfo> {
+        let payload = Payload {
+            run_vars: self.run_vars.clone(),
+            ..payload
+        };
+        let output = self.output.clone();
+        let recording =
+            payload
+                .record
+                .as_ref()
+                .and_then(|dir| match Recording::create(dir, &self.script) {
+                    Ok(recording) => Some(output.start_recording(recording)),
+                    Err(e) => {
+                        report_error(e);
+                        None
+                    }
+                });
+        let problems = payload.problems.is_some();
+        if problems {
+            // anything from before the run doesn't count
+            output.take_problems();
+        }
+        let junit = payload.junit.clone();
+        if junit.is_some() || payload.keep_history {
+            output.take_transcript();
+        }
+        if payload.directives {
+            // a skip-then from a run that got cancelled
+            // doesn't carry over
+            output.take_skip_then();
+        }
+        let output_after_run = output.clone();
+        // with --queue nothing else deletes the jobs once
+        // they're done
+        let queued_jobs = payload
+            .queue
+            .then(|| (self.job.clone(), self.then_job.clone()));
+        payload
+            .loop_guard
+            .started(&self.path, payload.config.scripts.outputs.as_ref());
+        let mut info = self
+            .run(
+                payload.clone(),
+                output.clone(),
+                Arc::clone(&session),
+                recording.clone(),
+            )
+            .await;
+        if payload.keep_alive {
+            info = self
+                .keep_alive(info, &payload, &output, &session, recording.as_deref())
+                .await;
+        }
+        payload.loop_guard.finished(&self.path);
+        if let Some((job, then_job)) = queued_jobs {
+            job.delete_now();
+            if let Some(then_job) = then_job {
+                then_job.delete_now();
+            }
+        }
+        if let Some(id) = recording {
+            // the last of the output can still be on its way
+            output_after_run.drain().await;
+            output_after_run.stop_recording(&id);
+        }
+        if problems && info.is_some() {
+            let found = output_after_run.take_problems();
+            problems::print_summary(&found);
+            if payload.github {
+                problems::print_annotations(&found);
+            }
+        }
+        if let Some(junit) = junit
+            && let Some(info) = &info
+        {
+            junit.add(info, output_after_run.take_transcript());
+        }
+        info
+    }
+
+    // --keep-alive starts the job again until something
+    // else (a newer trigger, stopping it, quitting)
+    // deletes it. Runs that didn't get as far as starting
+    // the script (skipped, the before script failed)
+    // aren't retried.
+    async fn keep_alive(
+        &self,
+        mut info: Option<RunInfo>,
+        payload: &Payload,
+        output: &OutputCapture,
+        session: &Arc<Mutex<Session>>,
+        recording: Option<&str>,
+    ) -> Option<RunInfo> {
+        let mut wait = KEEP_ALIVE_FIRST;
+        while let Some(finished) = &info
+            && let Some(duration_ms) = finished.duration_ms
+        {
+            if duration_ms >= KEEP_ALIVE_STEADY.as_millis() {
+                wait = KEEP_ALIVE_FIRST;
+            }
+            println!(
+                "\x1b[33m{} {}, starting it again in {}\x1b[0m",
+                self.script.display(),
+                finished.status.as_deref().unwrap_or("exited"),
+                format_duration(&wait)
+            );
+            tokio::time::sleep(wait).await;
+            if self.job.is_dead() {
+                break;
+            }
+            wait = (wait * 2).min(KEEP_ALIVE_MAX);
+            info = self
+                .run(
+                    payload.clone(),
+                    output.clone(),
+                    Arc::clone(session),
+                    recording.map(str::to_string),
+                )
+                .await;
+        }
+        info
+    }
+
+    async fn run(
+        &self,
+        payload: Payload,
+        output: OutputCapture,
+        session: Arc<Mutex<Session>>,
+        recording: Option<String>,
+    ) -> Option<RunInfo> {
+        let alongside = payload
+            .health
+            .as_ref()
+            .is_some_and(|health| health.rollback);
+        let locks = payload
+            .locks
+            .acquire(&self.path, &self.script, alongside)
+            .await;
+        // a newer trigger can replace it while it waits
+        if self.job.is_dead() {
+            return None;
+        }
+        let Some(_locks) = locks else {
+            println!(
+                "\x1b[2mDropped a queued run of {}: a newer one replaced it\x1b[0m",
+                self.script.display()
+            );
+            return None;
+        };
+        let run_number = session.lock().unwrap().start_run(
+            self.script.clone(),
+            self.trigger_path.clone(),
+            recording,
+        );
+        payload
+            .run_vars
+            .start(&self.trigger_path, &self.path, run_number);
+        if let Some(guard) = &payload.guard
+            && !guard_passes(guard, &self.script.display().to_string(), &payload).await
+        {
+            return session.lock().unwrap().finish_run(run_number, |info| {
+                info.status = Some("skipped".to_string());
+            });
+        }
+        // the file can go away or change between the event
+        // and now (e.g. a `git checkout` in the middle)
+        // tasks aren't run from their file directly
+        let task = payload
+            .task
+            .as_ref()
+            .is_some_and(|task| task.file() == self.path);
+        let needs_exec = !payload.exec_map.handles(&self.path);
+        if !task
+            && let Err(reason) = check_still_runnable(&self.path, needs_exec)
+                .and_then(|()| payload.spawn_policy.check(&self.path))
+        {
+            println!(
+                "\x1b[33mSkipped {}: {}\x1b[0m",
+                self.script.display(),
+                reason
+            );
+            return session.lock().unwrap().finish_run(run_number, |info| {
+                info.status = Some("skipped".to_string());
+            });
+        }
+        // the before script doesn't need to run before
+        // itself when it's the one that changed
+        if let Some(before) = &payload.before_path
+            && before != &self.path
+            && !before_passes(before, &payload, &output, &self.job).await
+        {
+            return session.lock().unwrap().finish_run(run_number, |info| {
+                info.status = Some("before script failed".to_string());
+                info.success = Some(false);
+            });
+        }
+        let label = format!("running {} (run #{})", self.script.display(), run_number);
+        // with --then-after-output or --then-wait-port the
+        // then script starts as soon as the main script is
+        // ready (e.g. a server that's listening) and the
+        // run is over once they've both finished
+        let ready = self
+            .then_job
+            .as_ref()
+            .and_then(|_| then_ready(&payload, &output));
+        // anything older doesn't count for --then-wait-file
+        let started = SystemTime::now();
+        // --touched's before picture. The report comes
+        // right after the main script so the then script
+        // can use it.
+        // --loop-guard learns what the script writes from
+        // the same picture
+        let snapshot = match payload.touched || payload.loop_guard.learns() {
+            true => Some(Snapshot::take(&payload.watch_path()).await),
+            false => None,
+        };
+        let main = async {
+            let section = format!("{} (run #{})", self.script.display(), run_number);
+            let result = run_job(&self.job, label, &section, self.timeout, &payload, &output).await;
+            if let Some(snapshot) = &snapshot
+                && result.is_some()
+            {
+                let touched = snapshot.touched().await;
+                if payload.touched {
+                    touched.print(&self.script);
+                    payload.run_vars.touched(touched.list());
+                }
+                let root = payload.watch_path();
+                // scripts are left out since they're only
+                // ever changed by someone editing them
+                payload.loop_guard.wrote(
+                    &self.path,
+                    touched
+                        .all()
+                        .map(|file| root.join(file))
+                        .filter(|file| *file != self.path && !payload.can_run(file)),
+                );
+            }
+            result
+        };
+        let mut then_outcome = ThenOutcome::NotRun;
+        let result = match (ready, self.then_job.as_ref()) {
+            (Some(ready), Some(then_job)) => {
+                tokio::pin!(main);
+                tokio::select! {
+                    biased;
+                    outcome = ready.wait => {
+                        match outcome {
+                            Ok(()) => {
+                                then_outcome = run_then(then_job, None, &payload, &output).await;
+                            }
+                            Err(reason) => {
+                                println!("\x1b[33mSkipped the then script: {}\x1b[0m", reason);
+                                then_outcome = ThenOutcome::Skipped;
+                            }
+                        }
+                        main.await
+                    }
+                    result = &mut main => {
+                        println!(
+                            "\x1b[33mSkipped the then script: {} exited before {}\x1b[0m",
+                            self.script.display(),
+                            ready.what
+                        );
+                        then_outcome = ThenOutcome::Skipped;
+                        result
+                    }
+                }
+            }
+            _ => {
+                let result = main.await;
+                if let Some(result) = &result
+                    && payload.then_on.follows(result.success())
+                    && let Some(then_job) = &self.then_job
+                {
+                    let ready = match wait_for_file(&payload, started).await {
+                        Ok(()) => artifacts_changed(&payload).await,
+                        other => other,
+                    };
+                    let ready = match ready {
+                        Ok(()) => skip_then_directive(&payload, &output).await,
+                        other => other,
+                    };
+                    then_outcome = match ready {
+                        Ok(()) => run_then(then_job, Some(result), &payload, &output).await,
+                        Err(reason) => {
+                            println!("\x1b[33mSkipped the then script: {}\x1b[0m", reason);
+                            ThenOutcome::Skipped
+                        }
+                    };
+                }
+                result
+            }
+        };
+        if let Some(handoff) = placeholders::handoff_path(run_number) {
+            let _ = std::fs::remove_file(handoff);
+        }
+        // a job that's been deleted got replaced by a newer
+        // run so there's nothing left to do or report
+        let Some(result) = result.filter(|_| !matches!(then_outcome, ThenOutcome::Cancelled))
+        else {
+            session.lock().unwrap().finish_run(run_number, |info| {
+                info.status = Some("cancelled".to_string());
+            });
+            return None;
+        };
+        let (then_result, then_skipped) = match then_outcome {
+            ThenOutcome::Ran(then_result) => (Some(then_result), false),
+            ThenOutcome::Skipped => (None, true),
+            _ => (None, false),
+        };
+        let passed = result.success() && then_result.as_ref().is_none_or(|r| r.success());
+        let output_tail = match payload.keep_history && !passed {
+            true => output.transcript_tail(history::TAIL_LINES),
+            false => vec![],
+        };
+        let info = session.lock().unwrap().finish_run(run_number, |info| {
+            info.duration_ms = Some(result.duration.as_millis());
+            info.exit_code = exit_code(&result.status);
+            info.status = Some(describe_status(&result.status));
+            info.success = Some(passed);
+            info.output_tail = output_tail;
+            info.then_status = match &then_result {
+                Some(r) => Some(describe_status(&r.status)),
+                None if then_skipped => Some("skipped".to_string()),
+                None => None,
+            };
+        });
+        if payload.summary {
+            print_summary(&self.script, &result, then_result.as_ref(), run_number);
+        } else if payload.quiet
+            && result.success()
+            && then_result.as_ref().is_none_or(|r| r.success())
+        {
+            println!(
+                "\x1b[2m{} succeeded (output hidden)\x1b[0m",
+                self.script.display()
+            );
+        }
+        info
+    }
+}
+
+// What the then script waits for when it starts before
+// the main script is done
+struct ThenReady {
+    wait: Pin<Box<dyn Future<Output = Result<(), String>> + Send>>,
+    // for when the main script exits first
+    what: String,
+}
+
+fn then_ready(payload: &Payload, output: &OutputCapture) -> Option<ThenReady> {
+    if let Some(pattern) = &payload.then_after_output {
+        let line = output.wait_for_line(pattern.clone());
+        return Some(ThenReady {
+            wait: Box::pin(async move {
+                let _ = line.await;
+                Ok(())
+            }),
+            what: format!("printing a line matching `{}`", pattern),
+        });
+    }
+    let port = payload.then_wait_port?;
+    let timeout = payload.then_wait_timeout;
+    Some(ThenReady {
+        wait: Box::pin(wait_for_port(port, timeout)),
+        what: format!("anything was listening on port {}", port),
+    })
+}
+
+// Checks every 100ms until something accepts a
+// connection on the port on localhost
+async fn wait_for_port(port: u16, timeout: Duration) -> Result<(), String> {
+    let open = async {
+        while TcpStream::connect(("localhost", port)).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    tokio::time::timeout(timeout, open).await.map_err(|_| {
+        format!(
+            "nothing was listening on port {} after {}",
+            port,
+            format_duration(&timeout)
+        )
+    })
+}
+
+// --then-wait-file holds the then script until the
+// file's been written since the run started. Files
+// from an earlier run are stale and don't count. It
+// can still be on its way when the main script exits
+// (e.g. something it started in the background) so it
+// gets up to --then-wait-timeout.
+async fn wait_for_file(payload: &Payload, started: SystemTime) -> Result<(), String> {
+    let Some(path) = &payload.then_wait_file else {
+        return Ok(());
+    };
+    let path = payload.watch_path().join(path);
+    let fresh = || {
+        std::fs::metadata(&path)
+            .and_then(|meta| meta.modified())
+            .is_ok_and(|modified| modified >= started)
+    };
+    let written = async {
+        while !fresh() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    tokio::time::timeout(payload.then_wait_timeout, written)
+        .await
+        .map_err(|_| {
+            format!(
+                "{} wasn't written during the run",
+                payload.relative_path(&path).display()
+            )
+        })
+}
+
+async fn artifacts_changed(payload: &Payload) -> Result<(), String> {
+    match &payload.then_artifacts {
+        Some(artifacts) if !artifacts.changed().await => {
+            Err("the --then-if-changed files are the same as last time".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+// A --directives `::ws::skip-then` from the main script.
+// Its output can still be on its way so that goes first.
+async fn skip_then_directive(payload: &Payload, output: &OutputCapture) -> Result<(), String> {
+    if !payload.directives {
+        return Ok(());
+    }
+    output.drain().await;
+    match output.take_skip_then() {
+        true => Err("the script asked to (::ws::skip-then)".to_string()),
+        false => Ok(()),
+    }
+}
+
+enum ThenOutcome {
+    Cancelled,
+    NotRun,
+    Ran(JobResult),
+    Skipped,
+}
+
+// The then script's checks (still runnable, --then-guard,
+// cd-ing for it) and then the then script itself. The
+// main script's result is there unless the then script
+// started early (--then-after-output).
+async fn run_then(
+    then_job: &Job,
+    main: Option<&JobResult>,
+    payload: &Payload,
+    output: &OutputCapture,
+) -> ThenOutcome {
+    if let Some(main) = main {
+        payload.run_vars.finish(main);
+    }
+    check_handoff(payload);
+    let then_check = payload
+        .raw_then_path
+        .as_ref()
+        .map_or(Ok(()), |path| then_runnable(path, payload));
+    if let Some(guard) = &payload.then_guard
+        && !guard_passes(guard, "the then script", payload).await
+    {
+        return ThenOutcome::Skipped;
+    }
+    if let Err(reason) = then_check {
+        println!("\x1b[33mSkipped the then script: {}\x1b[0m", reason);
+        return ThenOutcome::Skipped;
+    }
+    let label = "running then script".to_string();
+    let section = match &payload.raw_then_path {
+        Some(path) => format!("then {}", payload.relative_path(path).display()),
+        None => "then script".to_string(),
+    };
+    match run_job(then_job, label, &section, None, payload, output).await {
+        Some(result) => ThenOutcome::Ran(result),
+        None => ThenOutcome::Cancelled,
+    }
+}
+
+// --all runs every script one after the other (or up
+// to --jobs of them at a time) in the config's order
+// and the then script once at the end if they all
+// passed. A newer trigger deletes the jobs which ends
+// the sequence early and returns None. Otherwise it
+// returns whether everything passed and prints the
+// --report table.
+pub async fn run_all(
+    mut runs: Vec<ScriptRun>,
+    then_run: Option<ScriptRun>,
+    payload: Payload,
+    session: Arc<Mutex<Session>>,
+) -> Option<bool> {
+    if let Some(junit) = &payload.junit {
+        junit.begin();
+    }
+    runs.sort_by_cached_key(|run| payload.run_order.rank(&run.path, run.priority));
+    let junit = payload.junit.clone();
+    let all_passed = run_each(runs, then_run, payload, session).await;
+    if let Some(junit) = junit {
+        junit.end();
+    }
+    all_passed
+}
+
+async fn run_each(
+    runs: Vec<ScriptRun>,
+    then_run: Option<ScriptRun>,
+    payload: Payload,
+    session: Arc<Mutex<Session>>,
+) -> Option<bool> {
+    let started = Instant::now();
+    let mut results = if payload.jobs > 1 {
+        run_side_by_side(runs, &payload, &session).await?
+    } else {
+        let mut results = vec![];
+        for (n, run) in runs.into_iter().enumerate() {
+            if n > 0 {
+                stagger(&payload).await;
+            }
+            if run.job.is_dead() {
+                return None;
+            }
+            let info = run.execute(payload.clone(), Arc::clone(&session)).await?;
+            results.push(info);
+        }
+        results
+    };
+    let mut all_passed = results.iter().all(|info| info.success == Some(true));
+    if payload.then_on.follows(all_passed)
+        && let Some(then_run) = then_run
+    {
+        let info = then_run.execute(payload.clone(), session).await?;
+        all_passed &= info.success == Some(true);
+        results.push(info);
+    }
+    if payload.report {
+        print_report(&results, &started.elapsed());
+    }
+    Some(all_passed)
+}
+
+// Up to --jobs runs at a time. Each one waits for a
+// free slot before the next gets a look in so they
+// start in the order they were given and the results
+// come back in that order too.
+async fn run_side_by_side(
+    runs: Vec<ScriptRun>,
+    payload: &Payload,
+    session: &Arc<Mutex<Session>>,
+) -> Option<Vec<RunInfo>> {
+    let slots = Arc::new(Semaphore::new(payload.jobs));
+    let mut running = vec![];
+    for (n, run) in runs.into_iter().enumerate() {
+        let slot = Arc::clone(&slots).acquire_owned().await.ok()?;
+        if n > 0 {
+            stagger(payload).await;
+        }
+        if run.job.is_dead() {
+            return None;
+        }
+        let payload = payload.clone();
+        let session = Arc::clone(session);
+        running.push(tokio::spawn(async move {
+            let info = run.execute(payload, session).await;
+            drop(slot);
+            info
+        }));
+    }
+    let mut results = vec![];
+    for run in running {
+        results.push(run.await.ok()??);
+    }
+    Some(results)
+}
+
+// The then script still runs when the main script left
+// something that isn't JSON in WS_HANDOFF but it's
+// pointed out since the then script probably won't
+// be able to make sense of it
+fn check_handoff(payload: &Payload) {
+    let Some(path) = payload.run_vars.get().get("WS_HANDOFF").map(PathBuf::from) else {
+        return;
+    };
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        return;
+    };
+    if let Err(e) = serde_json::from_str::<serde_json::Value>(&contents) {
+        println!("\x1b[33mWS_HANDOFF isn't valid JSON: {}\x1b[0m", e);
+    }
+}
+
+// --stagger's pause before each run in a set after the
+// first so heavy scripts don't all start at once
+async fn stagger(payload: &Payload) {
+    if let Some(wait) = payload.stagger {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+// Which end of the --pipe-then pipe a job is on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PipeEnd {
+    Main,
+    Then,
+}
+
+// Sets up the spawn hook that every job gets. There can
+// only be one hook per job so everything that needs to
+// adjust the command before it's spawned goes here.
+pub fn prepare_job(
+    job: &Job,
+    payload: &Payload,
+    output: &OutputCapture,
+    script: &Path,
+    dir: Option<&Path>,
+    pipe: Option<PipeEnd>,
+) {
+    let capture = payload.captures_output().then(|| output.clone());
+    let pipe = pipe
+        .filter(|_| payload.pipe_then)
+        .map(|end| (end, output.clone()));
+    let keys = payload.keys.is_some() || payload.enter || payload.stdin_commands;
+    // the process's own directory is shared by every run
+    // so each job gets one of its own, the watch root if
+    // nothing else
+    let dir = dir
+        .map(|dir| dir.to_path_buf())
+        .or_else(|| payload.initial_dir.clone());
+    let mut env = payload.config.scripts.env.clone();
+    env.extend(payload.env_args.clone());
+    let clean_env = payload.clean_env;
+    let path_dirs = payload.extend_path.then(|| payload.path_dirs(script));
+    let venv = payload.venv.then(|| payload.venv_dir(script)).flatten();
+    let direnv = payload
+        .direnv
+        .clone()
+        .zip(script.parent().and_then(|dir| dir.canonicalize().ok()));
+    if let Some((direnv, dir)) = &direnv {
+        direnv.add(dir);
+    }
+    let run_vars = payload.run_vars.clone();
+    let audit_logs: Vec<AuditLog> = [&payload.audit_log, &payload.journal]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let script = script.to_path_buf();
+    job.set_spawn_hook(move |command, context| {
+        let command = command.command_mut();
+        // scripts run from their own directory
+        if let Some(dir) = &dir {
+            command.current_dir(dir);
+        }
+        if clean_env {
+            clear_env(command);
+        }
+        // loaded right before the job starts
+        if let Some((direnv, dir)) = &direnv {
+            for (name, value) in direnv.get(dir) {
+                match value {
+                    Some(value) => command.env(name, value),
+                    None => command.env_remove(name),
+                };
+            }
+        }
+        command.envs(&env);
+        command.envs(run_vars.get());
+        if let Some(dirs) = &path_dirs {
+            prepend_path(command, dirs);
+        }
+        if let Some(venv) = &venv {
+            activate_venv(command, venv);
+        }
+        if let Some(capture) = &capture {
+            capture.capture(command);
+        }
+        // the key handler (or --stdin) owns stdin while
+        // it's listening
+        if keys {
+            command.stdin(Stdio::null());
+        }
+        match &pipe {
+            Some((PipeEnd::Main, output)) => output.pipe_stdout(command),
+            Some((PipeEnd::Then, output)) => output.pipe_into(command),
+            None => {}
+        }
+        for audit_log in &audit_logs {
+            audit_log.job_spawned(&context.command, command.as_std(), &script);
+        }
+    });
+}
+
+// --service lets the version that's running shut down
+// on its own terms (SIGTERM and up to --grace before it
+// gets killed) and waits for it to be gone so the new
+// one can have its port. With --health the new one gets
+// checked once it's started (see health::HealthCheck).
+pub async fn restart(
+    old: Vec<Job>,
+    new: Option<(Job, PathBuf)>,
+    payload: Payload,
+    session: Arc<Mutex<Session>>,
+    next: impl Future,
+) {
+    let Some(((job, script), health)) = new.zip(payload.health.clone()) else {
+        stop_all(old, payload.grace).await;
+        next.await;
+        return;
+    };
+    // without --health-rollback the old one goes first
+    // like any other restart
+    let old = if health.rollback {
+        old
+    } else {
+        stop_all(old, payload.grace).await;
+        vec![]
+    };
+    let check = async {
+        if health.wait_for(&job, &script, &payload, &session).await {
+            stop_all(old, payload.grace).await;
+        } else if !old.is_empty() {
+            println!("\x1b[31mRolled back: keeping the version that was already running\x1b[0m");
+            job.stop_with_signal(Signal::Terminate, payload.grace);
+        }
+    };
+    tokio::join!(next, check);
+}
+
+async fn stop_all(jobs: Vec<Job>, grace: Duration) {
+    for job in &jobs {
+        job.stop_with_signal(Signal::Terminate, grace);
+    }
+    for job in jobs {
+        job.to_wait().await;
+        job.delete_now();
+    }
+}
+
+// Scripts get their own process group so stopping or
+// replacing a job takes out anything it started too
+// (dev servers, background watchers, etc.) instead of
+// leaving them running and holding onto ports.
+pub fn spawn_options() -> SpawnOptions {
+    SpawnOptions {
+        grouped: true,
+        ..Default::default()
+    }
+}
+
+// Starts a job and waits for it to finish. In quiet mode
+// the output is held back and only shown if the job fails
+// or runs longer than the quiet threshold. A job that
+// goes past its timeout gets a SIGTERM (and a SIGKILL
+// once --grace runs out) and counts as failed.
+async fn run_job(
+    job: &Job,
+    label: String,
+    section: &str,
+    timeout: Option<Duration>,
+    payload: &Payload,
+    output: &OutputCapture,
+) -> Option<JobResult> {
+    // with --jobs-output grouped a job's output comes out
+    // in one piece once it's done so jobs running at the
+    // same time don't get mixed together
+    let grouped = payload.group_output && payload.jobs > 1;
+    if payload.quiet || grouped {
+        output.hold();
+    }
+    output.start_section(section);
+    if let Some(direnv) = &payload.direnv {
+        direnv.load(payload.audit_log.as_ref()).await;
+    }
+    job.start().await;
+    if payload.spinner {
+        output.spin(job, label);
+    }
+    let finished = async {
+        match payload.quiet_over {
+            Some(threshold) if payload.quiet && !grouped => {
+                if tokio::time::timeout(threshold, job.to_wait())
+                    .await
+                    .is_err()
+                {
+                    output.release();
+                    job.to_wait().await;
+                }
+            }
+            _ => job.to_wait().await,
+        }
+    };
+    match timeout {
+        Some(limit) => {
+            if tokio::time::timeout(limit, finished).await.is_err() {
+                output.release();
+                println!(
+                    "\x1b[31mTimed out after {}, stopping it\x1b[0m",
+                    format_duration(&limit)
+                );
+                // the ticket from stop_with_signal doesn't
+                // resolve until the job's gone so wait on
+                // the job instead
+                job.stop_with_signal(Signal::Terminate, payload.grace);
+                job.to_wait().await;
+            }
+        }
+        None => finished.await,
+    }
+    let result = job_result(job).await;
+    for audit_log in [&payload.audit_log, &payload.journal].into_iter().flatten() {
+        audit_log.job_finished(job, result.as_ref());
+    }
+    output.drain().await;
+    output.finish_truncated();
+    output.end_sections();
+    match &result {
+        Some(r) if r.success() && payload.quiet => output.discard(),
+        _ => output.release(),
+    }
+    result
+}
+
+// Runs a --guard or --then-guard command from the
+// directory the watcher was started in. Anything other
+// than exit 0 means the script gets skipped.
+async fn guard_passes(guard: &str, what: &str, payload: &Payload) -> bool {
+    let mut command = payload.shell.command(&placeholders::expand(guard));
+    command.envs(payload.run_vars.get()).stdin(Stdio::null());
+    if let Some(dir) = &payload.initial_dir {
+        command.current_dir(dir);
+    }
+    let status = match audited(&mut command, payload).await {
+        Ok(status) if status.success() => return true,
+        Ok(status) => match status.code() {
+            Some(code) => format!("exited {}", code),
+            None => "was killed".to_string(),
+        },
+        Err(e) => format!("could not run: {}", e),
+    };
+    println!(
+        "\x1b[33mSkipped {}: guard `{}` {}\x1b[0m",
+        what, guard, status
+    );
+    false
+}
+
+// Runs the --before script from its own directory as a
+// job of its own (so it gets a process group and its
+// output goes where the main script's does). The main
+// script only runs if it exits 0. A newer trigger
+// deleting the main job stops it too.
+async fn before_passes(
+    before: &Path,
+    payload: &Payload,
+    output: &OutputCapture,
+    main: &Job,
+) -> bool {
+    let relative = payload.relative_path(before);
+    if let Err(reason) = payload.spawn_policy.check(before) {
+        println!(
+            "\x1b[31mCalled off the run: the before script {} is {}\x1b[0m",
+            relative.display(),
+            reason
+        );
+        return false;
+    }
+    let (job, _handle) = watchexec::job::start_job(Arc::new(WatchCommand {
+        program: Program::Exec {
+            prog: before.to_path_buf(),
+            args: vec![],
+        },
+        options: spawn_options(),
+    }));
+    prepare_job(&job, payload, output, before, before.parent(), None);
+    let label = format!("running the before script {}", relative.display());
+    let replaced = async {
+        while !main.is_dead() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    let result = tokio::select! {
+        result = run_job(&job, label, "before", None, payload, output) => result,
+        _ = replaced => {
+            job.stop_with_signal(Signal::Terminate, payload.grace);
+            job.to_wait().await;
+            job.delete_now().await;
+            return false;
+        }
+    };
+    job.delete_now().await;
+    let status = match result {
+        Some(result) if result.success() => return true,
+        Some(result) => format!("failed ({})", describe_status(&result.status)),
+        None => "did not finish".to_string(),
+    };
+    println!(
+        "\x1b[31mCalled off the run: the before script {} {}\x1b[0m",
+        relative.display(),
+        status
+    );
+    false
+}
+
+// --clean-env leaves PATH and HOME so scripts can still
+// find their tools
+fn clear_env(command: &mut tokio::process::Command) {
+    command.env_clear();
+    for name in ["PATH", "HOME"] {
+        if let Some(value) = std::env::var_os(name) {
+            command.env(name, value);
+        }
+    }
+}
+
+// Goes after the env settings so a PATH from the config
+// gets added to too
+fn prepend_path(command: &mut tokio::process::Command, dirs: &[PathBuf]) {
+    let current = command
+        .as_std()
+        .get_envs()
+        .find(|(name, _)| *name == "PATH")
+        .map(|(_, value)| value.map(OsStr::to_os_string))
+        .unwrap_or_else(|| std::env::var_os("PATH"));
+    let mut paths = dirs.to_vec();
+    if let Some(current) = &current {
+        paths.extend(std::env::split_paths(current));
+    }
+    if let Ok(joined) = std::env::join_paths(paths) {
+        command.env("PATH", joined);
+    }
+}
+
+// Same as sourcing bin/activate
+fn activate_venv(command: &mut tokio::process::Command, venv: &Path) {
+    command.env("VIRTUAL_ENV", venv);
+    command.env_remove("PYTHONHOME");
+    prepend_path(command, &[venv.join("bin")]);
+}
+
+// Runs a command that isn't a job, with --audit-log
+// entries for it
+pub async fn audited(
+    command: &mut tokio::process::Command,
+    payload: &Payload,
+) -> std::io::Result<std::process::ExitStatus> {
+    let Some(audit_log) = &payload.audit_log else {
+        return command.status().await;
+    };
+    let id = audit_log.spawned(command.as_std());
+    let status = command.status().await;
+    audit_log.exited_with(id, &status);
+    status
+}
+
+// A then.d directory's hooks each get the checks the
+// then script would
+fn then_runnable(path: &Path, payload: &Payload) -> Result<(), String> {
+    check_still_runnable(path, true)
+        .and_then(|()| payload.spawn_policy.check(path))
+        .map_err(str::to_string)?;
+    if path.is_dir() {
+        for hook in crate::then_hooks(path) {
+            check_still_runnable(&hook, true)
+                .and_then(|()| payload.spawn_policy.check(&hook))
+                .map_err(|reason| {
+                    format!("{}: {}", payload.relative_path(&hook).display(), reason)
+                })?;
+        }
+    }
+    Ok(())
+}
+
+// Files run through --exec-map don't have to be
+// executable
+fn check_still_runnable(path: &Path, needs_exec: bool) -> Result<(), &'static str> {
+    if !path.exists() {
+        return Err("file vanished");
+    }
+    if needs_exec && !is_executable(path).unwrap_or(false) {
+        return Err("no longer executable");
+    }
+    Ok(())
+}
+
+// Pulls the final status out of a job that's finished.
+// Returns None if the job was stopped or deleted before
+// it got the chance to finish.
+pub async fn job_result(job: &Job) -> Option<JobResult> {
+    if job.is_dead() {
+        return None;
+    }
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    job.run(move |context| {
+        if let CommandState::Finished {
+            status,
+            started,
+            finished,
+        } = context.current
+        {
+            let _ = tx.send(JobResult {
+                duration: finished.duration_since(*started),
+                status: *status,
+            });
+        }
+    })
+    .await;
+    rx.await.ok()
+}
+
+pub fn describe_status(status: &ProcessEnd) -> String {
+    match status {
+        ProcessEnd::Success => "exit 0".to_string(),
+        ProcessEnd::ExitError(code) => format!("exit {}", code),
+        ProcessEnd::ExitSignal(sig) => format!("killed by {}", sig),
+        ProcessEnd::ExitStop(sig) => format!("stopped by signal {}", sig),
+        ProcessEnd::Exception(code) => format!("exception {}", code),
+        ProcessEnd::Continued => "continued".to_string(),
+    }
+}
+
+pub fn exit_code(status: &ProcessEnd) -> Option<i64> {
+    match status {
+        ProcessEnd::Success => Some(0),
+        ProcessEnd::ExitError(code) => Some(code.get()),
+        _ => None,
+    }
+}
+
+pub fn format_duration(duration: &Duration) -> String {
+    let secs = duration.as_secs_f64();
+    if secs < 1.0 {
+        format!("{}ms", duration.as_millis())
+    } else if secs < 60.0 {
+        format!("{:.2}s", secs)
+    } else {
+        format!(
+            "{}m{:02}s",
+            duration.as_secs() / 60,
+            duration.as_secs() % 60
+        )
+    }
+}
+
+fn print_summary(
+    script: &Path,
+    result: &JobResult,
+    then_result: Option<&JobResult>,
+    run_number: usize,
+) {
+    let (color, mark) = if result.success() && then_result.is_none_or(|r| r.success()) {
+        ("\x1b[32m", "✔")
+    } else {
+        ("\x1b[31m", "✘")
+    };
+    let then_text = match then_result {
+        Some(then_result) => format!(
+            "then {} in {}",
+            describe_status(&then_result.status),
+            format_duration(&then_result.duration)
+        ),
+        None => "then not run".to_string(),
+    };
+    println!(
+        "{}{} {} | {} | {} | {} | run #{}\x1b[0m",
+        color,
+        mark,
+        script.display(),
+        describe_status(&result.status),
+        format_duration(&result.duration),
+        then_text,
+        run_number
+    );
+}
+
+// The --report table for a --all run. Scripts that got
+// skipped (a guard, a vanished file, etc.) count against
+// the overall result the same as a failure.
+fn print_report(results: &[RunInfo], elapsed: &Duration) {
+    let width = results
+        .iter()
+        .map(|info| info.script.display().to_string().chars().count())
+        .max()
+        .unwrap_or(0);
+    println!();
+    for info in results {
+        let (color, mark) = match info.success {
+            Some(true) => ("\x1b[32m", "✔"),
+            Some(false) => ("\x1b[31m", "✘"),
+            None => ("\x1b[33m", "-"),
+        };
+        let duration = info
+            .duration_ms
+            .map(|ms| format_duration(&Duration::from_millis(ms as u64)))
+            .unwrap_or_default();
+        println!(
+            "{}{} {:<width$}  {:<12} {:>8}\x1b[0m",
+            color,
+            mark,
+            info.script.display().to_string(),
+            info.status.as_deref().unwrap_or("unknown"),
+            duration,
+            width = width
+        );
+    }
+    let passed = results
+        .iter()
+        .filter(|info| info.success == Some(true))
+        .count();
+    let failed = results
+        .iter()
+        .filter(|info| info.success == Some(false))
+        .count();
+    let skipped = results.len() - passed - failed;
+    let (color, verdict) = if passed == results.len() {
+        ("\x1b[32m", "PASS")
+    } else {
+        ("\x1b[31m", "FAIL")
+    };
+    println!(
+        "{}{} | {} passed | {} failed | {} skipped | {}\x1b[0m",
+        color,
+        verdict,
+        passed,
+        failed,
+        skipped,
+        format_duration(elapsed)
+    );
+}
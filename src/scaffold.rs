@@ -0,0 +1,196 @@
+use crate::config::CONFIG_NAME;
+use crate::filters::WSIGNORE_NAME;
+use crate::shell;
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+// `ws new <name>` writes a starter script and makes it
+// executable so it's picked up the next time it's saved.
+// The shebang goes off of the extension and anything
+// without one that's known gets bash.
+pub fn new_script(dir: &Path, name: &Path) -> Result<PathBuf> {
+    let path = dir.join(name);
+    if path.exists() {
+        return Err(anyhow!("{} already exists", name.display()));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, template(name))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    Ok(path)
+}
+
+fn template(name: &Path) -> String {
+    let (shebang, comment) = match name.extension().and_then(|ext| ext.to_str()) {
+        Some("py") => ("#!/usr/bin/env python3", "#"),
+        Some("rb") => ("#!/usr/bin/env ruby", "#"),
+        Some("js") | Some("mjs") => ("#!/usr/bin/env node", "//"),
+        Some("pl") => ("#!/usr/bin/env perl", "#"),
+        Some("zsh") => ("#!/usr/bin/env zsh", "#"),
+        Some("fish") => ("#!/usr/bin/env fish", "#"),
+        _ => ("#!/bin/bash", "#"),
+    };
+    let header = [
+        name.display().to_string(),
+        String::new(),
+        "Runs from its own directory every time it's saved".to_string(),
+        "while `ws` is watching.".to_string(),
+    ];
+    let mut contents = format!("{}\n", shebang);
+    for line in header {
+        if line.is_empty() {
+            contents.push_str(&format!("{}\n", comment));
+        } else {
+            contents.push_str(&format!("{} {}\n", comment, line));
+        }
+    }
+    contents.push('\n');
+    contents
+}
+
+// Build output and dependency directories that are
+// worth keeping out of the watch when they're there.
+// Hidden ones (.git, .venv) are already skipped by
+// no-dotfiles.
+const IGNORE_DIRS: [&str; 9] = [
+    "__pycache__",
+    "build",
+    "coverage",
+    "dist",
+    "node_modules",
+    "out",
+    "target",
+    "vendor",
+    "venv",
+];
+
+// `ws init` writes a .watch_scripts.toml with the
+// settings commented out except for what it could work
+// out from the directory and the environment. The
+// build and dependency directories that are there go
+// into a glob filter or, with --wsignore, into a
+// .wsignore instead. Nothing gets overwritten without
+// --force.
+pub fn init(dir: &Path, wsignore: bool, force: bool) -> Result<Vec<PathBuf>> {
+    let config_path = dir.join(CONFIG_NAME);
+    let ignore_path = dir.join(WSIGNORE_NAME);
+    for path in [Some(&config_path), wsignore.then_some(&ignore_path)]
+        .into_iter()
+        .flatten()
+    {
+        if path.exists() && !force {
+            return Err(anyhow!(
+                "{} already exists (use --force to replace it)",
+                path.display()
+            ));
+        }
+    }
+    let ignore_dirs: Vec<&str> = IGNORE_DIRS
+        .into_iter()
+        .filter(|name| dir.join(name).is_dir())
+        .collect();
+    fs::write(&config_path, config_template(&ignore_dirs, wsignore))?;
+    let mut written = vec![config_path];
+    if wsignore {
+        fs::write(&ignore_path, wsignore_template(&ignore_dirs))?;
+        written.push(ignore_path);
+    }
+    Ok(written)
+}
+
+// The login shell if it's one scripts can be run
+// through and bash if not
+fn detected_shell() -> String {
+    shell::login_shell()
+        .as_deref()
+        .and_then(|path| path.file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "bash".to_string())
+}
+
+fn config_template(ignore_dirs: &[&str], wsignore: bool) -> String {
+    let mut contents = format!(
+        r#"# Settings for `ws` in this directory. Flags and
+# WATCH_SCRIPTS_* environment variables win over
+# anything here.
+
+# Shell that scripts are run through
+shell = "{}"
+
+# Script to run after each one finishes
+# then = "scripts/after.sh"
+
+# How to clear the terminal before each run
+# (screen, reset, or none)
+# clear = "screen"
+
+# Where --extend-path looks for scripts to put on PATH
+# bin = "bin"
+
+# Defaults for every script (a script's own sidecar
+# file can override them)
+# [scripts]
+# cwd = "script"
+# timeout = "5m"
+# env = {{ RUST_LOG = "debug" }}
+
+# Scripts that never run at the same time
+# [locks]
+# database = ["db/*.sh", "migrate.sh"]
+
+"#,
+        detected_shell()
+    );
+    if wsignore {
+        contents.push_str(&format!(
+            "# Files matched by {} are never run.\n\
+             # Listing filters replaces the defaults\n\
+             # (executable-only and no-dotfiles).\n\
+             # [[filters]]\n\
+             # kind = \"glob\"\n\
+             # exclude = [\"tmp/**\"]\n",
+            WSIGNORE_NAME
+        ));
+    } else if ignore_dirs.is_empty() {
+        contents.push_str(
+            "# Listing filters replaces the defaults\n\
+             # (executable-only and no-dotfiles).\n\
+             # [[filters]]\n\
+             # kind = \"glob\"\n\
+             # exclude = [\"node_modules/**\"]\n",
+        );
+    } else {
+        let exclude: Vec<String> = ignore_dirs
+            .iter()
+            .map(|name| format!("\"{}/**\"", name))
+            .collect();
+        contents.push_str(&format!(
+            "# The defaults (executable-only and no-dotfiles)\n\
+             # plus the build and dependency directories\n\
+             # that were here\n\
+             [[filters]]\n\
+             kind = \"executable-only\"\n\n\
+             [[filters]]\n\
+             kind = \"no-dotfiles\"\n\n\
+             [[filters]]\n\
+             kind = \"glob\"\n\
+             exclude = [{}]\n",
+            exclude.join(", ")
+        ));
+    }
+    contents
+}
+
+fn wsignore_template(ignore_dirs: &[&str]) -> String {
+    let mut contents = String::from(
+        "# Same syntax as .gitignore. Nothing that matches\n\
+         # sets off a run.\n",
+    );
+    for name in ignore_dirs {
+        contents.push_str(&format!("{}/\n", name));
+    }
+    contents
+}
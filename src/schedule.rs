@@ -0,0 +1,380 @@
+use crate::config::ScheduleConfig;
+use crate::trigger;
+use anyhow::{Result, anyhow};
+use chrono::{
+    DateTime, Datelike, Duration as ChronoDuration, Local, NaiveDate, NaiveDateTime, TimeZone,
+    Timelike,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use watchexec::Watchexec;
+use watchexec_events::{Priority, Source};
+
+// A standard five field cron expression (minute, hour,
+// day of month, month, day of week). Each field takes
+// `*`, numbers, ranges like `1-5`, steps like `*/15` or
+// `0-30/10`, and comma separated lists of those. Sunday
+// is 0 (or 7) in the day of week field.
+#[derive(Debug, Clone)]
+pub struct Cron {
+    days: Vec<bool>,
+    hours: Vec<bool>,
+    minutes: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    // cron's odd rule: when both day fields are
+    // restricted a day matching either one counts
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Cron> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(anyhow!(
+                "schedules need five fields (minute hour day month weekday), got: {}",
+                expression
+            ));
+        };
+        let mut weekday_values = parse_field(weekdays, 0, 7, expression)?;
+        // 7 is another way to say Sunday
+        if weekday_values[7] {
+            weekday_values[0] = true;
+        }
+        Ok(Cron {
+            days: parse_field(days, 1, 31, expression)?,
+            hours: parse_field(hours, 0, 23, expression)?,
+            minutes: parse_field(minutes, 0, 59, expression)?,
+            months: parse_field(months, 1, 12, expression)?,
+            weekdays: weekday_values,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+        })
+    }
+
+    // Day of month and day of week together
+    fn day_matches(&self, time: &NaiveDateTime) -> bool {
+        let day = self.days[time.day() as usize];
+        let weekday = self.weekdays[time.weekday().num_days_from_sunday() as usize];
+        if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+
+    // The next whole minute after `after` that matches.
+    // It goes by the wall clock like cron does, skipping
+    // whole months, days, and hours that can't match, so
+    // it's only ever a few hundred steps. A time that's
+    // skipped over by a DST change doesn't happen and one
+    // that's repeated only happens the first time. Gives
+    // up after a little over four years (which covers
+    // Feb 29) for expressions that can't happen.
+    pub fn next_after(&self, after: &DateTime<Local>) -> Option<DateTime<Local>> {
+        let start =
+            after.naive_local().with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+        let limit = start + ChronoDuration::days(366 * 4 + 1);
+        let mut time = start;
+        while time < limit {
+            if !self.months[time.month() as usize] {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(&time) {
+                time = (time.date() + ChronoDuration::days(1)).and_hms_opt(0, 0, 0)?;
+            } else if !self.hours[time.hour() as usize] {
+                time = time.with_minute(0)? + ChronoDuration::hours(1);
+            } else {
+                if self.minutes[time.minute() as usize]
+                    && let Some(local) = Local.from_local_datetime(&time).earliest()
+                    && local > *after
+                {
+                    return Some(local);
+                }
+                time += ChronoDuration::minutes(1);
+            }
+        }
+        None
+    }
+}
+
+// Returns a lookup table indexed by value
+fn parse_field(field: &str, min: usize, max: usize, expression: &str) -> Result<Vec<bool>> {
+    let invalid = || anyhow!("invalid schedule field `{}` in: {}", field, expression);
+    let mut values = vec![false; max + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (
+                start.parse().map_err(|_| invalid())?,
+                end.parse().map_err(|_| invalid())?,
+            )
+        } else {
+            let value = range.parse().map_err(|_| invalid())?;
+            // `5/10` means starting at 5 every 10
+            if part.contains('/') {
+                (value, max)
+            } else {
+                (value, value)
+            }
+        };
+        if start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step) {
+            values[value] = true;
+        }
+    }
+    Ok(values)
+}
+
+// Checks the schedules from the config so mistakes show
+// up when it's loaded instead of being silently skipped
+pub fn validate(schedules: &[ScheduleConfig]) -> Result<()> {
+    for entry in schedules {
+        match (&entry.schedule, entry.interval) {
+            (Some(schedule), None) => {
+                Cron::parse(schedule)?;
+            }
+            (None, Some(interval)) if !interval.is_zero() => {}
+            (None, Some(_)) => {
+                return Err(anyhow!(
+                    "the interval for {} has to be more than 0",
+                    entry.script.display()
+                ));
+            }
+            _ => {
+                return Err(anyhow!(
+                    "schedules need either a schedule or an interval: {}",
+                    entry.script.display()
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Runs the scripts from the config's `[[schedules]]`
+// when they come due. They go through the action handler
+// as the same kind of manual trigger a key press or
+// `ws trigger` sends so they get the same job handling
+// and reporting as any other run. A reloaded config
+// replaces the schedules.
+//
+// An `interval = "5m"` one runs every five minutes from
+// when the watcher started whether or not the script
+// ran in between because of a change. Its timer keeps
+// going through a reload as long as it's still there.
+pub fn follow(mut config: watch::Receiver<Vec<ScheduleConfig>>, root: PathBuf, wx: Arc<Watchexec>) {
+    tokio::spawn(async move {
+        let mut due: HashMap<(PathBuf, Duration), Instant> = HashMap::new();
+        loop {
+            let mut schedules: Vec<(Cron, PathBuf)> = vec![];
+            let mut intervals = HashMap::new();
+            for entry in config.borrow_and_update().iter() {
+                let script = root.join(&entry.script);
+                let script = std::fs::canonicalize(&script).unwrap_or(script);
+                if let Some(cron) = entry
+                    .schedule
+                    .as_deref()
+                    .and_then(|schedule| Cron::parse(schedule).ok())
+                {
+                    schedules.push((cron, script.clone()));
+                }
+                if let Some(interval) = entry.interval.filter(|interval| !interval.is_zero()) {
+                    let key = (script, interval);
+                    let next = due
+                        .get(&key)
+                        .copied()
+                        .unwrap_or_else(|| Instant::now() + interval);
+                    intervals.insert(key, next);
+                }
+            }
+            due = intervals;
+            let now = Local::now();
+            let next = schedules
+                .iter()
+                .filter_map(|(cron, script)| cron.next_after(&now).map(|time| (time, script)))
+                .min_by_key(|(time, _)| *time)
+                .map(|(time, _)| time);
+            let cron_at =
+                next.map(|time| Instant::now() + (time - now).to_std().unwrap_or_default());
+            let Some(wake) = cron_at.into_iter().chain(due.values().copied()).min() else {
+                // nothing scheduled until the config changes
+                if config.changed().await.is_err() {
+                    return;
+                }
+                continue;
+            };
+            tokio::select! {
+                _ = tokio::time::sleep_until(wake) => {
+                    if let Some(time) = next.filter(|_| cron_at == Some(wake)) {
+                        for (cron, script) in &schedules {
+                            if cron.next_after(&now) == Some(time) {
+                                run(&wx, script).await;
+                            }
+                        }
+                    }
+                    let woke = Instant::now();
+                    for ((script, interval), next) in due.iter_mut() {
+                        if *next <= woke {
+                            run(&wx, script).await;
+                            *next = woke + *interval;
+                        }
+                    }
+                }
+                changed = config.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+}
+
+async fn run(wx: &Watchexec, script: &Path) {
+    let _ = wx
+        .send_event(
+            trigger::manual_event(script, Source::Time),
+            Priority::Normal,
+        )
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn set(values: &[bool]) -> Vec<usize> {
+        values
+            .iter()
+            .enumerate()
+            .filter(|(_, on)| **on)
+            .map(|(value, _)| value)
+            .collect()
+    }
+
+    #[test]
+    fn parses_each_kind_of_field() {
+        let cron = Cron::parse("*/15 0-20/10 5/10 1,6-7 1-5").unwrap();
+        assert_eq!(set(&cron.minutes), [0, 15, 30, 45]);
+        assert_eq!(set(&cron.hours), [0, 10, 20]);
+        assert_eq!(set(&cron.days), [5, 15, 25]);
+        assert_eq!(set(&cron.months), [1, 6, 7]);
+        assert_eq!(set(&cron.weekdays), [1, 2, 3, 4, 5]);
+        assert!(cron.days_restricted && cron.weekdays_restricted);
+    }
+
+    #[test]
+    fn seven_is_sunday() {
+        let cron = Cron::parse("0 0 * * 7").unwrap();
+        assert_eq!(set(&cron.weekdays), [0, 7]);
+    }
+
+    #[test]
+    fn rejects_bad_expressions() {
+        for expression in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "1- * * * *",
+        ] {
+            assert!(Cron::parse(expression).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn every_minute_is_the_next_minute() {
+        let cron = Cron::parse("* * * * *").unwrap();
+        let after = at(2025, 1, 15, 10, 0) + ChronoDuration::seconds(30);
+        assert_eq!(cron.next_after(&after), Some(at(2025, 1, 15, 10, 1)));
+    }
+
+    #[test]
+    fn next_is_always_after() {
+        let cron = Cron::parse("30 9 * * *").unwrap();
+        assert_eq!(
+            cron.next_after(&at(2025, 1, 15, 9, 30)),
+            Some(at(2025, 1, 16, 9, 30))
+        );
+    }
+
+    #[test]
+    fn weekdays_skip_the_weekend() {
+        // 2025-01-17 is a Friday
+        let cron = Cron::parse("30 9 * * 1-5").unwrap();
+        assert_eq!(
+            cron.next_after(&at(2025, 1, 17, 10, 0)),
+            Some(at(2025, 1, 20, 9, 30))
+        );
+    }
+
+    #[test]
+    fn either_day_field_counts_when_both_are_set() {
+        // the 1st or any Monday
+        let cron = Cron::parse("0 12 1 * 1").unwrap();
+        // 2025-01-01 is a Wednesday and the 6th a Monday
+        assert_eq!(
+            cron.next_after(&at(2024, 12, 31, 13, 0)),
+            Some(at(2025, 1, 1, 12, 0))
+        );
+        assert_eq!(
+            cron.next_after(&at(2025, 1, 1, 13, 0)),
+            Some(at(2025, 1, 6, 12, 0))
+        );
+    }
+
+    #[test]
+    fn rolls_over_the_year() {
+        let cron = Cron::parse("0 0 1 1 *").unwrap();
+        assert_eq!(
+            cron.next_after(&at(2025, 6, 1, 0, 0)),
+            Some(at(2026, 1, 1, 0, 0))
+        );
+    }
+
+    #[test]
+    fn finds_leap_days() {
+        let cron = Cron::parse("0 0 29 2 *").unwrap();
+        assert_eq!(
+            cron.next_after(&at(2025, 3, 1, 0, 0)),
+            Some(at(2028, 2, 29, 0, 0))
+        );
+    }
+
+    #[test]
+    fn gives_up_on_days_that_never_come() {
+        let cron = Cron::parse("0 0 31 2 *").unwrap();
+        assert_eq!(cron.next_after(&at(2025, 1, 1, 0, 0)), None);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::placeholders;
+use chrono::Local;
+use clearscreen::ClearScreen;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+// What to do to the terminal at the start of each run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClearMode {
+    // Clears the visible screen and leaves the scrollback
+    Screen,
+    // Resets the terminal and wipes the scrollback
+    Reset,
+    None,
+}
+
+impl ClearMode {
+    pub fn from_arg(value: &str) -> ClearMode {
+        match value {
+            "reset" => ClearMode::Reset,
+            "none" => ClearMode::None,
+            _ => ClearMode::Screen,
+        }
+    }
+
+    pub fn clear(&self) {
+        match self {
+            ClearMode::Screen => {
+                if ClearScreen::TerminfoScreen.clear().is_err() {
+                    print!("\x1b[H\x1b[2J");
+                    let _ = std::io::stdout().flush();
+                }
+            }
+            ClearMode::Reset => {
+                if ClearScreen::TerminfoReset.clear().is_err() {
+                    let _ = ClearScreen::XtermReset.clear();
+                }
+                let _ = ClearScreen::XtermClear.clear();
+            }
+            ClearMode::None => {}
+        }
+    }
+}
+
+// --banner replaces the line at the top of the screen
+// with a template that can use {script} (comma separated
+// when there's more than one) and {run_number} from
+// placeholders.rs plus:
+//
+//   {time}     the time as HH:MM:SS
+//   {date}     the date as YYYY-MM-DD
+//   {root}     the directory being watched
+//
+// It's printed at startup (before anything's run
+// {script} is empty and {run_number} is 0) and after the
+// clear at the start of every run.
+#[derive(Debug, Clone)]
+pub struct Banner {
+    template: String,
+}
+
+impl Banner {
+    pub fn new(template: &str) -> Banner {
+        Banner {
+            template: template.to_string(),
+        }
+    }
+
+    pub fn print(&self, scripts: &[String], run_number: usize, root: &Path) {
+        let now = Local::now();
+        let vars = BTreeMap::from([
+            ("WS_SCRIPT", scripts.join(", ")),
+            ("WS_RUN_NUMBER", run_number.to_string()),
+        ]);
+        let line = placeholders::fill(&self.template, &vars)
+            .replace("{time}", &now.format("%H:%M:%S").to_string())
+            .replace("{date}", &now.format("%Y-%m-%d").to_string())
+            .replace("{root}", &root.display().to_string());
+        println!("{}", line);
+    }
+}
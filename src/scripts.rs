@@ -0,0 +1,143 @@
+use permissions::is_executable;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+// The rules for what counts as a script that can be run:
+// it has to be executable, can't be hidden or in a hidden
+// directory, and can't be an editor backup file.
+pub fn is_runnable(path: &Path) -> bool {
+    is_executable(path).unwrap_or(false) && !is_hidden(path)
+}
+
+pub fn is_hidden(path: &Path) -> bool {
+    for component in path.components() {
+        if let std::path::Component::Normal(part) = component
+            && part.display().to_string().starts_with(".")
+        {
+            return true;
+        }
+    }
+    if let Some(file_name_path) = path.file_name() {
+        let file_name = file_name_path.display().to_string();
+        if file_name.ends_with("~") {
+            return true;
+        }
+    };
+    false
+}
+
+// A new script that --auto-chmod can fix: it starts
+// with a shebang but nobody's made it executable yet
+pub fn needs_chmod(path: &Path) -> bool {
+    if is_hidden(path) || !path.is_file() || is_executable(path).unwrap_or(true) {
+        return false;
+    }
+    let mut start = [0; 2];
+    fs::File::open(path).is_ok_and(|mut file| file.read_exact(&mut start).is_ok())
+        && &start == b"#!"
+}
+
+// Execute goes to whoever can read it (like 644 to 755)
+pub fn make_executable(path: &Path) -> std::io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    permissions.set_mode(mode | ((mode & 0o444) >> 2));
+    fs::set_permissions(path, permissions)
+}
+
+// What to do with a script that's a symlink to a file
+// somewhere else (like the ones dotfile managers set up)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymlinkMode {
+    // run it through the link from the link's directory
+    Link,
+    // run the file it points to from that file's
+    // directory
+    Target,
+}
+
+impl SymlinkMode {
+    pub fn from_arg(value: &str) -> SymlinkMode {
+        match value {
+            "link" => SymlinkMode::Link,
+            _ => SymlinkMode::Target,
+        }
+    }
+}
+
+pub fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink())
+}
+
+// Every runnable script under the root, sorted by path.
+// Hidden directories are skipped entirely instead of
+// being walked and filtered out afterwards.
+pub fn find_scripts(root: &Path) -> Vec<PathBuf> {
+    let mut scripts = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().starts_with(".") {
+                continue;
+            }
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => dirs.push(path),
+                Ok(_) => {
+                    if path.is_file() && is_runnable(&path) {
+                        scripts.push(path);
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+    scripts.sort();
+    scripts
+}
+
+// Every file under the root along with the reason it
+// can't be run if there is one. Hidden directories show
+// up once instead of listing everything in them.
+pub fn explain_files(root: &Path) -> Vec<(PathBuf, Option<&'static str>)> {
+    let mut files = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with(".");
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => {
+                    if hidden {
+                        files.push((path, Some("hidden directory")));
+                    } else {
+                        dirs.push(path);
+                    }
+                }
+                Ok(_) => {
+                    let reason = if hidden {
+                        Some("hidden")
+                    } else if is_hidden(&path) {
+                        Some("editor backup")
+                    } else if !is_executable(&path).unwrap_or(false) {
+                        Some("not executable")
+                    } else {
+                        None
+                    };
+                    files.push((path, reason));
+                }
+                Err(_) => {}
+            }
+        }
+    }
+    files.sort();
+    files
+}
@@ -0,0 +1,205 @@
+use crate::run::format_duration;
+use std::io::IsTerminal;
+use std::time::Instant;
+
+// How --sections marks them off
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SectionStyle {
+    // A heading and a footer with how long it took. The
+    // heading gets an OSC 133 mark in front of it so
+    // terminals that know them (WezTerm, kitty, iTerm2,
+    // Warp) can jump between or fold them.
+    Terminal,
+    // `::group::` and `::endgroup::` lines for CI logs
+    // (GitHub Actions, Gitea, Forgejo) to fold
+    Ci,
+}
+
+impl SectionStyle {
+    pub fn from_arg(value: &str) -> SectionStyle {
+        match value {
+            "ci" => SectionStyle::Ci,
+            _ => SectionStyle::Terminal,
+        }
+    }
+}
+
+// Every script and then script run gets a section of
+// its own. Scripts can split theirs up further with
+// `::ws::section <name>` and `::ws::endsection` lines.
+// A new one ends the last one the script started so
+// they only go one level deep (CI logs can't nest them
+// anyway).
+#[derive(Debug, Clone)]
+pub struct Sections {
+    style: SectionStyle,
+    open: Vec<(String, Instant)>,
+}
+
+impl Sections {
+    pub fn new(style: SectionStyle) -> Sections {
+        Sections {
+            style,
+            open: vec![],
+        }
+    }
+
+    // The same style with nothing open, for a --jobs job
+    pub fn fresh(&self) -> Sections {
+        Sections::new(self.style)
+    }
+
+    // The per-run one, which ends any left over from
+    // before
+    pub fn start_run(&mut self, name: &str) -> Vec<u8> {
+        let mut out = self.end_all();
+        out.extend(self.start(name));
+        out
+    }
+
+    pub fn start_script(&mut self, name: &str) -> Vec<u8> {
+        let mut out = vec![];
+        if self.open.len() > 1 {
+            if self.style == SectionStyle::Ci {
+                // going straight from one group to the
+                // next without the run's in between
+                self.open.pop();
+            } else {
+                out.extend(self.end());
+            }
+        }
+        out.extend(self.start(name));
+        out
+    }
+
+    // An `::ws::endsection` without a section of the
+    // script's own doesn't end the run's
+    pub fn end_script(&mut self) -> Vec<u8> {
+        if self.open.len() > 1 {
+            self.end()
+        } else {
+            vec![]
+        }
+    }
+
+    pub fn end_all(&mut self) -> Vec<u8> {
+        let mut out = vec![];
+        while !self.open.is_empty() {
+            out.extend(self.end());
+        }
+        out
+    }
+
+    fn start(&mut self, name: &str) -> Vec<u8> {
+        let depth = self.open.len();
+        let name = name.trim();
+        let out = match self.style {
+            // CI logs only have one level so a script's
+            // section ends the run's and the run's name
+            // goes in front
+            SectionStyle::Ci => {
+                let mut out = String::new();
+                if let Some((parent, _)) = self.open.last() {
+                    out.push_str("::endgroup::\n");
+                    out.push_str(&format!("::group::{} › {}\n", parent, name));
+                } else {
+                    out.push_str(&format!("::group::{}\n", name));
+                }
+                out
+            }
+            SectionStyle::Terminal => {
+                format!("{}{}\x1b[1m▼ {}\x1b[0m\n", mark(), "  ".repeat(depth), name)
+            }
+        };
+        self.open.push((name.to_string(), Instant::now()));
+        out.into_bytes()
+    }
+
+    fn end(&mut self) -> Vec<u8> {
+        let Some((name, started)) = self.open.pop() else {
+            return vec![];
+        };
+        let out = match self.style {
+            // the run's group picks back up for whatever
+            // comes after a script's section
+            SectionStyle::Ci if self.open.is_empty() => "::endgroup::\n".to_string(),
+            SectionStyle::Ci => format!("::endgroup::\n::group::{}\n", self.open[0].0),
+            SectionStyle::Terminal => format!(
+                "{}\x1b[2m▲ {} ({})\x1b[0m\n",
+                "  ".repeat(self.open.len()),
+                name,
+                format_duration(&started.elapsed())
+            ),
+        };
+        out.into_bytes()
+    }
+}
+
+// Only sent to a terminal since it's noise anywhere else
+fn mark() -> &'static str {
+    if std::io::stdout().is_terminal() {
+        "\x1b]133;A\x07"
+    } else {
+        ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: Vec<u8>) -> String {
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn ci_groups_only_go_one_level_deep() {
+        let mut sections = Sections::new(SectionStyle::Ci);
+        assert_eq!(text(sections.start_run("build.sh")), "::group::build.sh\n");
+        assert_eq!(
+            text(sections.start_script("tests")),
+            "::endgroup::\n::group::build.sh › tests\n"
+        );
+        assert_eq!(
+            text(sections.start_script("lint")),
+            "::endgroup::\n::group::build.sh › lint\n"
+        );
+        assert_eq!(
+            text(sections.end_script()),
+            "::endgroup::\n::group::build.sh\n"
+        );
+        assert_eq!(text(sections.end_script()), "");
+        assert_eq!(text(sections.end_all()), "::endgroup::\n");
+        assert_eq!(text(sections.end_all()), "");
+    }
+
+    #[test]
+    fn a_new_run_ends_what_was_left_open() {
+        let mut sections = Sections::new(SectionStyle::Ci);
+        sections.start_run("build.sh");
+        sections.start_script("tests");
+        assert_eq!(
+            text(sections.start_run("lint.sh")),
+            "::endgroup::\n::group::build.sh\n::endgroup::\n::group::lint.sh\n"
+        );
+    }
+
+    #[test]
+    fn terminal_sections_nest_with_a_footer() {
+        let mut sections = Sections::new(SectionStyle::Terminal);
+        assert!(text(sections.start_run("build.sh")).ends_with("\x1b[1m▼ build.sh\x1b[0m\n"));
+        assert!(text(sections.start_script(" tests ")).ends_with("  \x1b[1m▼ tests\x1b[0m\n"));
+        assert!(text(sections.end_script()).starts_with("  \x1b[2m▲ tests ("));
+        assert!(text(sections.end_all()).starts_with("\x1b[2m▲ build.sh ("));
+    }
+
+    #[test]
+    fn fresh_keeps_only_the_style() {
+        let mut sections = Sections::new(SectionStyle::Ci);
+        sections.start_run("build.sh");
+        let mut fresh = sections.fresh();
+        assert_eq!(text(fresh.end_all()), "");
+        assert_eq!(SectionStyle::from_arg("ci"), SectionStyle::Ci);
+        assert_eq!(SectionStyle::from_arg("terminal"), SectionStyle::Terminal);
+    }
+}
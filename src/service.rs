@@ -0,0 +1,210 @@
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Options that take a path. Their values get made
+// absolute since a service doesn't start from the
+// directory the command was run in.
+const PATH_OPTIONS: [&str; 9] = [
+    "-t",
+    "--then",
+    "--before",
+    "--config",
+    "--log-file",
+    "--pidfile",
+    "--record",
+    "--junit",
+    "--audit-log",
+];
+
+// `ws [options] install-service [--install]` prints (or
+// installs) a systemd user unit on Linux or a launchd
+// agent on macOS that runs `ws [options]` from the
+// current directory at login.
+pub fn install_service(install: bool) -> Result<()> {
+    let dir = std::env::current_dir()?;
+    let exe = std::env::current_exe()?;
+    let args = service_args(&dir);
+    let name = service_name(&dir);
+    let (path, contents) = if cfg!(target_os = "macos") {
+        let label = format!("com.watch_scripts.{}", name);
+        let path = home()?
+            .join("Library/LaunchAgents")
+            .join(format!("{}.plist", label));
+        (path, launchd_plist(&label, &exe, &args, &dir))
+    } else {
+        let path = home()?
+            .join(".config/systemd/user")
+            .join(format!("watch_scripts-{}.service", name));
+        (path, systemd_unit(&exe, &args, &dir))
+    };
+    if !install {
+        print!("{}", contents);
+        eprintln!("\nRun again with --install to write {}", path.display());
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, contents)?;
+    println!("Wrote {}", path.display());
+    if cfg!(target_os = "macos") {
+        run(Command::new("launchctl").arg("load").arg("-w").arg(&path))?;
+    } else {
+        run(Command::new("systemctl").args(["--user", "daemon-reload"]))?;
+        run(Command::new("systemctl")
+            .args(["--user", "enable", "--now"])
+            .arg(path.file_name().unwrap()))?;
+    }
+    println!("Installed and started");
+    Ok(())
+}
+
+// The options this was run with up to the subcommand.
+// --daemon gets dropped since the service manager takes
+// care of running things in the background.
+fn service_args(dir: &Path) -> Vec<String> {
+    let mut args = vec![];
+    let mut raw = std::env::args().skip(1);
+    while let Some(arg) = raw.next() {
+        if arg == "install-service" {
+            break;
+        }
+        if arg == "--daemon" {
+            continue;
+        }
+        if let Some((option, value)) = arg.split_once('=')
+            && PATH_OPTIONS.contains(&option)
+        {
+            args.push(format!("{}={}", option, absolute(dir, value).display()));
+        } else if PATH_OPTIONS.contains(&arg.as_str()) {
+            args.push(arg);
+            if let Some(value) = raw.next() {
+                args.push(absolute(dir, &value).display().to_string());
+            }
+        } else {
+            args.push(arg);
+        }
+    }
+    args
+}
+
+fn systemd_unit(exe: &Path, args: &[String], dir: &Path) -> String {
+    let mut exec_start = vec![systemd_quote(&exe.display().to_string())];
+    exec_start.extend(args.iter().map(|arg| systemd_quote(arg)));
+    format!(
+        "[Unit]\n\
+         Description=watch_scripts in {dir}\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         WorkingDirectory={dir}\n\
+         ExecStart={exec_start}\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        dir = dir.display(),
+        exec_start = exec_start.join(" ")
+    )
+}
+
+fn launchd_plist(label: &str, exe: &Path, args: &[String], dir: &Path) -> String {
+    let mut program = format!(
+        "    <string>{}</string>\n",
+        xml_escape(&exe.display().to_string())
+    );
+    for arg in args {
+        program.push_str(&format!("    <string>{}</string>\n", xml_escape(arg)));
+    }
+    let log = dir.join(crate::daemon::LOG_NAME);
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{label}</string>
+  <key>ProgramArguments</key>
+  <array>
+{program}  </array>
+  <key>WorkingDirectory</key>
+  <string>{dir}</string>
+  <key>RunAtLoad</key>
+  <true/>
+  <key>KeepAlive</key>
+  <true/>
+  <key>StandardOutPath</key>
+  <string>{log}</string>
+  <key>StandardErrorPath</key>
+  <string>{log}</string>
+</dict>
+</plist>
+"#,
+        label = xml_escape(label),
+        program = program,
+        dir = xml_escape(&dir.display().to_string()),
+        log = xml_escape(&log.display().to_string())
+    )
+}
+
+// Services are named after the directory so more than
+// one project can have a watcher.
+fn service_name(dir: &Path) -> String {
+    dir.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "root".to_string())
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' {
+                ch
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn absolute(dir: &Path, value: &str) -> PathBuf {
+    let path = dir.join(value);
+    std::fs::canonicalize(&path).unwrap_or(path)
+}
+
+fn systemd_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "/-_.=:,@+".contains(ch))
+    {
+        return arg.to_string();
+    }
+    format!(
+        "\"{}\"",
+        arg.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%")
+            .replace('$', "$$")
+    )
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn home() -> Result<PathBuf> {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("HOME is not set"))
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let status = command.status()?;
+    if !status.success() {
+        return Err(anyhow!("{:?} failed with {}", command, status));
+    }
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use crate::hooks::Hooks;
+use crate::inventory::Inventory;
+use crate::run::format_duration;
+use crate::stats::Stats;
+use crate::stream::RunStream;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+// How many finished runs `ws status` gets to show
+const RECENT_RUNS: usize = 5;
+
+// State that lives for the whole time the watcher is
+// running and is shared between the action handler
+// and the runs it kicks off.
+#[derive(Debug)]
+pub struct Session {
+    // what the watcher exits with after --exit-on-failure
+    // stopped it
+    pub exit_code: Option<i32>,
+    failures: usize,
+    // see finished_runs()
+    finished: Vec<UnboundedSender<RunInfo>>,
+    hooks: Hooks,
+    pub ignored_while_paused: usize,
+    pub last: Option<RunInfo>,
+    // when the last run finished, for the gap to the next
+    last_finished: Option<Instant>,
+    pub last_script: Option<PathBuf>,
+    // when a change last set a script off, even if it
+    // didn't end up running (for --idle-timeout)
+    last_trigger: Option<Instant>,
+    pub paused: bool,
+    // the last few finished runs, oldest first
+    recent: VecDeque<RunInfo>,
+    // oldest first. More than one with --jobs.
+    running: Vec<RunInfo>,
+    runs: usize,
+    started_at: DateTime<Local>,
+    pub stats: Stats,
+    stream: RunStream,
+    // the x key asking for the next run to be traced
+    pub xtrace_next: bool,
+}
+
+// What's known about a run. The result fields get
+// filled in once it's done.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunInfo {
+    pub duration_ms: Option<u128>,
+    pub exit_code: Option<i64>,
+    pub finished_at: Option<String>,
+    // how long it had been since the run before it
+    // finished
+    pub gap_ms: Option<u128>,
+    // the last of a failed run's output with
+    // --keep-history (see history.rs)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub output_tail: Vec<String>,
+    // the --record run id so `ws logs` can find its
+    // output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recording: Option<String>,
+    pub run_number: usize,
+    pub script: PathBuf,
+    pub started_at: String,
+    pub status: Option<String>,
+    pub success: Option<bool>,
+    pub then_status: Option<String>,
+    // the file that set the run off (the script itself
+    // unless it was triggered some other way)
+    pub trigger_path: PathBuf,
+}
+
+impl Session {
+    pub fn new(stream: RunStream, hooks: Hooks) -> Session {
+        Session {
+            exit_code: None,
+            failures: 0,
+            finished: vec![],
+            hooks,
+            ignored_while_paused: 0,
+            last: None,
+            last_finished: None,
+            last_script: None,
+            last_trigger: None,
+            paused: false,
+            recent: VecDeque::new(),
+            running: vec![],
+            runs: 0,
+            started_at: Local::now(),
+            stats: Stats::default(),
+            stream,
+            xtrace_next: false,
+        }
+    }
+
+    // Flips between paused and watching and returns
+    // the new paused state.
+    pub fn toggle_pause(&mut self) -> bool {
+        self.set_paused(!self.paused);
+        self.paused
+    }
+
+    // Returns false if it was already in that state.
+    pub fn set_paused(&mut self, paused: bool) -> bool {
+        if self.paused == paused {
+            return false;
+        }
+        self.paused = paused;
+        if paused {
+            self.ignored_while_paused = 0;
+        }
+        true
+    }
+
+    // Bumps the run counter and returns the number
+    // for the run that's starting. The recording is the
+    // --record run id when there is one.
+    pub fn start_run(
+        &mut self,
+        script: PathBuf,
+        trigger_path: PathBuf,
+        recording: Option<String>,
+    ) -> usize {
+        self.runs += 1;
+        let info = RunInfo {
+            duration_ms: None,
+            exit_code: None,
+            finished_at: None,
+            gap_ms: self
+                .last_finished
+                .map(|finished| finished.elapsed().as_millis()),
+            output_tail: vec![],
+            recording,
+            run_number: self.runs,
+            script,
+            started_at: Local::now().to_rfc3339(),
+            status: None,
+            success: None,
+            then_status: None,
+            trigger_path,
+        };
+        self.stream.send("run_started", serde_json::json!(info));
+        self.hooks.run_start(&info);
+        self.running.push(info);
+        self.runs
+    }
+
+    // The number the next run will get
+    pub fn next_run(&self) -> usize {
+        self.runs + 1
+    }
+
+    // The run that started the latest
+    pub fn current(&self) -> Option<&RunInfo> {
+        self.running.last()
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.running.is_empty()
+    }
+
+    // A new trigger replaces whatever's running so those
+    // runs don't get finished or reported. They go out
+    // on the stream as run_cancelled instead.
+    pub fn replace_running(&mut self) {
+        for mut info in self.running.drain(..) {
+            info.status = Some("cancelled".to_string());
+            self.stream.send("run_cancelled", serde_json::json!(info));
+        }
+    }
+
+    // Moves the run out of the running ones and into last
+    // with its results filled in by the callback. Runs
+    // that have already been replaced by a newer trigger
+    // are left alone. Returns the finished run.
+    pub fn finish_run(
+        &mut self,
+        run_number: usize,
+        fill: impl FnOnce(&mut RunInfo),
+    ) -> Option<RunInfo> {
+        if let Some(idx) = self
+            .running
+            .iter()
+            .position(|info| info.run_number == run_number)
+        {
+            let mut info = self.running.remove(idx);
+            info.finished_at = Some(Local::now().to_rfc3339());
+            self.last_finished = Some(Instant::now());
+            fill(&mut info);
+            if info.success == Some(false) {
+                self.failures += 1;
+            }
+            self.stats.record(&info);
+            self.stream.send("run_finished", serde_json::json!(info));
+            self.hooks.run_end(&info);
+            self.finished.retain(|tx| tx.send(info.clone()).is_ok());
+            if self.recent.len() == RECENT_RUNS {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(info.clone());
+            self.last = Some(info.clone());
+            return Some(info);
+        }
+        None
+    }
+
+    // Every run that finishes from here on, handed over
+    // directly for things that can't miss one (the stream
+    // drops messages for listeners that fall behind)
+    pub fn finished_runs(&mut self) -> UnboundedReceiver<RunInfo> {
+        let (tx, rx) = unbounded_channel();
+        self.finished.push(tx);
+        rx
+    }
+
+    // What gets printed on the way out
+    pub fn final_summary(&self) -> String {
+        let uptime = (Local::now() - self.started_at)
+            .to_std()
+            .unwrap_or_default();
+        let last = match &self.last {
+            Some(info) => format!(
+                " | last: {} {}",
+                info.script.display(),
+                info.status.as_deref().unwrap_or("unknown")
+            ),
+            None => String::new(),
+        };
+        format!(
+            "\x1b[2mStopped after {} | {} run{} | {} failed{}\x1b[0m",
+            format_duration(&uptime),
+            self.runs,
+            if self.runs == 1 { "" } else { "s" },
+            self.failures,
+            last
+        )
+    }
+
+    // e.g. "last run 3m ago" while nothing's running
+    pub fn idle_status(&self) -> Option<String> {
+        if self.is_running() {
+            return None;
+        }
+        self.last_finished
+            .map(|finished| format!("last run {} ago", format_ago(&finished.elapsed())))
+    }
+
+    pub fn triggered(&mut self) {
+        self.last_trigger = Some(Instant::now());
+    }
+
+    // How long it's been since the last trigger or run
+    // (or the watcher starting) while nothing's running
+    pub fn idle_for(&self) -> Option<Duration> {
+        if self.is_running() {
+            return None;
+        }
+        let since_start = (Local::now() - self.started_at)
+            .to_std()
+            .unwrap_or_default();
+        Some(
+            [self.last_finished, self.last_trigger]
+                .iter()
+                .flatten()
+                .map(Instant::elapsed)
+                .fold(since_start, Duration::min),
+        )
+    }
+
+    pub fn stream(&self) -> &RunStream {
+        &self.stream
+    }
+
+    pub fn status_json(&self, inventory: &Inventory) -> serde_json::Value {
+        serde_json::json!({
+            "current": self.current(),
+            "last": self.last,
+            "last_finished_secs_ago": self
+                .last_finished
+                .map(|finished| finished.elapsed().as_secs()),
+            "paused": self.paused,
+            "recent": self.recent,
+            "root": inventory.root(),
+            "running": self.running,
+            "runs": self.runs,
+            "scripts": inventory.scripts().len(),
+            "started_at": self.started_at.to_rfc3339(),
+            "uptime_secs": (Local::now() - self.started_at).num_seconds(),
+        })
+    }
+}
+
+// Rounded down to the biggest unit that fits
+fn format_ago(duration: &Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
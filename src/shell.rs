@@ -0,0 +1,148 @@
+use crate::sidecar::shell_quote;
+use std::path::{Path, PathBuf};
+use watchexec::command::Shell;
+
+// Login shells that scripts get run through when --shell
+// isn't set. Anything else (nu, pwsh, xonsh, csh, ...)
+// can't take the commands the watcher puts together
+// (quoted args, "$WS_*" placeholders) so bash is used.
+const POSIX_SHELLS: [&str; 8] = ["ash", "bash", "dash", "ksh", "mksh", "sh", "yash", "zsh"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quoting {
+    Posix,
+    // fish's single quotes take \\ and \' escapes where
+    // the others take everything as is
+    Fish,
+}
+
+// The shell scripts are run through. It's only what
+// gets the script started (by its path, with any args
+// from the config) so the script's own #! line still
+// decides what runs it. Going through the login shell
+// gets fish and zsh users the PATH and functions their
+// startup files set up.
+#[derive(Debug, Clone)]
+pub struct ScriptShell {
+    path: PathBuf,
+    quoting: Quoting,
+}
+
+impl ScriptShell {
+    // --shell or the config's shell, which is used as is
+    pub fn new(shell: &str) -> ScriptShell {
+        let quoting = match name_of(Path::new(shell)) {
+            Some("fish") => Quoting::Fish,
+            _ => Quoting::Posix,
+        };
+        ScriptShell {
+            path: PathBuf::from(shell),
+            quoting,
+        }
+    }
+
+    // $SHELL if it's one that works and bash if not
+    pub fn login() -> ScriptShell {
+        match login_shell() {
+            Some(path) => ScriptShell::new(&path.to_string_lossy()),
+            None => ScriptShell::bash(),
+        }
+    }
+
+    pub fn bash() -> ScriptShell {
+        ScriptShell::new("bash")
+    }
+
+    // For commands the watcher writes in sh syntax
+    // (e.g. the loop for a then.d directory)
+    pub fn posix(&self) -> ScriptShell {
+        match self.quoting {
+            Quoting::Posix => self.clone(),
+            Quoting::Fish => ScriptShell::bash(),
+        }
+    }
+
+    pub fn program(&self) -> Shell {
+        Shell::new(&self.path)
+    }
+
+    // For running a command of the user's own (a guard, a
+    // hook) outside of a job
+    pub fn command(&self, command: &str) -> tokio::process::Command {
+        let mut process = tokio::process::Command::new(&self.path);
+        process.arg("-c").arg(command);
+        process
+    }
+
+    // Quotes an arg for a command this shell runs
+    pub fn quote(&self, arg: &str) -> String {
+        let quoted = shell_quote(arg);
+        match self.quoting {
+            Quoting::Fish if quoted.starts_with('\'') => {
+                format!("'{}'", arg.replace('\\', r"\\").replace('\'', r"\'"))
+            }
+            _ => quoted,
+        }
+    }
+}
+
+// The login shell's path if it's one scripts can be run
+// through
+pub fn login_shell() -> Option<PathBuf> {
+    let path = PathBuf::from(std::env::var_os("SHELL")?);
+    let name = name_of(&path)?;
+    (POSIX_SHELLS.contains(&name) || name == "fish").then_some(path)
+}
+
+// The #! line's command for a script a POSIX shell runs
+// so it can be started with -x in front of it (for
+// --xtrace). `#!/usr/bin/env bash` gives `bash`.
+pub fn traceable(script: &Path) -> Option<Vec<String>> {
+    let contents = std::fs::read(script).ok()?;
+    let first_line = contents.split(|byte| *byte == b'\n').next()?;
+    let first_line = String::from_utf8_lossy(first_line);
+    let mut words: Vec<String> = first_line
+        .strip_prefix("#!")?
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    if words
+        .first()
+        .is_some_and(|word| name_of(Path::new(word)) == Some("env"))
+    {
+        words.remove(0);
+        if words.first().is_some_and(|word| word == "-S") {
+            words.remove(0);
+        }
+    }
+    let shell = name_of(Path::new(words.first()?))?;
+    POSIX_SHELLS.contains(&shell).then_some(words)
+}
+
+fn name_of(path: &Path) -> Option<&str> {
+    path.file_name().and_then(|name| name.to_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_only_what_needs_it() {
+        let bash = ScriptShell::bash();
+        assert_eq!(bash.quote("src/main.rs"), "src/main.rs");
+        assert_eq!(bash.quote(""), "''");
+        assert_eq!(bash.quote("my script.sh"), "'my script.sh'");
+        assert_eq!(bash.quote("it's"), r"'it'\''s'");
+        assert_eq!(bash.quote(r"a\b"), r"'a\b'");
+    }
+
+    #[test]
+    fn quotes_for_fish() {
+        let fish = ScriptShell::new("/usr/bin/fish");
+        assert_eq!(fish.quote("src/main.rs"), "src/main.rs");
+        assert_eq!(fish.quote("my script.sh"), "'my script.sh'");
+        assert_eq!(fish.quote("it's"), r"'it\'s'");
+        assert_eq!(fish.quote(r"a\b"), r"'a\\b'");
+    }
+}
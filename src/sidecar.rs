@@ -0,0 +1,173 @@
+use crate::config::ScriptConfig;
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Settings for a single script in a file next to it
+// named after it with `.ws.toml` on the end:
+//
+//   # build.sh.ws.toml
+//   args = ["--release"]
+//   cooldown = "2s"
+//   cwd = "root"
+//   env = { RUST_LOG = "debug" }
+//   outputs = ["dist/**"]
+//   priority = 10
+//   tags = ["unit", "fast"]
+//   then = "deploy.sh"
+//   timeout = "5m"
+//
+// They're read each time the script is triggered and
+// go over the `[scripts]` defaults from the config (all
+// but `then` which --then covers). Each env var is
+// merged in on its own. Everything else replaces the
+// default.
+pub fn sidecar_path(script: &Path) -> PathBuf {
+    let mut name = script.as_os_str().to_os_string();
+    name.push(".ws.toml");
+    PathBuf::from(name)
+}
+
+pub fn settings_for(script: &Path, defaults: &ScriptConfig) -> Result<ScriptConfig> {
+    let mut settings = read_settings(script, defaults)?;
+    settings.env.extend(read_env_file(script)?);
+    Ok(settings)
+}
+
+fn read_settings(script: &Path, defaults: &ScriptConfig) -> Result<ScriptConfig> {
+    let path = sidecar_path(script);
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        return Ok(defaults.clone());
+    };
+    let sidecar: ScriptConfig =
+        toml::from_str(&contents).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    let mut env = defaults.env.clone();
+    env.extend(sidecar.env);
+    Ok(ScriptConfig {
+        args: sidecar.args.or_else(|| defaults.args.clone()),
+        cooldown: sidecar.cooldown.or(defaults.cooldown),
+        cwd: sidecar.cwd.or(defaults.cwd),
+        env,
+        outputs: sidecar.outputs.or_else(|| defaults.outputs.clone()),
+        priority: sidecar.priority.or(defaults.priority),
+        tags: sidecar.tags.or_else(|| defaults.tags.clone()),
+        // only from the sidecar since --then is already
+        // the default. It's relative to the script.
+        then: sidecar
+            .then
+            .map(|then| script.parent().unwrap_or(Path::new("")).join(then)),
+        timeout: sidecar.timeout.or(defaults.timeout),
+    })
+}
+
+// A dotenv file next to a script named after it with
+// `.env` on the end has variables just for that script
+// (secrets, per-script settings):
+//
+//   # build.sh.env
+//   API_TOKEN=abc123
+//   export REGION="us-east-1"
+//
+// Blank lines and # comments are skipped, `export` in
+// front is fine, and a value can be in single or double
+// quotes. Nothing gets expanded. It's read each time the
+// script runs and goes over the env from the config and
+// the .ws.toml sidecar. --env still wins.
+pub fn env_file_path(script: &Path) -> PathBuf {
+    let mut name = script.as_os_str().to_os_string();
+    name.push(".env");
+    PathBuf::from(name)
+}
+
+fn read_env_file(script: &Path) -> Result<BTreeMap<String, String>> {
+    let path = env_file_path(script);
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        return Ok(BTreeMap::new());
+    };
+    let mut env = BTreeMap::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, value)) = line
+            .split_once('=')
+            .filter(|(name, _)| !name.trim().is_empty())
+        else {
+            return Err(anyhow!(
+                "{}:{}: expected NAME=value",
+                path.display(),
+                index + 1
+            ));
+        };
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| {
+                value
+                    .strip_prefix(*quote)
+                    .and_then(|value| value.strip_suffix(*quote))
+            })
+            .unwrap_or(value);
+        env.insert(name.trim().to_string(), value.to_string());
+    }
+    Ok(env)
+}
+
+// Quotes args for the shell command the script runs in
+pub fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_args() {
+        assert_eq!(shell_quote("--name=value,1"), "--name=value,1");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn reads_env_files() {
+        let dir = std::env::temp_dir().join(format!("ws-env-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("build.sh");
+        assert_eq!(read_env_file(&script).unwrap(), BTreeMap::new());
+        std::fs::write(
+            env_file_path(&script),
+            "# secrets\n\nAPI_TOKEN=abc123\nexport REGION=\"us-east-1\"\n  NAME = 'a b' \nEMPTY=\nRAW=$HOME\n",
+        )
+        .unwrap();
+        let env = read_env_file(&script).unwrap();
+        let expected = [
+            ("API_TOKEN", "abc123"),
+            ("EMPTY", ""),
+            ("NAME", "a b"),
+            ("RAW", "$HOME"),
+            ("REGION", "us-east-1"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        assert_eq!(env, BTreeMap::from(expected));
+        std::fs::write(env_file_path(&script), "OK=1\nnot a variable\n").unwrap();
+        let error = read_env_file(&script).unwrap_err().to_string();
+        assert!(
+            error.ends_with("build.sh.env:2: expected NAME=value"),
+            "{}",
+            error
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
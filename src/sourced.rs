@@ -0,0 +1,213 @@
+use crate::inventory::Inventory;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+// Shell scripts that pull in a shared file with
+//
+//   source lib.sh
+//   . "$(dirname "$0")/lib/common.sh"
+//
+// get run again when that file changes even though it
+// isn't a script itself. Paths are taken relative to the
+// script's directory (that's where scripts run from) and
+// then the watch root. Anything in front of the first
+// slash that starts with a `$` ($DIR, $(dirname "$0"),
+// ${BASH_SOURCE%/*}, ...) is taken to mean the script's
+// directory too. Other variables can't be worked out so
+// those lines are skipped. Files sourced from sourced
+// files count as well.
+//
+// What each file sources (and whether it's a shell
+// script at all) is kept until its modification time
+// changes so the scripts don't all get read again for
+// every change.
+#[derive(Debug, Clone, Default)]
+pub struct Sourced {
+    cache: Arc<Mutex<HashMap<PathBuf, Stamped>>>,
+}
+
+// What's kept about a file until it changes
+#[derive(Debug, Clone)]
+struct Stamped {
+    modified: Option<SystemTime>,
+    shell: bool,
+    sources: Vec<PathBuf>,
+}
+
+impl Sourced {
+    // The scripts that source the file, directly or not
+    pub fn scripts_sourcing(&self, changed: &Path, inventory: &Inventory) -> Vec<PathBuf> {
+        let Ok(changed) = changed.canonicalize() else {
+            return vec![];
+        };
+        inventory
+            .scripts()
+            .into_iter()
+            .filter(|script| self.stamped(script, inventory.root()).shell)
+            .filter(|script| {
+                self.all_sourced_by(script, inventory.root())
+                    .contains(&changed)
+            })
+            .collect()
+    }
+
+    fn all_sourced_by(&self, script: &Path, root: &Path) -> BTreeSet<PathBuf> {
+        let mut found = BTreeSet::new();
+        let mut pending = vec![script.to_path_buf()];
+        while let Some(file) = pending.pop() {
+            for sourced in self.stamped(&file, root).sources {
+                if found.insert(sourced.clone()) {
+                    pending.push(sourced);
+                }
+            }
+        }
+        found
+    }
+
+    fn stamped(&self, file: &Path, root: &Path) -> Stamped {
+        let modified = std::fs::metadata(file).and_then(|m| m.modified()).ok();
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(stamped) = cache.get(file)
+            && stamped.modified == modified
+        {
+            return stamped.clone();
+        }
+        let contents = std::fs::read(file).unwrap_or_default();
+        let contents = String::from_utf8_lossy(&contents);
+        let dir = file.parent().unwrap_or(root);
+        let stamped = Stamped {
+            modified,
+            shell: is_shell_script(file, &contents),
+            sources: contents
+                .lines()
+                .filter_map(sourced_path)
+                .filter_map(|path| resolve(&path, dir, root))
+                .collect(),
+        };
+        cache.insert(file.to_path_buf(), stamped.clone());
+        stamped
+    }
+}
+
+// Going by the extension or the #! line
+fn is_shell_script(path: &Path, contents: &str) -> bool {
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        return matches!(extension, "sh" | "bash" | "zsh" | "ksh");
+    }
+    let first_line = contents.lines().next().unwrap_or_default();
+    first_line.starts_with("#!")
+        && ["sh", "bash", "zsh", "ksh", "dash"].iter().any(|shell| {
+            first_line
+                .split(['/', ' '])
+                .any(|part| part.trim() == *shell)
+        })
+}
+
+// The path from a `source x` or `. x` line
+fn sourced_path(line: &str) -> Option<String> {
+    let line = line.trim_start();
+    let rest = line
+        .strip_prefix("source ")
+        .or_else(|| line.strip_prefix(". "))?
+        .trim();
+    let word = match rest.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let inner = &rest[1..];
+            // the closing quote, skipping over any
+            // $(...) with quotes of its own
+            let mut depth = 0;
+            let mut end = None;
+            for (index, c) in inner.char_indices() {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    c if c == quote && depth == 0 => {
+                        end = Some(index);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            inner[..end?].to_string()
+        }
+        _ => take_word(rest),
+    };
+    Some(word.replace(['"', '\''], ""))
+}
+
+// Up to the first space that isn't inside $(...)
+fn take_word(text: &str) -> String {
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ' ' | ';' | '&' | '|' if depth == 0 => return text[..index].to_string(),
+            _ => {}
+        }
+    }
+    text.to_string()
+}
+
+fn resolve(path: &str, dir: &Path, root: &Path) -> Option<PathBuf> {
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => PathBuf::from(std::env::var_os("HOME")?).join(rest),
+        None if path.starts_with('$') => dir.join(after_variable(path)?),
+        None => PathBuf::from(path),
+    };
+    if path.to_string_lossy().contains('$') {
+        return None;
+    }
+    [dir.join(&path), root.join(&path)]
+        .iter()
+        .find(|candidate| candidate.is_file())
+        .and_then(|candidate| candidate.canonicalize().ok())
+}
+
+// What's after a leading $VAR, ${...}, or $(...)
+fn after_variable(path: &str) -> Option<&str> {
+    let mut depth = 0;
+    for (index, c) in path.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            '/' if depth == 0 => return Some(&path[index + 1..]),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_sourced_paths() {
+        let path = |line: &str| sourced_path(line);
+        assert_eq!(path("source ./lib.sh").as_deref(), Some("./lib.sh"));
+        assert_eq!(path("  . lib.sh; echo done").as_deref(), Some("lib.sh"));
+        assert_eq!(path(". 'my lib.sh'").as_deref(), Some("my lib.sh"));
+        assert_eq!(
+            path(r#"source "$(dirname "$0")/lib.sh""#).as_deref(),
+            Some("$(dirname $0)/lib.sh")
+        );
+        assert_eq!(
+            path("source $(dirname $0)/lib.sh && main").as_deref(),
+            Some("$(dirname $0)/lib.sh")
+        );
+        assert_eq!(path("echo source lib.sh"), None);
+        assert_eq!(path("sourced lib.sh"), None);
+        assert_eq!(path("source"), None);
+    }
+
+    #[test]
+    fn skips_a_leading_variable() {
+        assert_eq!(after_variable("$DIR/lib/x.sh"), Some("lib/x.sh"));
+        assert_eq!(after_variable("${DIR:-.}/x.sh"), Some("x.sh"));
+        assert_eq!(after_variable("$(dirname $0/..)/x.sh"), Some("x.sh"));
+        assert_eq!(after_variable("$DIR"), None);
+    }
+}
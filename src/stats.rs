@@ -0,0 +1,142 @@
+use crate::run::format_duration;
+use crate::session::RunInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::time::Duration;
+
+pub const STATS_NAME: &str = ".watch_scripts.stats.json";
+
+// Only this many of the latest durations go into the
+// percentiles so a watcher that's been up for weeks
+// reflects how the script is doing now
+const KEEP_DURATIONS: usize = 500;
+
+// How each script has been doing: how often it's run and
+// failed and how long it takes. With --keep-stats they're
+// saved in the watch root on the way out and picked up
+// again at startup.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stats {
+    scripts: BTreeMap<String, ScriptStats>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ScriptStats {
+    durations_ms: VecDeque<u64>,
+    failures: usize,
+    runs: usize,
+}
+
+impl Stats {
+    // A missing or unreadable file just means starting
+    // over
+    pub fn load(root: &Path) -> Stats {
+        std::fs::read_to_string(root.join(STATS_NAME))
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, root: &Path) -> std::io::Result<()> {
+        std::fs::write(
+            root.join(STATS_NAME),
+            serde_json::to_string_pretty(self).unwrap_or_default(),
+        )
+    }
+
+    // Runs that were skipped or cancelled don't count
+    pub fn record(&mut self, info: &RunInfo) {
+        let Some(success) = info.success else {
+            return;
+        };
+        let stats = self
+            .scripts
+            .entry(info.script.display().to_string())
+            .or_default();
+        stats.runs += 1;
+        if !success {
+            stats.failures += 1;
+        }
+        if let Some(ms) = info.duration_ms {
+            stats.durations_ms.push_back(ms as u64);
+            while stats.durations_ms.len() > KEEP_DURATIONS {
+                stats.durations_ms.pop_front();
+            }
+        }
+    }
+
+    // Slowest (by median) first since that's usually
+    // what's being looked for
+    pub fn table(&self) -> String {
+        if self.scripts.is_empty() {
+            return "No runs yet".to_string();
+        }
+        let mut rows: Vec<(&String, &ScriptStats, Option<u64>)> = self
+            .scripts
+            .iter()
+            .map(|(script, stats)| (script, stats, stats.percentile(50)))
+            .collect();
+        rows.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(b.0)));
+        let width = rows
+            .iter()
+            .map(|(script, _, _)| script.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("script".len());
+        let mut table = format!(
+            "\x1b[2m{:<width$}  {:>5}  {:>6}  {:>8}  {:>8}  {:>8}\x1b[0m",
+            "script",
+            "runs",
+            "failed",
+            "p50",
+            "p90",
+            "p99",
+            width = width
+        );
+        for (script, stats, _) in rows {
+            let ms = |p: usize| {
+                stats
+                    .percentile(p)
+                    .map(|ms| format_duration(&Duration::from_millis(ms)))
+                    .unwrap_or_else(|| "-".to_string())
+            };
+            let failed = format!("{}%", stats.failures * 100 / stats.runs.max(1));
+            let (color, reset) = if stats.failures > 0 {
+                ("\x1b[31m", "\x1b[0m")
+            } else {
+                ("", "")
+            };
+            table.push_str(&format!(
+                "\n{:<width$}  {:>5}  {}{:>6}{}  {:>8}  {:>8}  {:>8}",
+                script,
+                stats.runs,
+                color,
+                failed,
+                reset,
+                ms(50),
+                ms(90),
+                ms(99),
+                width = width
+            ));
+        }
+        table
+    }
+}
+
+impl ScriptStats {
+    fn percentile(&self, p: usize) -> Option<u64> {
+        let mut sorted: Vec<u64> = self.durations_ms.iter().copied().collect();
+        sorted.sort_unstable();
+        percentile(&sorted, p)
+    }
+}
+
+// Nearest rank
+pub fn percentile(sorted: &[u64], p: usize) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
@@ -0,0 +1,92 @@
+use crate::output::OutputSink;
+use serde_json::{Value, json};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+// Fans out what the watcher is doing (runs starting and
+// finishing plus each line of output) to anything that's
+// listening, like a WebSocket client mirroring the
+// terminal. Sending with nobody listening is a no-op.
+#[derive(Debug, Clone)]
+pub struct RunStream {
+    // everything, for mirroring the terminal
+    tx: broadcast::Sender<String>,
+    // everything but the output lines so a follower that
+    // only cares about runs can't fall behind a script
+    // that prints a lot
+    events: broadcast::Sender<String>,
+}
+
+// What the followers (--webhook, --keep-history, the
+// hooks, ...) read the stream through
+pub struct Follower {
+    rx: broadcast::Receiver<String>,
+}
+
+impl RunStream {
+    pub fn new() -> RunStream {
+        let (tx, _) = broadcast::channel(1024);
+        let (events, _) = broadcast::channel(1024);
+        RunStream { tx, events }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.tx.subscribe()
+    }
+
+    // The run events, and the output lines too if asked
+    // for
+    pub fn follow(&self, with_output: bool) -> Follower {
+        Follower {
+            rx: match with_output {
+                true => self.tx.subscribe(),
+                false => self.events.subscribe(),
+            },
+        }
+    }
+
+    pub fn send(&self, kind: &str, data: Value) {
+        let to_events = kind != "output" && self.events.receiver_count() > 0;
+        if self.tx.receiver_count() == 0 && !to_events {
+            return;
+        }
+        let message = json!({ "type": kind, "data": data }).to_string();
+        if to_events {
+            let _ = self.events.send(message.clone());
+        }
+        let _ = self.tx.send(message);
+    }
+
+    pub fn line(&self, line: &str) {
+        self.send("output", json!({ "line": line }));
+    }
+}
+
+impl Follower {
+    // The next message, or None once the watcher's done.
+    // Anything missed by falling behind is skipped instead
+    // of ending the follower.
+    pub async fn next(&mut self) -> Option<Value> {
+        loop {
+            match self.rx.recv().await {
+                Ok(message) => {
+                    if let Ok(message) = serde_json::from_str(&message) {
+                        return Some(message);
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl OutputSink for RunStream {
+    fn line(&mut self, line: &str) {
+        RunStream::line(self, line);
+    }
+
+    fn for_job(&self) -> Option<Box<dyn OutputSink>> {
+        Some(Box::new(self.clone()))
+    }
+}
@@ -0,0 +1,172 @@
+use crate::stream::RunStream;
+use anyhow::{Result, anyhow};
+use chrono::Local;
+use serde_json::Value;
+use std::os::unix::net::UnixDatagram;
+
+// --system-log sends a line for each run that starts and
+// finishes to syslog or the systemd journal so a watcher
+// running as a service shows up with everything else on
+// the box. The run's details go along as structured data
+// (RFC 5424 params for syslog and WS_* fields for the
+// journal) so they can be filtered on:
+//
+//   journalctl -t ws WS_EVENT=failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SystemLog {
+    Journald,
+    Syslog,
+}
+
+const IDENTIFIER: &str = "ws";
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+// Linux and then macOS
+const SYSLOG_SOCKETS: [&str; 2] = ["/dev/log", "/var/run/syslog"];
+
+// 32473 is the enterprise number set aside for examples
+// which is what unregistered params are supposed to use
+const SD_ID: &str = "ws@32473";
+
+// From syslog(3)
+const USER_FACILITY: u8 = 1;
+const ERR: u8 = 3;
+const NOTICE: u8 = 5;
+const INFO: u8 = 6;
+
+impl SystemLog {
+    pub fn from_arg(value: &str) -> SystemLog {
+        match value {
+            "syslog" => SystemLog::Syslog,
+            _ => SystemLog::Journald,
+        }
+    }
+
+    // Fails right away when there's nothing listening so
+    // it doesn't go unnoticed until the first run
+    pub fn connect(self) -> Result<UnixDatagram> {
+        let socket = UnixDatagram::unbound()?;
+        let paths: &[&str] = match self {
+            SystemLog::Journald => &[JOURNALD_SOCKET],
+            SystemLog::Syslog => &SYSLOG_SOCKETS,
+        };
+        for path in paths {
+            if socket.connect(path).is_ok() {
+                return Ok(socket);
+            }
+        }
+        Err(anyhow!("could not connect to {}", paths.join(" or ")))
+    }
+
+    pub fn follow(self, socket: UnixDatagram, stream: &RunStream) {
+        let mut events = stream.follow(false);
+        tokio::spawn(async move {
+            while let Some(message) = events.next().await {
+                let run = &message["data"];
+                let (event, severity) = match (message["type"].as_str(), run["success"].as_bool()) {
+                    (Some("run_started"), _) => ("started", INFO),
+                    (Some("run_finished"), Some(true)) => ("finished", INFO),
+                    (Some("run_finished"), Some(false)) => ("failed", ERR),
+                    // skipped or cancelled
+                    (Some("run_finished"), None) => ("finished", NOTICE),
+                    _ => continue,
+                };
+                let entry = Entry::new(event, severity, run);
+                let datagram = match self {
+                    SystemLog::Journald => entry.journald(),
+                    SystemLog::Syslog => entry.syslog(),
+                };
+                // a full or restarted logger shouldn't hold
+                // up or stop the watcher
+                let _ = socket.send(datagram.as_bytes());
+            }
+        });
+    }
+}
+
+struct Entry {
+    fields: Vec<(&'static str, String)>,
+    message: String,
+    severity: u8,
+}
+
+impl Entry {
+    fn new(event: &'static str, severity: u8, run: &Value) -> Entry {
+        let text = |key: &str| match &run[key] {
+            Value::Null => None,
+            Value::String(value) => Some(value.clone()),
+            value => Some(value.to_string()),
+        };
+        let script = text("script").unwrap_or_default();
+        let message = match text("status") {
+            Some(status) => format!("{} {} ({})", script, event, status),
+            None => format!("{} {}", script, event),
+        };
+        let mut fields = vec![("EVENT", event.to_string())];
+        for (field, key) in [
+            ("SCRIPT", "script"),
+            ("RUN_NUMBER", "run_number"),
+            ("TRIGGER", "trigger_path"),
+            ("STATUS", "status"),
+            ("EXIT_CODE", "exit_code"),
+            ("DURATION_MS", "duration_ms"),
+            ("THEN_STATUS", "then_status"),
+        ] {
+            if let Some(value) = text(key) {
+                fields.push((field, value));
+            }
+        }
+        Entry {
+            fields,
+            message,
+            severity,
+        }
+    }
+
+    // The journal's native protocol: one KEY=value per
+    // line. Values with a newline would need the binary
+    // form so those get flattened.
+    fn journald(&self) -> String {
+        let mut datagram = format!(
+            "MESSAGE={}\nPRIORITY={}\nSYSLOG_IDENTIFIER={}\n",
+            one_line(&self.message),
+            self.severity,
+            IDENTIFIER
+        );
+        for (field, value) in &self.fields {
+            datagram.push_str(&format!("WS_{}={}\n", field, one_line(value)));
+        }
+        datagram
+    }
+
+    // RFC 5424 with the hostname left for the local
+    // daemon to fill in
+    fn syslog(&self) -> String {
+        let params: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(field, value)| {
+                let value = value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace(']', "\\]");
+                format!("{}=\"{}\"", field.to_lowercase(), one_line(&value))
+            })
+            .collect();
+        format!(
+            "<{}>1 {} - {} {} run [{} {}] {}",
+            USER_FACILITY * 8 + self.severity,
+            Local::now().to_rfc3339(),
+            IDENTIFIER,
+            std::process::id(),
+            SD_ID,
+            params.join(" "),
+            one_line(&self.message)
+        )
+    }
+}
+
+fn one_line(value: &str) -> String {
+    value.replace(['\n', '\r'], " ")
+}
@@ -0,0 +1,49 @@
+use crate::config::ScriptConfig;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+// How many lines from the top a `ws-tags:` comment can be
+const HEADER_LINES: usize = 20;
+
+// A script's tags for --tags come from `tags` in the
+// config's `[scripts]` or its sidecar along with a
+// comment near the top of the script itself:
+//
+//   #!/bin/bash
+//   # ws-tags: unit, fast
+//
+// Any comment style works since only what comes after
+// `ws-tags:` is read.
+pub fn tags_for(script: &Path, settings: &ScriptConfig) -> Vec<String> {
+    let mut tags = settings.tags.clone().unwrap_or_default();
+    tags.extend(header_tags(script));
+    tags
+}
+
+// --tags lets a script run when it has any of the tags
+pub fn has_any(script: &Path, settings: &ScriptConfig, wanted: &[String]) -> bool {
+    tags_for(script, settings)
+        .iter()
+        .any(|tag| wanted.contains(tag))
+}
+
+fn header_tags(script: &Path) -> Vec<String> {
+    let Ok(file) = std::fs::File::open(script) else {
+        return vec![];
+    };
+    BufReader::new(file)
+        .lines()
+        .take(HEADER_LINES)
+        .map_while(Result::ok)
+        .filter_map(|line| {
+            line.split_once("ws-tags:")
+                .map(|(_, tags)| tags.to_string())
+        })
+        .flat_map(|tags| {
+            tags.split(',')
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
@@ -0,0 +1,116 @@
+use crate::shell::ScriptShell;
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+
+// A recipe from a task runner that gets run from the
+// watch root on every change in place of the changed
+// file (`--just test` runs `just test`, `--make all`
+// runs `make all`, and `--npm build` runs `npm run
+// build`). Everything else (filters, reporting, --then,
+// etc.) works the same as it does for scripts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Task {
+    Just {
+        justfile: PathBuf,
+        recipe: String,
+    },
+    Make {
+        makefile: PathBuf,
+        target: String,
+    },
+    Npm {
+        manager: &'static str,
+        package_json: PathBuf,
+        script: String,
+    },
+}
+
+// The names just looks for
+const JUSTFILE_NAMES: [&str; 3] = ["justfile", "Justfile", ".justfile"];
+
+// The names GNU make looks for (in its order)
+const MAKEFILE_NAMES: [&str; 3] = ["GNUmakefile", "makefile", "Makefile"];
+
+// The package manager goes by whichever lockfile is
+// there and falls back to npm
+const LOCKFILES: [(&str, &str); 4] = [
+    ("pnpm-lock.yaml", "pnpm"),
+    ("yarn.lock", "yarn"),
+    ("bun.lock", "bun"),
+    ("bun.lockb", "bun"),
+];
+
+fn find_file(root: &Path, names: &[&str]) -> Option<PathBuf> {
+    names
+        .iter()
+        .map(|name| root.join(name))
+        .find(|path| path.is_file())
+}
+
+impl Task {
+    pub fn just(root: &Path, recipe: &str) -> Result<Task> {
+        let justfile = find_file(root, &JUSTFILE_NAMES)
+            .ok_or_else(|| anyhow!("--just needs a justfile in {}", root.display()))?;
+        Ok(Task::Just {
+            justfile,
+            recipe: recipe.to_string(),
+        })
+    }
+
+    pub fn make(root: &Path, target: &str) -> Result<Task> {
+        let makefile = find_file(root, &MAKEFILE_NAMES)
+            .ok_or_else(|| anyhow!("--make needs a Makefile in {}", root.display()))?;
+        Ok(Task::Make {
+            makefile,
+            target: target.to_string(),
+        })
+    }
+
+    pub fn npm(root: &Path, script: &str) -> Result<Task> {
+        let package_json = root.join("package.json");
+        let contents = std::fs::read_to_string(&package_json)
+            .map_err(|_| anyhow!("--npm needs a package.json in {}", root.display()))?;
+        let package: serde_json::Value = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("{}: {}", package_json.display(), e))?;
+        if package["scripts"].get(script).is_none() {
+            return Err(anyhow!(
+                "{} doesn't have a `{}` script",
+                package_json.display(),
+                script
+            ));
+        }
+        let manager = LOCKFILES
+            .iter()
+            .find(|(lockfile, _)| root.join(lockfile).is_file())
+            .map_or("npm", |(_, manager)| manager);
+        Ok(Task::Npm {
+            manager,
+            package_json,
+            script: script.to_string(),
+        })
+    }
+
+    // What the shell gets run with. The names come
+    // straight from the command line (or a package.json)
+    // so they're quoted for it.
+    pub fn command(&self, shell: &ScriptShell) -> String {
+        match self {
+            Task::Just { recipe, .. } => format!("just {}", shell.quote(recipe)),
+            Task::Make { target, .. } => format!("make {}", shell.quote(target)),
+            Task::Npm {
+                manager, script, ..
+            } => format!("{} run {}", manager, shell.quote(script)),
+        }
+    }
+
+    // The file that defines the task. It stands in for
+    // the script in the places that need a path (like
+    // re-running the last one).
+    pub fn file(&self) -> &Path {
+        match self {
+            Task::Just { justfile, .. } => justfile,
+            Task::Make { makefile, .. } => makefile,
+            Task::Npm { package_json, .. } => package_json,
+        }
+    }
+}
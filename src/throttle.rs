@@ -0,0 +1,64 @@
+use crate::held::HeldRuns;
+use crate::run::format_duration;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use watchexec::Watchexec;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+// --max-runs-per-minute <count> caps how often runs can
+// start over any minute. Once it's hit the scripts that
+// get set off are held (see held::HeldRuns) until
+// there's room again.
+#[derive(Debug, Clone)]
+pub struct RunThrottle {
+    held: HeldRuns,
+    max: usize,
+    // when the runs in the last minute started, oldest
+    // first
+    started: Arc<Mutex<VecDeque<Instant>>>,
+}
+
+impl RunThrottle {
+    pub fn new(max: usize) -> RunThrottle {
+        RunThrottle {
+            held: HeldRuns::default(),
+            max,
+            started: Arc::default(),
+        }
+    }
+
+    // Counts a run as started if there's room for it.
+    // Otherwise returns how long until there is.
+    pub fn admit(&self) -> Result<(), Duration> {
+        let mut started = self.started.lock().unwrap();
+        while started
+            .front()
+            .is_some_and(|start| start.elapsed() >= WINDOW)
+        {
+            started.pop_front();
+        }
+        if started.len() < self.max {
+            started.push_back(Instant::now());
+            return Ok(());
+        }
+        Err(WINDOW.saturating_sub(started.front().unwrap().elapsed()))
+    }
+
+    pub fn hold(
+        &self,
+        scripts: impl IntoIterator<Item = PathBuf>,
+        wait: Duration,
+        wx: Arc<Watchexec>,
+    ) {
+        if self.held.hold(scripts, wait, wx) {
+            println!(
+                "\x1b[33m⏳ Throttled (--max-runs-per-minute {}). The latest changes run in {}\x1b[0m",
+                self.max,
+                format_duration(&wait)
+            );
+        }
+    }
+}
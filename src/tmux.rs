@@ -0,0 +1,120 @@
+use crate::stream::RunStream;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::Command;
+
+// What gets written to the --tmux-pane target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TmuxSend {
+    // Every line of output plus the summary
+    Output,
+    Summary,
+}
+
+impl TmuxSend {
+    pub fn from_arg(value: &str) -> TmuxSend {
+        match value {
+            "output" => TmuxSend::Output,
+            _ => TmuxSend::Summary,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tmux {
+    pub pane: Option<String>,
+    pub send: TmuxSend,
+}
+
+impl Tmux {
+    // Follows the run stream to keep the window name
+    // showing the state of the latest run and to mirror
+    // runs into another pane. The pane's tty is written
+    // to directly so whatever is running in it (usually
+    // just a shell) isn't involved.
+    pub fn follow(self, stream: &RunStream) {
+        let mut tty = self.pane.as_ref().and_then(|pane| {
+            let tty = tmux(&["display-message", "-p", "-t", pane, "#{pane_tty}"])?;
+            match OpenOptions::new().append(true).open(tty.trim()) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    eprintln!("ERROR: could not open tmux pane {}: {}", pane, e);
+                    None
+                }
+            }
+        });
+        let mut events = stream.follow(self.send == TmuxSend::Output);
+        tokio::spawn(async move {
+            while let Some(message) = events.next().await {
+                let data = &message["data"];
+                let script = data["script"].as_str().unwrap_or_default();
+                let text = match message["type"].as_str() {
+                    Some("run_started") => {
+                        rename_window(&format!("● {}", script));
+                        (self.send == TmuxSend::Output)
+                            .then(|| format!("\x1b[H\x1b[2J\x1b[2mrunning {}\x1b[0m\n", script))
+                    }
+                    Some("output") if self.send == TmuxSend::Output => {
+                        Some(format!("{}\n", data["line"].as_str().unwrap_or_default()))
+                    }
+                    Some("run_finished") => {
+                        let (mark, color) = match data["success"].as_bool() {
+                            Some(true) => ("✔", "\x1b[32m"),
+                            Some(false) => ("✘", "\x1b[31m"),
+                            None => ("-", "\x1b[33m"),
+                        };
+                        rename_window(&format!("{} {}", mark, script));
+                        Some(format!(
+                            "{}{} {} | {} | run #{}\x1b[0m\n",
+                            color,
+                            mark,
+                            script,
+                            data["status"].as_str().unwrap_or("unknown"),
+                            data["run_number"]
+                        ))
+                    }
+                    _ => None,
+                };
+                if let (Some(file), Some(text)) = (tty.as_mut(), text) {
+                    let _ = file.write_all(text.as_bytes());
+                }
+            }
+        });
+    }
+}
+
+// Hands the window name back to tmux on the way out.
+pub fn restore_window_name() {
+    if std::env::var_os("TMUX").is_some() {
+        let mut args = vec!["set-window-option"];
+        let pane = std::env::var("TMUX_PANE").unwrap_or_default();
+        if !pane.is_empty() {
+            args.extend(["-t", pane.as_str()]);
+        }
+        args.extend(["automatic-rename", "on"]);
+        tmux(&args);
+    }
+}
+
+// Only does anything when the watcher itself is running
+// inside tmux.
+fn rename_window(name: &str) {
+    if std::env::var_os("TMUX").is_none() {
+        return;
+    }
+    let pane = std::env::var("TMUX_PANE").unwrap_or_default();
+    let mut args = vec!["rename-window"];
+    if !pane.is_empty() {
+        args.extend(["-t", pane.as_str()]);
+    }
+    args.push(name);
+    tmux(&args);
+}
+
+fn tmux(args: &[&str]) -> Option<String> {
+    let output = Command::new("tmux").args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
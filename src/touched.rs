@@ -0,0 +1,167 @@
+use ignore::WalkBuilder;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// The most files a snapshot looks at so a huge tree
+// doesn't hold up every run. Past that the report says
+// it only covers part of it.
+const SNAPSHOT_LIMIT: usize = 20_000;
+
+// How many of each kind get listed before the rest are
+// summed up
+const LIST_LIMIT: usize = 20;
+
+// --touched's picture of the watch root before and after
+// the main script runs. Modification times and sizes are
+// compared instead of contents to keep it quick. Ignored
+// and hidden files count (build output usually is) but
+// .git and the watcher's own files don't.
+#[derive(Debug)]
+pub struct Snapshot {
+    files: HashMap<PathBuf, (Option<SystemTime>, u64)>,
+    root: PathBuf,
+    partial: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Touched {
+    created: Vec<PathBuf>,
+    deleted: Vec<PathBuf>,
+    modified: Vec<PathBuf>,
+    partial: bool,
+}
+
+impl Snapshot {
+    // It looks at every file under the root so the walk
+    // happens off the runtime's threads
+    pub async fn take(root: &Path) -> Snapshot {
+        let walked = root.to_path_buf();
+        tokio::task::spawn_blocking(move || Snapshot::walk(&walked))
+            .await
+            .unwrap_or_else(|_| Snapshot {
+                files: HashMap::new(),
+                root: root.to_path_buf(),
+                partial: true,
+            })
+    }
+
+    fn walk(root: &Path) -> Snapshot {
+        let mut files = HashMap::new();
+        let mut partial = false;
+        let walk = WalkBuilder::new(root)
+            .standard_filters(false)
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build();
+        for entry in walk.flatten() {
+            if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+                continue;
+            }
+            let Ok(relative) = entry.path().strip_prefix(root) else {
+                continue;
+            };
+            if relative.to_string_lossy().starts_with(".watch_scripts.") {
+                continue;
+            }
+            if files.len() == SNAPSHOT_LIMIT {
+                partial = true;
+                break;
+            }
+            if let Ok(metadata) = entry.metadata() {
+                files.insert(
+                    relative.to_path_buf(),
+                    (metadata.modified().ok(), metadata.len()),
+                );
+            }
+        }
+        Snapshot {
+            files,
+            root: root.to_path_buf(),
+            partial,
+        }
+    }
+
+    // What's different in a new snapshot of the same root
+    pub async fn touched(&self) -> Touched {
+        let after = Snapshot::take(&self.root).await;
+        let mut touched = Touched {
+            partial: self.partial || after.partial,
+            ..Default::default()
+        };
+        for (path, stamp) in &after.files {
+            match self.files.get(path) {
+                None => touched.created.push(path.clone()),
+                Some(before) if before != stamp => touched.modified.push(path.clone()),
+                Some(_) => {}
+            }
+        }
+        touched.deleted = self
+            .files
+            .keys()
+            .filter(|path| !after.files.contains_key(*path))
+            .cloned()
+            .collect();
+        touched.created.sort();
+        touched.modified.sort();
+        touched.deleted.sort();
+        touched
+    }
+}
+
+impl Touched {
+    pub fn print(&self, script: &Path) {
+        if self.created.is_empty() && self.modified.is_empty() && self.deleted.is_empty() {
+            println!("\x1b[2m{} didn't touch any files\x1b[0m", script.display());
+            return;
+        }
+        println!(
+            "\x1b[2m{} touched files: {} created, {} modified, {} deleted{}\x1b[0m",
+            script.display(),
+            self.created.len(),
+            self.modified.len(),
+            self.deleted.len(),
+            if self.partial {
+                format!(" (only the first {} files were checked)", SNAPSHOT_LIMIT)
+            } else {
+                String::new()
+            }
+        );
+        for (paths, mark, color) in [
+            (&self.created, "+", 32),
+            (&self.modified, "~", 33),
+            (&self.deleted, "-", 31),
+        ] {
+            for path in paths.iter().take(LIST_LIMIT) {
+                println!("  \x1b[{}m{} {}\x1b[0m", color, mark, path.display());
+            }
+            if paths.len() > LIST_LIMIT {
+                println!(
+                    "  \x1b[2m{} ... and {} more\x1b[0m",
+                    mark,
+                    paths.len() - LIST_LIMIT
+                );
+            }
+        }
+    }
+
+    // Everything that was created, modified, or deleted
+    // relative to the watch root
+    pub fn all(&self) -> impl Iterator<Item = &PathBuf> {
+        self.created
+            .iter()
+            .chain(&self.modified)
+            .chain(&self.deleted)
+    }
+
+    // For WS_TOUCHED. One path a line relative to the
+    // watch root, of the files that are there now.
+    pub fn list(&self) -> String {
+        let mut paths: Vec<&PathBuf> = self.created.iter().chain(&self.modified).collect();
+        paths.sort();
+        paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+// The files git knows about for --tracked-only. The list
+// is reloaded whenever git's index file changes so adding
+// or removing a script is picked up without a restart.
+#[derive(Debug)]
+pub struct TrackedFiles {
+    files: HashSet<PathBuf>,
+    index: PathBuf,
+    index_modified: Option<SystemTime>,
+    toplevel: PathBuf,
+}
+
+impl TrackedFiles {
+    pub fn new(root: &Path) -> Result<TrackedFiles> {
+        let toplevel = std::fs::canonicalize(git(root, &["rev-parse", "--show-toplevel"])?.trim())?;
+        let index = PathBuf::from(git(root, &["rev-parse", "--git-path", "index"])?.trim());
+        let mut tracked = TrackedFiles {
+            files: HashSet::new(),
+            index: root.join(index),
+            index_modified: None,
+            toplevel,
+        };
+        tracked.refresh()?;
+        Ok(tracked)
+    }
+
+    pub fn contains(&mut self, path: &Path) -> bool {
+        if self.index_modified != modified(&self.index) {
+            let _ = self.refresh();
+        }
+        std::fs::canonicalize(path).is_ok_and(|path| self.files.contains(&path))
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        self.index_modified = modified(&self.index);
+        let listing = git(&self.toplevel, &["ls-files", "-z"])?;
+        self.files = listing
+            .split('\0')
+            .filter(|file| !file.is_empty())
+            .map(|file| self.toplevel.join(file))
+            .collect();
+        Ok(())
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,206 @@
+use crate::inventory::Inventory;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use watchexec_events::filekind::{DataChange, FileEventKind, MetadataKind, ModifyKind};
+use watchexec_events::{Event, Source, Tag};
+use watchexec_signals::Signal;
+
+// Events that don't come from the filesystem but should
+// be handled like a change to the script at the path
+// (e.g. a key press to re-run the last script).
+pub fn manual_event(path: &Path, source: Source) -> Event {
+    Event {
+        tags: vec![
+            Tag::Source(source),
+            Tag::Path {
+                path: path.to_path_buf(),
+                file_type: None,
+            },
+        ],
+        metadata: Default::default(),
+    }
+}
+
+// Runs the watcher sets off again on its own (like the
+// ones --resume picks back up) go through the action
+// handler the same as the change that would set them off
+// instead of as if they were asked for by name. There's
+// no change to look at so --events and the content check
+// let them through.
+pub fn released_event(paths: &[PathBuf]) -> Event {
+    Event {
+        tags: paths
+            .iter()
+            .map(|path| Tag::Path {
+                path: path.clone(),
+                file_type: None,
+            })
+            .collect(),
+        metadata: HashMap::from([("ws-released".to_string(), vec![])]),
+    }
+}
+
+pub fn is_released(event: &Event) -> bool {
+    event.metadata.contains_key("ws-released")
+}
+
+pub fn is_manual(event: &Event) -> bool {
+    event.tags.iter().any(|tag| {
+        matches!(
+            tag,
+            Tag::Source(Source::Keyboard)
+                | Tag::Source(Source::Internal)
+                | Tag::Source(Source::Time)
+        )
+    })
+}
+
+// Asked for by someone (keys, the socket, HTTP) rather
+// than by a schedule
+pub fn is_forced(event: &Event) -> bool {
+    event.tags.iter().any(|tag| {
+        matches!(
+            tag,
+            Tag::Source(Source::Keyboard) | Tag::Source(Source::Internal)
+        )
+    })
+}
+
+// The kinds of filesystem events that can set off a run
+// with --events. Only writes to a file's contents do by
+// default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TriggerKind {
+    Chmod,
+    Create,
+    Modify,
+    Remove,
+    Rename,
+}
+
+impl TriggerKind {
+    fn from_arg(value: &str) -> Option<TriggerKind> {
+        match value {
+            "chmod" => Some(TriggerKind::Chmod),
+            "create" => Some(TriggerKind::Create),
+            "modify" => Some(TriggerKind::Modify),
+            "remove" => Some(TriggerKind::Remove),
+            "rename" => Some(TriggerKind::Rename),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, kind: &FileEventKind) -> bool {
+        match self {
+            TriggerKind::Chmod => matches!(
+                kind,
+                FileEventKind::Modify(ModifyKind::Metadata(
+                    MetadataKind::Permissions | MetadataKind::Ownership | MetadataKind::Any
+                ))
+            ),
+            TriggerKind::Create => matches!(kind, FileEventKind::Create(_)),
+            TriggerKind::Modify => is_content_change(kind),
+            TriggerKind::Remove => matches!(kind, FileEventKind::Remove(_)),
+            TriggerKind::Rename => matches!(kind, FileEventKind::Modify(ModifyKind::Name(_))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TriggerKinds {
+    kinds: BTreeSet<TriggerKind>,
+}
+
+impl TriggerKinds {
+    pub fn from_args<'a>(values: impl Iterator<Item = &'a String>) -> TriggerKinds {
+        TriggerKinds {
+            kinds: values
+                .filter_map(|value| TriggerKind::from_arg(value))
+                .collect(),
+        }
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        event.tags.iter().any(|tag| match tag {
+            Tag::FileEventKind(kind) => self.kinds.iter().any(|trigger| trigger.matches(kind)),
+            _ => false,
+        })
+    }
+}
+
+// A file being written. The --poll watcher can only tell
+// that by its modification time going up.
+fn is_content_change(kind: &FileEventKind) -> bool {
+    matches!(
+        kind,
+        FileEventKind::Modify(
+            ModifyKind::Data(DataChange::Content) | ModifyKind::Metadata(MetadataKind::WriteTime)
+        )
+    )
+}
+
+// Whether the event was a write, which is the only kind
+// the content check (see hashes::ContentHashes) makes
+// sense for
+pub fn is_write(event: &Event) -> bool {
+    event.tags.iter().any(|tag| match tag {
+        Tag::FileEventKind(kind) => is_content_change(kind),
+        _ => false,
+    })
+}
+
+// Requests that aren't about a particular script (like
+// killing the running job) are carried in the metadata
+// so they can still go through the action handler.
+pub fn command_event(command: &str) -> Event {
+    Event {
+        tags: vec![Tag::Source(Source::Internal)],
+        metadata: HashMap::from([("ws-command".to_string(), vec![command.to_string()])]),
+    }
+}
+
+pub fn command(events: &[Event]) -> Option<&str> {
+    events.iter().find_map(|event| {
+        event
+            .metadata
+            .get("ws-command")
+            .and_then(|values| values.first())
+            .map(|value| value.as_str())
+    })
+}
+
+// Goes through the same path as a Ctrl+c
+pub fn quit_event() -> Event {
+    Event {
+        tags: vec![Tag::Signal(Signal::Interrupt)],
+        metadata: Default::default(),
+    }
+}
+
+// Pausing from a key goes through the same path as
+// a SIGUSR2
+pub fn pause_event() -> Event {
+    Event {
+        tags: vec![Tag::Signal(Signal::User2)],
+        metadata: Default::default(),
+    }
+}
+
+// Turns a script name from an outside request (HTTP,
+// the control socket, etc.) into the path to run.
+// Relative names are looked up from the watch root.
+// Only scripts in the inventory go through so a request
+// can't reach outside the root with an absolute path,
+// `..`, or a symlink.
+pub fn resolve_script(inventory: &Inventory, requested: &str) -> Result<PathBuf, String> {
+    let requested = Path::new(requested);
+    let path = std::fs::canonicalize(inventory.root().join(requested))
+        .map_err(|_| format!("{} does not exist", requested.display()))?;
+    if !path.starts_with(inventory.root()) {
+        return Err(format!("{} is outside the watch root", requested.display()));
+    }
+    if !inventory.contains(&path) {
+        return Err(format!("{} is not a runnable script", requested.display()));
+    }
+    Ok(path)
+}
@@ -0,0 +1,239 @@
+use std::collections::VecDeque;
+
+// --max-output's limit on how much of one job's output
+// gets through. The first half of it is shown as it
+// comes in and the last half is held in a ring buffer
+// until the job is done so a runaway script's start and
+// end both make it to the screen (and any recording)
+// with a note about how much was cut from the middle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputLimit {
+    Bytes(usize),
+    Lines(usize),
+}
+
+// Parses limits like `200 lines`, `200l`, or a size (see
+// parse_size). This is used directly as a clap value
+// parser.
+pub fn parse_output_limit(value: &str) -> Result<OutputLimit, String> {
+    let value = value.trim();
+    let limit = match value
+        .strip_suffix("lines")
+        .or_else(|| value.strip_suffix("line"))
+        .or_else(|| value.strip_suffix('l'))
+    {
+        Some(number) => OutputLimit::Lines(
+            number
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid output limit: {}", value))?,
+        ),
+        None => OutputLimit::Bytes(parse_size(value)? as usize),
+    };
+    if matches!(limit, OutputLimit::Bytes(0) | OutputLimit::Lines(0)) {
+        return Err("the output limit has to be more than 0".to_string());
+    }
+    Ok(limit)
+}
+
+// Parses sizes like `64k`, `10m`, `1g`, or `65536`
+// (bytes). This is used directly as a clap value parser.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split_at = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split_at);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size: {}", value))?;
+    let scale: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1 << 10,
+        "m" | "mb" => 1 << 20,
+        "g" | "gb" => 1 << 30,
+        _ => return Err(format!("invalid size unit in: {}", value)),
+    };
+    number
+        .checked_mul(scale)
+        .ok_or_else(|| format!("size too big: {}", value))
+}
+
+#[derive(Debug)]
+pub struct Truncator {
+    limit: OutputLimit,
+    // bytes or lines that went out with the head
+    shown: usize,
+    ended_line: bool,
+    tail: VecDeque<u8>,
+    tail_units: usize,
+    skipped: usize,
+}
+
+impl Truncator {
+    pub fn new(limit: OutputLimit) -> Truncator {
+        Truncator {
+            limit,
+            shown: 0,
+            ended_line: true,
+            tail: VecDeque::new(),
+            tail_units: 0,
+            skipped: 0,
+        }
+    }
+
+    pub fn limit(&self) -> OutputLimit {
+        self.limit
+    }
+
+    fn size(&self) -> usize {
+        match self.limit {
+            OutputLimit::Bytes(n) | OutputLimit::Lines(n) => n,
+        }
+    }
+
+    fn head_size(&self) -> usize {
+        self.size() - self.size() / 2
+    }
+
+    // Lines are counted when their newline comes in so a
+    // partial line stays with the one it's part of
+    fn counts(&self, byte: u8) -> bool {
+        match self.limit {
+            OutputLimit::Bytes(_) => true,
+            OutputLimit::Lines(_) => byte == b'\n',
+        }
+    }
+
+    // The part of the bytes that can be shown now
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut now = vec![];
+        for &byte in bytes {
+            if self.shown < self.head_size() {
+                now.push(byte);
+                self.ended_line = byte == b'\n';
+                if self.counts(byte) {
+                    self.shown += 1;
+                }
+            } else {
+                self.tail.push_back(byte);
+                if self.counts(byte) {
+                    self.tail_units += 1;
+                }
+            }
+        }
+        let tail_size = self.size() / 2;
+        while self.tail_units > tail_size {
+            while let Some(byte) = self.tail.pop_front() {
+                if self.counts(byte) {
+                    break;
+                }
+            }
+            self.tail_units -= 1;
+            self.skipped += 1;
+        }
+        now
+    }
+
+    // What's left once the job is done and a fresh start
+    // for the next one
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut rest = vec![];
+        if self.skipped > 0 {
+            if !self.ended_line {
+                rest.push(b'\n');
+            }
+            let unit = match self.limit {
+                OutputLimit::Bytes(_) => "bytes",
+                OutputLimit::Lines(_) => "lines",
+            };
+            rest.extend_from_slice(
+                format!(
+                    "\x1b[2m... skipped {} {} (--max-output) ...\x1b[0m\n",
+                    self.skipped, unit
+                )
+                .as_bytes(),
+            );
+        }
+        rest.extend(self.tail.drain(..));
+        *self = Truncator::new(self.limit);
+        rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skipped(count: usize, unit: &str) -> String {
+        format!(
+            "\x1b[2m... skipped {} {} (--max-output) ...\x1b[0m\n",
+            count, unit
+        )
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("65536"), Ok(65536));
+        assert_eq!(parse_size("64k"), Ok(64 << 10));
+        assert_eq!(parse_size("10 MB"), Ok(10 << 20));
+        assert_eq!(parse_size("1g"), Ok(1 << 30));
+        assert!(parse_size("10x").is_err());
+        assert!(parse_size("k").is_err());
+        assert!(
+            parse_size("99999999999999g")
+                .unwrap_err()
+                .contains("too big")
+        );
+    }
+
+    #[test]
+    fn parses_output_limits() {
+        assert_eq!(parse_output_limit("200 lines"), Ok(OutputLimit::Lines(200)));
+        assert_eq!(parse_output_limit("1 line"), Ok(OutputLimit::Lines(1)));
+        assert_eq!(parse_output_limit("50l"), Ok(OutputLimit::Lines(50)));
+        assert_eq!(parse_output_limit("2k"), Ok(OutputLimit::Bytes(2048)));
+        assert!(parse_output_limit("0 lines").is_err());
+        assert!(parse_output_limit("0").is_err());
+        assert!(parse_output_limit("many lines").is_err());
+    }
+
+    #[test]
+    fn passes_everything_under_the_limit() {
+        let mut truncator = Truncator::new(OutputLimit::Lines(4));
+        let mut out = truncator.feed(b"one\ntwo\n");
+        out.extend(truncator.feed(b"three\n"));
+        out.extend(truncator.finish());
+        assert_eq!(out, b"one\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn keeps_the_first_and_last_lines() {
+        let mut truncator = Truncator::new(OutputLimit::Lines(4));
+        let mut out = truncator.feed(b"1\n2\n3\n4\n5\n");
+        out.extend(truncator.feed(b"6\n7\n"));
+        assert_eq!(out, b"1\n2\n");
+        out.extend(truncator.finish());
+        let expected = format!("1\n2\n{}6\n7\n", skipped(3, "lines"));
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn ends_a_partial_line_before_the_note() {
+        let mut truncator = Truncator::new(OutputLimit::Bytes(4));
+        let mut out = truncator.feed(b"abcdefgh");
+        out.extend(truncator.finish());
+        let expected = format!("ab\n{}gh", skipped(4, "bytes"));
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn starts_fresh_after_finishing() {
+        let mut truncator = Truncator::new(OutputLimit::Bytes(2));
+        truncator.feed(b"abcdef");
+        truncator.finish();
+        let mut out = truncator.feed(b"xy");
+        out.extend(truncator.finish());
+        assert_eq!(out, b"xy");
+    }
+}
@@ -0,0 +1,143 @@
+use crate::notify;
+use crate::stream::RunStream;
+use serde_json::json;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use watchexec::ErrorHook;
+use watchexec::error::{FsWatcherError, RuntimeError};
+use watchexec_events::filekind::FileEventKind;
+use watchexec_events::{Event, Tag};
+
+// How long after a banner the next few just get counted
+const QUIET_FOR: Duration = Duration::from_secs(10);
+
+const WATCHES_HINT: &str = "Raise fs.inotify.max_user_watches (see inotify(7)) or use --poll";
+
+// watchexec keeps going when the file watcher runs into
+// trouble (a directory it couldn't watch, running out of
+// inotify watches, the kernel's queue overflowing, events
+// dropped because too many came in at once) so changes
+// can go missing without anything saying so. Those get
+// a banner instead, go out on the stream as
+// `watch_error`, and send a desktop notification with
+// --notify-errors. A burst of them gets one banner and
+// a count in the next one.
+#[derive(Debug, Clone)]
+pub struct WatchErrors {
+    notify: bool,
+    stream: RunStream,
+    // when the last banner went up and how many have
+    // come in since
+    last: Arc<Mutex<Option<(Instant, usize)>>>,
+}
+
+impl WatchErrors {
+    pub fn new(notify: bool, stream: RunStream) -> WatchErrors {
+        WatchErrors {
+            notify,
+            stream,
+            last: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // For watchexec's on_error
+    pub fn handle(&self, hook: ErrorHook) {
+        if let Some((message, hint)) = describe(&hook.error) {
+            self.report(message, hint);
+        }
+    }
+
+    // The kernel dropped events (see overflowed())
+    pub fn overflowed(&self) {
+        self.report(
+            "the file watcher's queue overflowed".to_string(),
+            Some("Everything was looked over again but changes in the meantime were missed"),
+        );
+    }
+
+    fn report(&self, message: String, hint: Option<&str>) {
+        self.stream
+            .send("watch_error", json!({ "message": message }));
+        let more = {
+            let mut last = self.last.lock().unwrap();
+            match last.as_mut() {
+                Some((shown, count)) if shown.elapsed() < QUIET_FOR => {
+                    *count += 1;
+                    return;
+                }
+                _ => last
+                    .replace((Instant::now(), 0))
+                    .map_or(0, |(_, count)| count),
+            }
+        };
+        eprintln!("\x1b[41;97m ws: the file watcher ran into a problem \x1b[0m");
+        eprintln!("\x1b[31m{}\x1b[0m", message);
+        if more > 0 {
+            eprintln!("\x1b[31m(and {} more before this one)\x1b[0m", more);
+        }
+        eprintln!(
+            "\x1b[2m{}Changes may have been missed.\x1b[0m",
+            hint.map(|hint| format!("{}. ", hint)).unwrap_or_default()
+        );
+        if self.notify {
+            notify::desktop(&format!("The file watcher ran into a problem: {}", message));
+        }
+    }
+}
+
+// inotify's overflow comes through as an event without
+// a path instead of an error
+pub fn overflowed(events: &[Event]) -> bool {
+    events.iter().any(|event| {
+        event
+            .tags
+            .iter()
+            .any(|tag| matches!(tag, Tag::FileEventKind(FileEventKind::Other)))
+            && !event.tags.iter().any(|tag| matches!(tag, Tag::Path { .. }))
+    })
+}
+
+// What went wrong and what to do about it. Errors that
+// don't mean anything was missed are left out.
+fn describe(error: &RuntimeError) -> Option<(String, Option<&'static str>)> {
+    let hint = match error {
+        RuntimeError::Exit => return None,
+        // a job that's already gone
+        RuntimeError::IoError { about, .. } if about.starts_with("waiting on process") => {
+            return None;
+        }
+        RuntimeError::FsWatcher { err, .. } => match err {
+            FsWatcherError::TooManyWatches(_) => Some(WATCHES_HINT),
+            // notify's own name for running out of them
+            // while adding a directory
+            FsWatcherError::PathAdd { err, .. } if err.to_string().contains("watch limit") => {
+                Some(WATCHES_HINT)
+            }
+            FsWatcherError::TooManyHandles(_) => {
+                Some("Raise the open file limit or fs.inotify.max_user_instances or use --poll")
+            }
+            FsWatcherError::Create(_) => Some("--poll might work where this didn't"),
+            _ => None,
+        },
+        RuntimeError::EventChannelSend { .. } | RuntimeError::EventChannelTrySend { .. } => {
+            Some("Too many changes came in at once and some were dropped")
+        }
+        _ => None,
+    };
+    Some((with_sources(error), hint))
+}
+
+// The error and everything under it since watchexec's
+// own messages ("Inotify fs watcher error") leave out
+// the part that says what happened
+fn with_sources(error: &dyn Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
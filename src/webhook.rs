@@ -0,0 +1,29 @@
+use crate::stream::RunStream;
+
+// POSTs the details of every finished run to the
+// --webhook url.
+pub fn follow(url: String, stream: &RunStream) {
+    let mut events = stream.follow(false);
+    tokio::spawn(async move {
+        while let Some(message) = events.next().await {
+            if message["type"] != "run_finished" {
+                continue;
+            }
+            post(url.clone(), message["data"].to_string());
+        }
+    });
+}
+
+// Requests go out on a blocking thread so a slow endpoint
+// never holds up the next run, and failures are reported
+// but otherwise ignored.
+pub fn post(url: String, body: String) {
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = ureq::post(&url)
+            .header("Content-Type", "application/json")
+            .send(&body)
+        {
+            eprintln!("\x1b[33mwebhook to {} failed: {}\x1b[0m", url, e);
+        }
+    });
+}
@@ -0,0 +1,238 @@
+use anyhow::{Result, anyhow};
+use globset::Glob;
+use nix::sys::signal::{Signal, kill};
+use nix::unistd::Pid;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+
+// Colors the root labels cycle through so it's easy to
+// tell them apart at a glance
+const COLORS: [u8; 5] = [36, 35, 33, 32, 34];
+
+// `--workspace ~/sites/*/scripts` watches several project
+// roots at once. Each one gets its own ws started in it
+// with the rest of the command line, so it has its own
+// config, then script, socket, and cwd handling the same
+// as if it had been started there by hand. Their output
+// comes back here a line at a time with the root in
+// front of it.
+pub struct Workspace {
+    roots: Vec<PathBuf>,
+}
+
+impl Workspace {
+    // The shell usually expands the globs, but they're
+    // expanded here too for when they're quoted or come
+    // from WATCH_SCRIPTS_WORKSPACE.
+    pub fn new<'a>(patterns: impl IntoIterator<Item = &'a String>) -> Result<Workspace> {
+        let mut roots = vec![];
+        for pattern in patterns {
+            let found = expand(pattern)?;
+            if found.is_empty() {
+                return Err(anyhow!("no directories match --workspace {}", pattern));
+            }
+            roots.extend(found);
+        }
+        roots.sort();
+        roots.dedup();
+        Ok(Workspace { roots })
+    }
+
+    // Ok(false) if any of the watchers stopped with an
+    // error
+    pub async fn run(&self) -> Result<bool> {
+        let exe = std::env::current_exe()?;
+        let args = child_args();
+        let mut children: Vec<(Child, String)> = vec![];
+        for (index, (root, label)) in self.roots.iter().zip(labels(&self.roots)).enumerate() {
+            let prefix = format!("\x1b[{}m[{}]\x1b[0m ", COLORS[index % COLORS.len()], label);
+            // The watchers share one terminal so they don't
+            // get to clear it or read keys from it,
+            // and none of them are left behind if this
+            // returns early
+            let spawned = Command::new(&exe)
+                .args(&args)
+                .arg("--no-clear")
+                .current_dir(root)
+                .env_remove("WATCH_SCRIPTS_WORKSPACE")
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn();
+            let mut child = match spawned {
+                Ok(child) => child,
+                Err(e) => {
+                    // the ones that did start get to shut
+                    // down cleanly first
+                    for (child, _) in &mut children {
+                        if let Some(id) = child.id() {
+                            let _ = kill(Pid::from_raw(id as i32), Signal::SIGTERM);
+                        }
+                        let _ = child.wait().await;
+                    }
+                    return Err(anyhow!(
+                        "could not start a watcher in {}: {}",
+                        root.display(),
+                        e
+                    ));
+                }
+            };
+            if let Some(stdout) = child.stdout.take() {
+                tokio::spawn(relay(stdout, prefix.clone(), false));
+            }
+            if let Some(stderr) = child.stderr.take() {
+                tokio::spawn(relay(stderr, prefix.clone(), true));
+            }
+            children.push((child, prefix));
+        }
+        // Ctrl+C already goes to every watcher since they're
+        // in the terminal's process group so this one just
+        // waits for them to finish up. SIGTERM (from a
+        // service manager or kill) gets passed along.
+        tokio::spawn(async { while tokio::signal::ctrl_c().await.is_ok() {} });
+        let pids: Vec<Pid> = children
+            .iter()
+            .filter_map(|(child, _)| child.id())
+            .map(|id| Pid::from_raw(id as i32))
+            .collect();
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::spawn(async move {
+            while terminate.recv().await.is_some() {
+                for pid in &pids {
+                    let _ = kill(*pid, Signal::SIGTERM);
+                }
+            }
+        });
+        let mut passed = true;
+        for (mut child, prefix) in children {
+            let status = child.wait().await?;
+            if !status.success() {
+                passed = false;
+                let how = match status.code() {
+                    Some(code) => format!("exited {}", code),
+                    None => "was killed".to_string(),
+                };
+                println!("{}\x1b[31mThe watcher {}\x1b[0m", prefix, how);
+            }
+        }
+        Ok(passed)
+    }
+}
+
+// Each line is written in one go so lines from different
+// roots don't get mixed together
+async fn relay(output: impl AsyncRead + Unpin, prefix: String, to_stderr: bool) {
+    let mut reader = BufReader::new(output);
+    let mut line = vec![];
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\r', '\n']);
+        let _ = if to_stderr {
+            writeln!(std::io::stderr().lock(), "{}{}", prefix, text)
+        } else {
+            writeln!(std::io::stdout().lock(), "{}{}", prefix, text)
+        };
+    }
+}
+
+// Everything from the command line except --workspace
+// itself and its directories
+fn child_args() -> Vec<OsString> {
+    let mut args = vec![];
+    let mut in_workspace = false;
+    for arg in std::env::args_os().skip(1) {
+        let text = arg.to_string_lossy();
+        if text == "--workspace" {
+            in_workspace = true;
+            continue;
+        }
+        if in_workspace && !text.starts_with('-') {
+            continue;
+        }
+        in_workspace = false;
+        if !text.starts_with("--workspace=") {
+            args.push(arg);
+        }
+    }
+    args
+}
+
+fn expand(pattern: &str) -> Result<Vec<PathBuf>> {
+    let pattern = match (pattern.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(pattern),
+    };
+    let mut found = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let part = component.as_os_str().to_string_lossy();
+        let is_glob = matches!(component, Component::Normal(_)) && part.contains(['*', '?', '[']);
+        if !is_glob {
+            found.iter_mut().for_each(|path| path.push(component));
+            continue;
+        }
+        let matcher = Glob::new(&part)?.compile_matcher();
+        let mut matched = vec![];
+        for dir in &found {
+            let listing = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            let Ok(entries) = fs::read_dir(listing) else {
+                continue;
+            };
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                if matcher.is_match(entry.file_name()) {
+                    matched.push(dir.join(entry.file_name()));
+                }
+            }
+        }
+        found = matched;
+    }
+    Ok(found.into_iter().filter(|path| path.is_dir()).collect())
+}
+
+// What's left of each root after the part they all have
+// in common (~/sites/blog/scripts and ~/sites/shop/scripts
+// are blog/scripts and shop/scripts)
+fn labels(roots: &[PathBuf]) -> Vec<String> {
+    let shared = roots
+        .first()
+        .map(|first| {
+            first
+                .components()
+                .enumerate()
+                .take_while(|(index, component)| {
+                    roots
+                        .iter()
+                        .all(|root| root.components().nth(*index) == Some(*component))
+                })
+                .count()
+        })
+        .unwrap_or(0);
+    roots
+        .iter()
+        .map(|root| {
+            let rest: PathBuf = root.components().skip(shared).collect();
+            if rest.as_os_str().is_empty() {
+                root.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| root.display().to_string())
+            } else {
+                rest.display().to_string()
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,58 @@
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+// Windows drives show up in WSL under /mnt/<letter> and
+// are served over 9p. Changes made from the Windows side
+// don't send inotify events and editors there often only
+// bump the modification time so polling is the only way
+// to see them.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WslMode {
+    // Poll when running under WSL and watching a
+    // Windows drive
+    Auto,
+    Off,
+    On,
+}
+
+impl WslMode {
+    pub fn from_arg(value: &str) -> WslMode {
+        match value {
+            "off" => WslMode::Off,
+            "on" => WslMode::On,
+            _ => WslMode::Auto,
+        }
+    }
+}
+
+pub fn is_wsl() -> bool {
+    std::env::var_os("WSL_DISTRO_NAME").is_some()
+        || std::fs::read_to_string("/proc/sys/kernel/osrelease")
+            .is_ok_and(|release| release.to_lowercase().contains("microsoft"))
+}
+
+pub fn on_windows_drive(path: &Path) -> bool {
+    let mut components = path.components();
+    matches!(
+        (components.next(), components.next(), components.next()),
+        (Some(Component::RootDir), Some(Component::Normal(mnt)), Some(Component::Normal(drive)))
+            if mnt == "mnt" && drive.len() == 1 && drive.to_string_lossy().chars().all(|c| c.is_ascii_alphabetic())
+    )
+}
+
+// Turns a Windows path pasted into WSL (C:\Users\me\x.sh
+// or C:/Users/me/x.sh) into the /mnt/c/... path it's at.
+// Anything else comes back as is.
+pub fn normalize(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(drive), Some(':'), Some('\\' | '/')) if drive.is_ascii_alphabetic() => {
+            let rest = text[3..].replace('\\', "/");
+            PathBuf::from(format!("/mnt/{}", drive.to_ascii_lowercase())).join(rest)
+        }
+        _ => path.to_path_buf(),
+    }
+}
@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+// A script in a directory of its own that waits (up to
+// 5s) for the other one to start too so they're running
+// at the same time when they write down where they are
+fn waiting_script(root: &Path, name: &str, other: &str) {
+    let dir = root.join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("run.sh");
+    std::fs::write(
+        &script,
+        format!(
+            "#!/bin/bash\ntouch ../{name}.started\nfor i in $(seq 50); do\n  [ -e ../{other}.started ] && break\n  sleep 0.1\ndone\npwd > ../{name}.pwd\n"
+        ),
+    )
+    .unwrap();
+    Command::new("chmod")
+        .arg("+x")
+        .arg(&script)
+        .status()
+        .unwrap();
+}
+
+// --jobs runs start from the directories their scripts
+// are in (and the then script from its own) no matter
+// what else is running
+#[test]
+fn concurrent_runs_keep_their_own_directories() {
+    let root = std::env::temp_dir().join(format!("ws-test-dirs-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("after")).unwrap();
+    let root = root.canonicalize().unwrap();
+    waiting_script(&root, "one", "two");
+    waiting_script(&root, "two", "one");
+    let then = root.join("after").join("then.sh");
+    std::fs::write(&then, "#!/bin/bash\npwd > ../then.pwd\n").unwrap();
+    Command::new("chmod").arg("+x").arg(&then).status().unwrap();
+    let output = root.with_extension("out");
+    let mut watcher = Command::new(env!("CARGO_BIN_EXE_ws"))
+        .args([
+            "--all",
+            "--jobs",
+            "2",
+            "--then",
+            "after/then.sh",
+            "--poll",
+            "100ms",
+        ])
+        .current_dir(&root)
+        .env("XDG_STATE_HOME", root.with_extension("state"))
+        .stdin(Stdio::null())
+        .stdout(File::create(&output).unwrap())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    let mut touched = false;
+    while !root.join("then.pwd").exists() && started.elapsed() < Duration::from_secs(20) {
+        std::thread::sleep(Duration::from_millis(100));
+        if !touched && started.elapsed() > Duration::from_secs(1) {
+            Command::new("touch")
+                .arg(root.join("one").join("run.sh"))
+                .status()
+                .unwrap();
+            touched = true;
+        }
+    }
+    std::thread::sleep(Duration::from_millis(200));
+    let _ = watcher.kill();
+    let _ = watcher.wait();
+    let pwd = |name: &str| {
+        std::fs::read_to_string(root.join(format!("{}.pwd", name)))
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+    };
+    let found = [pwd("one"), pwd("two"), pwd("then")];
+    let _ = std::fs::remove_dir_all(&root);
+    let _ = std::fs::remove_dir_all(root.with_extension("state"));
+    let _ = std::fs::remove_file(&output);
+    assert_eq!(
+        found,
+        ["one", "two", "after"].map(|dir| root.join(dir).display().to_string())
+    );
+}
@@ -0,0 +1,78 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+// A watch root of its own under the temp dir with a
+// script that prints a lot more than the run stream
+// holds and then fails. It's set up as the last script
+// (in a state file of its own) so --resume runs it right
+// away and nothing has to be changed.
+fn chatty_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("ws-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let script = root.join("loud.sh");
+    std::fs::write(&script, "#!/bin/bash\nseq 1 300000\nexit 3\n").unwrap();
+    Command::new("chmod")
+        .arg("+x")
+        .arg(&script)
+        .status()
+        .unwrap();
+    let state = root.with_extension("state").join("watch_scripts");
+    std::fs::create_dir_all(&state).unwrap();
+    let last = serde_json::json!({ "last": { root.canonicalize().unwrap().display().to_string(): "loud.sh" } });
+    std::fs::write(state.join("state.json"), last.to_string()).unwrap();
+    root
+}
+
+// Runs the watcher until it stops on its own and hands
+// back how it exited and what it printed
+fn watch(root: &Path, args: &[&str]) -> (ExitStatus, String) {
+    let output = root.with_extension("out");
+    let mut watcher = Command::new(env!("CARGO_BIN_EXE_ws"))
+        .args(args)
+        .current_dir(root)
+        .env("XDG_STATE_HOME", root.with_extension("state"))
+        .stdin(Stdio::null())
+        .stdout(File::create(&output).unwrap())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = watcher.try_wait().unwrap() {
+            break status;
+        }
+        if started.elapsed() > Duration::from_secs(30) {
+            let _ = watcher.kill();
+            panic!("the watcher didn't stop after the failed run");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    let printed = std::fs::read_to_string(&output).unwrap_or_default();
+    let _ = std::fs::remove_dir_all(root);
+    let _ = std::fs::remove_dir_all(root.with_extension("state"));
+    let _ = std::fs::remove_file(&output);
+    (status, printed)
+}
+
+// --sections has the output go through the watcher (and
+// onto the stream)
+#[test]
+fn chatty_failure_stops_the_watcher() {
+    let root = chatty_root("chatty-failure");
+    let (status, _) = watch(
+        &root,
+        &["--resume", "--exit-on-failure", "--sections", "ci"],
+    );
+    assert_eq!(status.code(), Some(3));
+}
+
+#[test]
+fn chatty_failure_fails_ci() {
+    let root = chatty_root("chatty-ci");
+    let (status, printed) = watch(&root, &["--resume", "--ci", "--idle-timeout", "20s"]);
+    assert_eq!(status.code(), Some(3));
+    assert!(printed.contains("ws: run=1 script=loud.sh status=\"exit 3\" success=false"));
+}